@@ -0,0 +1,96 @@
+// Times Pipeline::apply and apply_bytes over generated lines and counts the allocations it
+// makes per line. Run with `cargo bench --bench apply`.
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use zeezey::Pipeline;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const LINES: usize = 1_000_000;
+
+fn main() {
+    // A thousand distinct lines, repeated, so dedupes mostly see duplicates.
+    let lines: Vec<String> = (0..LINES)
+        .map(|number| {
+            let level = ["INFO", "WARN", "ERROR"][number % 3];
+            format!(
+                "2024-05-01T10:00:00Z {} request {} done",
+                level,
+                number % 1000
+            )
+        })
+        .collect();
+
+    bench("filter (drops 2 in 3)", &["filter", "ERROR"], &lines, false);
+    bench("filter (keeps all)", &["filter", "request"], &lines, false);
+    bench(
+        "filter -F (drops 2 in 3)",
+        &["filter", "-F", "ERROR"],
+        &lines,
+        false,
+    );
+    bench(
+        "filter -F on bytes",
+        &["filter", "-F", "ERROR"],
+        &lines,
+        true,
+    );
+    bench("dedupe", &["dedupe"], &lines, false);
+    bench("dedupe on bytes", &["dedupe"], &lines, true);
+    bench(
+        "filter + dedupe",
+        &["filter", "WARN", "dedupe"],
+        &lines,
+        false,
+    );
+    bench("upper (rewrites every line)", &["upper"], &lines, false);
+}
+
+// With `bytes`, lines go through apply_bytes, which skips the UTF-8 check for
+// steps that do not read text.
+fn bench(name: &str, commands: &[&str], lines: &[String], bytes: bool) {
+    let mut pipeline = Pipeline::build_pipeline(commands).unwrap();
+    let mut emitted = 0;
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let started = Instant::now();
+    for line in lines {
+        let count = if bytes {
+            pipeline.apply_bytes(line.as_bytes()).unwrap().len()
+        } else {
+            pipeline.apply(line).unwrap().len()
+        };
+        emitted += black_box(count);
+    }
+    let elapsed = started.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    println!(
+        "{:<28} {:>7.1} ns/line {:>6.2} allocations/line {:>8} emitted",
+        name,
+        elapsed.as_nanos() as f64 / lines.len() as f64,
+        allocations as f64 / lines.len() as f64,
+        emitted
+    );
+}
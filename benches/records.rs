@@ -0,0 +1,134 @@
+// Times how fast RecordReader splits generated input into records, next to the
+// BufRead::read_until loops it replaced. Run with `cargo bench --bench records`.
+use std::{
+    hint::black_box,
+    io::{BufRead, BufReader, Cursor},
+    time::Instant,
+};
+
+use zeezey::{RecordReader, RecordSeparator};
+
+const RECORDS: usize = 1_000_000;
+
+fn main() {
+    let lines: String = (0..RECORDS)
+        .map(|number| {
+            let level = ["INFO", "WARN", "ERROR"][number % 3];
+            format!(
+                "2024-05-01T10:00:00Z {} request {} done\n",
+                level,
+                number % 1000
+            )
+        })
+        .collect();
+    let paragraphs = lines.replace(" done\n", " done\n\n");
+    // Long lines, like minified JSON, are where scanning blocks pays off most.
+    let long_lines: String = (0..RECORDS / 50)
+        .map(|number| {
+            format!(
+                "{{\"id\":{},\"payload\":\"{}\"}}\n",
+                number,
+                "x".repeat(2000)
+            )
+        })
+        .collect();
+    let csv: String = (0..RECORDS)
+        .map(|number| format!("{},\"note {}\",plain\n", number, number % 1000))
+        .collect();
+
+    bench("newline", lines.as_bytes(), RecordSeparator::Newline);
+    bench(
+        "newline (2 KB)",
+        long_lines.as_bytes(),
+        RecordSeparator::Newline,
+    );
+    bench(
+        "literal \\n\\n",
+        paragraphs.as_bytes(),
+        RecordSeparator::literal(r"\n\n").unwrap(),
+    );
+    bench("csv", csv.as_bytes(), RecordSeparator::Csv);
+}
+
+fn bench(name: &str, input: &[u8], separator: RecordSeparator) {
+    let reader = || BufReader::with_capacity(64 * 1024, Cursor::new(input));
+
+    let started = Instant::now();
+    let mut records = RecordReader::new(reader(), separator.clone());
+    let mut count = 0;
+    while let Some(record) = records.next_record().unwrap() {
+        count += black_box(record).1.min(1);
+    }
+    report(
+        name,
+        "memchr",
+        input.len(),
+        count,
+        started.elapsed().as_secs_f64(),
+    );
+
+    let started = Instant::now();
+    let mut reader = reader();
+    let mut count = 0;
+    while let Some(record) = read_until_record(&mut reader, &separator) {
+        count += black_box(record).capacity().min(1);
+    }
+    report(
+        name,
+        "read_until",
+        input.len(),
+        count,
+        started.elapsed().as_secs_f64(),
+    );
+}
+
+// Splits records the way the reader did before it scanned blocks itself.
+fn read_until_record(reader: &mut impl BufRead, separator: &RecordSeparator) -> Option<Vec<u8>> {
+    let mut record = vec![];
+    match separator {
+        RecordSeparator::Literal(separator) => loop {
+            let read = reader
+                .read_until(*separator.last().unwrap(), &mut record)
+                .unwrap();
+            if read == 0 || record.ends_with(separator) {
+                break;
+            }
+        },
+        RecordSeparator::Csv => {
+            let mut quotes = 0;
+            loop {
+                let start = record.len();
+                let read = reader.read_until(b'\n', &mut record).unwrap();
+                quotes += record[start..].iter().filter(|&&byte| byte == b'"').count();
+                if read == 0 || quotes % 2 == 0 {
+                    break;
+                }
+            }
+        }
+        _ => {
+            reader.read_until(b'\n', &mut record).unwrap();
+        }
+    }
+    if record.is_empty() {
+        return None;
+    }
+
+    let length = match separator {
+        RecordSeparator::Literal(separator) if record.ends_with(separator) => separator.len(),
+        _ => usize::from(record.ends_with(b"\n")),
+    };
+    record.truncate(record.len() - length);
+
+    Some(record)
+}
+
+fn report(name: &str, scanner: &str, bytes: usize, records: usize, seconds: f64) {
+    println!(
+        "{:<16} {:<11} {:>8.1} MB/s {:>7.1} ns/record {:>8} records",
+        name,
+        scanner,
+        bytes as f64 / seconds / 1_000_000.0,
+        seconds * 1e9 / records as f64,
+        records
+    );
+}
@@ -0,0 +1,112 @@
+use regex::Regex;
+
+// Splits Common and Combined log format lines into ip, user, timestamp,
+// method, path, protocol, status, bytes, referer and user_agent. A trailing
+// number, as appended by nginx's `$request_time` or Apache's `%D`, becomes
+// latency. Nil values (`-`) are left out.
+#[derive(Debug)]
+pub struct AccessLogParser {
+    regex: Regex,
+}
+
+impl AccessLogParser {
+    pub fn new() -> AccessLogParser {
+        AccessLogParser {
+            regex: Regex::new(
+                r#"^(\S+) \S+ (\S+) \[([^\]]+)\] "((?:[^"\\]|\\.)*)" (\d{3}) (\d+|-)(?: "((?:[^"\\]|\\.)*)" "((?:[^"\\]|\\.)*)")?(?: (\d+(?:\.\d+)?))?\s*$"#,
+            )
+            .unwrap(),
+        }
+    }
+
+    pub fn fields(&self, line: &str) -> Option<Vec<(String, String)>> {
+        let captures = self.regex.captures(line)?;
+        let capture = |index: usize| captures.get(index).map(|c| c.as_str());
+
+        let mut request = capture(4).unwrap_or_default().splitn(3, ' ');
+        let (method, path, protocol) = (request.next(), request.next(), request.next());
+
+        let mut fields = vec![];
+        for (name, value) in [
+            ("ip", capture(1)),
+            ("user", capture(2)),
+            ("timestamp", capture(3)),
+            ("method", method),
+            ("path", path),
+            ("protocol", protocol),
+            ("status", capture(5)),
+            ("bytes", capture(6)),
+            ("referer", capture(7)),
+            ("user_agent", capture(8)),
+            ("latency", capture(9)),
+        ] {
+            match value {
+                Some(value) if !value.is_empty() && value != "-" => {
+                    fields.push((name.to_string(), value.to_string()))
+                }
+                _ => {}
+            }
+        }
+
+        Some(fields)
+    }
+}
+
+impl PartialEq for AccessLogParser {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AccessLogParser;
+
+    fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn fields_parses_combined_format_with_latency() {
+        //+ Act
+        let fields = AccessLogParser::new()
+            .fields(r#"10.0.0.1 - bob [10/Oct/2000:13:55:36 -0700] "GET /a?b=1 HTTP/1.1" 503 2326 "-" "curl/8.0" 0.250"#)
+            .unwrap();
+
+        //+ Assert
+        assert_eq!(field(&fields, "ip"), Some("10.0.0.1"));
+        assert_eq!(field(&fields, "user"), Some("bob"));
+        assert_eq!(
+            field(&fields, "timestamp"),
+            Some("10/Oct/2000:13:55:36 -0700")
+        );
+        assert_eq!(field(&fields, "method"), Some("GET"));
+        assert_eq!(field(&fields, "path"), Some("/a?b=1"));
+        assert_eq!(field(&fields, "protocol"), Some("HTTP/1.1"));
+        assert_eq!(field(&fields, "status"), Some("503"));
+        assert_eq!(field(&fields, "bytes"), Some("2326"));
+        assert_eq!(field(&fields, "referer"), None);
+        assert_eq!(field(&fields, "user_agent"), Some("curl/8.0"));
+        assert_eq!(field(&fields, "latency"), Some("0.250"));
+    }
+
+    #[test]
+    fn fields_parses_common_format() {
+        //+ Arrange
+        let parser = AccessLogParser::new();
+
+        //+ Act
+        let fields = parser
+            .fields(r#"::1 - - [10/Oct/2000:13:55:36 -0700] "-" 400 -"#)
+            .unwrap();
+
+        //+ Assert
+        assert_eq!(field(&fields, "status"), Some("400"));
+        assert_eq!(field(&fields, "method"), None);
+        assert_eq!(field(&fields, "bytes"), None);
+        assert_eq!(parser.fields("GET / 200"), None);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::width::display_width;
+
+// Buffers the whole stream and, at the end, pads every column to the width of
+// its widest cell, like `column -t`. Widths are in terminal columns, so wide
+// characters still line up. The last column is never padded, and a
+// whitespace delimiter splits on runs of whitespace.
+#[derive(Debug, PartialEq)]
+pub struct Align {
+    delimiter: String,
+    rows: Vec<Vec<String>>,
+    stored: usize,
+}
+
+impl Align {
+    pub fn new(delimiter: &str) -> Align {
+        Align {
+            delimiter: delimiter.to_string(),
+            rows: vec![],
+            stored: 0,
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        self.stored += line.len();
+        let cells: Vec<String> = if self.delimiter.trim().is_empty() {
+            line.split_whitespace().map(str::to_string).collect()
+        } else {
+            line.split(self.delimiter.as_str())
+                .map(|cell| cell.trim().to_string())
+                .collect()
+        };
+        self.rows.push(cells);
+    }
+
+    pub fn flush(&mut self) -> Vec<String> {
+        let mut widths: Vec<usize> = vec![];
+        for row in self.rows.iter() {
+            for (column, cell) in row.iter().enumerate() {
+                let width = display_width(cell);
+                match widths.get_mut(column) {
+                    Some(existing) => *existing = (*existing).max(width),
+                    None => widths.push(width),
+                }
+            }
+        }
+
+        self.stored = 0;
+        std::mem::take(&mut self.rows)
+            .into_iter()
+            .map(|row| {
+                let last = row.len().saturating_sub(1);
+                let mut line = String::new();
+
+                for (column, cell) in row.iter().enumerate() {
+                    line.push_str(cell);
+                    if column < last {
+                        let padding = widths[column] - display_width(cell) + 2;
+                        line.extend(std::iter::repeat_n(' ', padding));
+                    }
+                }
+
+                line
+            })
+            .collect()
+    }
+
+    pub fn memory(&self) -> usize {
+        self.stored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Align;
+
+    #[test]
+    fn flush_pads_columns_to_widest_cell() {
+        //+ Arrange
+        let mut align = Align::new(",");
+        align.push("name,age,city".to_string());
+        align.push("bob,7".to_string());
+        align.push("rosalind, 102 ,Zürich".to_string());
+
+        //+ Act
+        let lines = align.flush();
+
+        //+ Assert
+        assert_eq!(
+            lines,
+            vec![
+                "name      age  city",
+                "bob       7",
+                "rosalind  102  Zürich",
+            ]
+        );
+        assert_eq!(align.memory(), 0);
+    }
+
+    #[test]
+    fn flush_lines_up_wide_characters_by_display_width() {
+        //+ Arrange
+        let mut align = Align::new(" ");
+        align.push("東京 13960000".to_string());
+        align.push("Osaka 2750000".to_string());
+
+        //+ Act
+        let lines = align.flush();
+
+        //+ Assert
+        assert_eq!(lines, vec!["東京   13960000", "Osaka  2750000"]);
+    }
+}
@@ -0,0 +1,252 @@
+use std::time::{Duration, Instant};
+
+use indicatif::HumanBytes;
+
+use crate::{
+    error::RanglerError,
+    inputs::{expand_globs, open_inputs},
+    options::Options,
+    pipeline::Pipeline,
+    records::{strip_carriage_returns, RecordReader},
+    sample::Random,
+    stats::StepStats,
+};
+
+const METHODS: &[&str] = &["GET", "GET", "GET", "POST", "PUT", "DELETE"];
+const PATHS: &[&str] = &[
+    "/",
+    "/login",
+    "/api/v1/items",
+    "/api/v1/orders",
+    "/static/app.js",
+];
+const STATUSES: &[&str] = &["200", "200", "200", "200", "304", "404", "500"];
+const AGENTS: &[&str] = &[
+    "curl/8.5.0",
+    "Mozilla/5.0 (X11; Linux x86_64)",
+    "python-requests/2.31",
+];
+
+/// Where `rangler bench` gets its lines: the first records of the input, or
+/// made-up access log lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Corpus {
+    Input(usize),
+    Synthetic(usize),
+}
+
+/// What a benchmark measured: the corpus, the time the whole pipeline took
+/// over it and each step's share of that time, with its lines in and out.
+#[derive(Debug)]
+pub struct Profile {
+    pub lines: usize,
+    pub bytes: usize,
+    pub emitted: usize,
+    pub elapsed: Duration,
+    pub steps: Vec<(StepStats, Duration)>,
+}
+
+/// Runs the commands over the corpus, held in memory so that reading the
+/// input is not timed. A step's time is how much longer the steps up to it
+/// take than the steps before it, so the steps run as written, without the
+/// optimizer moving them, and steps with side effects, like tee or exec,
+/// repeat them once for every step from theirs on.
+pub fn bench(
+    options: &Options,
+    commands: &[String],
+    corpus: Corpus,
+) -> Result<Profile, RanglerError> {
+    if options.bytes {
+        Err("Benchmarks need text mode")?;
+    }
+    let lines = match corpus {
+        Corpus::Input(count) => read_corpus(options, count)?,
+        Corpus::Synthetic(count) => synthetic_corpus(count),
+    };
+    let step_commands = Pipeline::build_pipeline(commands)?.step_commands();
+
+    let mut profile = Profile {
+        lines: lines.len(),
+        bytes: lines.iter().map(|line| line.len() + 1).sum(),
+        emitted: lines.len(),
+        elapsed: Duration::ZERO,
+        steps: vec![],
+    };
+    for count in 1..=step_commands.len() {
+        let mut pipeline = Pipeline::build_pipeline(&step_commands[..count].concat())?;
+        if let Some(policy) = options.on_error {
+            pipeline.set_error_policy(policy);
+        }
+        if let Some(seed) = options.seed {
+            pipeline.set_seed(seed);
+        }
+        if let Some(rules) = options.case_rules {
+            pipeline.set_case_rules(rules);
+        }
+        if options.ignore_case {
+            pipeline.set_ignore_case();
+        }
+
+        let started = Instant::now();
+        let mut emitted = 0;
+        for line in lines.iter() {
+            emitted += pipeline.apply(line)?.len();
+        }
+        emitted += pipeline.finish()?.len();
+        let elapsed = started.elapsed();
+
+        let stats = pipeline.step_stats()[count - 1].clone();
+        profile
+            .steps
+            .push((stats, elapsed.saturating_sub(profile.elapsed)));
+        profile.elapsed = profile.elapsed.max(elapsed);
+        profile.emitted = emitted;
+    }
+
+    Ok(profile)
+}
+
+impl Profile {
+    pub fn table(&self) -> Vec<String> {
+        let seconds = self.elapsed.as_secs_f64();
+        let total: f64 = self.steps.iter().map(|(_, time)| time.as_secs_f64()).sum();
+        let mut rows = vec![[
+            "step".to_string(),
+            "in".to_string(),
+            "out".to_string(),
+            "time".to_string(),
+            "share".to_string(),
+            "lines/s".to_string(),
+        ]];
+        let outputs = self
+            .steps
+            .iter()
+            .skip(1)
+            .map(|(step, _)| step.received)
+            .chain([self.emitted]);
+        for ((step, time), out) in self.steps.iter().zip(outputs) {
+            let time = time.as_secs_f64();
+            rows.push([
+                step.name.clone(),
+                step.received.to_string(),
+                out.to_string(),
+                format!("{:.3}s", time),
+                format!("{:.1}%", 100.0 * time / total.max(f64::EPSILON)),
+                rate(step.received, time),
+            ]);
+        }
+
+        let mut widths = [0; 6];
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut lines = vec![format!(
+            "{} lines ({}) through {} steps in {:.3}s, {} lines/s, {}/s",
+            self.lines,
+            HumanBytes(self.bytes as u64),
+            self.steps.len(),
+            seconds,
+            rate(self.lines, seconds),
+            HumanBytes((self.bytes as f64 / seconds.max(f64::EPSILON)) as u64)
+        )];
+        for row in rows {
+            let mut line = format!("  {:<width$}", row[0], width = widths[0]);
+            for (cell, width) in row.iter().zip(widths).skip(1) {
+                line.push_str(&format!("  {:>width$}", cell, width = width));
+            }
+            lines.push(line);
+        }
+
+        lines
+    }
+}
+
+fn rate(lines: usize, seconds: f64) -> String {
+    match seconds {
+        0.0 => "-".to_string(),
+        _ => format!("{:.0}", lines as f64 / seconds),
+    }
+}
+
+// The first records of the inputs, decoded lossily like a preview.
+fn read_corpus(options: &Options, count: usize) -> Result<Vec<String>, RanglerError> {
+    let mut paths = options.inputs.clone();
+    paths.extend(expand_globs(&options.globs)?);
+    let (sources, _) = open_inputs(&paths, options.read_buffer, options.mmap)?;
+
+    let mut lines = vec![];
+    for (_, source) in sources {
+        let mut records = RecordReader::new(source, options.record_separator.clone());
+        while lines.len() < count {
+            let Some((mut record, _)) = records.next_record()? else {
+                break;
+            };
+            if !options.keep_eol {
+                strip_carriage_returns(&mut record);
+            }
+            lines.push(String::from_utf8_lossy(&record).into_owned());
+        }
+    }
+
+    Ok(lines)
+}
+
+// Combined log format lines, the same ones on every run.
+fn synthetic_corpus(count: usize) -> Vec<String> {
+    let mut random = Random::new(7);
+    let mut pick = |choices: &[&'static str]| choices[random.below(choices.len())];
+
+    (0..count)
+        .map(|number| {
+            let (method, path, status, agent) =
+                (pick(METHODS), pick(PATHS), pick(STATUSES), pick(AGENTS));
+            format!(
+                "10.0.{}.{} - - [01/May/2024:{:02}:{:02}:{:02} +0000] \"{} {}?id={} HTTP/1.1\" {} {} \"-\" \"{}\"",
+                number / 256 % 256,
+                number % 256,
+                number / 3600 % 24,
+                number / 60 % 60,
+                number % 60,
+                method,
+                path,
+                number % 997,
+                status,
+                200 + number * 37 % 5000,
+                agent
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bench, synthetic_corpus, Corpus};
+    use crate::options::Options;
+
+    #[test]
+    fn bench_profiles_every_step_over_a_synthetic_corpus() {
+        //+ Arrange
+        let commands = ["filter", "\" 200 ", "dedupe", "upper"].map(String::from);
+
+        //+ Act
+        let profile = bench(&Options::default(), &commands, Corpus::Synthetic(2000)).unwrap();
+        let table = profile.table();
+
+        //+ Assert
+        let names: Vec<&str> = profile
+            .steps
+            .iter()
+            .map(|(step, _)| step.name.as_str())
+            .collect();
+        assert_eq!(names, ["filter", "dedupe", "upper"]);
+        assert_eq!(profile.steps[0].0.received, 2000);
+        assert_eq!(profile.steps[1].0.received, profile.steps[2].0.received);
+        assert!(profile.emitted > 0 && profile.emitted < 2000);
+        assert_eq!(table.len(), 5);
+        assert!(table[0].starts_with("2000 lines"));
+        assert_eq!(synthetic_corpus(3), synthetic_corpus(3));
+    }
+}
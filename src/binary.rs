@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+
+use regex::bytes::Regex;
+
+use crate::{
+    codec::{base64_decode_bytes, base64_encode_bytes},
+    error::RanglerError,
+    hash::HashAlgorithm,
+    pipeline::{compiled, next_argument, next_flag},
+    stats::StepStats,
+};
+
+// The steps that make sense on raw bytes. Regexes match bytes and case
+// changes and trimming only touch ASCII, so nothing that is not valid UTF-8
+// is ever altered.
+#[derive(Debug)]
+pub enum ByteStep {
+    Filter(Regex),
+    Lower,
+    Upper,
+    Trim,
+    Dedupe(HashSet<Vec<u8>>, usize),
+    Append(Vec<u8>),
+    Prepend(Vec<u8>),
+    Hash(HashAlgorithm, bool),
+    Base64Encode(bool),
+    Base64Decode(bool),
+    MinLength(usize),
+    MaxLength(usize),
+}
+
+#[derive(Debug)]
+pub struct BytePipeline {
+    steps: Vec<ByteStep>,
+    stats: Vec<StepStats>,
+}
+
+impl BytePipeline {
+    pub fn build_pipeline<T: AsRef<str>>(commands: &[T]) -> Result<BytePipeline, RanglerError> {
+        let mut tokens = commands;
+        let pipeline = Self::parse_steps(&mut tokens);
+
+        pipeline.map_err(|message| RanglerError::from(message).at(commands.len() - tokens.len()))
+    }
+
+    fn parse_steps<T: AsRef<str>>(tokens: &mut &[T]) -> Result<BytePipeline, &'static str> {
+        let mut steps = vec![];
+        let mut stats = vec![];
+
+        while let Some(command) = next_argument(tokens) {
+            let step = match command.to_lowercase().as_str() {
+                "filter" => {
+                    let fixed = next_flag(tokens, "-F");
+                    let pattern = next_argument(tokens).ok_or("Missing regular expression")?;
+                    let pattern = match fixed {
+                        true => regex::escape(pattern),
+                        false => pattern.to_string(),
+                    };
+
+                    ByteStep::Filter(
+                        Regex::new(&pattern).map_err(|_| "Invalid regular expression")?,
+                    )
+                }
+                "lower" => ByteStep::Lower,
+                "upper" => ByteStep::Upper,
+                "trim" => ByteStep::Trim,
+                "dedupe" => ByteStep::Dedupe(HashSet::new(), 0),
+                "append" => ByteStep::Append(next_argument(tokens).ok_or("Missing suffix")?.into()),
+                "prepend" => {
+                    ByteStep::Prepend(next_argument(tokens).ok_or("Missing prefix")?.into())
+                }
+                "hash" => {
+                    let algorithm = HashAlgorithm::parse(
+                        next_argument(tokens).ok_or("Missing hash algorithm")?,
+                    )?;
+
+                    ByteStep::Hash(algorithm, next_flag(tokens, "--append"))
+                }
+                "base64" => {
+                    let mode = next_argument(tokens).ok_or("Missing base64 mode")?;
+                    let url_safe = next_flag(tokens, "--url");
+
+                    match mode {
+                        "encode" => ByteStep::Base64Encode(url_safe),
+                        "decode" => ByteStep::Base64Decode(url_safe),
+                        _ => Err("Invalid base64 mode")?,
+                    }
+                }
+                "minlen" | "maxlen" => {
+                    let length = next_argument(tokens)
+                        .ok_or("Missing length")?
+                        .parse::<usize>()
+                        .map_err(|_| "Invalid length")?;
+
+                    if command.eq_ignore_ascii_case("minlen") {
+                        ByteStep::MinLength(length)
+                    } else {
+                        ByteStep::MaxLength(length)
+                    }
+                }
+                _ => Err("Command not supported in byte mode")?,
+            };
+
+            steps.push(step);
+            stats.push(StepStats::new(&command.to_lowercase()));
+        }
+
+        if steps.is_empty() {
+            Err("No commands specified")
+        } else {
+            Ok(BytePipeline { steps, stats })
+        }
+    }
+
+    pub fn apply(&mut self, line: &[u8]) -> Option<Vec<u8>> {
+        let mut output = line.to_vec();
+
+        for (step, stats) in self.steps.iter_mut().zip(self.stats.iter_mut()) {
+            stats.received += 1;
+            output = match step {
+                ByteStep::Filter(regex) => {
+                    if !regex.is_match(&output) {
+                        return None;
+                    }
+
+                    output
+                }
+                ByteStep::Lower => output.to_ascii_lowercase(),
+                ByteStep::Upper => output.to_ascii_uppercase(),
+                ByteStep::Trim => output.trim_ascii().to_vec(),
+                ByteStep::Dedupe(seen, bytes) => {
+                    if seen.contains(&output) {
+                        return None;
+                    }
+
+                    *bytes += output.len();
+                    seen.insert(output.clone());
+                    output
+                }
+                ByteStep::Append(suffix) => [output, suffix.clone()].concat(),
+                ByteStep::Prepend(prefix) => [prefix.clone(), output].concat(),
+                ByteStep::Hash(algorithm, append) => {
+                    let digest = algorithm.digest_bytes(&output).into_bytes();
+                    if *append {
+                        [output, b" ".to_vec(), digest].concat()
+                    } else {
+                        digest
+                    }
+                }
+                ByteStep::Base64Encode(url_safe) => {
+                    base64_encode_bytes(&output, *url_safe).into_bytes()
+                }
+                ByteStep::Base64Decode(url_safe) => base64_decode_bytes(&output, *url_safe)?,
+                ByteStep::MinLength(length) => {
+                    if output.len() < *length {
+                        return None;
+                    }
+
+                    output
+                }
+                ByteStep::MaxLength(length) => {
+                    if output.len() > *length {
+                        return None;
+                    }
+
+                    output
+                }
+            };
+        }
+
+        Some(output)
+    }
+
+    // Also remembers each step's peak for the run summary.
+    // Like Pipeline::explain, for the few steps byte mode has.
+    pub fn explain(&self) -> String {
+        let mut text = String::new();
+        for (index, (step, stats)) in self.steps.iter().zip(&self.stats).enumerate() {
+            let memory = match step {
+                ByteStep::Dedupe(..) => "grows with every distinct line",
+                _ => "constant",
+            };
+
+            text += &format!("{}. {}\n", index + 1, stats.name);
+            text += &format!("   compiled: {}\n", compiled(step));
+            text += "   buffers:  no\n";
+            text += &format!("   memory:   {}\n", memory);
+        }
+
+        text
+    }
+
+    pub fn sample_memory(&mut self) -> usize {
+        let mut memory = 0;
+        for (step, stats) in self.steps.iter().zip(self.stats.iter_mut()) {
+            let step_memory = match step {
+                ByteStep::Dedupe(_, bytes) => *bytes,
+                _ => 0,
+            };
+            stats.peak_memory = stats.peak_memory.max(step_memory);
+            memory += step_memory;
+        }
+
+        memory
+    }
+
+    pub fn step_stats(&self) -> &[StepStats] {
+        &self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BytePipeline;
+
+    #[test]
+    fn build_pipeline_rejects_text_only_commands() {
+        //+ Act
+        let pipeline = BytePipeline::build_pipeline(&["filter", "a", "json", ".a"]);
+
+        //+ Assert
+        assert_eq!(
+            pipeline.err().unwrap(),
+            "Command not supported in byte mode"
+        );
+    }
+
+    #[test]
+    fn apply_preserves_invalid_utf8() {
+        //+ Arrange
+        let mut pipeline = BytePipeline::build_pipeline(&[
+            "filter",
+            r"(?-u)\xff",
+            "upper",
+            "dedupe",
+            "append",
+            "!",
+        ])
+        .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(pipeline.apply(b"a\xffb"), Some(b"A\xffB!".to_vec()));
+        assert_eq!(pipeline.apply(b"a\xffb"), None);
+        assert_eq!(pipeline.apply(b"plain"), None);
+    }
+}
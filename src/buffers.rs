@@ -0,0 +1,64 @@
+use std::io::{stdin, stdout, IsTerminal};
+
+// What a standard stream is attached to, which decides how large a buffer is
+// worth giving it when none is configured. A terminal gets a small buffer so
+// lines show up promptly, a pipe one that comfortably exceeds the kernel's
+// pipe capacity, and a regular file a large one to keep system calls rare.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StreamKind {
+    Terminal,
+    Pipe,
+    File,
+}
+
+impl StreamKind {
+    pub fn stdin() -> StreamKind {
+        StreamKind::detect(stdin().is_terminal(), "/dev/stdin")
+    }
+
+    pub fn stdout() -> StreamKind {
+        StreamKind::detect(stdout().is_terminal(), "/dev/stdout")
+    }
+
+    // Anything that is neither a terminal nor a regular file, including
+    // platforms without `/dev/stdin`, is treated as a pipe.
+    fn detect(is_terminal: bool, device: &str) -> StreamKind {
+        if is_terminal {
+            return StreamKind::Terminal;
+        }
+
+        match std::fs::metadata(device) {
+            Ok(metadata) if metadata.is_file() => StreamKind::File,
+            _ => StreamKind::Pipe,
+        }
+    }
+
+    pub fn buffer_size(self) -> usize {
+        match self {
+            StreamKind::Terminal => 8 << 10,
+            StreamKind::Pipe => 256 << 10,
+            StreamKind::File => 1 << 20,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StreamKind;
+
+    #[test]
+    fn detect_prefers_terminal_then_regular_file() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-buffers-{}.txt", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let path = path.to_str().unwrap();
+
+        //+ Act + Assert
+        assert_eq!(StreamKind::detect(true, path), StreamKind::Terminal);
+        assert_eq!(StreamKind::detect(false, path), StreamKind::File);
+        assert_eq!(
+            StreamKind::detect(false, "/nonexistent/rangler"),
+            StreamKind::Pipe
+        );
+    }
+}
@@ -0,0 +1,242 @@
+use std::{collections::HashSet, num::NonZeroUsize};
+
+use regex::Regex;
+
+use crate::{
+    dedupe::{BloomFilter, DuplicateSet, LruSet, SpillingSet, WindowSet},
+    hash::HashAlgorithm,
+    markdown::Markdown,
+    normalize::NormalizationForm,
+    pipeline::{Pipeline, PipelineStep},
+    step::Step,
+    template::Template,
+};
+
+/// Builds a [`Pipeline`] step by step from typed arguments, for library users
+/// who would rather not assemble command line tokens. Only arguments that
+/// still need parsing, such as regular expressions, can fail.
+///
+/// ```
+/// use zeezey::Pipeline;
+///
+/// let mut pipeline = Pipeline::builder().filter("error")?.trim().dedupe().build();
+/// assert_eq!(pipeline.apply(" error ")?, vec!["error".to_string()]);
+/// assert!(pipeline.apply("fine")?.is_empty());
+/// # Ok::<(), &'static str>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct PipelineBuilder {
+    steps: Vec<(String, PipelineStep)>,
+}
+
+impl PipelineBuilder {
+    pub fn filter(self, pattern: &str) -> Result<PipelineBuilder, &'static str> {
+        let regex = Regex::new(pattern).map_err(|_| "Invalid regular expression")?;
+
+        Ok(self.step("filter", PipelineStep::Filter(regex)))
+    }
+
+    pub fn lower(self) -> PipelineBuilder {
+        self.step("lower", PipelineStep::Lower)
+    }
+
+    pub fn upper(self) -> PipelineBuilder {
+        self.step("upper", PipelineStep::Upper)
+    }
+
+    pub fn trim(self) -> PipelineBuilder {
+        self.step("trim", PipelineStep::Trim)
+    }
+
+    pub fn ltrim(self) -> PipelineBuilder {
+        self.step("ltrim", PipelineStep::TrimStart)
+    }
+
+    pub fn rtrim(self) -> PipelineBuilder {
+        self.step("rtrim", PipelineStep::TrimEnd)
+    }
+
+    pub fn ascii(self) -> PipelineBuilder {
+        self.step("ascii", PipelineStep::Ascii)
+    }
+
+    pub fn strip_html(self, squeeze: bool) -> PipelineBuilder {
+        self.step("strip-html", PipelineStep::StripHtml(squeeze))
+    }
+
+    pub fn unescape_html(self) -> PipelineBuilder {
+        self.step("unescape-html", PipelineStep::UnescapeHtml)
+    }
+
+    pub fn strip_markdown(self) -> PipelineBuilder {
+        self.step(
+            "strip-markdown",
+            PipelineStep::StripMarkdown(Markdown::new()),
+        )
+    }
+
+    pub fn dedupe(self) -> PipelineBuilder {
+        self.step("dedupe", PipelineStep::Dedupe(HashSet::new(), 0))
+    }
+
+    pub fn dedupe_recent(self, capacity: NonZeroUsize) -> PipelineBuilder {
+        self.step(
+            "dedupe",
+            PipelineStep::DedupeRecent(LruSet::new(capacity.get())),
+        )
+    }
+
+    pub fn dedupe_window(self, size: NonZeroUsize) -> PipelineBuilder {
+        self.step(
+            "dedupe",
+            PipelineStep::DedupeWindow(WindowSet::new(size.get())),
+        )
+    }
+
+    pub fn dedupe_approx(
+        self,
+        expected_items: NonZeroUsize,
+        false_positive_rate: f64,
+    ) -> Result<PipelineBuilder, &'static str> {
+        if !(false_positive_rate > 0.0 && false_positive_rate < 1.0) {
+            return Err("Invalid false positive rate");
+        }
+
+        Ok(self.step(
+            "dedupe",
+            PipelineStep::DedupeApprox(BloomFilter::new(expected_items.get(), false_positive_rate)),
+        ))
+    }
+
+    pub fn dedupe_spill(self, memory_limit: usize) -> PipelineBuilder {
+        self.step(
+            "dedupe",
+            PipelineStep::DedupeSpill(SpillingSet::new(memory_limit)),
+        )
+    }
+
+    pub fn only_duplicates(self, once: bool) -> PipelineBuilder {
+        self.step(
+            "only-duplicates",
+            PipelineStep::OnlyDuplicates(DuplicateSet::new(once)),
+        )
+    }
+
+    pub fn append(self, suffix: &str) -> PipelineBuilder {
+        self.step("append", PipelineStep::Append(suffix.to_string()))
+    }
+
+    pub fn prepend(self, prefix: &str) -> PipelineBuilder {
+        self.step("prepend", PipelineStep::Prepend(prefix.to_string()))
+    }
+
+    pub fn pad_left(self, width: usize, fill: char) -> PipelineBuilder {
+        self.step("pad-left", PipelineStep::PadLeft(width, fill))
+    }
+
+    pub fn pad_right(self, width: usize, fill: char) -> PipelineBuilder {
+        self.step("pad-right", PipelineStep::PadRight(width, fill))
+    }
+
+    // With `append`, the digest follows the line instead of replacing it.
+    pub fn hash(self, algorithm: HashAlgorithm, append: bool) -> PipelineBuilder {
+        self.step("hash", PipelineStep::Hash(algorithm, append))
+    }
+
+    pub fn normalize(self, form: NormalizationForm) -> PipelineBuilder {
+        self.step("normalize", PipelineStep::Normalize(form))
+    }
+
+    // With `bytes`, lengths are counted in bytes rather than characters.
+    pub fn min_length(self, length: usize, bytes: bool) -> PipelineBuilder {
+        self.step("minlen", PipelineStep::MinLength(length, bytes))
+    }
+
+    pub fn max_length(self, length: usize, bytes: bool) -> PipelineBuilder {
+        self.step("maxlen", PipelineStep::MaxLength(length, bytes))
+    }
+
+    pub fn tag(self, name: &str, pattern: &str) -> Result<PipelineBuilder, &'static str> {
+        let regex = Regex::new(pattern).map_err(|_| "Invalid regular expression")?;
+
+        Ok(self.step("tag", PipelineStep::Tag(name.to_string(), regex)))
+    }
+
+    pub fn format(self, template: &str) -> Result<PipelineBuilder, &'static str> {
+        Ok(self.step("format", PipelineStep::Format(Template::parse(template)?)))
+    }
+
+    /// Adds a step of your own, under the name it is reported by in run
+    /// statistics.
+    pub fn custom(self, name: &str, step: impl Step + 'static) -> PipelineBuilder {
+        self.step(name, PipelineStep::Custom(Box::new(step)))
+    }
+
+    /// Adds any other step, under the name it is reported by in run
+    /// statistics.
+    pub fn step(mut self, name: &str, step: PipelineStep) -> PipelineBuilder {
+        self.steps.push((name.to_string(), step));
+        self
+    }
+
+    pub fn build(self) -> Pipeline {
+        Pipeline::new(self.steps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use super::PipelineBuilder;
+    use crate::pipeline::Pipeline;
+
+    #[test]
+    fn build_matches_parsed_pipeline() {
+        //+ Arrange
+        let mut built = PipelineBuilder::default()
+            .filter("^a")
+            .unwrap()
+            .dedupe_recent(NonZeroUsize::new(2).unwrap())
+            .append("!")
+            .build();
+        let mut parsed =
+            Pipeline::build_pipeline(&["filter", "^a", "dedupe", "--recent", "2", "append", "!"])
+                .unwrap();
+
+        //+ Act
+        let mut built_lines = vec![];
+        let mut parsed_lines = vec![];
+        for line in ["ab", "b", "ab", "ac"] {
+            built_lines.extend(built.apply(line).unwrap());
+            parsed_lines.extend(parsed.apply(line).unwrap());
+        }
+
+        //+ Assert
+        assert_eq!(built_lines, vec!["ab!".to_string(), "ac!".to_string()]);
+        assert_eq!(built_lines, parsed_lines);
+        assert_eq!(
+            built
+                .step_stats()
+                .iter()
+                .map(|stats| stats.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["filter", "dedupe", "append"]
+        );
+    }
+
+    #[test]
+    fn builder_rejects_unparseable_arguments() {
+        //+ Act + Assert
+        assert_eq!(
+            PipelineBuilder::default().filter("(").err(),
+            Some("Invalid regular expression")
+        );
+        assert_eq!(
+            PipelineBuilder::default()
+                .dedupe_approx(NonZeroUsize::new(10).unwrap(), 1.5)
+                .err(),
+            Some("Invalid false positive rate")
+        );
+    }
+}
@@ -0,0 +1,278 @@
+#[derive(Debug, PartialEq)]
+pub enum Expression {
+    Number(f64),
+    Field(usize),
+    Negate(Box<Expression>),
+    Binary(Box<Expression>, Operator, Box<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Calculation {
+    pub target: Option<usize>,
+    pub expression: Expression,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Field(usize),
+    Operator(Operator),
+    Open,
+    Close,
+    Assign,
+}
+
+impl Calculation {
+    // Accepts `<expression>` to replace the line with the result, or
+    // `f<n> = <expression>` to store the result in field n. Fields are
+    // 1-based and written either as `f1` or `{1}`.
+    pub fn parse(source: &str) -> Result<Calculation, &'static str> {
+        let tokens = tokenize(source)?;
+
+        let (target, tokens) = match tokens.as_slice() {
+            [Token::Field(field), Token::Assign, rest @ ..] => (Some(*field), rest),
+            _ => (None, tokens.as_slice()),
+        };
+
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+        };
+        let expression = parser.expression()?;
+        if parser.position != tokens.len() {
+            return Err("Invalid expression");
+        }
+
+        Ok(Calculation { target, expression })
+    }
+
+    pub fn apply(&self, line: &str, delimiter: Option<&str>) -> Option<String> {
+        let mut fields: Vec<&str> = match delimiter {
+            Some(delimiter) => line.split(delimiter).collect(),
+            None => line.split_whitespace().collect(),
+        };
+
+        let result = format_number(self.expression.evaluate(&fields)?);
+
+        match self.target {
+            None => Some(result),
+            Some(target) => {
+                if fields.len() < target {
+                    fields.resize(target, "");
+                }
+                fields[target - 1] = &result;
+
+                Some(fields.join(delimiter.unwrap_or(" ")))
+            }
+        }
+    }
+}
+
+impl Expression {
+    pub fn evaluate(&self, fields: &[&str]) -> Option<f64> {
+        let value = match self {
+            Expression::Number(number) => *number,
+            Expression::Field(field) => fields.get(field - 1)?.trim().parse().ok()?,
+            Expression::Negate(inner) => -inner.evaluate(fields)?,
+            Expression::Binary(left, operator, right) => {
+                let left = left.evaluate(fields)?;
+                let right = right.evaluate(fields)?;
+
+                match operator {
+                    Operator::Add => left + right,
+                    Operator::Subtract => left - right,
+                    Operator::Multiply => left * right,
+                    Operator::Divide => left / right,
+                    Operator::Remainder => left % right,
+                }
+            }
+        };
+
+        if value.is_finite() {
+            Some(value)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn format_number(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{}", value)
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, &'static str> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            ' ' | '\t' => {
+                chars.next();
+            }
+            '0'..='9' | '.' => {
+                let mut number = String::new();
+                while let Some(&digit) = chars.peek().filter(|d| d.is_ascii_digit() || **d == '.') {
+                    number.push(digit);
+                    chars.next();
+                }
+                tokens.push(Token::Number(number.parse().map_err(|_| "Invalid number")?));
+            }
+            'f' | '{' => {
+                chars.next();
+                let mut digits = String::new();
+                while let Some(&digit) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    digits.push(digit);
+                    chars.next();
+                }
+                if c == '{' && chars.next() != Some('}') {
+                    return Err("Invalid field reference");
+                }
+
+                match digits.parse::<usize>() {
+                    Ok(field) if field > 0 => tokens.push(Token::Field(field)),
+                    _ => return Err("Invalid field reference"),
+                }
+            }
+            _ => {
+                chars.next();
+                tokens.push(match c {
+                    '+' => Token::Operator(Operator::Add),
+                    '-' => Token::Operator(Operator::Subtract),
+                    '*' => Token::Operator(Operator::Multiply),
+                    '/' => Token::Operator(Operator::Divide),
+                    '%' => Token::Operator(Operator::Remainder),
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    '=' => Token::Assign,
+                    _ => return Err("Invalid expression"),
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn expression(&mut self) -> Result<Expression, &'static str> {
+        let mut left = self.term()?;
+
+        while let Some(operator) = self.operator(&[Operator::Add, Operator::Subtract]) {
+            left = Expression::Binary(Box::new(left), operator, Box::new(self.term()?));
+        }
+
+        Ok(left)
+    }
+
+    fn term(&mut self) -> Result<Expression, &'static str> {
+        let mut left = self.factor()?;
+
+        while let Some(operator) =
+            self.operator(&[Operator::Multiply, Operator::Divide, Operator::Remainder])
+        {
+            left = Expression::Binary(Box::new(left), operator, Box::new(self.factor()?));
+        }
+
+        Ok(left)
+    }
+
+    fn factor(&mut self) -> Result<Expression, &'static str> {
+        let token = self.tokens.get(self.position).ok_or("Invalid expression")?;
+        self.position += 1;
+
+        match token {
+            Token::Number(number) => Ok(Expression::Number(*number)),
+            Token::Field(field) => Ok(Expression::Field(*field)),
+            Token::Operator(Operator::Subtract) => Ok(Expression::Negate(Box::new(self.factor()?))),
+            Token::Open => {
+                let inner = self.expression()?;
+                if self.tokens.get(self.position) != Some(&Token::Close) {
+                    return Err("Unbalanced parentheses");
+                }
+                self.position += 1;
+
+                Ok(inner)
+            }
+            _ => Err("Invalid expression"),
+        }
+    }
+
+    fn operator(&mut self, accepted: &[Operator]) -> Option<Operator> {
+        match self.tokens.get(self.position) {
+            Some(Token::Operator(operator)) if accepted.contains(operator) => {
+                self.position += 1;
+                Some(*operator)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Calculation;
+
+    #[test]
+    fn parse_rejects_malformed_expressions() {
+        //+ Act + Assert
+        assert_eq!(Calculation::parse("1 +").err(), Some("Invalid expression"));
+        assert_eq!(
+            Calculation::parse("(1 + 2").err(),
+            Some("Unbalanced parentheses")
+        );
+        assert_eq!(
+            Calculation::parse("f0 + 1").err(),
+            Some("Invalid field reference")
+        );
+        assert_eq!(
+            Calculation::parse("{2 + 1").err(),
+            Some("Invalid field reference")
+        );
+    }
+
+    #[test]
+    fn apply_replaces_line_with_result() {
+        //+ Arrange
+        let calculation = Calculation::parse("{1} + {2} * (2 - -1)").unwrap();
+
+        //+ Act + Assert
+        assert_eq!(calculation.apply("1 2", None), Some("7".to_string()));
+        assert_eq!(
+            calculation.apply("1.5,2", Some(",")),
+            Some("7.5".to_string())
+        );
+        assert_eq!(calculation.apply("1 x", None), None);
+    }
+
+    #[test]
+    fn apply_assigns_to_field() {
+        //+ Arrange
+        let calculation = Calculation::parse("f3 = f1 / f2 * 100").unwrap();
+
+        //+ Act + Assert
+        assert_eq!(calculation.apply("1 4", None), Some("1 4 25".to_string()));
+        assert_eq!(
+            calculation.apply("1,4,x", Some(",")),
+            Some("1,4,25".to_string())
+        );
+        assert_eq!(calculation.apply("1 0", None), None);
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    fs::{read_to_string, remove_file, rename, write},
+    io::{self, BufRead, ErrorKind, Read},
+    time::{Duration, Instant},
+};
+
+use serde_json::{json, Value};
+
+use crate::error::RanglerError;
+
+// How often a run records how far it got.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How far a run got: the input it was reading, how many of its bytes and
+/// records were done, how much output had been written by then and what the
+/// steps held at that point.
+#[derive(Debug, PartialEq)]
+pub struct Checkpoint {
+    pub commands: Vec<String>,
+    pub inputs: Vec<String>,
+    pub input: usize,
+    pub offset: usize,
+    pub record_number: usize,
+    pub output_bytes: u64,
+    pub pipeline: Value,
+}
+
+// Writes the checkpoints of one run to the same file, each replacing the last.
+pub struct Checkpointer {
+    path: String,
+    commands: Vec<String>,
+    inputs: Vec<String>,
+    output_start: u64,
+    last: Instant,
+}
+
+impl Checkpoint {
+    // No file means the run starts from the beginning.
+    pub fn load(path: &str) -> Result<Option<Checkpoint>, RanglerError> {
+        let text = match read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(source) => Err(RanglerError::Io {
+                message: "Could not read checkpoint",
+                path: path.to_string(),
+                source,
+            })?,
+        };
+
+        let json: Value = serde_json::from_str(&text).map_err(|_| "Invalid checkpoint")?;
+        let strings = |key: &str| -> Option<Vec<String>> {
+            json[key]
+                .as_array()?
+                .iter()
+                .map(|value| value.as_str().map(str::to_string))
+                .collect()
+        };
+        let number = |key: &str| json[key].as_u64().ok_or("Invalid checkpoint");
+
+        Ok(Some(Checkpoint {
+            commands: strings("commands").ok_or("Invalid checkpoint")?,
+            inputs: strings("inputs").ok_or("Invalid checkpoint")?,
+            input: number("input")? as usize,
+            offset: number("offset")? as usize,
+            record_number: number("record_number")? as usize,
+            output_bytes: number("output_bytes")?,
+            pipeline: json["pipeline"].clone(),
+        }))
+    }
+
+    // Resuming with other commands or inputs would skip the wrong lines.
+    pub fn check(&self, commands: &[String], inputs: &[String]) -> Result<(), RanglerError> {
+        if self.commands != commands || self.inputs != inputs || self.input >= inputs.len() {
+            Err("The checkpoint is for other commands or inputs")?;
+        }
+
+        Ok(())
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "commands": self.commands,
+            "inputs": self.inputs,
+            "input": self.input,
+            "offset": self.offset,
+            "record_number": self.record_number,
+            "output_bytes": self.output_bytes,
+            "pipeline": self.pipeline,
+        })
+    }
+}
+
+impl Checkpointer {
+    // `output_start` is how much output there was when this run started, as
+    // a resumed run appends to what the last one wrote.
+    pub fn new(
+        path: &str,
+        commands: &[String],
+        inputs: Vec<String>,
+        output_start: u64,
+    ) -> Checkpointer {
+        Checkpointer {
+            path: path.to_string(),
+            commands: commands.to_vec(),
+            inputs,
+            output_start,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.last.elapsed() >= CHECKPOINT_INTERVAL
+    }
+
+    // The checkpoint is written beside the last one and renamed over it, so
+    // a crash while writing leaves the last one whole.
+    pub fn save(
+        &mut self,
+        input: usize,
+        offset: usize,
+        record_number: usize,
+        written: usize,
+        pipeline: Value,
+    ) -> Result<(), RanglerError> {
+        let checkpoint = Checkpoint {
+            commands: self.commands.clone(),
+            inputs: self.inputs.clone(),
+            input,
+            offset,
+            record_number,
+            output_bytes: self.output_start + written as u64,
+            pipeline,
+        };
+
+        let temporary = format!("{}.tmp", self.path);
+        write(&temporary, checkpoint.to_json().to_string())
+            .and_then(|_| rename(&temporary, &self.path))
+            .map_err(|source| RanglerError::Io {
+                message: "Could not write checkpoint",
+                path: self.path.clone(),
+                source,
+            })?;
+        self.last = Instant::now();
+
+        Ok(())
+    }
+
+    // A run that got to the end has nothing to resume.
+    pub fn finish(self) -> Result<(), RanglerError> {
+        match remove_file(&self.path) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(RanglerError::Io {
+                message: "Could not remove checkpoint",
+                path: self.path,
+                source: error,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Reads past the part of an input a checkpoint says is done. The bytes still
+// have to be read, as inputs may be compressed or piped, but no step sees them.
+pub fn skip_bytes(source: &mut dyn BufRead, count: usize) -> Result<(), RanglerError> {
+    let skipped = io::copy(&mut source.take(count as u64), &mut io::sink())
+        .map_err(|_| "Could not read input")?;
+    if skipped < count as u64 {
+        Err("The input is shorter than the checkpoint says")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Cursor};
+
+    use serde_json::json;
+
+    use super::{skip_bytes, Checkpoint, Checkpointer};
+
+    #[test]
+    fn save_and_load_round_trip() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-checkpoint-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let commands = ["dedupe".to_string()];
+        let inputs = vec!["a.log".to_string(), "b.log".to_string()];
+        let mut checkpointer = Checkpointer::new(path, &commands, inputs.clone(), 100);
+
+        //+ Act
+        checkpointer
+            .save(1, 2048, 30, 500, json!({ "line_number": 70 }))
+            .unwrap();
+        let loaded = Checkpoint::load(path).unwrap().unwrap();
+        let other_commands = loaded.check(&["trim".to_string()], &inputs);
+        checkpointer.finish().unwrap();
+
+        //+ Assert
+        assert_eq!(
+            loaded,
+            Checkpoint {
+                commands: commands.to_vec(),
+                inputs: inputs.clone(),
+                input: 1,
+                offset: 2048,
+                record_number: 30,
+                output_bytes: 600,
+                pipeline: json!({ "line_number": 70 }),
+            }
+        );
+        assert!(loaded.check(&commands, &inputs).is_ok());
+        assert!(other_commands.is_err());
+        assert!(Checkpoint::load(path).unwrap().is_none());
+    }
+
+    #[test]
+    fn skip_bytes_stops_at_the_offset() {
+        //+ Arrange
+        let mut source = Cursor::new(b"one\ntwo\n".to_vec());
+
+        //+ Act
+        skip_bytes(&mut source, 4).unwrap();
+        let past_the_end = skip_bytes(&mut Cursor::new(b"one".to_vec()), 4);
+
+        //+ Assert
+        assert_eq!(source.lines().next().unwrap().unwrap(), "two");
+        assert!(past_the_end.is_err());
+    }
+}
@@ -0,0 +1,103 @@
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChunkMode {
+    Separator(String),
+    Join(String),
+}
+
+// Groups the stream into runs of `size` lines, either by emitting a
+// separator line between runs or by joining each run into a single line.
+#[derive(Debug, PartialEq)]
+pub struct Chunk {
+    size: usize,
+    mode: ChunkMode,
+    seen: usize,
+    buffer: Vec<String>,
+}
+
+impl Chunk {
+    pub fn new(size: usize, mode: ChunkMode) -> Chunk {
+        Chunk {
+            size,
+            mode,
+            seen: 0,
+            buffer: vec![],
+        }
+    }
+
+    pub fn push(&mut self, line: String) -> Vec<String> {
+        match &self.mode {
+            ChunkMode::Separator(separator) => {
+                let starts_chunk = self.seen > 0 && self.seen.is_multiple_of(self.size);
+                self.seen += 1;
+
+                if starts_chunk {
+                    vec![separator.clone(), line]
+                } else {
+                    vec![line]
+                }
+            }
+            ChunkMode::Join(_) => {
+                self.buffer.push(line);
+
+                if self.buffer.len() == self.size {
+                    self.flush()
+                } else {
+                    vec![]
+                }
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> Vec<String> {
+        match &self.mode {
+            ChunkMode::Join(delimiter) if !self.buffer.is_empty() => {
+                let joined = self.buffer.join(delimiter);
+                self.buffer.clear();
+
+                vec![joined]
+            }
+            _ => vec![],
+        }
+    }
+
+    pub fn memory(&self) -> usize {
+        self.buffer.iter().map(|line| line.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Chunk, ChunkMode};
+
+    #[test]
+    fn push_separates_chunks() {
+        //+ Arrange
+        let mut chunk = Chunk::new(2, ChunkMode::Separator("--".to_string()));
+
+        //+ Act
+        let mut lines: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .flat_map(|line| chunk.push(line.to_string()))
+            .collect();
+        lines.extend(chunk.flush());
+
+        //+ Assert
+        assert_eq!(lines, vec!["a", "b", "--", "c"]);
+    }
+
+    #[test]
+    fn push_joins_chunks_and_flushes_remainder() {
+        //+ Arrange
+        let mut chunk = Chunk::new(2, ChunkMode::Join(",".to_string()));
+
+        //+ Act
+        let mut lines: Vec<String> = ["a", "b", "c"]
+            .iter()
+            .flat_map(|line| chunk.push(line.to_string()))
+            .collect();
+        lines.extend(chunk.flush());
+
+        //+ Assert
+        assert_eq!(lines, vec!["a,b", "c"]);
+    }
+}
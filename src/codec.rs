@@ -0,0 +1,130 @@
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+
+const STANDARD: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+const URL_SAFE: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::URL_SAFE,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+pub fn base64_encode(input: &str, url_safe: bool) -> String {
+    base64_encode_bytes(input.as_bytes(), url_safe)
+}
+
+pub fn base64_encode_bytes(input: &[u8], url_safe: bool) -> String {
+    engine(url_safe).encode(input)
+}
+
+pub fn base64_decode(input: &str, url_safe: bool) -> Option<String> {
+    String::from_utf8(base64_decode_bytes(input.as_bytes(), url_safe)?).ok()
+}
+
+pub fn base64_decode_bytes(input: &[u8], url_safe: bool) -> Option<Vec<u8>> {
+    engine(url_safe).decode(input.trim_ascii()).ok()
+}
+
+pub fn url_encode(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for byte in input.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+pub fn url_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'%' => {
+                let hex = std::str::from_utf8(bytes.get(index + 1..index + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                index += 3;
+            }
+            b'+' => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            byte => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+fn engine(url_safe: bool) -> &'static GeneralPurpose {
+    if url_safe {
+        &URL_SAFE
+    } else {
+        &STANDARD
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base64_decode, base64_encode, url_decode, url_encode};
+
+    #[test]
+    fn base64_round_trips() {
+        //+ Arrange
+        let input = "hello?>world";
+
+        //+ Act
+        let standard = base64_encode(input, false);
+        let url_safe = base64_encode(input, true);
+
+        //+ Assert
+        assert_eq!(standard, "aGVsbG8/Pndvcmxk");
+        assert_eq!(url_safe, "aGVsbG8_Pndvcmxk");
+        assert_eq!(base64_decode(&standard, false), Some(input.to_string()));
+        assert_eq!(base64_decode(&url_safe, true), Some(input.to_string()));
+    }
+
+    #[test]
+    fn base64_decode_accepts_missing_padding() {
+        //+ Act + Assert
+        assert_eq!(base64_decode("Zm9vYg", false), Some("foob".to_string()));
+        assert_eq!(base64_decode("Zm9vYg==", false), Some("foob".to_string()));
+    }
+
+    #[test]
+    fn base64_decode_rejects_invalid_input() {
+        //+ Act + Assert
+        assert_eq!(base64_decode("not base64!", false), None);
+        assert_eq!(base64_decode("/w==", false), None);
+    }
+
+    #[test]
+    fn url_encode_escapes_reserved_characters() {
+        //+ Act + Assert
+        assert_eq!(url_encode("a b&c=d/é~"), "a%20b%26c%3Dd%2F%C3%A9~");
+    }
+
+    #[test]
+    fn url_decode_handles_escapes_and_plus() {
+        //+ Act + Assert
+        assert_eq!(url_decode("a%20b+c%3d%C3%A9"), Some("a b c=é".to_string()));
+        assert_eq!(url_decode("100%"), None);
+        assert_eq!(url_decode("%zz"), None);
+        assert_eq!(url_decode("%FF"), None);
+    }
+}
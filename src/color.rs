@@ -0,0 +1,119 @@
+use std::borrow::Cow;
+
+use regex::Regex;
+
+// The escapes grep uses by default: bold red, then back to normal.
+const MATCH_START: &str = "\x1b[01;31m";
+const MATCH_END: &str = "\x1b[m";
+
+/// When to highlight what the filters matched, like grep's --color.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorMode {
+    pub fn parse(mode: &str) -> Result<ColorMode, &'static str> {
+        match mode {
+            "auto" => Ok(ColorMode::Auto),
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            _ => Err("Invalid color mode"),
+        }
+    }
+
+    // Auto only colors a terminal, and not when NO_COLOR is set, so escapes
+    // never end up in a file or another program's input by accident.
+    pub fn enabled(self, to_terminal: bool) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => to_terminal && std::env::var_os("NO_COLOR").is_none(),
+        }
+    }
+}
+
+// Wraps whatever the filters' patterns match in the lines that are written
+// out. It runs on the output, after every step, so no step ever sees the
+// escapes.
+#[derive(Debug)]
+pub struct Highlighter {
+    patterns: Vec<Regex>,
+}
+
+impl Highlighter {
+    pub fn new(patterns: Vec<Regex>) -> Option<Highlighter> {
+        (!patterns.is_empty()).then_some(Highlighter { patterns })
+    }
+
+    pub fn highlight<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        let mut ranges: Vec<(usize, usize)> = self
+            .patterns
+            .iter()
+            .flat_map(|pattern| pattern.find_iter(line))
+            .filter(|found| found.start() < found.end())
+            .map(|found| (found.start(), found.end()))
+            .collect();
+        if ranges.is_empty() {
+            return Cow::Borrowed(line);
+        }
+
+        // Matches of different patterns may overlap, so they are merged
+        // before any escape goes in.
+        ranges.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = vec![];
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        let mut highlighted = String::with_capacity(line.len() + merged.len() * 12);
+        let mut position = 0;
+        for (start, end) in merged {
+            highlighted.push_str(&line[position..start]);
+            highlighted.push_str(MATCH_START);
+            highlighted.push_str(&line[start..end]);
+            highlighted.push_str(MATCH_END);
+            position = end;
+        }
+        highlighted.push_str(&line[position..]);
+
+        Cow::Owned(highlighted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::{ColorMode, Highlighter};
+
+    #[test]
+    fn highlight_merges_overlapping_matches() {
+        //+ Arrange
+        let highlighter = Highlighter::new(vec![
+            Regex::new("ERR").unwrap(),
+            Regex::new("RROR").unwrap(),
+            Regex::new("disk").unwrap(),
+        ])
+        .unwrap();
+
+        //+ Act
+        let highlighted = highlighter.highlight("ERROR: disk full");
+        let untouched = highlighter.highlight("all good");
+
+        //+ Assert
+        assert_eq!(
+            highlighted,
+            "\x1b[01;31mERROR\x1b[m: \x1b[01;31mdisk\x1b[m full"
+        );
+        assert_eq!(untouched, "all good");
+        assert!(!ColorMode::Auto.enabled(false));
+        assert!(ColorMode::Always.enabled(false));
+    }
+}
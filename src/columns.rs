@@ -0,0 +1,88 @@
+// Cuts fixed-width columns out of every line, like `cut -c`. Ranges count
+// from 1 and include both ends: `5` is one column, `1-10` ten, `30-` runs to
+// the end of the line and `-4` starts at the beginning. The pieces are joined
+// on the delimiter, nothing by default, in the order the ranges were given.
+// Columns past the end of a short line are simply empty.
+#[derive(Debug, PartialEq)]
+pub struct Columns {
+    ranges: Vec<(usize, Option<usize>)>,
+    bytes: bool,
+    delimiter: String,
+}
+
+impl Columns {
+    pub fn parse(ranges: &str, bytes: bool, delimiter: &str) -> Result<Columns, &'static str> {
+        let ranges = ranges
+            .split(',')
+            .map(parse_range)
+            .collect::<Result<_, _>>()?;
+
+        Ok(Columns {
+            ranges,
+            bytes,
+            delimiter: delimiter.to_string(),
+        })
+    }
+
+    pub fn apply(&self, line: &str) -> String {
+        let pieces: Vec<String> = self
+            .ranges
+            .iter()
+            .map(|&(start, end)| {
+                let take = end.map_or(usize::MAX, |end| end - start + 1);
+                match self.bytes {
+                    // A range may split a character; what is left of it is
+                    // replaced rather than left invalid.
+                    true => {
+                        let bytes = line.as_bytes();
+                        let from = (start - 1).min(bytes.len());
+                        let to = from.saturating_add(take).min(bytes.len());
+                        String::from_utf8_lossy(&bytes[from..to]).into_owned()
+                    }
+                    false => line.chars().skip(start - 1).take(take).collect(),
+                }
+            })
+            .collect();
+
+        pieces.join(&self.delimiter)
+    }
+}
+
+fn parse_range(range: &str) -> Result<(usize, Option<usize>), &'static str> {
+    let column = |text: &str| match text.trim().parse::<usize>() {
+        Ok(0) | Err(_) => Err("Invalid column range"),
+        Ok(column) => Ok(column),
+    };
+
+    let (start, end) = match range.split_once('-') {
+        Some(("", end)) => (1, Some(column(end)?)),
+        Some((start, "")) => (column(start)?, None),
+        Some((start, end)) => (column(start)?, Some(column(end)?)),
+        None => (column(range)?, Some(column(range)?)),
+    };
+    if end.is_some_and(|end| end < start) {
+        return Err("Invalid column range");
+    }
+
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Columns;
+
+    #[test]
+    fn apply_cuts_character_and_byte_ranges() {
+        //+ Arrange
+        let columns = Columns::parse("1-3,7-", false, "|").unwrap();
+        let bytes = Columns::parse("-2,5", true, "").unwrap();
+
+        //+ Act + Assert
+        assert_eq!(columns.apply("abc   ünïcode"), "abc|ünïcode");
+        assert_eq!(columns.apply("ab"), "ab|");
+        assert_eq!(bytes.apply("héllo"), "h\u{fffd}l");
+        assert!(Columns::parse("0-4", false, "").is_err());
+        assert!(Columns::parse("9-4", false, "").is_err());
+        assert!(Columns::parse("1,x", false, "").is_err());
+    }
+}
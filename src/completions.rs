@@ -0,0 +1,159 @@
+use std::str::FromStr;
+
+use clap::{builder::PossibleValue, Command};
+use clap_complete::{generate, Shell};
+use regex::Regex;
+
+use crate::help::COMMANDS;
+
+/// Writes a completion script for `bash`, `zsh`, `fish` or `powershell`.
+///
+/// The script is generated from the command line's clap `command`, so its
+/// subcommands and global options complete as declared. The words after
+/// them complete to command names and the words a command takes, such as
+/// `md5` after `hash` or `--recent` after `dedupe`.
+pub fn completions(shell: &str, command: Command) -> Result<String, &'static str> {
+    let shell = Shell::from_str(shell)
+        .ok()
+        .filter(|shell| !matches!(shell, Shell::Elvish))
+        .ok_or("Unknown shell, expected bash, zsh, fish or powershell")?;
+
+    let mut words: Vec<PossibleValue> = COMMANDS
+        .iter()
+        .map(|command| PossibleValue::new(command.name).help(command.about))
+        .collect();
+    for word in COMMANDS
+        .iter()
+        .flat_map(|command| command_words(command.usage))
+    {
+        if !words.iter().any(|seen| seen.matches(word, false)) {
+            words.push(PossibleValue::new(word));
+        }
+    }
+
+    let mut command = command.mut_arg("commands", |commands| {
+        commands.hide(false).value_parser(words.clone())
+    });
+    let mut script = vec![];
+    generate(shell, &mut command, "rangler", &mut script);
+    let mut script = String::from_utf8_lossy(&script).into_owned();
+
+    // clap_complete's fish and PowerShell scripts leave positional arguments
+    // out, so the command words are added to them here.
+    let described = words.iter().map(|word| {
+        let name = word.get_name();
+        (
+            name,
+            word.get_help()
+                .map_or(name.to_string(), ToString::to_string),
+        )
+    });
+    match shell {
+        Shell::Fish => {
+            let quote =
+                |text: &str| format!("'{}'", text.replace('\\', r"\\").replace('\'', r"\'"));
+            for (name, about) in described {
+                script += &format!(
+                    "complete -c rangler -a {} -d {}\n",
+                    quote(name),
+                    quote(&about)
+                );
+            }
+        }
+        Shell::PowerShell => {
+            let quote = |text: &str| format!("'{}'", text.replace('\'', "''"));
+            let results: String = described
+                .map(|(name, about)| {
+                    format!(
+                        "            [CompletionResult]::new({0}, {0}, [CompletionResultType]::ParameterValue, {1})\n",
+                        quote(name),
+                        quote(&about)
+                    )
+                })
+                .collect();
+            script = script.replacen(
+                "        'rangler' {\n",
+                &format!("        'rangler' {{\n{}", results),
+                1,
+            );
+        }
+        _ => {}
+    }
+
+    Ok(script)
+}
+
+// The literal words in a usage line: flags, keywords such as `when` and the
+// choices in `<md5|sha1>`. Placeholders like `<regex>` or `<file|stderr>`'s
+// `file` are left for the shell to complete as files.
+fn command_words(usage: &str) -> Vec<&str> {
+    let name = usage.split_whitespace().next().unwrap_or("");
+    let pattern = Regex::new(r"<[^>]*>|[^\s\[\]<>|.]+").unwrap();
+
+    let mut words: Vec<&str> = vec![];
+    for part in pattern.find_iter(usage).map(|part| part.as_str()) {
+        let candidates: Vec<&str> = match part.strip_prefix('<') {
+            Some(choices) if choices.contains('|') => choices
+                .trim_end_matches('>')
+                .split('|')
+                .filter(|choice| !choice.contains(' ') && !["file", "regex"].contains(choice))
+                .collect(),
+            Some(_) => vec![],
+            None => vec![part],
+        };
+
+        for word in candidates {
+            let literal = word.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+            if literal && word != name && !words.contains(&word) {
+                words.push(word);
+            }
+        }
+    }
+
+    words
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Arg, Command};
+
+    use super::{command_words, completions};
+
+    #[test]
+    fn command_words_finds_flags_keywords_and_choices() {
+        //+ Act + Assert
+        assert_eq!(
+            command_words("hash <md5|sha1|sha256|xxhash> [--append]"),
+            ["md5", "sha1", "sha256", "xxhash", "--append"]
+        );
+        assert_eq!(command_words("tag <name> when <regex>"), ["when"]);
+        assert_eq!(command_words("tee <file|stderr>"), ["stderr"]);
+        assert_eq!(
+            command_words("kv get <key,...> | kv where <key><op><value>"),
+            ["get", "where"]
+        );
+    }
+
+    #[test]
+    fn completions_cover_commands_and_options_for_every_shell() {
+        //+ Arrange
+        let command = || {
+            Command::new("rangler")
+                .arg(Arg::new("follow").short('f').long("follow"))
+                .arg(Arg::new("commands").num_args(0..).trailing_var_arg(true))
+                .subcommand(Command::new("save-preset"))
+        };
+
+        //+ Act + Assert
+        for shell in ["bash", "zsh", "fish", "powershell"] {
+            let script = completions(shell, command()).unwrap();
+            for word in ["dedupe", "recent", "sha256", "follow", "save-preset"] {
+                assert!(script.contains(word), "{} is missing {}", shell, word);
+            }
+        }
+        assert_eq!(
+            completions("tcsh", command()).err(),
+            Some("Unknown shell, expected bash, zsh, fish or powershell")
+        );
+    }
+}
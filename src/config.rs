@@ -0,0 +1,96 @@
+use std::path::PathBuf;
+
+use serde_json::Value;
+
+use crate::presets::config_directory;
+
+/// Reads the options every run starts from, kept in
+/// `$XDG_CONFIG_HOME/rangler/config.toml` (or `~/.config`), or the file named
+/// by `RANGLER_CONFIG`, which when empty turns the config file off. Returns
+/// them as option tokens to go in front of those on the command line, so the
+/// command line wins. No config file means no defaults.
+///
+/// Each key is an option without its dashes:
+///
+/// ```toml
+/// read-buffer = "4MiB"
+/// no-progress = true
+/// color = "never"
+/// invalid-utf8 = "skip"
+/// ```
+pub fn load_config() -> Result<Vec<String>, &'static str> {
+    let path = match std::env::var_os("RANGLER_CONFIG") {
+        Some(path) if path.is_empty() => return Ok(vec![]),
+        Some(path) => PathBuf::from(path),
+        None => match config_directory() {
+            Ok(directory) => directory.join("config.toml"),
+            Err(_) => return Ok(vec![]),
+        },
+    };
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+
+    let source = std::fs::read_to_string(path).map_err(|_| "Could not read config file")?;
+    parse_config(&source)
+}
+
+// `key = true` is a flag, `false` leaves it out, a list repeats the option
+// and anything else is its value.
+fn parse_config(source: &str) -> Result<Vec<String>, &'static str> {
+    let document: Value = toml::from_str(source).map_err(|_| "Invalid TOML in config file")?;
+    let Value::Object(options) = document else {
+        return Err("Invalid TOML in config file");
+    };
+
+    let mut tokens = vec![];
+    for (key, value) in options {
+        let flag = format!("--{}", key);
+        let values = match value {
+            Value::Array(values) => values,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                Value::Bool(true) => tokens.push(flag.clone()),
+                Value::Bool(false) => {}
+                Value::String(text) => tokens.extend([flag.clone(), text]),
+                Value::Number(number) => tokens.extend([flag.clone(), number.to_string()]),
+                _ => return Err("Config options take a value, true or a list"),
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_config;
+
+    #[test]
+    fn parse_config_turns_keys_into_option_tokens() {
+        //+ Arrange
+        let source = "read-buffer = \"4MiB\"\nno-progress = true\nstrict = false\nthreads = 4\nglob = [\"a/*\", \"b/*\"]\n";
+
+        //+ Act
+        let tokens = parse_config(source).unwrap();
+
+        //+ Assert
+        assert_eq!(
+            tokens,
+            [
+                "--read-buffer",
+                "4MiB",
+                "--no-progress",
+                "--threads",
+                "4",
+                "--glob",
+                "a/*",
+                "--glob",
+                "b/*"
+            ]
+        );
+        assert!(parse_config("color = { mode = \"never\" }").is_err());
+    }
+}
@@ -0,0 +1,345 @@
+use serde_json::{Map, Value};
+
+use crate::json::render_value;
+
+// Steps that treat every line as one CSV record. The first record is the
+// header: it names the columns the steps refer to and passes through (or is
+// projected) like any other row.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CsvSelect {
+    columns: Vec<String>,
+    delimiter: u8,
+    indices: Option<Vec<usize>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CsvWhere {
+    column: String,
+    comparison: Comparison,
+    value: String,
+    delimiter: u8,
+    index: Option<usize>,
+}
+
+// Converts JSON objects into CSV rows. Nested objects are flattened into
+// dotted column names. Without explicit columns the header is taken from the
+// first object; later keys outside it are ignored.
+#[derive(Debug, PartialEq)]
+pub struct JsonlToCsv {
+    columns: Option<Vec<String>>,
+    delimiter: u8,
+    header_written: bool,
+}
+
+// Converts CSV rows into JSON objects keyed by the header record.
+#[derive(Debug, PartialEq)]
+pub struct CsvToJsonl {
+    delimiter: u8,
+    header: Option<Vec<String>>,
+}
+
+impl CsvSelect {
+    pub fn new(columns: &str, delimiter: u8) -> CsvSelect {
+        CsvSelect {
+            columns: columns.split(',').map(|c| c.trim().to_string()).collect(),
+            delimiter,
+            indices: None,
+        }
+    }
+
+    pub fn apply(&mut self, line: &str) -> Result<Option<String>, &'static str> {
+        let record = match parse_record(line, self.delimiter) {
+            Some(record) => record,
+            None => return Ok(None),
+        };
+
+        let indices = match &self.indices {
+            Some(indices) => indices,
+            None => {
+                let indices = self
+                    .columns
+                    .iter()
+                    .map(|column| record.iter().position(|name| name == column))
+                    .collect::<Option<Vec<usize>>>()
+                    .ok_or("Unknown CSV column")?;
+                self.indices.insert(indices)
+            }
+        };
+
+        let projected: Vec<&str> = indices
+            .iter()
+            .map(|index| record.get(*index).map(String::as_str).unwrap_or(""))
+            .collect();
+
+        Ok(Some(write_record(&projected, self.delimiter)))
+    }
+}
+
+impl CsvWhere {
+    // Parses `column=value` or `column!=value`.
+    pub fn new(condition: &str, delimiter: u8) -> Result<CsvWhere, &'static str> {
+        let (column, comparison, value) = if let Some((column, value)) = condition.split_once("!=")
+        {
+            (column, Comparison::NotEqual, value)
+        } else if let Some((column, value)) = condition.split_once('=') {
+            (column, Comparison::Equal, value)
+        } else {
+            return Err("Invalid CSV condition");
+        };
+
+        Ok(CsvWhere {
+            column: column.trim().to_string(),
+            comparison,
+            value: value.to_string(),
+            delimiter,
+            index: None,
+        })
+    }
+
+    pub fn apply(&mut self, line: &str) -> Result<bool, &'static str> {
+        let record = match parse_record(line, self.delimiter) {
+            Some(record) => record,
+            None => return Ok(false),
+        };
+
+        let index = match self.index {
+            Some(index) => index,
+            None => {
+                let index = record
+                    .iter()
+                    .position(|name| *name == self.column)
+                    .ok_or("Unknown CSV column")?;
+                self.index = Some(index);
+
+                return Ok(true);
+            }
+        };
+
+        let matches = record.get(index).map(String::as_str) == Some(self.value.as_str());
+        Ok(match self.comparison {
+            Comparison::Equal => matches,
+            Comparison::NotEqual => !matches,
+        })
+    }
+}
+
+impl JsonlToCsv {
+    pub fn new(columns: Option<&str>, delimiter: u8) -> JsonlToCsv {
+        JsonlToCsv {
+            columns: columns.map(|c| c.split(',').map(|c| c.trim().to_string()).collect()),
+            delimiter,
+            header_written: false,
+        }
+    }
+
+    // Returns the header ahead of the first row. Lines that are not JSON
+    // objects produce nothing.
+    pub fn push(&mut self, line: &str) -> Vec<String> {
+        let object = match serde_json::from_str::<Value>(line) {
+            Ok(Value::Object(object)) => object,
+            _ => return vec![],
+        };
+
+        let mut fields = vec![];
+        flatten("", &object, &mut fields);
+
+        let columns = self
+            .columns
+            .get_or_insert_with(|| fields.iter().map(|(name, _)| name.clone()).collect());
+
+        let row: Vec<&str> = columns
+            .iter()
+            .map(|column| {
+                fields
+                    .iter()
+                    .find(|(name, _)| name == column)
+                    .map_or("", |(_, value)| value.as_str())
+            })
+            .collect();
+
+        let mut lines = vec![];
+        if !self.header_written {
+            lines.push(write_record(columns, self.delimiter));
+            self.header_written = true;
+        }
+        lines.push(write_record(&row, self.delimiter));
+
+        lines
+    }
+}
+
+impl CsvToJsonl {
+    pub fn new(delimiter: u8) -> CsvToJsonl {
+        CsvToJsonl {
+            delimiter,
+            header: None,
+        }
+    }
+
+    // The header record is consumed and produces no output. Missing trailing
+    // fields become empty strings.
+    pub fn apply(&mut self, line: &str) -> Option<String> {
+        let record = parse_record(line, self.delimiter)?;
+
+        let header = match &self.header {
+            Some(header) => header,
+            None => {
+                self.header = Some(record);
+                return None;
+            }
+        };
+
+        let object: Map<String, Value> = header
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let value = record.get(index).cloned().unwrap_or_default();
+                (name.clone(), Value::String(value))
+            })
+            .collect();
+
+        Some(Value::Object(object).to_string())
+    }
+}
+
+fn flatten(prefix: &str, object: &Map<String, Value>, fields: &mut Vec<(String, String)>) {
+    for (key, value) in object {
+        let name = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+
+        match value {
+            Value::Object(inner) => flatten(&name, inner, fields),
+            Value::Null => fields.push((name, String::new())),
+            other => fields.push((name, render_value(other))),
+        }
+    }
+}
+
+pub fn parse_delimiter(delimiter: Option<&str>) -> Result<u8, &'static str> {
+    match delimiter {
+        None => Ok(b','),
+        Some("\\t") => Ok(b'\t'),
+        Some(delimiter) if delimiter.len() == 1 => Ok(delimiter.as_bytes()[0]),
+        Some(_) => Err("CSV delimiter must be a single byte"),
+    }
+}
+
+pub fn parse_record(line: &str, delimiter: u8) -> Option<Vec<String>> {
+    let mut reader = ::csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .delimiter(delimiter)
+        .from_reader(line.as_bytes());
+
+    let record = reader.records().next()?.ok()?;
+    Some(record.iter().map(str::to_string).collect())
+}
+
+pub fn write_record<T: AsRef<[u8]>>(fields: &[T], delimiter: u8) -> String {
+    let mut writer = ::csv::WriterBuilder::new()
+        .delimiter(delimiter)
+        .terminator(::csv::Terminator::Any(b'\n'))
+        .from_writer(vec![]);
+    // Writing into a Vec cannot fail.
+    writer.write_record(fields).unwrap();
+
+    let mut bytes = writer.into_inner().unwrap_or_default();
+    bytes.pop();
+    String::from_utf8(bytes).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CsvSelect, CsvToJsonl, CsvWhere, JsonlToCsv};
+
+    #[test]
+    fn select_projects_columns_by_header_name() {
+        //+ Arrange
+        let mut select = CsvSelect::new("age,name", b',');
+
+        //+ Act + Assert
+        assert_eq!(
+            select.apply("name,age,city"),
+            Ok(Some("age,name".to_string()))
+        );
+        assert_eq!(
+            select.apply(r#""Smith, Bob",42,"Paris""#),
+            Ok(Some(r#"42,"Smith, Bob""#.to_string()))
+        );
+        assert_eq!(select.apply("Ann"), Ok(Some(",Ann".to_string())));
+    }
+
+    #[test]
+    fn select_rejects_unknown_column() {
+        //+ Arrange
+        let mut select = CsvSelect::new("missing", b',');
+
+        //+ Act + Assert
+        assert_eq!(select.apply("name,age"), Err("Unknown CSV column"));
+    }
+
+    #[test]
+    fn where_filters_rows_and_keeps_header() {
+        //+ Arrange
+        let mut condition = CsvWhere::new("status=error", b'\t').unwrap();
+        let mut negated = CsvWhere::new("status!=error", b',').unwrap();
+
+        //+ Act + Assert
+        assert_eq!(condition.apply("id\tstatus"), Ok(true));
+        assert_eq!(condition.apply("1\terror"), Ok(true));
+        assert_eq!(condition.apply("2\tok"), Ok(false));
+        assert_eq!(negated.apply("id,status"), Ok(true));
+        assert_eq!(negated.apply("1,error"), Ok(false));
+        assert_eq!(
+            CsvWhere::new("status", b',').err(),
+            Some("Invalid CSV condition")
+        );
+    }
+
+    #[test]
+    fn jsonl_to_csv_flattens_objects_under_inferred_header() {
+        //+ Arrange
+        let mut convert = JsonlToCsv::new(None, b',');
+
+        //+ Act + Assert
+        assert_eq!(
+            convert.push(r#"{"id":1,"user":{"name":"Smith, Bob"},"tags":["a"]}"#),
+            vec![
+                "id,user.name,tags".to_string(),
+                r#"1,"Smith, Bob","[""a""]""#.to_string()
+            ]
+        );
+        assert_eq!(
+            convert.push(r#"{"user":{"name":"Ann"},"id":null,"extra":true}"#),
+            vec![",Ann,".to_string()]
+        );
+        assert_eq!(convert.push("[1,2]"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn csv_to_jsonl_keys_rows_by_header() {
+        //+ Arrange
+        let mut convert = CsvToJsonl::new(b',');
+
+        //+ Act + Assert
+        assert_eq!(convert.apply("id,name"), None);
+        assert_eq!(
+            convert.apply(r#"1,"Smith, Bob""#),
+            Some(r#"{"id":"1","name":"Smith, Bob"}"#.to_string())
+        );
+        assert_eq!(
+            convert.apply("2"),
+            Some(r#"{"id":"2","name":""}"#.to_string())
+        );
+    }
+}
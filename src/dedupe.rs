@@ -0,0 +1,910 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{remove_dir_all, File},
+    io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+};
+
+const SPILL_BLOCK_SIZE: usize = 64;
+const SHARDS: usize = 64;
+
+// Lines, each with the lowest line number that claimed it.
+type Shard = HashMap<Vec<u8>, usize>;
+// Starts every dedupe state file, so a file of anything else is refused
+// rather than read as hashes.
+const STATE_MAGIC: &[u8; 8] = b"RNGLDD01";
+static SPILL_DIRECTORIES: AtomicUsize = AtomicUsize::new(0);
+
+// How `dedupe --key-transform` turns a line into the key it compares, so
+// lines that only differ in case or spacing count as duplicates while the
+// first one seen still goes out as it was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyTransform {
+    Lower,
+    Upper,
+    Trim,
+    // Collapses every run of whitespace to one space.
+    Squeeze,
+    Ascii,
+}
+
+impl KeyTransform {
+    pub fn parse_list(list: &str) -> Result<Vec<KeyTransform>, &'static str> {
+        list.split(',')
+            .map(|name| match name.trim().to_lowercase().as_str() {
+                "lower" => Ok(KeyTransform::Lower),
+                "upper" => Ok(KeyTransform::Upper),
+                "trim" => Ok(KeyTransform::Trim),
+                "squeeze" => Ok(KeyTransform::Squeeze),
+                "ascii" => Ok(KeyTransform::Ascii),
+                _ => Err("Unknown key transform"),
+            })
+            .collect()
+    }
+
+    // Applies the transforms in the order they were listed.
+    pub fn key(transforms: &[KeyTransform], line: &str) -> String {
+        transforms
+            .iter()
+            .fold(line.to_string(), |key, transform| match transform {
+                KeyTransform::Lower => key.to_lowercase(),
+                KeyTransform::Upper => key.to_uppercase(),
+                KeyTransform::Trim => key.trim().to_string(),
+                KeyTransform::Squeeze => key.split_whitespace().collect::<Vec<_>>().join(" "),
+                KeyTransform::Ascii => deunicode::deunicode(&key),
+            })
+    }
+}
+
+// Holds every distinct line until the input ends, then emits each in the
+// order first seen with how often it turned up before it, like `uniq -c`
+// without having to sort first. Lines are counted by their key, so with key
+// transforms the first line as written stands for all of them.
+#[derive(Debug, PartialEq)]
+pub struct DedupeCount {
+    transforms: Vec<KeyTransform>,
+    lines: Vec<(String, usize)>,
+    positions: HashMap<String, usize>,
+    stored: usize,
+}
+
+impl DedupeCount {
+    pub fn new(transforms: Vec<KeyTransform>) -> DedupeCount {
+        DedupeCount {
+            transforms,
+            lines: vec![],
+            positions: HashMap::new(),
+            stored: 0,
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        let key = match self.transforms.is_empty() {
+            true => line.clone(),
+            false => KeyTransform::key(&self.transforms, &line),
+        };
+
+        match self.positions.get(&key) {
+            Some(&position) => self.lines[position].1 += 1,
+            None => {
+                self.stored += key.len() + line.len();
+                self.positions.insert(key, self.lines.len());
+                self.lines.push((line, 1));
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> Vec<String> {
+        self.positions.clear();
+        self.stored = 0;
+        std::mem::take(&mut self.lines)
+            .into_iter()
+            .map(|(line, count)| format!("{} {}", count, line))
+            .collect()
+    }
+
+    pub fn memory(&self) -> usize {
+        self.stored
+    }
+}
+
+// Lets a line through only when it turned up before, the question dedupe
+// answers turned around. With `once`, only the second copy goes out, so each
+// duplicated line is listed a single time however often it repeats.
+#[derive(Debug, PartialEq)]
+pub struct DuplicateSet {
+    once: bool,
+    // Every line seen, with whether a copy of it went out yet.
+    seen: HashMap<Vec<u8>, bool>,
+    stored: usize,
+}
+
+impl DuplicateSet {
+    pub fn new(once: bool) -> DuplicateSet {
+        DuplicateSet {
+            once,
+            seen: HashMap::new(),
+            stored: 0,
+        }
+    }
+
+    // Returns true when the line was seen before and is let through.
+    pub fn insert(&mut self, line: &[u8]) -> bool {
+        match self.seen.get_mut(line) {
+            Some(emitted) => {
+                let keep = !(self.once && *emitted);
+                *emitted = true;
+                keep
+            }
+            None => {
+                self.seen.insert(line.to_vec(), false);
+                self.stored += line.len();
+                false
+            }
+        }
+    }
+
+    pub fn memory(&self) -> usize {
+        self.stored
+    }
+}
+
+// A set that forgets its least recently seen entry once it holds `capacity`
+// lines. Seeing a line again refreshes it; `order` keeps stale entries around
+// until they reach the front (or a compaction), which keeps every operation
+// amortised O(1).
+#[derive(Debug)]
+pub struct LruSet {
+    capacity: usize,
+    generation: u64,
+    entries: HashMap<String, u64>,
+    order: VecDeque<(u64, String)>,
+    stored: usize,
+}
+
+impl LruSet {
+    pub fn new(capacity: usize) -> LruSet {
+        LruSet {
+            capacity,
+            generation: 0,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            stored: 0,
+        }
+    }
+
+    // Returns true when the line was not in the set.
+    pub fn insert(&mut self, line: &str) -> bool {
+        self.generation += 1;
+
+        if let Some(generation) = self.entries.get_mut(line) {
+            *generation = self.generation;
+            self.order.push_back((self.generation, line.to_string()));
+            self.compact();
+
+            return false;
+        }
+
+        if self.entries.len() >= self.capacity {
+            self.evict();
+        }
+
+        self.entries.insert(line.to_string(), self.generation);
+        self.order.push_back((self.generation, line.to_string()));
+        self.stored += line.len();
+
+        true
+    }
+
+    pub fn memory(&self) -> usize {
+        self.stored * 2
+    }
+
+    fn evict(&mut self) {
+        while let Some((generation, line)) = self.order.pop_front() {
+            if self.entries.get(&line) == Some(&generation) {
+                self.entries.remove(&line);
+                self.stored -= line.len();
+                return;
+            }
+        }
+    }
+
+    fn compact(&mut self) {
+        if self.order.len() <= self.capacity * 2 {
+            return;
+        }
+
+        let entries = &self.entries;
+        self.order
+            .retain(|(generation, line)| entries.get(line) == Some(generation));
+    }
+}
+
+// Remembers only the last `size` lines read, duplicates included, so a line
+// is dropped when it already turned up within that many lines and comes back
+// once it has not. Unlike LruSet, which counts distinct lines and keeps a
+// line for as long as it keeps repeating, a quiet stretch of `size` lines
+// always lets it through again. Lines are kept as 128-bit hashes, counted so
+// a line repeated inside the window stays until its last copy leaves.
+#[derive(Debug, PartialEq)]
+pub struct WindowSet {
+    size: usize,
+    window: VecDeque<u128>,
+    counts: HashMap<u128, usize>,
+}
+
+impl WindowSet {
+    pub fn new(size: usize) -> WindowSet {
+        WindowSet {
+            size,
+            window: VecDeque::with_capacity(size),
+            counts: HashMap::new(),
+        }
+    }
+
+    // Returns true when the line was not among the last `size` lines.
+    pub fn insert(&mut self, line: &str) -> bool {
+        let hash = xxhash_rust::xxh3::xxh3_128(line.as_bytes());
+        let new = !self.counts.contains_key(&hash);
+
+        if self.window.len() == self.size {
+            if let Some(oldest) = self.window.pop_front() {
+                if let Some(count) = self.counts.get_mut(&oldest) {
+                    *count -= 1;
+                    if *count == 0 {
+                        self.counts.remove(&oldest);
+                    }
+                }
+            }
+        }
+        self.window.push_back(hash);
+        *self.counts.entry(hash).or_insert(0) += 1;
+
+        new
+    }
+
+    pub fn memory(&self) -> usize {
+        self.window.len() * std::mem::size_of::<u128>()
+            + self.counts.len() * (std::mem::size_of::<u128>() + std::mem::size_of::<usize>())
+    }
+}
+
+// A Bloom filter sized for `expected_items` at the requested false positive
+// rate. Lines it reports as already seen may be false positives, so every
+// drop is counted as a lossy event.
+#[derive(Debug)]
+pub struct BloomFilter {
+    expected_items: usize,
+    false_positive_rate: f64,
+    bits: Vec<u64>,
+    bit_count: u64,
+    hash_count: u32,
+}
+
+impl BloomFilter {
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> BloomFilter {
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = (-(expected_items as f64) * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let hash_count = ((bit_count as f64 / expected_items as f64) * ln2)
+            .round()
+            .clamp(1.0, 32.0) as u32;
+
+        BloomFilter {
+            expected_items,
+            false_positive_rate,
+            bits: vec![0; bit_count.div_ceil(64) as usize],
+            bit_count,
+            hash_count,
+        }
+    }
+
+    // Returns true when the line was definitely not seen before.
+    pub fn insert(&mut self, line: &str) -> bool {
+        let mut new = false;
+
+        for (word, mask) in self.positions(line) {
+            if self.bits[word] & mask == 0 {
+                self.bits[word] |= mask;
+                new = true;
+            }
+        }
+
+        new
+    }
+
+    pub fn contains(&self, line: &str) -> bool {
+        self.positions(line)
+            .all(|(word, mask)| self.bits[word] & mask != 0)
+    }
+
+    fn positions(&self, line: &str) -> impl Iterator<Item = (usize, u64)> {
+        let hash = xxhash_rust::xxh3::xxh3_128(line.as_bytes());
+        let (first, second) = (hash as u64, (hash >> 64) as u64 | 1);
+        let bit_count = self.bit_count;
+
+        (0..self.hash_count as u64).map(move |index| {
+            let bit = first.wrapping_add(index.wrapping_mul(second)) % bit_count;
+            ((bit / 64) as usize, 1u64 << (bit % 64))
+        })
+    }
+
+    pub fn memory(&self) -> usize {
+        self.bits.len() * 8
+    }
+}
+
+// Exact dedupe that keeps recently added lines in memory and, once they take
+// more than `memory_limit` bytes, writes them to a sorted run file on disk.
+// Each run keeps a Bloom filter and the first line of every block in memory,
+// so checking a new line only reads from disk when a filter says it might be
+// there. Runs hold each line after its length, as records may hold newlines.
+#[derive(Debug)]
+pub struct SpillingSet {
+    memory_limit: usize,
+    hot: HashSet<String>,
+    hot_bytes: usize,
+    directory: Option<PathBuf>,
+    runs: Vec<SpillRun>,
+}
+
+#[derive(Debug)]
+struct SpillRun {
+    reader: BufReader<File>,
+    index: Vec<(String, u64)>,
+    filter: BloomFilter,
+}
+
+impl SpillingSet {
+    pub fn new(memory_limit: usize) -> SpillingSet {
+        SpillingSet {
+            memory_limit,
+            hot: HashSet::new(),
+            hot_bytes: 0,
+            directory: None,
+            runs: vec![],
+        }
+    }
+
+    // Returns true when the line was not seen before.
+    pub fn insert(&mut self, line: &str) -> Result<bool, &'static str> {
+        if self.hot.contains(line) {
+            return Ok(false);
+        }
+
+        for run in self.runs.iter_mut() {
+            if run.contains(line)? {
+                return Ok(false);
+            }
+        }
+
+        self.hot.insert(line.to_string());
+        self.hot_bytes += line.len();
+
+        if self.hot_bytes > self.memory_limit {
+            self.spill()?;
+        }
+
+        Ok(true)
+    }
+
+    pub fn memory(&self) -> usize {
+        self.hot_bytes
+            + self
+                .runs
+                .iter()
+                .map(|run| {
+                    run.filter.memory()
+                        + run
+                            .index
+                            .iter()
+                            .map(|(line, _)| line.len() + 8)
+                            .sum::<usize>()
+                })
+                .sum::<usize>()
+    }
+
+    fn spill(&mut self) -> Result<(), &'static str> {
+        let directory = match &self.directory {
+            Some(directory) => directory.clone(),
+            None => {
+                let directory = std::env::temp_dir().join(format!(
+                    "rangler-dedupe-{}-{}",
+                    std::process::id(),
+                    SPILL_DIRECTORIES.fetch_add(1, Ordering::Relaxed)
+                ));
+                std::fs::create_dir_all(&directory)
+                    .map_err(|_| "Could not create spill directory")?;
+                self.directory = Some(directory.clone());
+                directory
+            }
+        };
+
+        let mut lines: Vec<String> = self.hot.drain().collect();
+        lines.sort_unstable();
+        self.hot_bytes = 0;
+
+        let path = directory.join(format!("run-{}", self.runs.len()));
+        let mut writer =
+            BufWriter::new(File::create(&path).map_err(|_| "Could not create spill file")?);
+        let mut filter = BloomFilter::new(lines.len(), 0.01);
+        let mut index = vec![];
+        let mut offset = 0u64;
+
+        for (position, line) in lines.iter().enumerate() {
+            if position % SPILL_BLOCK_SIZE == 0 {
+                index.push((line.clone(), offset));
+            }
+            filter.insert(line);
+            writer
+                .write_all(&(line.len() as u64).to_le_bytes())
+                .and_then(|_| writer.write_all(line.as_bytes()))
+                .map_err(|_| "Could not write spill file")?;
+            offset += line.len() as u64 + 8;
+        }
+        writer.flush().map_err(|_| "Could not write spill file")?;
+
+        let reader = BufReader::new(File::open(&path).map_err(|_| "Could not read spill file")?);
+        self.runs.push(SpillRun {
+            reader,
+            index,
+            filter,
+        });
+
+        Ok(())
+    }
+}
+
+impl SpillRun {
+    fn contains(&mut self, line: &str) -> Result<bool, &'static str> {
+        if !self.filter.contains(line) {
+            return Ok(false);
+        }
+
+        let block = match self
+            .index
+            .partition_point(|(first, _)| first.as_str() <= line)
+        {
+            0 => return Ok(false),
+            block => block - 1,
+        };
+
+        self.reader
+            .seek(SeekFrom::Start(self.index[block].1))
+            .map_err(|_| "Could not read spill file")?;
+
+        let mut length = [0; 8];
+        let mut candidate = vec![];
+        for _ in 0..SPILL_BLOCK_SIZE {
+            match self.reader.read_exact(&mut length) {
+                Ok(()) => {}
+                Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+                Err(_) => Err("Could not read spill file")?,
+            }
+            candidate.resize(u64::from_le_bytes(length) as usize, 0);
+            self.reader
+                .read_exact(&mut candidate)
+                .map_err(|_| "Could not read spill file")?;
+
+            match candidate.as_slice().cmp(line.as_bytes()) {
+                std::cmp::Ordering::Equal => return Ok(true),
+                std::cmp::Ordering::Greater => break,
+                std::cmp::Ordering::Less => {}
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+// Exact dedupe whose seen lines outlive the run. The 128-bit hashes of every
+// line seen before are loaded from a state file, and written back with the
+// new ones once the input ends, 16 bytes a line.
+#[derive(Debug)]
+pub struct StateSet {
+    path: PathBuf,
+    seen: HashSet<u128>,
+    added: usize,
+}
+
+impl StateSet {
+    // A missing file is an empty state, as on the first run.
+    pub fn load(path: &str) -> Result<StateSet, &'static str> {
+        let path = PathBuf::from(path);
+        let bytes = match std::fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(error) if error.kind() == ErrorKind::NotFound => STATE_MAGIC.to_vec(),
+            Err(_) => Err("Could not read dedupe state")?,
+        };
+        let hashes = bytes
+            .strip_prefix(STATE_MAGIC.as_slice())
+            .filter(|hashes| hashes.len() % 16 == 0)
+            .ok_or("Invalid dedupe state file")?;
+        let seen = hashes
+            .chunks_exact(16)
+            .map(|hash| u128::from_le_bytes(hash.try_into().unwrap()))
+            .collect();
+
+        Ok(StateSet {
+            path,
+            seen,
+            added: 0,
+        })
+    }
+
+    // Returns true when the line was not seen in this run or an earlier one.
+    pub fn insert(&mut self, line: &[u8]) -> bool {
+        let new = self.seen.insert(xxhash_rust::xxh3::xxh3_128(line));
+        if new {
+            self.added += 1;
+        }
+
+        new
+    }
+
+    // Writes the hashes, sorted, to a file beside the state and renames it
+    // over, so a run that fails while saving leaves the old state whole.
+    pub fn save(&mut self) -> Result<(), &'static str> {
+        if self.added == 0 && self.path.exists() {
+            return Ok(());
+        }
+
+        let mut hashes: Vec<u128> = self.seen.iter().copied().collect();
+        hashes.sort_unstable();
+        let mut bytes = Vec::with_capacity(STATE_MAGIC.len() + hashes.len() * 16);
+        bytes.extend_from_slice(STATE_MAGIC);
+        for hash in hashes {
+            bytes.extend_from_slice(&hash.to_le_bytes());
+        }
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, bytes)
+            .and_then(|_| std::fs::rename(&temporary, &self.path))
+            .map_err(|_| "Could not write dedupe state")?;
+        self.added = 0;
+
+        Ok(())
+    }
+
+    pub fn memory(&self) -> usize {
+        self.seen.len() * 16
+    }
+}
+
+// The exact dedupe set worker threads share under --threads. Lines are spread
+// over shards by hash, each behind a lock of its own, so threads rarely wait
+// on each other. Every line keeps the lowest line number that claimed it: a
+// thread that got to a later copy first loses the line to the earlier one,
+// and the copy it let through is dropped when the batches are put back in
+// order (see `owns`). Jobs under --jobs write lines out as they go, so there
+// the first claim keeps the line whatever its number.
+#[derive(Debug, Clone)]
+pub struct ShardedSet {
+    shards: Arc<[Mutex<Shard>]>,
+    first_come: bool,
+}
+
+impl ShardedSet {
+    pub fn new() -> ShardedSet {
+        ShardedSet {
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+            first_come: false,
+        }
+    }
+
+    pub fn first_come() -> ShardedSet {
+        ShardedSet {
+            first_come: true,
+            ..ShardedSet::new()
+        }
+    }
+
+    // Returns true when no earlier line number claimed the line. A line
+    // number claims a line once, so seeing it again counts as a duplicate.
+    pub fn claim(&self, line: &[u8], line_number: usize) -> bool {
+        let mut shard = self.shard(line);
+        match shard.get_mut(line) {
+            Some(owner) if self.first_come || *owner <= line_number => false,
+            Some(owner) => {
+                *owner = line_number;
+                true
+            }
+            None => {
+                shard.insert(line.to_vec(), line_number);
+                true
+            }
+        }
+    }
+
+    // Whether the line number still holds its claim. Only settled once every
+    // earlier line was claimed, which holds for batches taken back in order.
+    pub fn owns(&self, line: &[u8], line_number: usize) -> bool {
+        self.shard(line).get(line) == Some(&line_number)
+    }
+
+    fn shard(&self, line: &[u8]) -> MutexGuard<'_, Shard> {
+        let index = xxhash_rust::xxh3::xxh3_64(line) as usize % SHARDS;
+        // A thread that panicked holding the lock left whole entries behind.
+        self.shards[index]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for ShardedSet {
+    fn default() -> Self {
+        ShardedSet::new()
+    }
+}
+
+impl Drop for SpillingSet {
+    fn drop(&mut self) {
+        self.runs.clear();
+        if let Some(directory) = &self.directory {
+            let _ = remove_dir_all(directory);
+        }
+    }
+}
+
+impl PartialEq for SpillingSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.memory_limit == other.memory_limit
+    }
+}
+
+impl PartialEq for BloomFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.expected_items == other.expected_items
+            && self.false_positive_rate == other.false_positive_rate
+    }
+}
+
+impl PartialEq for StateSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path
+    }
+}
+
+impl PartialEq for ShardedSet {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.shards, &other.shards)
+    }
+}
+
+impl PartialEq for LruSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.capacity == other.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BloomFilter, DedupeCount, DuplicateSet, KeyTransform, LruSet, ShardedSet, SpillingSet,
+        StateSet, WindowSet,
+    };
+
+    #[test]
+    fn insert_lets_lines_back_once_they_leave_the_window() {
+        //+ Arrange
+        let mut window = WindowSet::new(3);
+
+        //+ Act
+        let kept: Vec<bool> = ["a", "b", "b", "b", "a", "b", "c", "c"]
+            .iter()
+            .map(|line| window.insert(line))
+            .collect();
+
+        //+ Assert
+        assert_eq!(kept, [true, true, false, false, true, false, true, false]);
+        assert_eq!(window.memory(), 3 * 16 + 2 * 24);
+    }
+
+    #[test]
+    fn key_applies_transforms_in_order() {
+        //+ Arrange
+        let transforms = KeyTransform::parse_list("squeeze,lower,ascii").unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            KeyTransform::key(&transforms, "  Café \t LATTE "),
+            "cafe latte"
+        );
+        assert!(KeyTransform::parse_list("lower,reverse").is_err());
+    }
+
+    #[test]
+    fn insert_forgets_least_recently_seen_line() {
+        //+ Arrange
+        let mut set = LruSet::new(2);
+
+        //+ Act + Assert
+        assert!(set.insert("a"));
+        assert!(set.insert("b"));
+        assert!(!set.insert("a"));
+        assert!(set.insert("c"));
+        assert!(!set.insert("a"));
+        assert!(set.insert("b"));
+    }
+
+    #[test]
+    fn insert_keeps_order_bounded_under_repeats() {
+        //+ Arrange
+        let mut set = LruSet::new(2);
+        set.insert("a");
+
+        //+ Act
+        for _ in 0..100 {
+            set.insert("a");
+        }
+
+        //+ Assert
+        assert!(set.order.len() <= 4);
+        assert_eq!(set.memory(), 2);
+    }
+
+    #[test]
+    fn bloom_filter_is_sized_from_expected_items() {
+        //+ Arrange
+        let filter = BloomFilter::new(1_000_000, 0.01);
+
+        //+ Assert
+        assert_eq!(filter.hash_count, 7);
+        assert_eq!(filter.memory(), 1_198_136);
+    }
+
+    #[test]
+    fn bloom_filter_stays_near_false_positive_rate() {
+        //+ Arrange
+        let mut filter = BloomFilter::new(10_000, 0.01);
+
+        //+ Act
+        let new_lines = (0..10_000)
+            .filter(|n| filter.insert(&format!("line {}", n)))
+            .count();
+        let repeats_dropped = (0..100).all(|n| !filter.insert(&format!("line {}", n)));
+
+        //+ Assert
+        assert!(new_lines > 9_800);
+        assert!(repeats_dropped);
+    }
+
+    #[test]
+    fn spilling_set_stays_exact_after_spilling() {
+        //+ Arrange
+        let mut set = SpillingSet::new(1_000);
+
+        //+ Act
+        let first_pass = (0..2_000)
+            .filter(|n| set.insert(&format!("line {}", n)).unwrap())
+            .count();
+        let second_pass = (0..2_000)
+            .filter(|n| set.insert(&format!("line {}", n)).unwrap())
+            .count();
+        let directory = set.directory.clone().unwrap();
+        let runs = set.runs.len();
+        drop(set);
+
+        //+ Assert
+        assert_eq!(first_pass, 2_000);
+        assert_eq!(second_pass, 0);
+        assert!(runs > 10);
+        assert!(!directory.exists());
+    }
+
+    #[test]
+    fn spilling_set_matches_records_holding_newlines() {
+        //+ Arrange
+        let mut set = SpillingSet::new(1);
+
+        //+ Act
+        let first = ["a\nb", "c", "d", "a\n", "e"].map(|line| set.insert(line).unwrap());
+        let second = ["a\nb", "a\n", "a", "c"].map(|line| set.insert(line).unwrap());
+
+        //+ Assert
+        assert_eq!(first, [true; 5]);
+        assert_eq!(second, [false, false, true, false]);
+        assert!(set.runs.len() > 1);
+    }
+
+    #[test]
+    fn state_set_remembers_lines_across_loads() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-state-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut first = StateSet::load(path).unwrap();
+
+        //+ Act
+        let first_run = ["a", "b", "a"].map(|line| first.insert(line.as_bytes()));
+        first.save().unwrap();
+        let mut second = StateSet::load(path).unwrap();
+        let second_run = ["b", "c"].map(|line| second.insert(line.as_bytes()));
+        let saved = std::fs::metadata(path).unwrap().len();
+        std::fs::write(path, b"not a state file").unwrap();
+        let invalid = StateSet::load(path);
+        std::fs::remove_file(path).unwrap();
+
+        //+ Assert
+        assert_eq!(first_run, [true, true, false]);
+        assert_eq!(second_run, [false, true]);
+        assert_eq!(saved, 8 + 2 * 16);
+        assert_eq!(invalid.err(), Some("Invalid dedupe state file"));
+    }
+
+    #[test]
+    fn claim_gives_each_line_to_its_lowest_line_number() {
+        //+ Arrange
+        let set = ShardedSet::new();
+
+        //+ Act
+        let later = set.claim(b"a", 7);
+        let earlier = set.claim(b"a", 3);
+        let again = set.claim(b"a", 3);
+        let last = set.claim(b"a", 9);
+
+        //+ Assert
+        assert_eq!((later, earlier, again, last), (true, true, false, false));
+        assert!(set.owns(b"a", 3));
+        assert!(!set.owns(b"a", 7));
+        assert!(!set.owns(b"b", 3));
+    }
+
+    #[test]
+    fn claim_first_come_keeps_the_first_claim() {
+        //+ Arrange
+        let set = ShardedSet::first_come();
+
+        //+ Act
+        let later = set.claim(b"a", 7);
+        let earlier = set.claim(b"a", 3);
+
+        //+ Assert
+        assert_eq!((later, earlier), (true, false));
+        assert!(set.owns(b"a", 7));
+    }
+
+    #[test]
+    fn insert_keeps_repeats_or_only_second_copies() {
+        //+ Arrange
+        let lines = ["a", "b", "a", "a", "c", "b"];
+        let (mut every, mut once) = (DuplicateSet::new(false), DuplicateSet::new(true));
+
+        //+ Act
+        let kept: Vec<bool> = lines
+            .iter()
+            .map(|line| every.insert(line.as_bytes()))
+            .collect();
+        let kept_once: Vec<bool> = lines
+            .iter()
+            .map(|line| once.insert(line.as_bytes()))
+            .collect();
+
+        //+ Assert
+        assert_eq!(kept, [false, false, true, true, false, true]);
+        assert_eq!(kept_once, [false, false, true, false, false, true]);
+        assert_eq!(every.memory(), 3);
+    }
+
+    #[test]
+    fn flush_counts_lines_in_first_seen_order() {
+        //+ Arrange
+        let mut counts = DedupeCount::new(vec![KeyTransform::Lower]);
+
+        //+ Act
+        for line in ["b", "A", "B", "a", "b", "c"] {
+            counts.push(line.to_string());
+        }
+        let counted = counts.flush();
+
+        //+ Assert
+        assert_eq!(counted, ["3 b", "2 A", "1 c"]);
+        assert_eq!(counts.memory(), 0);
+    }
+}
@@ -0,0 +1,178 @@
+use std::path::Path;
+
+use serde_json::Value;
+
+/// Reads a pipeline definition file and returns the commands it describes,
+/// as the tokens [`Pipeline::build_pipeline`](crate::Pipeline::build_pipeline)
+/// takes, so a file is checked exactly like the same steps on the command line.
+///
+/// Files ending in `.toml` are TOML, anything else is YAML (and so also JSON).
+/// The steps are a list, either the whole document or under `steps`, and each
+/// one is a bare command or a command mapped to its arguments:
+///
+/// ```yaml
+/// steps:
+///   - trim
+///   - filter: 'ERROR|WARN'
+///   - dedupe: { recent: 1000 }
+///   - calc: ['{1} * 1000', { on-error: pass }]
+/// ```
+///
+/// Arguments are a single value, a list, or a map of options: `key: value`
+/// becomes `--key value`, `key: true` becomes `--key` and a list repeats the
+/// option for every item. A document with `args` instead of `steps` lists the
+/// tokens themselves, the way saved presets are stored.
+pub fn load_definition(path: &str) -> Result<Vec<String>, &'static str> {
+    let source = std::fs::read_to_string(path).map_err(|_| "Could not read pipeline file")?;
+
+    let document: Value = match Path::new(path).extension().and_then(|e| e.to_str()) {
+        Some("toml") => toml::from_str(&source).map_err(|_| "Invalid TOML in pipeline file")?,
+        _ => serde_yaml::from_str(&source).map_err(|_| "Invalid YAML in pipeline file")?,
+    };
+
+    parse_definition(&document)
+}
+
+fn parse_definition(document: &Value) -> Result<Vec<String>, &'static str> {
+    let steps = match document {
+        Value::Array(steps) => steps,
+        Value::Object(fields) => match (fields.get("steps"), fields.get("args")) {
+            (Some(Value::Array(steps)), _) => steps,
+            (None, Some(Value::Array(args))) => return args.iter().map(scalar).collect(),
+            _ => return Err("Pipeline file must list its steps"),
+        },
+        _ => return Err("Pipeline file must list its steps"),
+    };
+
+    let mut tokens = vec![];
+    for step in steps {
+        match step {
+            Value::String(command) => tokens.push(command.clone()),
+            Value::Object(step) if step.len() == 1 => {
+                let (command, arguments) = step.iter().next().unwrap();
+                tokens.push(command.clone());
+                match arguments {
+                    Value::Array(arguments) => {
+                        for argument in arguments {
+                            push_argument(&mut tokens, argument)?;
+                        }
+                    }
+                    argument => push_argument(&mut tokens, argument)?,
+                }
+            }
+            _ => {
+                return Err(
+                    "Every pipeline step must be a command or map one command to its arguments",
+                )
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn push_argument(tokens: &mut Vec<String>, argument: &Value) -> Result<(), &'static str> {
+    match argument {
+        Value::Null => {}
+        Value::Object(options) => {
+            for (name, value) in options {
+                let values = match value {
+                    Value::Array(values) => values.as_slice(),
+                    value => std::slice::from_ref(value),
+                };
+
+                for value in values {
+                    match value {
+                        Value::Bool(false) => {}
+                        Value::Bool(true) => tokens.push(format!("--{}", name)),
+                        value => {
+                            tokens.push(format!("--{}", name));
+                            tokens.push(scalar(value)?);
+                        }
+                    }
+                }
+            }
+        }
+        value => tokens.push(scalar(value)?),
+    }
+
+    Ok(())
+}
+
+fn scalar(value: &Value) -> Result<String, &'static str> {
+    match value {
+        Value::String(text) => Ok(text.clone()),
+        Value::Number(number) => Ok(number.to_string()),
+        Value::Bool(value) => Ok(value.to_string()),
+        _ => Err("Pipeline step arguments must be text, numbers or options"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::load_definition;
+    use crate::pipeline::Pipeline;
+
+    #[test]
+    fn load_definition_reads_yaml_and_toml() {
+        //+ Arrange
+        let directory = std::env::temp_dir();
+        let yaml = directory.join(format!("rangler-definition-{}.yaml", std::process::id()));
+        let toml = directory.join(format!("rangler-definition-{}.toml", std::process::id()));
+        std::fs::write(
+            &yaml,
+            "steps:\n  - trim\n  - filter: 'a|b'\n  - dedupe: { recent: 10 }\n  - calc: ['{1} * 2', { on-error: pass }]\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &toml,
+            "steps = [\"trim\", { filter = \"a|b\" }, { dedupe = { recent = 10 } }, { calc = [\"{1} * 2\", { on-error = \"pass\" }] }]\n",
+        )
+        .unwrap();
+        let expected = [
+            "trim",
+            "filter",
+            "a|b",
+            "dedupe",
+            "--recent",
+            "10",
+            "calc",
+            "{1} * 2",
+            "--on-error",
+            "pass",
+        ];
+
+        //+ Act
+        let from_yaml = load_definition(yaml.to_str().unwrap());
+        let from_toml = load_definition(toml.to_str().unwrap());
+
+        //+ Assert
+        assert_eq!(from_yaml, Ok(expected.map(String::from).to_vec()));
+        assert_eq!(from_toml, Ok(expected.map(String::from).to_vec()));
+        assert!(Pipeline::build_pipeline(&from_yaml.unwrap()).is_ok());
+        std::fs::remove_file(yaml).unwrap();
+        std::fs::remove_file(toml).unwrap();
+    }
+
+    #[test]
+    fn load_definition_rejects_malformed_steps() {
+        //+ Arrange
+        let path =
+            std::env::temp_dir().join(format!("rangler-definition-{}.yml", std::process::id()));
+        std::fs::write(&path, "steps:\n  - filter: a\n    upper: ~\n").unwrap();
+
+        //+ Act
+        let result = load_definition(path.to_str().unwrap());
+
+        //+ Assert
+        assert_eq!(
+            result,
+            Err("Every pipeline step must be a command or map one command to its arguments")
+        );
+        assert_eq!(
+            load_definition("/nonexistent/pipeline.yaml"),
+            Err("Could not read pipeline file")
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LossyEvent {
+    InvalidUtf8Skipped,
+    InvalidUtf8Replaced,
+    ApproximateTop,
+    ApproximateCount,
+    ApproximatePercentile,
+}
+
+impl LossyEvent {
+    pub fn description(&self) -> &'static str {
+        match self {
+            LossyEvent::InvalidUtf8Skipped => "lines skipped because they were not valid UTF-8",
+            LossyEvent::InvalidUtf8Replaced => {
+                "lines with invalid UTF-8 replaced by U+FFFD characters"
+            }
+            LossyEvent::ApproximateTop => {
+                "counters evicted by approximate top (reported counts may be overestimates)"
+            }
+            LossyEvent::ApproximateCount => {
+                "distinct counts estimated by count-distinct --approx (typically within 1%)"
+            }
+            LossyEvent::ApproximatePercentile => {
+                "percentiles estimated by stats from a t-digest (typically within 1% of their rank)"
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DegradationReport {
+    strict: bool,
+    counts: BTreeMap<LossyEvent, usize>,
+}
+
+impl DegradationReport {
+    pub fn new(strict: bool) -> DegradationReport {
+        DegradationReport {
+            strict,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    // In strict mode the first lossy event fails the run instead of being
+    // counted.
+    pub fn record(&mut self, event: LossyEvent) -> Result<(), String> {
+        if self.strict {
+            return Err(format!("Strict mode: {}", event.description()));
+        }
+
+        *self.counts.entry(event).or_insert(0) += 1;
+        Ok(())
+    }
+
+    // Folds in events that steps counted themselves over the whole run.
+    pub fn record_count(&mut self, event: LossyEvent, count: usize) -> Result<(), String> {
+        if count == 0 {
+            return Ok(());
+        }
+
+        if self.strict {
+            return Err(format!("Strict mode: {} {}", count, event.description()));
+        }
+
+        *self.counts.entry(event).or_insert(0) += count;
+        Ok(())
+    }
+
+    pub fn summary(&self) -> Vec<String> {
+        self.counts
+            .iter()
+            .map(|(event, count)| format!("{} {}", count, event.description()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DegradationReport, LossyEvent};
+
+    #[test]
+    fn record_counts_events_per_category() {
+        //+ Arrange
+        let mut report = DegradationReport::new(false);
+
+        //+ Act
+        report.record(LossyEvent::InvalidUtf8Skipped).unwrap();
+        report.record(LossyEvent::InvalidUtf8Skipped).unwrap();
+
+        //+ Assert
+        assert_eq!(
+            report.summary(),
+            vec!["2 lines skipped because they were not valid UTF-8".to_string()]
+        );
+    }
+
+    #[test]
+    fn record_fails_in_strict_mode() {
+        //+ Arrange
+        let mut report = DegradationReport::new(true);
+
+        //+ Act
+        let result = report.record(LossyEvent::InvalidUtf8Skipped);
+
+        //+ Assert
+        assert!(result.is_err());
+        assert!(report.summary().is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+
+use xxhash_rust::xxh3::{xxh3_128, xxh3_64};
+
+// 2^14 registers keep the HyperLogLog estimate within about 0.8% in 16 KiB.
+const PRECISION: u32 = 14;
+const REGISTERS: usize = 1 << PRECISION;
+
+// Counts the distinct lines and emits only that count once the input ends.
+// The exact count keeps a 128-bit hash of every distinct line rather than
+// the line, and the approximate one a fixed-size HyperLogLog sketch.
+#[derive(Debug, PartialEq)]
+pub enum CountDistinct {
+    Exact(HashSet<u128>),
+    Approximate(Vec<u8>),
+}
+
+impl CountDistinct {
+    pub fn exact() -> CountDistinct {
+        CountDistinct::Exact(HashSet::new())
+    }
+
+    pub fn approximate() -> CountDistinct {
+        CountDistinct::Approximate(vec![0; REGISTERS])
+    }
+
+    pub fn is_approximate(&self) -> bool {
+        matches!(self, CountDistinct::Approximate(_))
+    }
+
+    // The top bits of the hash pick a register, which keeps the longest run
+    // of leading zeros seen in the rest.
+    pub fn push(&mut self, line: &str) {
+        match self {
+            CountDistinct::Exact(seen) => {
+                seen.insert(xxh3_128(line.as_bytes()));
+            }
+            CountDistinct::Approximate(registers) => {
+                let hash = xxh3_64(line.as_bytes());
+                let register = (hash >> (64 - PRECISION)) as usize;
+                let rank = ((hash << PRECISION) | (1 << (PRECISION - 1))).leading_zeros() + 1;
+                registers[register] = registers[register].max(rank as u8);
+            }
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        match self {
+            CountDistinct::Exact(seen) => seen.len(),
+            CountDistinct::Approximate(registers) => {
+                let m = REGISTERS as f64;
+                let alpha = 0.7213 / (1.0 + 1.079 / m);
+                let sum: f64 = registers
+                    .iter()
+                    .map(|&rank| 2f64.powi(-(rank as i32)))
+                    .sum();
+                let estimate = alpha * m * m / sum;
+
+                // Small counts leave registers empty, where linear counting
+                // is the better estimate.
+                let empty = registers.iter().filter(|&&rank| rank == 0).count();
+                if estimate <= 2.5 * m && empty > 0 {
+                    (m * (m / empty as f64).ln()).round() as usize
+                } else {
+                    estimate.round() as usize
+                }
+            }
+        }
+    }
+
+    pub fn memory(&self) -> usize {
+        match self {
+            CountDistinct::Exact(seen) => seen.len() * std::mem::size_of::<u128>(),
+            CountDistinct::Approximate(registers) => registers.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CountDistinct;
+
+    #[test]
+    fn exact_and_approximate_counts_agree_closely() {
+        //+ Arrange
+        let mut exact = CountDistinct::exact();
+        let mut approximate = CountDistinct::approximate();
+
+        //+ Act
+        for number in 0..100_000 {
+            let line = format!("user-{}", number % 50_000);
+            exact.push(&line);
+            approximate.push(&line);
+        }
+
+        //+ Assert
+        assert_eq!(exact.count(), 50_000);
+        let error = (approximate.count() as f64 - 50_000.0).abs() / 50_000.0;
+        assert!(error < 0.03, "estimate off by {}", error);
+        assert_eq!(approximate.memory(), 1 << 14);
+    }
+}
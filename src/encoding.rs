@@ -0,0 +1,125 @@
+use std::io::{BufRead, BufReader, Write};
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use encoding_rs_io::DecodeReaderBytesBuilder;
+
+pub fn parse_encoding(label: &str) -> Result<&'static Encoding, &'static str> {
+    Encoding::for_label(label.trim().as_bytes()).ok_or("Unknown encoding")
+}
+
+// Transcodes an input to UTF-8. A byte order mark, when present, wins over
+// the given encoding and is stripped either way.
+pub fn decode_input(
+    source: Box<dyn BufRead>,
+    encoding: &'static Encoding,
+    capacity: usize,
+) -> Box<dyn BufRead> {
+    let decoder = DecodeReaderBytesBuilder::new()
+        .encoding(Some(encoding))
+        .bom_override(true)
+        .strip_bom(true)
+        .build(source);
+
+    Box::new(BufReader::with_capacity(capacity, decoder))
+}
+
+// Transcodes output from UTF-8. encoding_rs only decodes UTF-16, so that is
+// encoded by hand, starting with a byte order mark as Windows tools expect.
+// Every write must hold whole characters, which is how lines are written.
+pub struct EncodedWriter<W: Write> {
+    inner: W,
+    encoding: &'static Encoding,
+    started: bool,
+}
+
+impl<W: Write> EncodedWriter<W> {
+    pub fn new(inner: W, encoding: &'static Encoding) -> EncodedWriter<W> {
+        EncodedWriter {
+            inner,
+            encoding,
+            started: false,
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for EncodedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+
+        if self.encoding == UTF_16LE || self.encoding == UTF_16BE {
+            let little_endian = self.encoding == UTF_16LE;
+            let to_bytes = |unit: u16| match little_endian {
+                true => unit.to_le_bytes(),
+                false => unit.to_be_bytes(),
+            };
+
+            let mut encoded = Vec::with_capacity(buf.len() * 2 + 2);
+            if !self.started {
+                encoded.extend(to_bytes(0xfeff));
+            }
+            for unit in text.encode_utf16() {
+                encoded.extend(to_bytes(unit));
+            }
+            self.inner.write_all(&encoded)?;
+        } else if self.encoding == UTF_8 {
+            self.inner.write_all(buf)?;
+        } else {
+            let (encoded, _, _) = self.encoding.encode(&text);
+            self.inner.write_all(&encoded)?;
+        }
+
+        self.started = true;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::{decode_input, parse_encoding, EncodedWriter};
+
+    #[test]
+    fn decode_input_transcodes_and_strips_bom() {
+        //+ Arrange
+        let latin1 = Box::new(&b"caf\xe9\n"[..]);
+        let utf16 = Box::new(&b"\xff\xfeh\x00i\x00\n\x00"[..]);
+
+        //+ Act
+        let mut from_latin1 = String::new();
+        decode_input(latin1, parse_encoding("latin1").unwrap(), 64)
+            .read_to_string(&mut from_latin1)
+            .unwrap();
+        let mut from_utf16 = String::new();
+        decode_input(utf16, parse_encoding("latin1").unwrap(), 64)
+            .read_to_string(&mut from_utf16)
+            .unwrap();
+
+        //+ Assert
+        assert_eq!(from_latin1, "café\n");
+        assert_eq!(from_utf16, "hi\n");
+    }
+
+    #[test]
+    fn encoded_writer_writes_bom_once_for_utf16() {
+        //+ Arrange
+        let mut output = vec![];
+        let mut writer = EncodedWriter::new(&mut output, parse_encoding("utf-16le").unwrap());
+
+        //+ Act
+        writer.write_all(b"a\n").unwrap();
+        writer.write_all("é\n".as_bytes()).unwrap();
+
+        //+ Assert
+        assert_eq!(output, b"\xff\xfea\x00\n\x00\xe9\x00\n\x00");
+        assert!(parse_encoding("klingon").is_err());
+    }
+}
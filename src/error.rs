@@ -0,0 +1,230 @@
+use std::{error::Error, fmt, io};
+
+use crate::width::display_width;
+
+/// Why building or running a pipeline failed. Mistakes in the commands record
+/// the index of the token they are about, so [`RanglerError::locate`] can
+/// point at it.
+#[derive(Debug)]
+pub enum RanglerError {
+    UnknownCommand {
+        command: String,
+        suggestion: Option<&'static str>,
+        position: usize,
+    },
+    MissingArgument {
+        message: String,
+        position: usize,
+    },
+    InvalidArgument {
+        message: String,
+        position: usize,
+    },
+    InvalidRegex {
+        pattern: String,
+        position: usize,
+        source: regex::Error,
+    },
+    Io {
+        message: &'static str,
+        path: String,
+        source: io::Error,
+    },
+    Message(String),
+}
+
+impl RanglerError {
+    /// The index of the command token the error is about, if it is about one.
+    /// A missing argument points just past the last token it could follow.
+    pub fn position(&self) -> Option<usize> {
+        match self {
+            RanglerError::UnknownCommand { position, .. }
+            | RanglerError::MissingArgument { position, .. }
+            | RanglerError::InvalidArgument { position, .. }
+            | RanglerError::InvalidRegex { position, .. } => Some(*position),
+            RanglerError::Io { .. } | RanglerError::Message(_) => None,
+        }
+    }
+
+    /// Shows the commands on one line with carets under the token the error
+    /// is about, or nothing when it is not about a token.
+    pub fn locate<T: AsRef<str>>(&self, commands: &[T]) -> Option<String> {
+        let position = self.position()?;
+        let mut line = String::new();
+        let mut marker = String::new();
+
+        for (index, token) in commands.iter().enumerate() {
+            let token = quote(token.as_ref());
+            if index == position {
+                marker =
+                    " ".repeat(display_width(&line)) + "^".repeat(display_width(&token)).as_str();
+            }
+            line += token.as_str();
+            line += " ";
+        }
+        if position >= commands.len() {
+            marker = " ".repeat(display_width(&line)) + "^";
+        }
+
+        Some(format!("    {}\n    {}", line.trim_end(), marker))
+    }
+
+    // Points an error from parsing commands at the token it is about, given
+    // how many tokens had been read when it happened: the last one read, or
+    // the one after it for an argument that is missing.
+    pub(crate) fn at(self, read: usize) -> RanglerError {
+        let last = read.saturating_sub(1);
+        match self {
+            RanglerError::Message(message) if message.starts_with("Missing") => {
+                RanglerError::MissingArgument {
+                    message,
+                    position: read,
+                }
+            }
+            RanglerError::Message(message) => RanglerError::InvalidArgument {
+                message,
+                position: last,
+            },
+            RanglerError::UnknownCommand {
+                command,
+                suggestion,
+                ..
+            } => RanglerError::UnknownCommand {
+                command,
+                suggestion,
+                position: last,
+            },
+            RanglerError::InvalidRegex {
+                pattern, source, ..
+            } => RanglerError::InvalidRegex {
+                pattern,
+                position: last,
+                source,
+            },
+            error => error,
+        }
+    }
+}
+
+fn quote(token: &str) -> String {
+    if token.is_empty() || token.contains(|c: char| c.is_whitespace() || c == '\'' || c == '"') {
+        format!("'{}'", token.replace('\'', r"'\''"))
+    } else {
+        token.to_string()
+    }
+}
+
+impl fmt::Display for RanglerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RanglerError::UnknownCommand {
+                command,
+                suggestion: Some(suggestion),
+                ..
+            } => write!(
+                f,
+                "Unknown command '{}', did you mean '{}'?",
+                command, suggestion
+            ),
+            RanglerError::UnknownCommand { command, .. } => {
+                write!(f, "Unknown command '{}'", command)
+            }
+            RanglerError::MissingArgument { message, .. }
+            | RanglerError::InvalidArgument { message, .. }
+            | RanglerError::Message(message) => f.write_str(message),
+            RanglerError::InvalidRegex { .. } => f.write_str("Invalid regular expression"),
+            RanglerError::Io { message, path, .. } => write!(f, "{} {}", message, path),
+        }
+    }
+}
+
+impl Error for RanglerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RanglerError::InvalidRegex { source, .. } => Some(source),
+            RanglerError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<&'static str> for RanglerError {
+    fn from(message: &'static str) -> RanglerError {
+        RanglerError::Message(message.to_string())
+    }
+}
+
+impl From<String> for RanglerError {
+    fn from(message: String) -> RanglerError {
+        RanglerError::Message(message)
+    }
+}
+
+impl From<RanglerError> for String {
+    fn from(error: RanglerError) -> String {
+        error.to_string()
+    }
+}
+
+// Lets tests and callers compare an error with the message it shows.
+impl PartialEq<&str> for RanglerError {
+    fn eq(&self, other: &&str) -> bool {
+        self.to_string().as_str() == *other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RanglerError;
+    use crate::pipeline::Pipeline;
+
+    #[test]
+    fn build_pipeline_errors_point_at_the_bad_token() {
+        //+ Arrange
+        let commands = ["trim", "filter", "(", "dedupe"];
+
+        //+ Act
+        let invalid = Pipeline::build_pipeline(&commands).err().unwrap();
+        let missing = Pipeline::build_pipeline(&["trim", "append"]).err().unwrap();
+        let unknown = Pipeline::build_pipeline(&["trim", "uper"]).err().unwrap();
+
+        //+ Assert
+        assert!(matches!(
+            invalid,
+            RanglerError::InvalidRegex { position: 2, .. }
+        ));
+        assert_eq!(
+            invalid.locate(&commands).unwrap(),
+            "    trim filter ( dedupe\n                ^"
+        );
+        assert!(matches!(
+            missing,
+            RanglerError::MissingArgument { position: 2, .. }
+        ));
+        assert_eq!(
+            missing.locate(&["trim", "append"]).unwrap(),
+            "    trim append\n                ^"
+        );
+        assert_eq!(unknown.position(), Some(1));
+        assert_eq!(unknown, "Unknown command 'uper', did you mean 'upper'?");
+    }
+
+    #[test]
+    fn locate_quotes_tokens_with_spaces() {
+        //+ Arrange
+        let error = RanglerError::InvalidArgument {
+            message: "Invalid length".to_string(),
+            position: 2,
+        };
+
+        //+ Act
+        let located = error.locate(&["append", "a b", "minlen", "x"]);
+
+        //+ Assert
+        assert_eq!(
+            located.unwrap(),
+            "    append 'a b' minlen x\n                 ^^^^^^"
+        );
+        assert_eq!(RanglerError::from("Broken pipe").locate(&["trim"]), None);
+    }
+}
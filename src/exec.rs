@@ -0,0 +1,195 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    thread,
+};
+
+#[derive(Debug)]
+pub enum Exec {
+    PerLine(String),
+    Coprocess(Coprocess),
+}
+
+// A child that stays alive for the whole run and answers every line written
+// to its stdin with exactly one line on its stdout. The child must flush after
+// each line or the pipeline will wait on it forever.
+#[derive(Debug)]
+pub struct Coprocess {
+    command: String,
+    child: Child,
+    stdin: Option<ChildStdin>,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Exec {
+    pub fn new(command: &str, coprocess: bool) -> Result<Exec, &'static str> {
+        if !coprocess {
+            return Ok(Exec::PerLine(command.to_string()));
+        }
+
+        let mut child = shell(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|_| "Could not start command")?;
+        let stdin = child.stdin.take();
+        let stdout = BufReader::new(child.stdout.take().ok_or("Could not start command")?);
+
+        Ok(Exec::Coprocess(Coprocess {
+            command: command.to_string(),
+            child,
+            stdin,
+            stdout,
+        }))
+    }
+
+    // Ok(None) means the command ran but failed for this line (non-zero exit
+    // or output that is not UTF-8); errors are reserved for the process itself
+    // being unusable.
+    pub fn apply(&mut self, line: &str) -> Result<Option<String>, &'static str> {
+        match self {
+            Exec::PerLine(command) => {
+                let mut child = shell(command)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .map_err(|_| "Could not start command")?;
+
+                // The line is written from a thread of its own while the
+                // output is read, as a child answering a line longer than the
+                // pipe holds would otherwise wait on us as we wait on it.
+                let mut stdin = child.stdin.take().ok_or("Could not start command")?;
+                let (written, output) = thread::scope(|scope| {
+                    let writer = scope.spawn(move || writeln!(stdin, "{}", line));
+                    let output = child.wait_with_output();
+                    (writer.join().is_ok_and(|written| written.is_ok()), output)
+                });
+
+                let output = output.map_err(|_| "Command failed")?;
+                if !written || !output.status.success() {
+                    return Ok(None);
+                }
+
+                Ok(String::from_utf8(output.stdout)
+                    .ok()
+                    .map(|text| strip_newline(&text).to_string()))
+            }
+            Exec::Coprocess(coprocess) => {
+                let stdin = coprocess.stdin.as_mut().ok_or("Coprocess exited")?;
+                writeln!(stdin, "{}", line)
+                    .and_then(|_| stdin.flush())
+                    .map_err(|_| "Coprocess exited")?;
+
+                let mut response = Vec::new();
+                let read = coprocess
+                    .stdout
+                    .read_until(b'\n', &mut response)
+                    .map_err(|_| "Coprocess exited")?;
+                if read == 0 {
+                    return Err("Coprocess exited");
+                }
+
+                Ok(String::from_utf8(response)
+                    .ok()
+                    .map(|text| strip_newline(&text).to_string()))
+            }
+        }
+    }
+
+    pub fn finish(&mut self) -> Result<(), &'static str> {
+        if let Exec::Coprocess(coprocess) = self {
+            coprocess.stdin.take();
+            coprocess.child.wait().map_err(|_| "Command failed")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl PartialEq for Exec {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Exec::PerLine(left_command), Exec::PerLine(right_command)) => {
+                left_command == right_command
+            }
+            (Exec::Coprocess(left), Exec::Coprocess(right)) => left.command == right.command,
+            _ => false,
+        }
+    }
+}
+
+impl Drop for Coprocess {
+    fn drop(&mut self) {
+        if self.stdin.is_some() {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+fn strip_newline(text: &str) -> &str {
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    text.strip_suffix('\r').unwrap_or(text)
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::Exec;
+
+    #[test]
+    fn apply_runs_command_per_line() {
+        //+ Arrange
+        let mut exec = Exec::new("tr a-z A-Z", false).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(exec.apply("hello"), Ok(Some("HELLO".to_string())));
+    }
+
+    #[test]
+    fn apply_reports_failed_commands_as_none() {
+        //+ Arrange
+        let mut exec = Exec::new("grep -q nothing", false).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(exec.apply("hello"), Ok(None));
+    }
+
+    #[test]
+    fn apply_reuses_coprocess() {
+        //+ Arrange
+        let mut exec = Exec::new("cat", true).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(exec.apply("one"), Ok(Some("one".to_string())));
+        assert_eq!(exec.apply("two"), Ok(Some("two".to_string())));
+        assert_eq!(exec.finish(), Ok(()));
+        assert_eq!(exec.apply("three"), Err("Coprocess exited"));
+    }
+
+    #[test]
+    fn apply_passes_lines_longer_than_a_pipe_holds() {
+        //+ Arrange
+        let mut exec = Exec::new("cat", false).unwrap();
+        let line = "x".repeat(300_000);
+
+        //+ Act
+        let output = exec.apply(&line).unwrap();
+
+        //+ Assert
+        assert_eq!(output.map(|output| output.len()), Some(300_000));
+    }
+}
@@ -0,0 +1,76 @@
+use chrono::{DateTime, Local};
+
+use crate::timestamp::validate_format;
+
+// Fills `${NAME}` in with the environment variable. One that is not set is
+// left as written, so a mistyped name shows up in the output instead of
+// silently becoming nothing, and `$${` writes a literal `${`. A `$` not
+// followed by `{`, or a `${` never closed, is left as it is.
+pub fn expand_env(text: &str) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        if let Some(before) = rest[..start].strip_suffix('$') {
+            expanded.push_str(before);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let Some(length) = rest[start + 2..].find('}') else {
+            break;
+        };
+
+        expanded.push_str(&rest[..start]);
+        let name = &rest[start + 2..start + 2 + length];
+        match std::env::var(name) {
+            Ok(value) => expanded.push_str(&value),
+            Err(_) => expanded.push_str(&rest[start..start + 3 + length]),
+        }
+        rest = &rest[start + 3 + length..];
+    }
+    expanded.push_str(rest);
+
+    expanded
+}
+
+// Fills strftime tokens like `%Y-%m-%d` in with the time given. Text with a
+// `%` that is not a valid token, like `50% off`, is left alone entirely.
+pub fn expand_date(text: &str, now: &DateTime<Local>) -> String {
+    match text.contains('%') && validate_format(text).is_ok() {
+        true => now.format(text).to_string(),
+        false => text.to_string(),
+    }
+}
+
+// Expands the dates as of when the run starts, then the environment, so a
+// variable's value is never taken for a date format. The dates stay as they
+// were at the start for the whole run, however long it follows its input.
+pub fn expand(text: &str) -> String {
+    expand_env(&expand_date(text, &Local::now()))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Local, TimeZone};
+
+    use super::{expand_date, expand_env};
+
+    #[test]
+    fn expand_fills_in_environment_variables_and_dates() {
+        //+ Arrange
+        std::env::set_var("RANGLER_EXPAND_TEST", "web-1");
+        let now = Local.with_ymd_and_hms(2024, 3, 9, 12, 0, 0).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            expand_env("host=${RANGLER_EXPAND_TEST} ${RANGLER_UNSET_TEST}$HOME ${x"),
+            "host=web-1 ${RANGLER_UNSET_TEST}$HOME ${x"
+        );
+        assert_eq!(
+            expand_env("$${RANGLER_EXPAND_TEST} costs $5"),
+            "${RANGLER_EXPAND_TEST} costs $5"
+        );
+        assert_eq!(expand_date("day=%Y-%m-%d", &now), "day=2024-03-09");
+        assert_eq!(expand_date("50% off", &now), "50% off");
+    }
+}
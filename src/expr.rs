@@ -0,0 +1,595 @@
+use regex::Regex;
+
+use crate::calc::format_number;
+use crate::width::display_width;
+
+// A small expression language for the `where` and `map` steps, e.g.
+// `len > 80 && line contains 'ERROR'` or `upper(field(2, ','))`. Everything is
+// parsed when the pipeline is built, so mistakes surface before any input is
+// read, with a caret under the offending token.
+#[derive(Debug)]
+pub enum Expr {
+    Literal(Value),
+    Line,
+    Length,
+    Number,
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Binary(Box<Expr>, BinaryOperator, Box<Expr>),
+    Matches(Box<Expr>, Regex),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Text(String),
+    Number(f64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Function {
+    Field,
+    Upper,
+    Lower,
+    Trim,
+    Len,
+    Replace,
+    Num,
+}
+
+// Values an expression can draw on for the line being evaluated.
+pub struct ExprContext<'a> {
+    pub line: &'a str,
+    pub number: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Text(String),
+    Identifier(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 16] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "+", "-", "*", "/", "(", ")", ",",
+];
+
+impl Function {
+    fn parse(name: &str) -> Option<(Function, usize, usize)> {
+        // The function with the fewest and most arguments it takes.
+        match name {
+            "field" => Some((Function::Field, 1, 2)),
+            "upper" => Some((Function::Upper, 1, 1)),
+            "lower" => Some((Function::Lower, 1, 1)),
+            "trim" => Some((Function::Trim, 1, 1)),
+            "len" => Some((Function::Len, 1, 1)),
+            "replace" => Some((Function::Replace, 3, 3)),
+            "num" => Some((Function::Num, 1, 1)),
+            _ => None,
+        }
+    }
+}
+
+impl Value {
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Bool(value) => *value,
+            Value::Number(number) => *number != 0.0,
+            Value::Text(text) => !text.is_empty(),
+        }
+    }
+
+    fn number(&self) -> Option<f64> {
+        match self {
+            Value::Number(number) => Some(*number),
+            Value::Text(text) => text.trim().parse().ok(),
+            Value::Bool(_) => None,
+        }
+    }
+
+    fn text(self) -> String {
+        match self {
+            Value::Text(text) => text,
+            Value::Number(number) => format_number(number),
+            Value::Bool(value) => value.to_string(),
+        }
+    }
+}
+
+impl Expr {
+    // Errors name what is wrong and show where, e.g.
+    //
+    //     Unknown function in expression:
+    //         uper(line)
+    //         ^
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        let describe = |message: &str, position: usize| {
+            format!(
+                "{} in expression:\n    {}\n    {}^",
+                message,
+                source,
+                " ".repeat(display_width(&source[..position]))
+            )
+        };
+
+        let tokens = tokenize(source).map_err(|(message, position)| describe(message, position))?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            position: 0,
+            end: source.len(),
+        };
+
+        let expression = parser
+            .or()
+            .and_then(|expression| match parser.peek() {
+                None => Ok(expression),
+                Some(_) => Err(("Unexpected token", parser.offset())),
+            })
+            .map_err(|(message, position)| describe(message, position))?;
+
+        Ok(expression)
+    }
+
+    pub fn evaluate(&self, context: &ExprContext) -> Option<Value> {
+        let value = match self {
+            Expr::Literal(value) => value.clone(),
+            Expr::Line => Value::Text(context.line.to_string()),
+            Expr::Length => Value::Number(context.line.chars().count() as f64),
+            Expr::Number => Value::Number(context.number as f64),
+            Expr::Not(inner) => Value::Bool(!inner.evaluate(context)?.is_truthy()),
+            Expr::Negate(inner) => Value::Number(-inner.evaluate(context)?.number()?),
+            Expr::And(left, right) => Value::Bool(
+                left.evaluate(context)?.is_truthy() && right.evaluate(context)?.is_truthy(),
+            ),
+            Expr::Or(left, right) => Value::Bool(
+                left.evaluate(context)?.is_truthy() || right.evaluate(context)?.is_truthy(),
+            ),
+            Expr::Matches(inner, regex) => {
+                Value::Bool(regex.is_match(&inner.evaluate(context)?.text()))
+            }
+            Expr::Binary(left, operator, right) => {
+                binary(left.evaluate(context)?, *operator, right.evaluate(context)?)?
+            }
+            Expr::Call(function, arguments) => {
+                let mut values = vec![];
+                for argument in arguments {
+                    values.push(argument.evaluate(context)?);
+                }
+
+                call(*function, values, context)?
+            }
+        };
+
+        match value {
+            Value::Number(number) if !number.is_finite() => None,
+            value => Some(value),
+        }
+    }
+
+    pub fn evaluate_text(&self, context: &ExprContext) -> Option<String> {
+        self.evaluate(context).map(Value::text)
+    }
+}
+
+fn binary(left: Value, operator: BinaryOperator, right: Value) -> Option<Value> {
+    let arithmetic =
+        |apply: fn(f64, f64) -> f64| Some(Value::Number(apply(left.number()?, right.number()?)));
+
+    match operator {
+        // Adding text to anything that is not a number joins them.
+        BinaryOperator::Add => match (left.number(), right.number()) {
+            (Some(left), Some(right)) => Some(Value::Number(left + right)),
+            _ => Some(Value::Text(left.text() + right.text().as_str())),
+        },
+        BinaryOperator::Subtract => arithmetic(|left, right| left - right),
+        BinaryOperator::Multiply => arithmetic(|left, right| left * right),
+        BinaryOperator::Divide => arithmetic(|left, right| left / right),
+        BinaryOperator::Contains => Some(Value::Bool(left.text().contains(&right.text()))),
+        BinaryOperator::StartsWith => Some(Value::Bool(left.text().starts_with(&right.text()))),
+        BinaryOperator::EndsWith => Some(Value::Bool(left.text().ends_with(&right.text()))),
+        // Values that both read as numbers compare as numbers, anything else
+        // compares as text.
+        comparison => {
+            let ordering = match (left.number(), right.number()) {
+                (Some(left), Some(right)) => left.partial_cmp(&right)?,
+                _ => left.text().cmp(&right.text()),
+            };
+
+            Some(Value::Bool(match comparison {
+                BinaryOperator::Equal => ordering.is_eq(),
+                BinaryOperator::NotEqual => ordering.is_ne(),
+                BinaryOperator::Less => ordering.is_lt(),
+                BinaryOperator::LessOrEqual => ordering.is_le(),
+                BinaryOperator::Greater => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }))
+        }
+    }
+}
+
+fn call(function: Function, values: Vec<Value>, context: &ExprContext) -> Option<Value> {
+    let mut texts = values.into_iter().map(Value::text);
+    let first = texts.next()?;
+
+    let value = match function {
+        // Fields of the line are 1-based, split on whitespace unless a
+        // delimiter is given, and empty when the line has fewer of them.
+        Function::Field => {
+            let index = first
+                .trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|index| *index > 0)?;
+            let field = match texts.next() {
+                Some(delimiter) => context.line.split(delimiter.as_str()).nth(index - 1),
+                None => context.line.split_whitespace().nth(index - 1),
+            };
+
+            Value::Text(field.unwrap_or("").to_string())
+        }
+        Function::Upper => Value::Text(first.to_uppercase()),
+        Function::Lower => Value::Text(first.to_lowercase()),
+        Function::Trim => Value::Text(first.trim().to_string()),
+        Function::Len => Value::Number(first.chars().count() as f64),
+        Function::Replace => {
+            let from = texts.next()?;
+            let to = texts.next()?;
+            Value::Text(first.replace(&from, &to))
+        }
+        Function::Num => Value::Number(first.trim().parse().ok()?),
+    };
+
+    Some(value)
+}
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, (&'static str, usize)> {
+    let mut tokens = vec![];
+    let mut position = 0;
+
+    while position < source.len() {
+        let rest = &source[position..];
+        let c = rest.chars().next().unwrap();
+
+        if c.is_whitespace() {
+            position += c.len_utf8();
+        } else if c.is_ascii_digit()
+            || (c == '.' && rest[1..].starts_with(|d: char| d.is_ascii_digit()))
+        {
+            let length = rest
+                .find(|d: char| !d.is_ascii_digit() && d != '.')
+                .unwrap_or(rest.len());
+            let number = rest[..length]
+                .parse()
+                .map_err(|_| ("Invalid number", position))?;
+            tokens.push((Token::Number(number), position));
+            position += length;
+        } else if c == '\'' || c == '"' {
+            let mut text = String::new();
+            let mut chars = rest[1..].char_indices();
+            let length = loop {
+                match chars.next() {
+                    // Only the quote needs escaping, so regular expressions
+                    // keep their backslashes.
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) if escaped == c => text.push(escaped),
+                        Some((_, escaped)) => {
+                            text.push('\\');
+                            text.push(escaped);
+                        }
+                        None => return Err(("Unterminated string", position)),
+                    },
+                    Some((index, quote)) if quote == c => break index + 2,
+                    Some((_, other)) => text.push(other),
+                    None => return Err(("Unterminated string", position)),
+                }
+            };
+            tokens.push((Token::Text(text), position));
+            position += length;
+        } else if c.is_alphabetic() || c == '_' {
+            let length = rest
+                .find(|d: char| !d.is_alphanumeric() && d != '_')
+                .unwrap_or(rest.len());
+            tokens.push((Token::Identifier(rest[..length].to_string()), position));
+            position += length;
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(**symbol))
+                .ok_or(("Unexpected character", position))?;
+            tokens.push((Token::Symbol(symbol), position));
+            position += symbol.len();
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(Token, usize)],
+    position: usize,
+    end: usize,
+}
+
+type ParseResult = Result<Expr, (&'static str, usize)>;
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    // Where the next token starts, or the end of the source.
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .map_or(self.end, |(_, offset)| *offset)
+    }
+
+    fn accept(&mut self, accepted: &[&'static str]) -> Option<&'static str> {
+        let word = match self.peek()? {
+            Token::Symbol(symbol) => *symbol,
+            Token::Identifier(name) => name.as_str(),
+            _ => return None,
+        };
+        let word = accepted.iter().find(|candidate| **candidate == word)?;
+        self.position += 1;
+
+        Some(word)
+    }
+
+    fn expect(
+        &mut self,
+        symbol: &'static str,
+        message: &'static str,
+    ) -> Result<(), (&'static str, usize)> {
+        match self.accept(&[symbol]) {
+            Some(_) => Ok(()),
+            None => Err((message, self.offset())),
+        }
+    }
+
+    fn or(&mut self) -> ParseResult {
+        let mut left = self.and()?;
+        while self.accept(&["||", "or"]).is_some() {
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+
+        Ok(left)
+    }
+
+    fn and(&mut self) -> ParseResult {
+        let mut left = self.not()?;
+        while self.accept(&["&&", "and"]).is_some() {
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+
+        Ok(left)
+    }
+
+    fn not(&mut self) -> ParseResult {
+        if self.accept(&["!", "not"]).is_some() {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> ParseResult {
+        let left = self.additive()?;
+
+        let operator = match self.accept(&[
+            "==",
+            "!=",
+            "<=",
+            ">=",
+            "<",
+            ">",
+            "contains",
+            "startswith",
+            "endswith",
+            "matches",
+        ]) {
+            Some(operator) => operator,
+            None => return Ok(left),
+        };
+
+        if operator == "matches" {
+            let offset = self.offset();
+            return match self.peek() {
+                Some(Token::Text(pattern)) => {
+                    let regex =
+                        Regex::new(pattern).map_err(|_| ("Invalid regular expression", offset))?;
+                    self.position += 1;
+
+                    Ok(Expr::Matches(Box::new(left), regex))
+                }
+                _ => Err(("Expected a quoted regular expression", offset)),
+            };
+        }
+
+        let operator = match operator {
+            "==" => BinaryOperator::Equal,
+            "!=" => BinaryOperator::NotEqual,
+            "<" => BinaryOperator::Less,
+            "<=" => BinaryOperator::LessOrEqual,
+            ">" => BinaryOperator::Greater,
+            ">=" => BinaryOperator::GreaterOrEqual,
+            "contains" => BinaryOperator::Contains,
+            "startswith" => BinaryOperator::StartsWith,
+            _ => BinaryOperator::EndsWith,
+        };
+
+        Ok(Expr::Binary(
+            Box::new(left),
+            operator,
+            Box::new(self.additive()?),
+        ))
+    }
+
+    fn additive(&mut self) -> ParseResult {
+        let mut left = self.term()?;
+        while let Some(operator) = self.accept(&["+", "-"]) {
+            let operator = match operator {
+                "+" => BinaryOperator::Add,
+                _ => BinaryOperator::Subtract,
+            };
+            left = Expr::Binary(Box::new(left), operator, Box::new(self.term()?));
+        }
+
+        Ok(left)
+    }
+
+    fn term(&mut self) -> ParseResult {
+        let mut left = self.unary()?;
+        while let Some(operator) = self.accept(&["*", "/"]) {
+            let operator = match operator {
+                "*" => BinaryOperator::Multiply,
+                _ => BinaryOperator::Divide,
+            };
+            left = Expr::Binary(Box::new(left), operator, Box::new(self.unary()?));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> ParseResult {
+        if self.accept(&["-"]).is_some() {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> ParseResult {
+        let offset = self.offset();
+        let token = self.peek().ok_or(("Unexpected end", offset))?;
+        self.position += 1;
+
+        match token {
+            Token::Number(number) => Ok(Expr::Literal(Value::Number(*number))),
+            Token::Text(text) => Ok(Expr::Literal(Value::Text(text.clone()))),
+            Token::Symbol("(") => {
+                let inner = self.or()?;
+                self.expect(")", "Expected )")?;
+
+                Ok(inner)
+            }
+            Token::Symbol(_) => Err(("Unexpected token", offset)),
+            Token::Identifier(name) => match name.as_str() {
+                "line" => Ok(Expr::Line),
+                "len" if self.peek() != Some(&Token::Symbol("(")) => Ok(Expr::Length),
+                "n" => Ok(Expr::Number),
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                name => {
+                    let (function, least, most) =
+                        Function::parse(name).ok_or(("Unknown name", offset))?;
+                    self.expect("(", "Expected (")?;
+
+                    let mut arguments = vec![];
+                    if self.accept(&[")"]).is_none() {
+                        loop {
+                            arguments.push(self.or()?);
+                            if self.accept(&[")"]).is_some() {
+                                break;
+                            }
+                            self.expect(",", "Expected , or )")?;
+                        }
+                    }
+                    if arguments.len() < least || arguments.len() > most {
+                        return Err(("Wrong number of arguments", offset));
+                    }
+
+                    Ok(Expr::Call(function, arguments))
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Expr, ExprContext, Value};
+
+    fn evaluate(source: &str, line: &str) -> Option<Value> {
+        Expr::parse(source)
+            .unwrap()
+            .evaluate(&ExprContext { line, number: 7 })
+    }
+
+    #[test]
+    fn evaluate_filters_and_maps_lines() {
+        //+ Act + Assert
+        assert_eq!(
+            evaluate("len > 5 && line contains 'ERROR'", "an ERROR"),
+            Some(Value::Bool(true))
+        );
+        assert_eq!(
+            evaluate("len > 80 || !(line startswith 'an')", "an ERROR"),
+            Some(Value::Bool(false))
+        );
+        assert_eq!(
+            evaluate("upper(field(2, ','))", "a,b,c"),
+            Some(Value::Text("B".to_string()))
+        );
+        assert_eq!(
+            evaluate("field(2) * 2 + n", "x 21"),
+            Some(Value::Number(49.0))
+        );
+        assert_eq!(
+            evaluate("field(1) matches '^\\d+$' and field(2) == 10", "42 10.0"),
+            Some(Value::Bool(true))
+        );
+        assert_eq!(evaluate("num(line) / 0", "1"), None);
+    }
+
+    #[test]
+    fn parse_points_at_the_bad_token() {
+        //+ Act + Assert
+        assert_eq!(
+            Expr::parse("upper(line").err(),
+            Some("Expected , or ) in expression:\n    upper(line\n              ^".to_string())
+        );
+        assert_eq!(
+            Expr::parse("len > 80 && uper(line)").err(),
+            Some(
+                "Unknown name in expression:\n    len > 80 && uper(line)\n                ^"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            Expr::parse("replace(line, 'a')").err(),
+            Some(
+                "Wrong number of arguments in expression:\n    replace(line, 'a')\n    ^"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            Expr::parse("line contains 'x").err(),
+            Some(
+                "Unterminated string in expression:\n    line contains 'x\n                  ^"
+                    .to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,269 @@
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+}
+
+// Two-character operators come first so `<=` is not read as `<`.
+const OPERATORS: [(&str, Comparison); 7] = [
+    (">=", Comparison::GreaterOrEqual),
+    ("<=", Comparison::LessOrEqual),
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    ("=", Comparison::Equal),
+    (">", Comparison::Greater),
+    ("<", Comparison::Less),
+];
+
+// A condition on one named field, e.g. `status>=500` or `level=error`.
+// Values compare numerically when both sides are numbers and as text
+// otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldCondition {
+    field: String,
+    comparison: Comparison,
+    value: String,
+}
+
+// A condition on one delimited field, counted from 1 like awk's `$9`. A
+// single space as the delimiter splits on runs of whitespace, also like awk;
+// anything else splits on that exact text.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldFilter {
+    delimiter: String,
+    index: usize,
+    comparison: Comparison,
+    value: String,
+}
+
+// What the structured parsing steps (kv, syslog, accesslog) do with the
+// fields of a line: project them, or keep the line only when a condition
+// holds.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldQuery {
+    Get(Vec<String>),
+    Where(FieldCondition),
+}
+
+impl Comparison {
+    // Splits `<left><operator><right>` at the first operator.
+    pub fn split(source: &str) -> Option<(&str, Comparison, &str)> {
+        let start = source.find(['=', '!', '<', '>'])?;
+        let (left, rest) = source.split_at(start);
+
+        OPERATORS.iter().find_map(|(operator, comparison)| {
+            rest.strip_prefix(operator)
+                .map(|right| (left, *comparison, right))
+        })
+    }
+
+    pub fn parse(operator: &str) -> Result<Comparison, &'static str> {
+        OPERATORS
+            .iter()
+            .find(|(known, _)| *known == operator)
+            .map(|(_, comparison)| *comparison)
+            .ok_or("Unknown comparison operator")
+    }
+
+    // Numerically when both sides are numbers, as text otherwise.
+    pub fn compares(self, actual: &str, expected: &str) -> bool {
+        let ordering = match (actual.parse::<f64>(), expected.parse::<f64>()) {
+            (Ok(actual), Ok(expected)) => actual.partial_cmp(&expected),
+            _ => Some(actual.cmp(expected)),
+        };
+
+        ordering.is_some_and(|ordering| self.holds(ordering))
+    }
+
+    pub fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Less => ordering == Ordering::Less,
+            Comparison::LessOrEqual => ordering != Ordering::Greater,
+            Comparison::Greater => ordering == Ordering::Greater,
+            Comparison::GreaterOrEqual => ordering != Ordering::Less,
+            Comparison::Equal => ordering == Ordering::Equal,
+            Comparison::NotEqual => ordering != Ordering::Equal,
+        }
+    }
+}
+
+impl FieldCondition {
+    pub fn parse(source: &str) -> Result<FieldCondition, &'static str> {
+        match Comparison::split(source) {
+            Some((field, comparison, value)) if !field.trim().is_empty() => Ok(FieldCondition {
+                field: field.trim().to_string(),
+                comparison,
+                value: value.to_string(),
+            }),
+            _ => Err("Invalid field condition"),
+        }
+    }
+
+    pub fn matches(&self, fields: &[(String, String)]) -> bool {
+        let actual = match fields.iter().find(|(name, _)| *name == self.field) {
+            Some((_, actual)) => actual,
+            None => return false,
+        };
+
+        self.comparison.compares(actual, &self.value)
+    }
+}
+
+impl FieldFilter {
+    pub fn new(
+        delimiter: &str,
+        index: &str,
+        operator: &str,
+        value: &str,
+    ) -> Result<FieldFilter, &'static str> {
+        if delimiter.is_empty() {
+            return Err("Field delimiter cannot be empty");
+        }
+
+        Ok(FieldFilter {
+            delimiter: delimiter.to_string(),
+            index: index
+                .parse()
+                .ok()
+                .filter(|index| *index > 0)
+                .ok_or("Field numbers start at 1")?,
+            comparison: Comparison::parse(operator)?,
+            value: value.to_string(),
+        })
+    }
+
+    // Lines without the field never match.
+    pub fn matches(&self, line: &str) -> bool {
+        let field = match self.delimiter.as_str() {
+            " " => line.split_whitespace().nth(self.index - 1),
+            delimiter => line.split(delimiter).nth(self.index - 1),
+        };
+
+        field.is_some_and(|field| self.comparison.compares(field, &self.value))
+    }
+}
+
+impl FieldQuery {
+    // `get` renders the requested fields, in the requested order, as
+    // `key=value` pairs; `where` passes the line through untouched. None
+    // means the line is dropped.
+    pub fn apply(&self, line: &str, fields: &[(String, String)]) -> Option<String> {
+        match self {
+            FieldQuery::Get(names) => {
+                let selected: Vec<&(String, String)> = names
+                    .iter()
+                    .filter_map(|name| fields.iter().find(|(field, _)| field == name))
+                    .collect();
+
+                if selected.is_empty() {
+                    None
+                } else {
+                    Some(format_pairs(selected))
+                }
+            }
+            FieldQuery::Where(condition) => {
+                if condition.matches(fields) {
+                    Some(line.to_string())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+pub fn format_pairs<'a>(pairs: impl IntoIterator<Item = &'a (String, String)>) -> String {
+    let mut output = String::new();
+    for (key, value) in pairs {
+        if !output.is_empty() {
+            output.push(' ');
+        }
+        output.push_str(key);
+        output.push('=');
+
+        if value.is_empty() || value.contains([' ', '\t', '"', '=']) {
+            output.push('"');
+            output.push_str(&value.replace('\\', "\\\\").replace('"', "\\\""));
+            output.push('"');
+        } else {
+            output.push_str(value);
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FieldCondition, FieldFilter, FieldQuery};
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn condition_compares_numbers_and_text() {
+        //+ Arrange
+        let status = FieldCondition::parse("status>=500").unwrap();
+        let level = FieldCondition::parse("level!=debug").unwrap();
+
+        //+ Act + Assert
+        assert!(status.matches(&fields(&[("status", "503")])));
+        assert!(!status.matches(&fields(&[("status", "60")])));
+        assert!(!status.matches(&fields(&[("code", "503")])));
+        assert!(level.matches(&fields(&[("level", "info")])));
+        assert!(!level.matches(&fields(&[("level", "debug")])));
+        assert_eq!(
+            FieldCondition::parse(">=5").err(),
+            Some("Invalid field condition")
+        );
+    }
+
+    #[test]
+    fn field_filter_splits_like_awk() {
+        //+ Arrange
+        let status = FieldFilter::new(" ", "9", ">=", "500").unwrap();
+        let user = FieldFilter::new(",", "2", "==", "").unwrap();
+
+        //+ Act + Assert
+        assert!(
+            status.matches(r#"1.2.3.4 - - [01/May/2024:10:00:00 +0000] "GET / HTTP/1.1"  503 12"#)
+        );
+        assert!(
+            !status.matches(r#"1.2.3.4 - - [01/May/2024:10:00:00 +0000] "GET / HTTP/1.1" 200 12"#)
+        );
+        assert!(!status.matches("too short"));
+        assert!(user.matches("1,,x"));
+        assert!(!user.matches("1,ann,x"));
+        assert_eq!(
+            FieldFilter::new(" ", "0", ">", "1").err(),
+            Some("Field numbers start at 1")
+        );
+        assert_eq!(
+            FieldFilter::new(" ", "1", "=~", "1").err(),
+            Some("Unknown comparison operator")
+        );
+    }
+
+    #[test]
+    fn get_projects_and_quotes_fields() {
+        //+ Arrange
+        let query = FieldQuery::Get(vec!["msg".to_string(), "level".to_string()]);
+
+        //+ Act + Assert
+        assert_eq!(
+            query.apply("", &fields(&[("level", "warn"), ("msg", "disk \"full\"")])),
+            Some(r#"msg="disk \"full\"" level=warn"#.to_string())
+        );
+        assert_eq!(query.apply("", &fields(&[("other", "1")])), None);
+    }
+}
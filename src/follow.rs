@@ -0,0 +1,206 @@
+use std::{
+    collections::HashSet,
+    fs::{metadata, read_dir, File},
+    io::{Read, Result},
+    path::PathBuf,
+    thread::sleep,
+    time::Duration,
+};
+
+use glob::Pattern;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Reads a file like `tail -f`: at the end of the file it waits for more data
+// instead of returning EOF. A file that shrinks was truncated and one whose
+// path now names a different file was rotated; both are read again from the
+// start.
+pub struct Follow {
+    path: String,
+    file: File,
+    position: u64,
+}
+
+impl Follow {
+    pub fn open(path: &str) -> std::result::Result<Follow, String> {
+        let file = File::open(path).map_err(|_| format!("Could not open input file {}", path))?;
+
+        Ok(Follow {
+            path: path.to_string(),
+            file,
+            position: 0,
+        })
+    }
+
+    fn replaced(&self) -> bool {
+        let (Ok(current), Ok(open)) = (metadata(&self.path), self.file.metadata()) else {
+            return false;
+        };
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            if current.ino() != open.ino() || current.dev() != open.dev() {
+                return true;
+            }
+        }
+        #[cfg(not(unix))]
+        let _ = open;
+
+        current.len() < self.position
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                self.position += read as u64;
+                return Ok(read);
+            }
+
+            // While rotating, the path may briefly not exist; keep waiting.
+            if self.replaced() {
+                if let Ok(file) = File::open(&self.path) {
+                    self.file = file;
+                    self.position = 0;
+                    continue;
+                }
+            }
+
+            sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+// Reads the files that appear in a directory after it started watching, in
+// name order, so hourly rotation output is picked up as it lands. The newest
+// file is followed until a newer one shows up, since it may still be written.
+pub struct Watch {
+    directory: String,
+    pattern: Pattern,
+    seen: HashSet<PathBuf>,
+    current: Option<File>,
+}
+
+impl Watch {
+    pub fn open(directory: &str, pattern: &str) -> std::result::Result<Watch, String> {
+        let mut watch = Watch {
+            directory: directory.to_string(),
+            pattern: Pattern::new(pattern)
+                .map_err(|_| format!("Invalid glob pattern {}", pattern))?,
+            seen: HashSet::new(),
+            current: None,
+        };
+        watch.seen = watch
+            .matching_files()
+            .map_err(|_| format!("Could not watch directory {}", directory))?
+            .into_iter()
+            .collect();
+
+        Ok(watch)
+    }
+
+    fn matching_files(&self) -> Result<Vec<PathBuf>> {
+        let mut files = vec![];
+        for entry in read_dir(&self.directory)? {
+            let path = entry?.path();
+            let matches = path
+                .file_name()
+                .is_some_and(|name| self.pattern.matches(&name.to_string_lossy()));
+            if matches && path.is_file() {
+                files.push(path);
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn next_new_file(&mut self) -> Result<Option<PathBuf>> {
+        let next = self
+            .matching_files()?
+            .into_iter()
+            .filter(|path| !self.seen.contains(path))
+            .min();
+        if let Some(path) = &next {
+            self.seen.insert(path.clone());
+        }
+
+        Ok(next)
+    }
+}
+
+impl Read for Watch {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if let Some(file) = self.current.as_mut() {
+                let read = file.read(buf)?;
+                if read > 0 || buf.is_empty() {
+                    return Ok(read);
+                }
+            }
+
+            match self.next_new_file()? {
+                Some(path) => self.current = Some(File::open(path)?),
+                None => sleep(POLL_INTERVAL),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::{Follow, Watch};
+
+    fn read_available(reader: &mut impl Read) -> String {
+        let mut buffer = [0; 64];
+        let read = reader.read(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..read]).into_owned()
+    }
+
+    #[test]
+    fn read_resumes_after_rotation_and_truncation() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-follow-{}.log", std::process::id()));
+        std::fs::write(&path, "first line\n").unwrap();
+        let mut follow = Follow::open(path.to_str().unwrap()).unwrap();
+
+        //+ Act
+        let first = read_available(&mut follow);
+
+        std::fs::rename(&path, path.with_extension("log.1")).unwrap();
+        std::fs::write(&path, "rotated\n").unwrap();
+        let rotated = read_available(&mut follow);
+
+        std::fs::write(&path, "cut\n").unwrap();
+        let truncated = read_available(&mut follow);
+
+        //+ Assert
+        assert_eq!(first, "first line\n");
+        assert_eq!(rotated, "rotated\n");
+        assert_eq!(truncated, "cut\n");
+    }
+
+    #[test]
+    fn watch_reads_only_new_matching_files() {
+        //+ Arrange
+        let directory = std::env::temp_dir().join(format!("rangler-watch-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("00.log"), "existing\n").unwrap();
+        let mut watch = Watch::open(directory.to_str().unwrap(), "*.log").unwrap();
+
+        //+ Act
+        std::fs::write(directory.join("01.txt"), "ignored\n").unwrap();
+        std::fs::write(directory.join("02.log"), "second\n").unwrap();
+        std::fs::write(directory.join("01.log"), "first\n").unwrap();
+        let first = read_available(&mut watch);
+        let second = read_available(&mut watch);
+
+        //+ Assert
+        assert_eq!(first, "first\n");
+        assert_eq!(second, "second\n");
+    }
+}
@@ -0,0 +1,147 @@
+use std::{fmt, net::IpAddr};
+
+use maxminddb::{geoip2, Reader};
+use regex::Regex;
+
+use crate::{fields::format_pairs, step::Step};
+
+// Appends where the first IP address in a line is from, looked up in a local
+// MaxMind database, as `country=US asn=15169 as_org="Google LLC"`. Country,
+// City and ASN databases all work; whatever the database knows is appended.
+// Lines without an address it knows pass through untouched.
+pub struct GeoIp {
+    path: String,
+    reader: Reader<Vec<u8>>,
+    size: usize,
+    addresses: Regex,
+}
+
+impl GeoIp {
+    pub fn load(path: &str) -> Result<GeoIp, &'static str> {
+        let bytes = std::fs::read(path).map_err(|_| "Could not load GeoIP database")?;
+        let size = bytes.len();
+        let reader = Reader::from_source(bytes).map_err(|_| "Could not load GeoIP database")?;
+
+        Ok(GeoIp {
+            path: path.to_string(),
+            reader,
+            size,
+            // Candidates only; anything that does not parse as an address,
+            // like a time of day, is skipped.
+            addresses: Regex::new(
+                r"\b(?:\d{1,3}\.){3}\d{1,3}\b|(?:[0-9A-Fa-f]{0,4}:){2,7}[0-9A-Fa-f]{0,4}",
+            )
+            .unwrap(),
+        })
+    }
+
+    fn describe(&self, address: IpAddr) -> Option<Vec<(String, String)>> {
+        let mut fields = vec![];
+
+        let country = self
+            .reader
+            .lookup::<geoip2::Country>(address)
+            .ok()
+            .flatten()
+            .and_then(|found| found.country)
+            .and_then(|country| country.iso_code);
+        if let Some(country) = country {
+            fields.push(("country".to_string(), country.to_string()));
+        }
+
+        if let Some(asn) = self.reader.lookup::<geoip2::Asn>(address).ok().flatten() {
+            if let Some(number) = asn.autonomous_system_number {
+                fields.push(("asn".to_string(), number.to_string()));
+            }
+            if let Some(organization) = asn.autonomous_system_organization {
+                fields.push(("as_org".to_string(), organization.to_string()));
+            }
+        }
+
+        (!fields.is_empty()).then_some(fields)
+    }
+}
+
+impl Step for GeoIp {
+    fn apply(&mut self, line: String) -> Result<Vec<String>, &'static str> {
+        let found = self
+            .addresses
+            .find_iter(&line)
+            .filter_map(|candidate| candidate.as_str().parse::<IpAddr>().ok())
+            .find_map(|address| self.describe(address));
+
+        Ok(vec![match found {
+            Some(fields) => format!("{} {}", line, format_pairs(&fields)),
+            None => line,
+        }])
+    }
+
+    fn memory(&self) -> usize {
+        self.size
+    }
+}
+
+impl fmt::Debug for GeoIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeoIp").field("path", &self.path).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GeoIp;
+    use crate::step::Step;
+
+    // A MaxMind database by hand: one search tree node sending 0.0.0.0/1 to a
+    // record and 128.0.0.0/1 nowhere, the record, and the metadata.
+    fn database() -> Vec<u8> {
+        let mut bytes = vec![0x00, 0x00, 0x11, 0x00, 0x00, 0x01];
+        bytes.extend([0; 16]);
+
+        bytes.push(0xe3);
+        bytes.extend(b"\x47country\xe1\x48iso_code\x42US");
+        bytes.extend(b"\x58autonomous_system_number\xc2\x3b\x41");
+        bytes.extend(b"\x5d\x01autonomous_system_organization\x4aGoogle LLC");
+
+        bytes.extend(b"\xab\xcd\xefMaxMind.com");
+        bytes.push(0xe9);
+        bytes.extend(b"\x4anode_count\xc1\x01");
+        bytes.extend(b"\x4brecord_size\xa1\x18");
+        bytes.extend(b"\x4aip_version\xa1\x04");
+        bytes.extend(b"\x4ddatabase_type\x44Test");
+        bytes.extend(b"\x49languages\x00\x04");
+        bytes.extend(b"\x5bbinary_format_major_version\xa1\x02");
+        bytes.extend(b"\x5bbinary_format_minor_version\xa0");
+        bytes.extend(b"\x4bbuild_epoch\x00\x02");
+        bytes.extend(b"\x4bdescription\xe0");
+
+        bytes
+    }
+
+    #[test]
+    fn apply_appends_what_the_database_knows() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-geoip-{}.mmdb", std::process::id()));
+        std::fs::write(&path, database()).unwrap();
+        let mut step = GeoIp::load(path.to_str().unwrap()).unwrap();
+
+        //+ Act
+        let known = step.apply("10:00:00 GET / from 8.8.8.8:443".to_string());
+        let unknown = step.apply("GET / from 200.1.1.1".to_string());
+        std::fs::remove_file(path).unwrap();
+
+        //+ Assert
+        assert_eq!(
+            known,
+            Ok(vec![
+                r#"10:00:00 GET / from 8.8.8.8:443 country=US asn=15169 as_org="Google LLC""#
+                    .to_string()
+            ])
+        );
+        assert_eq!(unknown, Ok(vec!["GET / from 200.1.1.1".to_string()]));
+        assert_eq!(
+            GeoIp::load("/nonexistent/rangler.mmdb").err(),
+            Some("Could not load GeoIP database")
+        );
+    }
+}
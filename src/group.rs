@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+use regex::Regex;
+
+use crate::calc::format_number;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Mean,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Stats {
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+// Aggregates per key once the input ends, emitting `<key> <result>` in key
+// order. The key comes from the `key` named group (or the first group) and
+// the value from the `value` named group (or the second group). Lines that do
+// not match, or whose value is not a number, are left out.
+#[derive(Debug)]
+pub struct GroupBy {
+    regex: Regex,
+    aggregate: Aggregate,
+    groups: BTreeMap<String, Stats>,
+}
+
+impl Aggregate {
+    pub fn parse(name: &str) -> Result<Aggregate, &'static str> {
+        match name.to_lowercase().as_str() {
+            "count" => Ok(Aggregate::Count),
+            "sum" => Ok(Aggregate::Sum),
+            "min" => Ok(Aggregate::Min),
+            "max" => Ok(Aggregate::Max),
+            "mean" | "avg" => Ok(Aggregate::Mean),
+            _ => Err("Unknown aggregate"),
+        }
+    }
+}
+
+impl GroupBy {
+    pub fn new(regex: Regex, aggregate: Aggregate) -> Result<GroupBy, &'static str> {
+        let groups = regex.captures_len() - 1;
+        let needed = if aggregate == Aggregate::Count { 1 } else { 2 };
+        if groups < needed {
+            return Err("Group regex needs a key and a value capture group");
+        }
+
+        Ok(GroupBy {
+            regex,
+            aggregate,
+            groups: BTreeMap::new(),
+        })
+    }
+
+    pub fn push(&mut self, line: &str) {
+        let captures = match self.regex.captures(line) {
+            Some(captures) => captures,
+            None => return,
+        };
+
+        let key = match captures.name("key").or(captures.get(1)) {
+            Some(key) => key.as_str(),
+            None => return,
+        };
+
+        let value = if self.aggregate == Aggregate::Count {
+            0.0
+        } else {
+            match captures
+                .name("value")
+                .or(captures.get(2))
+                .and_then(|value| value.as_str().trim().parse::<f64>().ok())
+            {
+                Some(value) => value,
+                None => return,
+            }
+        };
+
+        let stats = self.groups.entry(key.to_string()).or_insert(Stats {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        });
+        stats.count += 1;
+        stats.sum += value;
+        stats.min = stats.min.min(value);
+        stats.max = stats.max.max(value);
+    }
+
+    pub fn flush(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.groups)
+            .into_iter()
+            .map(|(key, stats)| {
+                let result = match self.aggregate {
+                    Aggregate::Count => stats.count.to_string(),
+                    Aggregate::Sum => format_number(stats.sum),
+                    Aggregate::Min => format_number(stats.min),
+                    Aggregate::Max => format_number(stats.max),
+                    Aggregate::Mean => format_number(stats.sum / stats.count as f64),
+                };
+
+                format!("{} {}", key, result)
+            })
+            .collect()
+    }
+
+    pub fn memory(&self) -> usize {
+        self.groups
+            .keys()
+            .map(|key| key.len() + std::mem::size_of::<Stats>())
+            .sum()
+    }
+}
+
+impl PartialEq for GroupBy {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+            && self.aggregate == other.aggregate
+            && self.groups == other.groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::{Aggregate, GroupBy};
+
+    #[test]
+    fn flush_aggregates_per_key() {
+        //+ Arrange
+        let regex = Regex::new(r"(?P<value>\S+)ms (?P<key>\w+)").unwrap();
+        let mut mean = GroupBy::new(regex.clone(), Aggregate::Mean).unwrap();
+        let mut max = GroupBy::new(regex, Aggregate::Max).unwrap();
+
+        //+ Act
+        for line in ["10ms GET", "2.5ms POST", "20ms GET", "xms GET", "noise"] {
+            mean.push(line);
+            max.push(line);
+        }
+
+        //+ Assert
+        assert_eq!(
+            mean.flush(),
+            vec!["GET 15".to_string(), "POST 2.5".to_string()]
+        );
+        assert_eq!(
+            max.flush(),
+            vec!["GET 20".to_string(), "POST 2.5".to_string()]
+        );
+    }
+
+    #[test]
+    fn new_requires_value_group_for_numeric_aggregates() {
+        //+ Act + Assert
+        assert!(GroupBy::new(Regex::new(r"(\w+)").unwrap(), Aggregate::Count).is_ok());
+        assert_eq!(
+            GroupBy::new(Regex::new(r"(\w+)").unwrap(), Aggregate::Sum).err(),
+            Some("Group regex needs a key and a value capture group")
+        );
+        assert_eq!(Aggregate::parse("median"), Err("Unknown aggregate"));
+    }
+}
@@ -0,0 +1,152 @@
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    XxHash,
+}
+
+impl HashAlgorithm {
+    pub fn parse(name: &str) -> Result<HashAlgorithm, &'static str> {
+        match name.to_lowercase().as_str() {
+            "md5" => Ok(HashAlgorithm::Md5),
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            "xxhash" => Ok(HashAlgorithm::XxHash),
+            _ => Err("Unknown hash algorithm"),
+        }
+    }
+
+    pub fn digest(&self, input: &str) -> String {
+        self.digest_bytes(input.as_bytes())
+    }
+
+    pub fn digest_bytes(&self, input: &[u8]) -> String {
+        match self {
+            HashAlgorithm::Md5 => to_hex(&Md5::digest(input)),
+            HashAlgorithm::Sha1 => to_hex(&Sha1::digest(input)),
+            HashAlgorithm::Sha256 => to_hex(&Sha256::digest(input)),
+            HashAlgorithm::XxHash => format!("{:016x}", xxhash_rust::xxh3::xxh3_64(input)),
+        }
+    }
+
+    pub fn stream(&self) -> StreamDigest {
+        match self {
+            HashAlgorithm::Md5 => StreamDigest::Md5(Md5::new()),
+            HashAlgorithm::Sha1 => StreamDigest::Sha1(Sha1::new()),
+            HashAlgorithm::Sha256 => StreamDigest::Sha256(Sha256::new()),
+            HashAlgorithm::XxHash => StreamDigest::XxHash(Box::default()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Md5 => "md5",
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::XxHash => "xxhash",
+        }
+    }
+}
+
+// A digest fed piece by piece, for hashing output as it is written rather
+// than holding all of it. Finishing gives the same hex as `digest_bytes`
+// over the concatenated pieces.
+#[derive(Clone)]
+pub enum StreamDigest {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    XxHash(Box<xxhash_rust::xxh3::Xxh3>),
+}
+
+impl StreamDigest {
+    pub fn update(&mut self, bytes: &[u8]) {
+        match self {
+            StreamDigest::Md5(digest) => digest.update(bytes),
+            StreamDigest::Sha1(digest) => digest.update(bytes),
+            StreamDigest::Sha256(digest) => digest.update(bytes),
+            StreamDigest::XxHash(digest) => digest.update(bytes),
+        }
+    }
+
+    pub fn finish(self) -> String {
+        match self {
+            StreamDigest::Md5(digest) => to_hex(&digest.finalize()),
+            StreamDigest::Sha1(digest) => to_hex(&digest.finalize()),
+            StreamDigest::Sha256(digest) => to_hex(&digest.finalize()),
+            StreamDigest::XxHash(digest) => format!("{:016x}", digest.digest()),
+        }
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        hex.push_str(&format!("{:02x}", byte));
+    }
+
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HashAlgorithm;
+
+    #[test]
+    fn digest_matches_known_values() {
+        //+ Arrange
+        let input = "abc";
+
+        //+ Act + Assert
+        assert_eq!(
+            HashAlgorithm::Md5.digest(input),
+            "900150983cd24fb0d6963f7d28e17f72"
+        );
+        assert_eq!(
+            HashAlgorithm::Sha1.digest(input),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            HashAlgorithm::Sha256.digest(input),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(HashAlgorithm::XxHash.digest(input).len(), 16);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_algorithm() {
+        //+ Act
+        let algorithm = HashAlgorithm::parse("crc32");
+
+        //+ Assert
+        assert_eq!(algorithm, Err("Unknown hash algorithm"));
+    }
+
+    #[test]
+    fn stream_matches_digest_of_the_whole_input() {
+        //+ Arrange
+        let pieces: [&[u8]; 3] = [b"first line\n", b"", b"second line\n"];
+        let whole = pieces.concat();
+
+        for algorithm in [
+            HashAlgorithm::Md5,
+            HashAlgorithm::Sha1,
+            HashAlgorithm::Sha256,
+            HashAlgorithm::XxHash,
+        ] {
+            //+ Act
+            let mut stream = algorithm.stream();
+            for piece in pieces {
+                stream.update(piece);
+            }
+
+            //+ Assert
+            assert_eq!(stream.finish(), algorithm.digest_bytes(&whole));
+        }
+    }
+}
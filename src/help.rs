@@ -0,0 +1,160 @@
+/// What `rangler help <command>` shows for one pipeline command.
+#[derive(Debug, PartialEq)]
+pub struct CommandHelp {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub about: &'static str,
+    pub example: &'static str,
+}
+
+const fn command(
+    name: &'static str,
+    usage: &'static str,
+    about: &'static str,
+    example: &'static str,
+) -> CommandHelp {
+    CommandHelp {
+        name,
+        usage,
+        about,
+        example,
+    }
+}
+
+/// Every built-in pipeline command, in the order the usage text lists them.
+pub static COMMANDS: &[CommandHelp] = &[
+    command("filter", "filter [--fancy] [-i] [-m] [-s] [-F] [-o] <regex>", "excludes lines that do not match; -i ignores case, -m lets ^ and $ match at line breaks inside a record and -s lets . match them, as do the inline (?i), (?m) and (?s), -F matches the pattern as a fixed string and -o passes on each match as a line of its own instead of the whole line; patterns with lookaround or backreferences, or any with --fancy, use the slower fancy-regex backend in builds with the fancy feature", "rangler filter -i 'error|warn' < app.log"),
+    command("filter-any", "filter-any [-i] [-m] [-s] <regex> [<regex> ...] end", "keeps lines that match any of the patterns, checked in one pass", "rangler filter-any ERROR FATAL 'status=5\\d\\d' end < app.log"),
+    command("filter-all", "filter-all [-i] [-m] [-s] <regex> [<regex> ...] end", "keeps lines that match every one of the patterns, checked in one pass", "rangler filter-all disk full end < app.log"),
+    command("append", "append <quoted string>", "appends the text in quotes to every line, filling in {file}, {host} and {n} with where the line came from, ${VAR} from the environment (left as written when unset; $${ writes a literal ${) and strftime tokens like %Y-%m-%d with the date the run started, which stays the same for the whole run, even under --follow or --listen, so use timestamp for each line's own time", "rangler append ' ({file}:{n})' -- app.log worker.log"),
+    command("pad-left", "pad-left <width> <char>", "pads lines narrower than the width, in terminal columns so CJK and emoji count double, with the character on the left, up to 65536 columns; wider lines are left alone", "rangler pad-left 8 0 < ids.txt"),
+    command("pad-right", "pad-right <width> <char>", "pads lines narrower than the width, in terminal columns so CJK and emoji count double, with the character on the right, up to 65536 columns; wider lines are left alone", "rangler pad-right 20 ' ' < labels.txt"),
+    command("prepend", "prepend <quoted string>", "prepends the text in quotes to every line, filling in {file}, {host}, {n}, ${VAR} and dates like append", "rangler prepend '- ' < list.txt"),
+    command("trim", "trim", "removes whitespace at both ends of every line", "rangler trim < padded.txt"),
+    command("ltrim", "ltrim", "removes whitespace at the start of every line", "rangler ltrim < indented.txt"),
+    command("rtrim", "rtrim", "removes whitespace at the end of every line", "rangler rtrim < padded.txt"),
+    command("trim-chars", "trim-chars <set> [--left | --right]", "removes the characters in the set from both ends of every line, or one end; sets accept ranges like a-z", "rangler trim-chars '\",' --right < values.txt"),
+    command("lower", "lower", "converts English letters to lower case", "rangler lower < names.txt"),
+    command("upper", "upper", "converts English letters to upper case", "rangler upper < names.txt"),
+    command("minlen", "minlen <length> [--bytes]", "excludes lines shorter than length characters (or bytes)", "rangler minlen 8 < passwords.txt"),
+    command("maxlen", "maxlen <length> [--bytes]", "excludes lines longer than length characters (or bytes)", "rangler maxlen 1024 --bytes < app.log"),
+    command("dedupe", "dedupe [--recent <count> | --window <lines> | --approx <expected count> <false positive rate> | --spill <memory limit> | --state <file> | --count] [--key-transform <lower,upper,trim,squeeze,ascii>]", "dedupes lines, optionally remembering only recent ones, only the last lines read so bursts of repeats collapse but come back later, using a Bloom filter, spilling to disk, or remembering lines from earlier runs in a state file; --key-transform compares a normalized key but keeps the first line as written; --count holds the lines until the end of input and emits each with how often it occurred, in the order first seen", "rangler dedupe --recent 10000 < events.log"),
+    command("hash", "hash <md5|sha1|sha256|xxhash> [--append]", "replaces every line with its digest, or appends it", "rangler hash sha256 --append < emails.txt"),
+    command("base64", "base64 <encode|decode> [--url] [--on-error skip|pass|annotate|error]", "encodes or decodes every line", "rangler base64 decode --on-error pass < tokens.txt"),
+    command("urlencode", "urlencode", "percent-encodes every line", "rangler urlencode < queries.txt"),
+    command("urldecode", "urldecode [--on-error skip|pass|annotate|error]", "decodes percent-encoded lines", "rangler urldecode < access.log"),
+    command("calc", "calc <expression> [--delimiter <text>] [--on-error skip|pass|annotate|error]", "evaluates arithmetic over fields, e.g. 'f3 = f1 / f2 * 100' or '{1} + {2}'", "rangler calc 'f3 = f1 / f2 * 100' --delimiter , < stats.csv"),
+    command("where", "where <expression>", "keeps lines the expression holds for, e.g. \"len > 80 && line contains 'ERROR'\"; has line, len, n, field(i[, delim]), upper, lower, trim, len(), replace, num, contains, startswith, endswith and matches", "rangler where \"len > 80 && line contains 'ERROR'\" < app.log"),
+    command("where-field", "where-field <delimiter> <field> <op> <value>", "keeps lines whose field, counted from 1 like awk's $1, compares true with the value; ops are = == != < <= > >=, numbers compare as numbers, and ' ' splits on runs of whitespace", "rangler where-field ' ' 9 '>=' 500 < access.log"),
+    command("map", "map <expression> [--on-error skip|pass|annotate|error]", "replaces the line with the expression's value, e.g. \"upper(field(2, ','))\"", "rangler map \"upper(field(2, ','))\" < users.csv"),
+    command("tee", "tee <file|stderr>", "writes every line it sees to a file and passes it along unchanged", "rangler filter ERROR tee errors.log dedupe < app.log"),
+    command("tee-pipeline", "tee-pipeline <file|stderr|drop> [commands] end", "runs its own commands on a copy of every line, writing what they emit to a sink, and passes the line along unchanged", "rangler tee-pipeline error-counts.txt filter ERROR group-by 'code=(\\w+)' count end trim < app.log"),
+    command("exec", "exec <shell command> [--coprocess] [--on-error skip|pass|annotate|error]", "pipes every line through a command, or through one long-lived process that answers each line with one line", "rangler exec 'rev' < words.txt"),
+    command("format", "format <template>", "rewrites every line from a template using {line}, {n}, {len}, {file}, {host} and {1} or {name} groups of the last filter; ${VAR} and strftime tokens are filled in when the run starts", "rangler filter '(?P<user>\\w+)@' format '{n}: {user}' < emails.txt"),
+    command("dateparse", "dateparse <iso|rfc2822|clf|strftime format> <iso|strftime format> [--on-error skip|pass|annotate|error]", "rewrites the first timestamp in every line", "rangler dateparse clf iso < access.log"),
+    command("timestamp", "timestamp [--format <iso|strftime format> | --elapsed] [--separator <text>]", "prefixes every line with the local time it went through, or the seconds since the first line, like ts", "rangler timestamp --format '%H:%M:%S%.3f' < events.log"),
+    command("uuid", "uuid [--v5 <dns|url|oid|x500|uuid>] [--append] [--separator <text>]", "prefixes every line with a random UUIDv4, or with a UUIDv5 named by the line in the namespace so the same line always gets the same id", "rangler uuid --v5 url --append --separator , < rows.csv"),
+    command("ulid", "ulid [--append] [--separator <text>]", "prefixes every line with a ULID, which sorts by the time the line went through", "rangler ulid < events.log"),
+    command("humanize-epoch", "humanize-epoch [--format <iso|strftime format>] [--relative]", "replaces 10 and 13 digit epoch timestamps with dates, or with \"3h ago\"", "rangler humanize-epoch --relative < events.log"),
+    command("since", "since <datetime> [--format <input>] [--on-error skip|pass|annotate|error]", "keeps lines timestamped at or after datetime", "rangler since 2024-05-01T00:00:00Z < app.log"),
+    command("until", "until <datetime> [--format <input>] [--on-error skip|pass|annotate|error]", "keeps lines timestamped before datetime", "rangler until 2024-05-02T00:00:00Z < app.log"),
+    command("per-window", "per-window <duration> count [--by <regex>] [--format <input>]", "counts lines per time window (e.g. 1m), optionally per key, at the end of input", "rangler per-window 5m count --by 'status=(\\d+)' < app.log"),
+    command("count-distinct", "count-distinct [--approx]", "emits only the number of distinct lines at the end of input, keeping a hash per distinct line, or with --approx a fixed 16 KiB HyperLogLog sketch good to about 1%", "rangler json .user count-distinct --approx < events.jsonl"),
+    command("top", "top <k> [--approx <counters>]", "emits the k most frequent lines with counts at the end of input; --approx bounds memory", "rangler top 10 < ips.txt"),
+    command("top-by", "top-by <k> <field|regex> [--smallest]", "emits the k lines with the largest number in a whitespace-separated field or a regex's first group at the end of input, holding only k lines; --smallest keeps the smallest instead", "rangler top-by 20 'rt=(\\S+)' < access.log"),
+    command("group-by", "group-by <regex> count|sum|min|max|mean", "aggregates the value group per key group at the end of input", "rangler group-by '(?P<key>\\w+) (?P<value>\\d+)' sum < sales.txt"),
+    command("stats", "stats <count|sum|min|max|mean|median|p<percent>,...> [--key <field|regex>]", "summarizes the number each line (or its key) starts with and emits one name=value line at the end of input; percentiles come from a t-digest, so memory stays bounded", "rangler stats p50,p95,p99 --key 'rt=(\\S+)' < access.log"),
+    command("first-per-key", "first-per-key <regex>", "keeps the first line for each key (first capture group or whole match)", "rangler first-per-key 'user=(\\w+)' < app.log"),
+    command("last-per-key", "last-per-key <regex>", "keeps the last line for each key, emitted at the end of input", "rangler last-per-key 'user=(\\w+)' < app.log"),
+    command("throttle", "throttle <lines per second>", "delays lines to cap throughput", "rangler throttle 100 < requests.txt"),
+    command("sample", "sample <rate>", "keeps each line with the given probability, e.g. 0.01 for about one in a hundred; --seed makes the picks repeatable", "rangler --seed 7 sample 0.01 < events.log"),
+    command("shuffle", "shuffle", "buffers all lines and emits them in a random order at the end of input; --seed makes the order repeatable", "rangler --seed 7 shuffle < lines.txt"),
+    command("sort", "sort [--numeric | --natural] [--reverse] [--key <field|regex>]", "buffers all lines and emits them sorted at the end of input; --numeric compares the number a key starts with, --natural compares digit runs by value so file2 comes before file10, --key picks a whitespace-separated field by number or a regex's first group, and ties keep their input order", "rangler sort --numeric --reverse --key 'rt=(\\S+)' < access.log"),
+    command("chunk", "chunk <size> [--separator <text> | --join <delimiter>]", "emits a separator line (blank by default) between every size lines, or joins them", "rangler chunk 3 --join , < ids.txt"),
+    command("align", "align <delimiter>", "buffers all lines and pads the delimited columns to line up, like column -t", "rangler align , < table.csv"),
+    command("csv-select", "csv-select <column,...> [--delimiter <char>]", "keeps the named CSV columns, using the first line as the header", "rangler csv-select name,email < users.csv"),
+    command("csv-where", "csv-where <column=value|column!=value> [--delimiter <char>]", "keeps the CSV header and the rows whose column matches", "rangler csv-where country=NZ < users.csv"),
+    command("json", "json <path> [--on-error skip|pass|annotate|error]", "replaces every JSON line with the value at a path like .request.headers[\"user-agent\"]", "rangler json .request.path < events.jsonl"),
+    command("json-pretty", "json-pretty [--on-error skip|pass|annotate|error]", "rewrites every JSON line indented over several lines, keeping its key order", "rangler filter timeout json-pretty < events.jsonl"),
+    command("json-compact", "json-compact [--on-error skip|pass|annotate|error]", "rewrites every JSON line on one line without any whitespace", "rangler json-compact --on-error pass < events.jsonl"),
+    command("json-filter", "json-filter <path> <regex|op number>", "keeps JSON lines whose value at path matches, e.g. .status '>=500'", "rangler json-filter .status '>=500' < events.jsonl"),
+    command("jsonl2csv", "jsonl2csv [--columns <a,b,...>] [--delimiter <char>]", "converts JSON objects to CSV rows, header from the first object unless given", "rangler jsonl2csv --columns id,status < events.jsonl"),
+    command("csv2jsonl", "csv2jsonl [--delimiter <char>]", "converts CSV rows to JSON objects keyed by the header row", "rangler csv2jsonl < users.csv"),
+    command("kv", "kv get <key,...> | kv where <key><op><value>", "projects logfmt key=value fields, or keeps lines where a field compares (=, !=, <, <=, >, >=)", "rangler kv where 'duration>250' kv get path,duration < app.log"),
+    command("syslog", "syslog get <field,...> | syslog where <field><op><value>", "parses RFC 3164/5424 lines into facility, severity, timestamp, host, app, pid, msgid and message", "rangler syslog where 'severity<=3' < /var/log/syslog"),
+    command("accesslog", "accesslog get <field,...> | accesslog where <field><op><value>", "parses Common/Combined log lines into ip, user, timestamp, method, path, protocol, status, bytes, referer, user_agent and latency", "rangler accesslog where 'status>=500' accesslog get ip,path < access.log"),
+    command("redact", "redact [--only <email,ipv4,ipv6,card,bearer>] [--pattern <regex>]... [--hash]", "masks PII with [REDACTED], or a stable digest with --hash", "rangler redact --only email,ipv4 < app.log"),
+    command("diff", "diff <reference-file>", "emits +line for lines not in the file and, at the end, -line for file lines never seen", "rangler diff yesterday.txt < today.txt"),
+    command("filter-literals", "filter-literals [-i] <file>", "keeps lines containing any of the literal strings in the file, one per line, found in a single pass however many there are; -i ignores ASCII case", "rangler filter-literals indicators.txt < dns.log"),
+    command("records", "records [-i] [-m] [-s] [-F] <regex>", "splits each record wherever the pattern matches, every non-empty piece going through the rest of the pipeline as a record of its own; -F splits on a fixed string", "rangler json .items records '^\\[\\{|\\},\\{|\\}\\]$' prepend '{' append '}' < orders.jsonl"),
+    command("only-duplicates", "only-duplicates [--once]", "keeps only lines that were seen before, the opposite of dedupe; --once passes on just the second copy, listing each duplicated line once", "rangler only-duplicates --once < export.csv"),
+    command("only-in", "only-in <file>", "keeps lines that appear in the file", "rangler only-in allowed.txt < users.txt"),
+    command("not-in", "not-in <file>", "keeps lines that do not appear in the file", "rangler not-in blocked.txt < users.txt"),
+    command("lookup", "lookup <mapping-file> [--key <regex>] [--delimiter <char>] [--annotate] [--on-miss pass|drop|default <value>]", "replaces keys with values from a two-column CSV/TSV file, or appends them with --annotate", "rangler lookup hosts.csv --key 'host=(\\S+)' --annotate < app.log"),
+    command("every", "every <n> <text>", "inserts a line of text after every n lines, e.g. to mark chunks or end SQL batches; the text fills in dates and ${VAR} like append", "rangler --header 'BEGIN;' --footer 'COMMIT;' every 1000 'COMMIT; BEGIN;' < inserts.sql"),
+    command("tr", "tr <set1> <set2> | tr <set> --delete", "translates or deletes characters; sets accept ranges like a-z", "rangler tr a-z A-Z < names.txt"),
+    command("cols", "cols <ranges> [--bytes] [--delimiter <text>]", "keeps character (or byte) column ranges like cut -c, e.g. 1-10,25-40, 30- or -4, joined on the delimiter, for fixed-width output such as ps or mainframe files", "rangler cols 1-8,66- --delimiter ' ' < processes.txt"),
+    command("normalize", "normalize nfc|nfd|nfkc|nfkd", "applies Unicode normalization so equivalent text compares equal", "rangler normalize nfc dedupe < names.txt"),
+    command("ascii", "ascii", "transliterates Unicode text to plain ASCII, e.g. é to e and ß to ss", "rangler ascii < names.txt"),
+    command("strip-html", "strip-html [--squeeze]", "removes HTML and XML tags, comments and script or style content and decodes entities like &amp;, leaving plain text; --squeeze collapses the whitespace left behind", "rangler json .body strip-html --squeeze dedupe < pages.jsonl"),
+    command("unescape-html", "unescape-html", "decodes HTML character references like &amp;, &#x27; and &eacute; so escaped and plain text compare equal; unknown references are kept", "rangler unescape-html dedupe < titles.txt"),
+    command("strip-markdown", "strip-markdown", "turns Markdown into plain text: links and images keep their text, code keeps its contents and heading, list, quote and emphasis markers go, as do fence lines, rules and link definitions", "rangler strip-markdown lower count-distinct < CHANGELOG.md"),
+    command("if", "if [-i] [-m] [-s] <regex> then [commands] end", "runs its own commands only on lines that match, passing the others along untouched", "rangler if WARN then upper end < app.log"),
+    command("repeat-until-stable", "repeat-until-stable [--max <count>] [commands] end", "applies its own commands to every line again and again until it stops changing, at most 100 times by default", "rangler repeat-until-stable map \"replace(line, '--', '-')\" end < dashes.txt"),
+    command("tag", "tag <name> when [-i] [-m] [-s] <regex>", "tags lines that match", "rangler tag errors when ERROR route errors to errors.log < app.log"),
+    command("route", "route <name> to <file|stderr|drop|pipeline [commands] end> | route <regex> <template>", "sends tagged lines to a sink, or writes matching lines to the file named by the template, e.g. 'logs/{service}.log', instead of passing them on", "rangler route 'service=(?P<service>\\w+)' 'logs/{service}.log' < app.log"),
+    command("script", "script <file|inline script>", "runs a Rhai script per line with line, n, captures and groups in scope; a string replaces the line, () drops it and an array fans out; needs the script feature", "rangler script 'if line.len() > 80 { line.sub_string(0, 80) } else { line }' < app.log"),
+    command("geoip", "geoip <database.mmdb>", "appends the country and ASN of the first IP address in each line from a local MaxMind database, as country=US asn=15169 as_org=\"Google LLC\"; needs the geoip feature", "rangler geoip GeoLite2-ASN.mmdb < access.log"),
+    command("wasm", "wasm <module.wasm>", "runs every line through a sandboxed WebAssembly module exporting memory, alloc and apply; needs the wasm feature", "rangler wasm redact.wasm < app.log"),
+];
+
+/// Finds the help for a pipeline command by name.
+pub fn command_help(name: &str) -> Option<&'static CommandHelp> {
+    COMMANDS
+        .iter()
+        .find(|command| command.name.eq_ignore_ascii_case(name))
+}
+
+/// The closest command to a misspelt one, if any is close enough to be what
+/// was meant.
+pub fn suggest_command(name: &str) -> Option<&'static str> {
+    COMMANDS
+        .iter()
+        .map(|command| (strsim::jaro_winkler(name, command.name), command.name))
+        .filter(|(similarity, _)| *similarity > 0.8)
+        .max_by(|left, right| left.0.total_cmp(&right.0))
+        .map(|(_, name)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{command_help, suggest_command, COMMANDS};
+    use crate::pipeline::Pipeline;
+
+    #[test]
+    fn command_help_covers_every_listed_command() {
+        //+ Act + Assert
+        for command in COMMANDS {
+            assert_eq!(command_help(command.name), Some(command));
+            assert!(command.usage.starts_with(command.name), "{}", command.name);
+            assert!(command.example.starts_with("rangler "), "{}", command.name);
+        }
+        assert_eq!(command_help("bogus"), None);
+    }
+
+    #[test]
+    fn build_pipeline_suggests_close_commands() {
+        //+ Act + Assert
+        assert_eq!(suggest_command("uper"), Some("upper"));
+        assert_eq!(suggest_command("dedup"), Some("dedupe"));
+        assert_eq!(suggest_command("xyzzy"), None);
+        assert_eq!(
+            Pipeline::build_pipeline(&["trim", "uper"]).err().unwrap(),
+            "Unknown command 'uper', did you mean 'upper'?"
+        );
+        assert_eq!(
+            Pipeline::build_pipeline(&["xyzzy"]).err().unwrap(),
+            "Unknown command 'xyzzy'"
+        );
+    }
+}
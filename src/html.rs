@@ -0,0 +1,279 @@
+// Tags that break text into separate blocks; dropping them outright would
+// run the words on either side together, so they leave a space instead.
+const BLOCK_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "br",
+    "dd",
+    "div",
+    "dl",
+    "dt",
+    "footer",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "li",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "table",
+    "td",
+    "th",
+    "tr",
+    "ul",
+];
+
+const NAMED_REFERENCES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("apos", '\''),
+    ("nbsp", '\u{a0}'),
+    ("copy", '\u{a9}'),
+    ("reg", '\u{ae}'),
+    ("trade", '\u{2122}'),
+    ("deg", '\u{b0}'),
+    ("times", '\u{d7}'),
+    ("divide", '\u{f7}'),
+    ("middot", '\u{b7}'),
+    ("bull", '\u{2022}'),
+    ("hellip", '\u{2026}'),
+    ("ndash", '\u{2013}'),
+    ("mdash", '\u{2014}'),
+    ("lsquo", '\u{2018}'),
+    ("rsquo", '\u{2019}'),
+    ("ldquo", '\u{201c}'),
+    ("rdquo", '\u{201d}'),
+    ("laquo", '\u{ab}'),
+    ("raquo", '\u{bb}'),
+    ("cent", '\u{a2}'),
+    ("pound", '\u{a3}'),
+    ("yen", '\u{a5}'),
+    ("euro", '\u{20ac}'),
+    ("sect", '\u{a7}'),
+    ("para", '\u{b6}'),
+    ("iexcl", '\u{a1}'),
+    ("iquest", '\u{bf}'),
+    ("aacute", '\u{e1}'),
+    ("eacute", '\u{e9}'),
+    ("iacute", '\u{ed}'),
+    ("oacute", '\u{f3}'),
+    ("uacute", '\u{fa}'),
+    ("agrave", '\u{e0}'),
+    ("egrave", '\u{e8}'),
+    ("ntilde", '\u{f1}'),
+    ("ccedil", '\u{e7}'),
+    ("auml", '\u{e4}'),
+    ("ouml", '\u{f6}'),
+    ("uuml", '\u{fc}'),
+    ("szlig", '\u{df}'),
+];
+
+// Elements whose content is code rather than text.
+const RAW_TEXT_TAGS: &[&str] = &["script", "style"];
+
+/// Replaces character references like `&amp;`, `&#x27;` and `&eacute;` with
+/// the characters they stand for, keeping any it does not know as they are.
+pub fn unescape_html(input: &str) -> String {
+    let mut text = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find('&') {
+        text.push_str(&rest[..start]);
+        rest = push_reference(&mut text, &rest[start + 1..]);
+    }
+    text.push_str(rest);
+
+    text
+}
+
+/// Removes HTML and XML tags, comments and the content of script and style
+/// elements, and decodes character references. A `<` that does not start a
+/// tag, or a tag never closed on the line, is kept as text. With `squeeze`,
+/// runs of whitespace become one space and the ends are trimmed.
+pub fn strip_html(input: &str, squeeze: bool) -> String {
+    let mut text = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find(['<', '&']) {
+        let (before, from) = rest.split_at(start);
+        text.push_str(before);
+
+        if let Some(after) = from.strip_prefix('&') {
+            rest = push_reference(&mut text, after);
+            continue;
+        }
+
+        match skip_markup(from) {
+            Some((name, length)) => {
+                if BLOCK_TAGS.contains(&name.as_str()) {
+                    text.push(' ');
+                }
+                rest = &from[length..];
+                let opening = !from[1..].starts_with('/') && !from[..length].ends_with("/>");
+                if opening && RAW_TEXT_TAGS.contains(&name.as_str()) {
+                    rest = skip_raw_text(rest, &name);
+                }
+            }
+            None => {
+                text.push('<');
+                rest = &from[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+
+    if squeeze {
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    } else {
+        text
+    }
+}
+
+// The lower-cased name of the tag at the start of `input` and its length, an
+// empty name for comments and declarations. None when `input` does not start
+// a tag or the tag is not closed.
+fn skip_markup(input: &str) -> Option<(String, usize)> {
+    if let Some(comment) = input.strip_prefix("<!--") {
+        let end = comment.find("-->")?;
+        return Some((String::new(), end + 7));
+    }
+
+    let after = &input[1..];
+    let name_start = usize::from(after.starts_with('/'));
+    let first = after[name_start..].chars().next()?;
+    if !(first.is_ascii_alphabetic() || (name_start == 0 && matches!(first, '!' | '?'))) {
+        return None;
+    }
+
+    // A `>` inside a quoted attribute value does not close the tag.
+    let mut quote = None;
+    for (index, character) in after.char_indices() {
+        match (quote, character) {
+            (Some(open), _) if character == open => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(character),
+            (None, '>') => {
+                let name = after[name_start..index]
+                    .split(|character: char| {
+                        !(character.is_ascii_alphanumeric() || character == '-' || character == ':')
+                    })
+                    .next()
+                    .unwrap_or("")
+                    .to_ascii_lowercase();
+                return Some((name, index + 2));
+            }
+            (None, _) => {}
+        }
+    }
+
+    None
+}
+
+// Skips past the closing tag of a script or style element, or to the end of
+// the line when it does not close on it.
+fn skip_raw_text<'a>(input: &'a str, name: &str) -> &'a str {
+    let closing = format!("</{}", name);
+    let lowered = input.to_ascii_lowercase();
+    let Some(start) = lowered.find(&closing) else {
+        return "";
+    };
+
+    match input[start..].find('>') {
+        Some(end) => &input[start + end + 1..],
+        None => "",
+    }
+}
+
+// Pushes the character the reference after an `&` stands for, or the `&`
+// itself when there is no reference it knows, and returns what follows.
+fn push_reference<'a>(text: &mut String, after: &'a str) -> &'a str {
+    match decode_reference(after) {
+        Some((decoded, length)) => {
+            text.push(decoded);
+            &after[length..]
+        }
+        None => {
+            text.push('&');
+            after
+        }
+    }
+}
+
+// The character a reference like `amp;`, `#39;` or `#x2014;` stands for and
+// the reference's length. Names are limited to the ones common in text.
+fn decode_reference(input: &str) -> Option<(char, usize)> {
+    let end = input.bytes().take(10).position(|byte| byte == b';')?;
+    let name = &input[..end];
+    let decoded = match name.strip_prefix('#') {
+        Some(number) => {
+            let code = match number.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => number.parse().ok()?,
+            };
+            char::from_u32(code)?
+        }
+        None => NAMED_REFERENCES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, decoded)| *decoded)?,
+    };
+
+    Some((decoded, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_html, unescape_html};
+
+    #[test]
+    fn strip_html_keeps_only_the_text() {
+        //+ Arrange
+        let line = r#"<div class="post"><p>Fish &amp; <b>chips</b></p><!-- ad --><p title="a > b">&#163;5</p></div>"#;
+
+        //+ Act
+        let text = strip_html(line, false);
+        let squeezed = strip_html(line, true);
+
+        //+ Assert
+        assert_eq!(text, "  Fish & chips  \u{a3}5  ");
+        assert_eq!(squeezed, "Fish & chips \u{a3}5");
+    }
+
+    #[test]
+    fn strip_html_drops_scripts_and_keeps_stray_brackets() {
+        //+ Arrange
+        let line = "if a < b && c <3 <script>if (x > 1) {}</SCRIPT>then &bogus; <unclosed";
+
+        //+ Act
+        let text = strip_html(line, true);
+
+        //+ Assert
+        assert_eq!(text, "if a < b && c <3 then &bogus; <unclosed");
+    }
+
+    #[test]
+    fn unescape_html_decodes_named_and_numeric_references() {
+        //+ Arrange
+        let line = "Tom &amp; Jerry&#x27;s &quot;caf&eacute;&quot; &#8212; &lt;b&gt; &amp;amp; &copy &unknown; &#xZZ;";
+
+        //+ Act
+        let text = unescape_html(line);
+
+        //+ Assert
+        assert_eq!(
+            text,
+            "Tom & Jerry's \"caf\u{e9}\" \u{2014} <b> &amp; &copy &unknown; &#xZZ;"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::{copy, ErrorKind, Read, Result};
+
+const MAX_RETRIES: u32 = 5;
+
+// Streams a remote file. When the connection drops part way, the request is
+// retried from where it stopped with a Range header; a server that ignores
+// the range sends everything again and the part already read is skipped.
+pub struct HttpReader {
+    url: String,
+    prepare: Box<dyn Fn(ureq::Request) -> ureq::Request>,
+    body: Box<dyn Read + Send + Sync>,
+    position: u64,
+    retries: u32,
+}
+
+impl HttpReader {
+    // Returns the reader along with the Content-Length, when the server sent one.
+    pub fn open(url: &str) -> std::result::Result<(HttpReader, Option<u64>), String> {
+        HttpReader::open_with(url, Box::new(|request| request))
+    }
+
+    // Every request, including retries, is passed through `prepare` first,
+    // e.g. to sign it.
+    pub fn open_with(
+        url: &str,
+        prepare: Box<dyn Fn(ureq::Request) -> ureq::Request>,
+    ) -> std::result::Result<(HttpReader, Option<u64>), String> {
+        let response = prepare(ureq::get(url))
+            .call()
+            .map_err(|_| format!("Could not download {}", url))?;
+        let size = response
+            .header("Content-Length")
+            .and_then(|length| length.parse().ok());
+
+        let reader = HttpReader {
+            url: url.to_string(),
+            prepare,
+            body: response.into_reader(),
+            position: 0,
+            retries: 0,
+        };
+
+        Ok((reader, size))
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        let response = (self.prepare)(ureq::get(&self.url))
+            .set("Range", &format!("bytes={}-", self.position))
+            .call()
+            .map_err(|error| std::io::Error::other(error.to_string()))?;
+
+        let skip = match response.status() {
+            206 => 0,
+            _ => self.position,
+        };
+        let mut body = response.into_reader();
+        copy(&mut (&mut body).take(skip), &mut std::io::sink())?;
+        self.body = body;
+
+        Ok(())
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            match self.body.read(buf) {
+                Ok(read) => {
+                    self.position += read as u64;
+                    return Ok(read);
+                }
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) if self.retries >= MAX_RETRIES => return Err(error),
+                Err(_) => {
+                    self.retries += 1;
+                    self.resume()?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use super::HttpReader;
+
+    #[test]
+    fn read_resumes_with_range_after_dropped_connection() {
+        //+ Arrange
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/app.log", server.local_addr().unwrap());
+        let requests = thread::spawn(move || {
+            let mut ranges = vec![];
+            for (attempt, stream) in server.incoming().take(2).enumerate() {
+                let mut stream = stream.unwrap();
+                let mut request = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while request.read_line(&mut line).unwrap() > 2 {
+                    if line.to_lowercase().starts_with("range:") {
+                        ranges.push(line.trim().to_string());
+                    }
+                    line.clear();
+                }
+
+                let response = match attempt {
+                    0 => "HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nfirst\n",
+                    _ => "HTTP/1.1 206 Partial Content\r\nContent-Length: 6\r\n\r\nsecond",
+                };
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            ranges
+        });
+
+        //+ Act
+        let (mut reader, size) = HttpReader::open(&url).unwrap();
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+
+        //+ Assert
+        assert_eq!(size, Some(12));
+        assert_eq!(text, "first\nsecond");
+        assert_eq!(requests.join().unwrap(), vec!["Range: bytes=6-"]);
+    }
+}
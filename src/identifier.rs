@@ -0,0 +1,203 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sha1::{Digest, Sha1};
+
+use crate::sample::Random;
+
+// Crockford's base32, which ULIDs are written in.
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+// The namespaces RFC 9562 defines for name-based UUIDs.
+const NAMESPACES: &[(&str, &str)] = &[
+    ("dns", "6ba7b810-9dad-11d1-80b4-00c04fd430c8"),
+    ("url", "6ba7b811-9dad-11d1-80b4-00c04fd430c8"),
+    ("oid", "6ba7b812-9dad-11d1-80b4-00c04fd430c8"),
+    ("x500", "6ba7b814-9dad-11d1-80b4-00c04fd430c8"),
+];
+
+const ULID_RANDOM_BITS: u128 = (1 << 80) - 1;
+
+/// What the `uuid` and `ulid` steps mark lines with: a random UUIDv4, a
+/// UUIDv5 named by the line within a namespace, so the same line always gets
+/// the same id, or a ULID, which sorts by the millisecond it was made.
+#[derive(Debug, PartialEq)]
+pub enum Identifier {
+    Random(Random),
+    Named([u8; 16]),
+    // The millisecond and random part of the last ULID; ULIDs made within
+    // the same millisecond count up from it so they still sort in order.
+    Ulid(Random, Option<(u64, u128)>),
+}
+
+impl Identifier {
+    pub fn uuid() -> Identifier {
+        Identifier::Random(Random::from_entropy())
+    }
+
+    // The namespace is `dns`, `url`, `oid`, `x500` or a UUID of its own.
+    pub fn named_uuid(namespace: &str) -> Result<Identifier, &'static str> {
+        let namespace = NAMESPACES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(namespace))
+            .map_or(namespace, |(_, uuid)| uuid);
+
+        parse_uuid(namespace)
+            .map(Identifier::Named)
+            .ok_or("Invalid UUID namespace")
+    }
+
+    pub fn ulid() -> Identifier {
+        Identifier::Ulid(Random::from_entropy(), None)
+    }
+
+    // Named UUIDs depend only on the line, so copies of the step on other
+    // threads agree.
+    pub fn is_named(&self) -> bool {
+        matches!(self, Identifier::Named(_))
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        match self {
+            Identifier::Random(random) | Identifier::Ulid(random, _) => *random = Random::new(seed),
+            Identifier::Named(_) => {}
+        }
+    }
+
+    pub fn next(&mut self, line: &str) -> String {
+        match self {
+            Identifier::Random(random) => {
+                let mut bytes = [0; 16];
+                bytes[..8].copy_from_slice(&random.next_u64().to_be_bytes());
+                bytes[8..].copy_from_slice(&random.next_u64().to_be_bytes());
+
+                format_uuid(bytes, 4)
+            }
+            Identifier::Named(namespace) => {
+                let digest = Sha1::new()
+                    .chain_update(namespace)
+                    .chain_update(line)
+                    .finalize();
+
+                format_uuid(digest[..16].try_into().unwrap(), 5)
+            }
+            Identifier::Ulid(random, last) => {
+                let millis = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_millis() as u64);
+                let bits = match last {
+                    Some((previous, bits)) if *previous >= millis && *bits < ULID_RANDOM_BITS => {
+                        (*previous, *bits + 1)
+                    }
+                    _ => {
+                        let high = u128::from(random.next_u64() & 0xffff) << 64;
+                        (millis, high | u128::from(random.next_u64()))
+                    }
+                };
+                *last = Some(bits);
+
+                format_ulid(bits.0, bits.1)
+            }
+        }
+    }
+}
+
+fn parse_uuid(text: &str) -> Option<[u8; 16]> {
+    let hex: String = text.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    let mut bytes = [0; 16];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+
+    Some(bytes)
+}
+
+// Stamps the version and the RFC variant into the bytes and writes them out
+// as 8-4-4-4-12 lower-case hex.
+fn format_uuid(mut bytes: [u8; 16], version: u8) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | (version << 4);
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+// 48 bits of milliseconds and 80 random bits as 26 base32 characters.
+fn format_ulid(millis: u64, random: u128) -> String {
+    let value = (u128::from(millis & 0xffff_ffff_ffff) << 80) | (random & ULID_RANDOM_BITS);
+
+    (0..26)
+        .map(|index| CROCKFORD[((value >> ((25 - index) * 5)) & 31) as usize] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_ulid, Identifier};
+
+    #[test]
+    fn named_uuid_matches_rfc_namespaces() {
+        //+ Arrange
+        let mut dns = Identifier::named_uuid("dns").unwrap();
+        let mut custom = Identifier::named_uuid("6BA7B811-9DAD-11D1-80B4-00C04FD430C8").unwrap();
+
+        //+ Act
+        let host = dns.next("www.example.com");
+        let line = custom.next("GET /index.html 200");
+
+        //+ Assert
+        assert_eq!(host, "2ed6657d-e927-568b-95e1-2665a8aea6a2");
+        assert_eq!(line, "975865bf-e2f7-55dd-a3cc-f4accf0f3d0d");
+        assert!(Identifier::named_uuid("example").is_err());
+    }
+
+    #[test]
+    fn uuid_is_random_version_4_and_repeatable_with_a_seed() {
+        //+ Arrange
+        let (mut first, mut second) = (Identifier::uuid(), Identifier::uuid());
+        first.reseed(7);
+        second.reseed(7);
+
+        //+ Act
+        let ids: Vec<String> = (0..2).map(|_| first.next("")).collect();
+        let again = second.next("");
+
+        //+ Assert
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(ids[0], again);
+        assert_eq!(ids[0].len(), 36);
+        assert_eq!(&ids[0][14..15], "4");
+        assert!(matches!(&ids[0][19..20], "8" | "9" | "a" | "b"));
+    }
+
+    #[test]
+    fn ulid_sorts_by_time_and_counts_up_within_a_millisecond() {
+        //+ Arrange
+        let mut ulid = Identifier::ulid();
+
+        //+ Act
+        let ids: Vec<String> = (0..100).map(|_| ulid.next("")).collect();
+
+        //+ Assert
+        assert_eq!(format_ulid(0, 0), "00000000000000000000000000");
+        assert_eq!(
+            format_ulid(1_469_922_850_259, 0),
+            "01ARZ3NDEK0000000000000000"
+        );
+        assert_eq!(
+            format_ulid(u64::MAX, u128::MAX),
+            "7ZZZZZZZZZZZZZZZZZZZZZZZZZ"
+        );
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,302 @@
+use std::{
+    fs::File,
+    io::{stdin, BufRead, BufReader, Read},
+};
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use memmap2::Mmap;
+
+use crate::{buffers::StreamKind, http::HttpReader};
+
+pub type Source = (String, Box<dyn BufRead>);
+
+#[derive(Debug, PartialEq)]
+enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Zstd,
+}
+
+impl Compression {
+    // Magic bytes win; the extension only decides when the input is too short
+    // to tell, e.g. an empty `.gz` file.
+    fn detect(header: &[u8], path: &str) -> Compression {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if header.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if header.len() >= 4 {
+            Compression::None
+        } else if path.ends_with(".gz") {
+            Compression::Gzip
+        } else if path.ends_with(".bz2") {
+            Compression::Bzip2
+        } else if path.ends_with(".zst") {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+// Peeks at the start of the input and wraps it in the matching decoder, so
+// compressed logs read the same as plain ones. Reports whether it did.
+fn decompress<R: Read + 'static>(
+    mut reader: BufReader<R>,
+    path: &str,
+    capacity: usize,
+) -> Result<(Box<dyn BufRead>, bool), String> {
+    let header = reader
+        .fill_buf()
+        .map_err(|_| format!("Could not read input file {}", path))?;
+
+    let decoded: Box<dyn Read> = match Compression::detect(header, path) {
+        Compression::None => return Ok((Box::new(reader), false)),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+        Compression::Zstd => Box::new(
+            zstd::Decoder::with_buffer(reader)
+                .map_err(|_| format!("Could not decompress input file {}", path))?,
+        ),
+    };
+
+    Ok((Box::new(BufReader::with_capacity(capacity, decoded)), true))
+}
+
+// A whole file mapped into memory, read without copying it into a buffer
+// first: its unread rest is the buffer, so lines are scanned for right where
+// the file's pages are.
+struct MappedFile {
+    map: Mmap,
+    position: usize,
+}
+
+impl MappedFile {
+    // Files that are empty, compressed or cannot be mapped come back as None,
+    // to be read the usual way.
+    fn open(path: &str) -> Option<(MappedFile, u64)> {
+        let file = File::open(path).ok()?;
+        let size = file
+            .metadata()
+            .ok()
+            .filter(|metadata| metadata.is_file())?
+            .len();
+        if size == 0 {
+            return None;
+        }
+
+        // The mapping is only sound while nothing truncates the file, the
+        // risk --mmap asks to take for local files that are not being written.
+        let map = unsafe { Mmap::map(&file) }.ok()?;
+        let header = &map[..map.len().min(4)];
+        if Compression::detect(header, path) != Compression::None {
+            return None;
+        }
+
+        Some((MappedFile { map, position: 0 }, size))
+    }
+}
+
+impl Read for MappedFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = (&self.map[self.position..]).read(buf)?;
+        self.position += count;
+        Ok(count)
+    }
+}
+
+impl BufRead for MappedFile {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        Ok(&self.map[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.map.len());
+    }
+}
+
+// Opens every input file or URL up front, so a typo fails before any output is
+// written, and sums their sizes for the progress bar. No inputs means stdin.
+// Compressed sizes say nothing about how many bytes will be read, so any
+// compressed input leaves the total unknown. Without a configured buffer size,
+// files get a large one and stdin one to suit whatever it is attached to.
+// With `mmap`, plain local files are mapped into memory instead.
+pub fn open_inputs(
+    paths: &[String],
+    buffer: Option<usize>,
+    mmap: bool,
+) -> Result<(Vec<Source>, Option<u64>), String> {
+    if paths.is_empty() {
+        let capacity = buffer.unwrap_or(StreamKind::stdin().buffer_size());
+        let std_in = BufReader::with_capacity(capacity, stdin());
+        let (reader, _) = decompress(std_in, "(standard input)", capacity)?;
+        return Ok((vec![("(standard input)".to_string(), reader)], None));
+    }
+
+    let capacity = buffer.unwrap_or(StreamKind::File.buffer_size());
+    let mut sources: Vec<Source> = vec![];
+    let mut total_size = Some(0);
+    for path in paths {
+        let mapped = match mmap && !path.contains("://") {
+            true => MappedFile::open(path),
+            false => None,
+        };
+        if let Some((mapped, size)) = mapped {
+            total_size = total_size.map(|total| total + size);
+            sources.push((path.clone(), Box::new(mapped)));
+            continue;
+        }
+
+        let (input, size): (Box<dyn Read>, Option<u64>) =
+            if path.starts_with("http://") || path.starts_with("https://") {
+                let (reader, size) = HttpReader::open(path)?;
+                (Box::new(reader), size)
+            } else if path.starts_with("s3://") {
+                open_s3(path)?
+            } else {
+                let file =
+                    File::open(path).map_err(|_| format!("Could not open input file {}", path))?;
+                let size = file.metadata().map(|metadata| metadata.len()).ok();
+                (Box::new(file), size)
+            };
+        let (reader, compressed) =
+            decompress(BufReader::with_capacity(capacity, input), path, capacity)?;
+
+        total_size = total_size
+            .zip(size)
+            .filter(|_| !compressed)
+            .map(|(total, size)| total + size);
+        sources.push((path.clone(), reader));
+    }
+
+    Ok((sources, total_size))
+}
+
+#[cfg(feature = "s3")]
+fn open_s3(location: &str) -> Result<(Box<dyn Read>, Option<u64>), String> {
+    let (reader, size) = crate::s3::open(location)?;
+    Ok((Box::new(reader), size))
+}
+
+#[cfg(not(feature = "s3"))]
+fn open_s3(_: &str) -> Result<(Box<dyn Read>, Option<u64>), String> {
+    Err("Reading from S3 needs rangler built with the s3 feature".to_string())
+}
+
+// Expands each pattern (`**` recurses into directories) into the files it
+// matches. Every pattern's matches are sorted so runs are deterministic on
+// all platforms, and a pattern matching nothing is an error.
+pub fn expand_globs(patterns: &[String]) -> Result<Vec<String>, String> {
+    let mut paths = vec![];
+    for pattern in patterns {
+        let entries =
+            glob::glob(pattern).map_err(|_| format!("Invalid glob pattern {}", pattern))?;
+
+        let mut matched: Vec<String> = entries
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect();
+        if matched.is_empty() {
+            return Err(format!("No files match {}", pattern));
+        }
+
+        matched.sort();
+        paths.extend(matched);
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Read, Write};
+
+    use super::{expand_globs, open_inputs};
+
+    #[test]
+    fn open_inputs_decompresses_by_magic_bytes() {
+        //+ Arrange
+        let directory = std::env::temp_dir();
+        let gzip_path = directory.join(format!("rangler-gzip-{}.log", std::process::id()));
+        let zstd_path = directory.join(format!("rangler-zstd-{}.data", std::process::id()));
+
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(b"from gzip\n").unwrap();
+        std::fs::write(&gzip_path, gzip.finish().unwrap()).unwrap();
+        std::fs::write(
+            &zstd_path,
+            zstd::encode_all(&b"from zstd\n"[..], 0).unwrap(),
+        )
+        .unwrap();
+
+        let paths = [gzip_path, zstd_path].map(|path| path.to_string_lossy().into_owned());
+
+        //+ Act
+        let (sources, total_size) = open_inputs(&paths, None, false).unwrap();
+
+        //+ Assert
+        let contents: Vec<String> = sources
+            .into_iter()
+            .map(|(_, mut reader)| {
+                let mut text = String::new();
+                reader.read_to_string(&mut text).unwrap();
+                text
+            })
+            .collect();
+        assert_eq!(contents, vec!["from gzip\n", "from zstd\n"]);
+        assert_eq!(total_size, None);
+    }
+
+    #[test]
+    fn expand_globs_walks_directories_in_sorted_order() {
+        //+ Arrange
+        let root = std::env::temp_dir().join(format!("rangler-glob-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("b/deep")).unwrap();
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        for file in ["b/deep/z.log", "a/y.log", "x.log", "a/skip.txt"] {
+            std::fs::write(root.join(file), "").unwrap();
+        }
+        let pattern = format!("{}/**/*.log", root.display());
+
+        //+ Act
+        let paths = expand_globs(&[pattern]).unwrap();
+
+        //+ Assert
+        let relative: Vec<String> = paths
+            .iter()
+            .map(|path| path[root.display().to_string().len() + 1..].replace('\\', "/"))
+            .collect();
+        assert_eq!(relative, vec!["a/y.log", "b/deep/z.log", "x.log"]);
+        assert!(expand_globs(&[format!("{}/*.none", root.display())]).is_err());
+    }
+
+    #[test]
+    fn open_inputs_maps_plain_files_and_reads_compressed_ones_as_usual() {
+        //+ Arrange
+        let directory = std::env::temp_dir();
+        let plain_path = directory.join(format!("rangler-mmap-{}.log", std::process::id()));
+        let gzip_path = directory.join(format!("rangler-mmap-{}.log.gz", std::process::id()));
+        std::fs::write(&plain_path, "one\ntwo\n").unwrap();
+        let mut gzip = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        gzip.write_all(b"three\n").unwrap();
+        std::fs::write(&gzip_path, gzip.finish().unwrap()).unwrap();
+        let paths = [&plain_path, &gzip_path].map(|path| path.to_string_lossy().into_owned());
+
+        //+ Act
+        let (sources, _) = open_inputs(&paths, None, true).unwrap();
+
+        //+ Assert
+        let lines: Vec<String> = sources
+            .into_iter()
+            .flat_map(|(_, reader)| reader.lines().map(Result::unwrap))
+            .collect();
+        assert_eq!(lines, ["one", "two", "three"]);
+        std::fs::remove_file(plain_path).unwrap();
+        std::fs::remove_file(gzip_path).unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    borrow::Cow,
+    mem::take,
+    slice,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, sync_channel, Receiver, SyncSender},
+        Arc,
+    },
+    thread::{Scope, ScopedJoinHandle},
+};
+
+use crate::{
+    buffers::StreamKind,
+    dedupe::ShardedSet,
+    degradation::LossyEvent,
+    encoding::decode_input,
+    error::RanglerError,
+    inputs::open_inputs,
+    options::Options,
+    pipeline::Pipeline,
+    records::{strip_carriage_returns, InvalidUtf8, RecordReader},
+    run::{build_pipeline, record_prefix},
+    stats::StepStats,
+};
+
+// Lines are sent back this many at a time; a batch is never split, so the
+// lines of one file stay together in it.
+const BATCH_SIZE: usize = 1024;
+
+// How many batches can wait to be written before the jobs block.
+const CHANNEL_CAPACITY: usize = 64;
+
+/// A run of one file's lines as they came out of the pipeline, each with its
+/// prefix, with how many records and bytes of the file it covers and what was
+/// lost decoding them. A batch with an error is the last of its job.
+#[derive(Debug, Default)]
+pub struct Batch {
+    pub lines: Vec<(String, Vec<String>)>,
+    pub records: usize,
+    pub bytes: usize,
+    pub events: Vec<LossyEvent>,
+    pub error: Option<RanglerError>,
+}
+
+// Runs whole input files through copies of a stateless pipeline on threads
+// of their own, each job taking the next file once done with the last. Every
+// file's lines come back in order, but the files' batches interleave. An
+// exact dedupe is shared between the copies, keeping whichever copy of a
+// line a job got to first.
+pub struct Jobs<'scope> {
+    batches: Receiver<Batch>,
+    threads: Vec<ScopedJoinHandle<'scope, Vec<StepStats>>>,
+}
+
+impl<'scope> Jobs<'scope> {
+    // Every job builds its own pipeline from the commands, as pipelines stay
+    // on the thread they were built on.
+    pub fn start<'env>(
+        scope: &'scope Scope<'scope, 'env>,
+        count: usize,
+        commands: &'env [String],
+        options: &'env Options,
+        paths: &'env [String],
+    ) -> Result<Jobs<'scope>, RanglerError> {
+        let (sender, batches) = sync_channel(CHANNEL_CAPACITY);
+        let next = Arc::new(AtomicUsize::new(0));
+        let dedupe = ShardedSet::first_come();
+        let mut threads = vec![];
+
+        for _ in 0..count.min(paths.len()) {
+            let (sender, next, dedupe) = (sender.clone(), next.clone(), dedupe.clone());
+            let (sent_ready, ready) = channel();
+
+            threads.push(scope.spawn(move || {
+                let mut pipeline = match copy_pipeline(commands, options, &dedupe) {
+                    Ok(pipeline) => pipeline,
+                    Err(error) => {
+                        sent_ready.send(Err(error)).ok();
+                        return vec![];
+                    }
+                };
+                sent_ready.send(Ok(())).ok();
+
+                while let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let mut batch = Batch::default();
+                    if let Err(error) = run_file(&mut pipeline, options, path, &mut batch, &sender)
+                    {
+                        batch.error = Some(error);
+                    }
+                    let failed = batch.error.is_some();
+                    if sender.send(batch).is_err() || failed {
+                        break;
+                    }
+                }
+
+                pipeline.step_stats().to_vec()
+            }));
+            ready.recv().map_err(|_| "Job thread stopped")??;
+        }
+
+        Ok(Jobs { batches, threads })
+    }
+
+    // Batches as the jobs send them, until every file is done or a job fails.
+    pub fn batches(&self) -> impl Iterator<Item = Batch> + '_ {
+        self.batches.iter()
+    }
+
+    // Waits for the jobs, giving back the step stats of each.
+    pub fn stop(self) -> Vec<Vec<StepStats>> {
+        drop(self.batches);
+        self.threads
+            .into_iter()
+            .filter_map(|thread| thread.join().ok())
+            .collect()
+    }
+}
+
+// Builds a job's copy of the pipeline the way the run's own was built.
+fn copy_pipeline(
+    commands: &[String],
+    options: &Options,
+    dedupe: &ShardedSet,
+) -> Result<Pipeline, RanglerError> {
+    let mut pipeline = build_pipeline(options, commands)?;
+    if !options.no_optimize {
+        pipeline.optimize();
+    }
+    if pipeline.can_share_dedupe() {
+        pipeline.share_dedupe(dedupe);
+    }
+
+    Ok(pipeline)
+}
+
+// Runs one file through the pipeline, sending every full batch on. What is
+// left in the batch is the job's to send.
+fn run_file(
+    pipeline: &mut Pipeline,
+    options: &Options,
+    path: &str,
+    batch: &mut Batch,
+    sender: &SyncSender<Batch>,
+) -> Result<(), RanglerError> {
+    let (mut sources, _) = open_inputs(
+        slice::from_ref(&path.to_string()),
+        options.read_buffer,
+        options.mmap,
+    )?;
+    let (name, source) = sources.remove(0);
+    let source = match options.encoding {
+        Some(encoding) => {
+            let capacity = options
+                .read_buffer
+                .unwrap_or(StreamKind::File.buffer_size());
+            decode_input(source, encoding, capacity)
+        }
+        None => source,
+    };
+    pipeline.set_source(&name);
+
+    let mut records = RecordReader::new(source, options.record_separator.clone());
+    let (mut offset, mut record_number) = (0, 0);
+    while let Some((mut record, bytes_read)) = records.next_record()? {
+        record_number += 1;
+        if !options.keep_eol {
+            strip_carriage_returns(&mut record);
+        }
+        let prefix = record_prefix(options, &name, record_number, offset);
+
+        let text = match std::str::from_utf8(&record) {
+            Ok(text) => Some(Cow::Borrowed(text)),
+            Err(error) => match options.invalid_utf8 {
+                InvalidUtf8::Skip => {
+                    batch.events.push(LossyEvent::InvalidUtf8Skipped);
+                    None
+                }
+                InvalidUtf8::Abort => Err(format!(
+                    "Invalid UTF-8 in {} at byte {}",
+                    name,
+                    offset + error.valid_up_to()
+                ))?,
+                // Raw records are refused with --jobs, as lines go back as text.
+                InvalidUtf8::Lossy | InvalidUtf8::Raw => {
+                    batch.events.push(LossyEvent::InvalidUtf8Replaced);
+                    Some(String::from_utf8_lossy(&record))
+                }
+            },
+        };
+        if let Some(text) = text {
+            let lines = pipeline.apply_numbered(record_number, text.into_owned())?;
+            batch.lines.push((prefix, lines));
+        }
+        batch.records += 1;
+        batch.bytes += bytes_read;
+        offset += bytes_read;
+
+        if batch.records == BATCH_SIZE {
+            sender
+                .send(take(batch))
+                .map_err(|_| "Output stopped taking lines")?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::Jobs;
+    use crate::options::Options;
+
+    #[test]
+    fn jobs_keep_each_files_order_and_share_a_dedupe() {
+        //+ Arrange
+        let directory = std::env::temp_dir();
+        let paths: Vec<String> = (0..3)
+            .map(|file| {
+                let path = directory.join(format!("rangler-jobs-{}-{}", std::process::id(), file));
+                let lines: Vec<String> = (0..3000).map(|n| (n + file * 1000).to_string()).collect();
+                std::fs::write(&path, lines.join("\n")).unwrap();
+                path.to_string_lossy().into_owned()
+            })
+            .collect();
+        let commands = ["dedupe"].map(String::from);
+        let options = Options {
+            with_filename: true,
+            ..Options::default()
+        };
+
+        //+ Act
+        let (files, stats) = thread::scope(|scope| {
+            let jobs = Jobs::start(scope, 2, &commands, &options, &paths).unwrap();
+            let mut files = vec![vec![]; paths.len()];
+            for batch in jobs.batches() {
+                assert!(batch.error.is_none());
+                for (prefix, lines) in batch.lines {
+                    let file = paths.iter().position(|path| prefix == path.clone() + ":");
+                    for line in lines {
+                        files[file.unwrap()].push(line.parse::<usize>().unwrap());
+                    }
+                }
+            }
+
+            (files, jobs.stop())
+        });
+        for path in &paths {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        //+ Assert
+        let mut kept: Vec<usize> = files.concat();
+        kept.sort();
+        assert_eq!(kept, (0..5000).collect::<Vec<_>>());
+        assert!(files
+            .iter()
+            .all(|lines| lines.windows(2).all(|pair| pair[0] < pair[1])));
+        let received: usize = stats.iter().map(|steps| steps[0].received).sum();
+        assert_eq!(received, 9000);
+    }
+}
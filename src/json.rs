@@ -0,0 +1,287 @@
+use regex::Regex;
+use serde_json::Value;
+
+use crate::fields::Comparison;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+// A jq-like path such as `.`, `.request.headers["user-agent"]` or
+// `.items[0].id`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath {
+    segments: Vec<Segment>,
+}
+
+impl JsonPath {
+    pub fn parse(source: &str) -> Result<JsonPath, &'static str> {
+        let rest = source
+            .strip_prefix('.')
+            .ok_or("JSON path must start with .")?;
+        let mut chars = rest.chars().peekable();
+        let mut segments = vec![];
+
+        // The leading dot may be followed directly by a key, e.g. `.a`.
+        let mut expect_key = chars.peek().is_some_and(|c| *c != '[');
+
+        loop {
+            if expect_key {
+                let mut key = String::new();
+                while let Some(&c) = chars
+                    .peek()
+                    .filter(|c| c.is_alphanumeric() || **c == '_' || **c == '-')
+                {
+                    key.push(c);
+                    chars.next();
+                }
+                if key.is_empty() {
+                    return Err("Invalid JSON path");
+                }
+                segments.push(Segment::Key(key));
+                expect_key = false;
+            }
+
+            match chars.next() {
+                None => break,
+                Some('.') => expect_key = true,
+                Some('[') => {
+                    segments.push(parse_bracket(&mut chars)?);
+                    if chars.next() != Some(']') {
+                        return Err("Invalid JSON path");
+                    }
+                }
+                Some(_) => return Err("Invalid JSON path"),
+            }
+        }
+
+        Ok(JsonPath { segments })
+    }
+
+    pub fn lookup<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.segments
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.get(key),
+                Segment::Index(index) => value.get(index),
+            })
+    }
+
+    // Parses the line and renders the value at the path: strings come out
+    // raw (like `jq -r`), everything else as compact JSON. None when the line
+    // is not JSON or the path is missing.
+    pub fn extract(&self, line: &str) -> Option<String> {
+        let document: Value = serde_json::from_str(line).ok()?;
+
+        self.lookup(&document).map(render_value)
+    }
+}
+
+fn parse_bracket(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+) -> Result<Segment, &'static str> {
+    if chars.peek() == Some(&'"') {
+        chars.next();
+        let mut key = String::new();
+        loop {
+            match chars.next() {
+                Some('\\') => key.push(chars.next().ok_or("Invalid JSON path")?),
+                Some('"') => return Ok(Segment::Key(key)),
+                Some(c) => key.push(c),
+                None => return Err("Invalid JSON path"),
+            }
+        }
+    }
+
+    let mut digits = String::new();
+    while let Some(&digit) = chars.peek().filter(|d| d.is_ascii_digit()) {
+        digits.push(digit);
+        chars.next();
+    }
+
+    digits
+        .parse()
+        .map(Segment::Index)
+        .map_err(|_| "Invalid JSON path")
+}
+
+#[derive(Debug)]
+pub enum JsonCondition {
+    Matches(Regex),
+    Compare(Comparison, f64),
+}
+
+#[derive(Debug)]
+pub struct JsonFilter {
+    path: JsonPath,
+    condition: JsonCondition,
+}
+
+impl JsonCondition {
+    // A condition such as `>=500` or `!=0` compares numerically; anything
+    // else is a regex matched against the rendered value.
+    pub fn parse(source: &str) -> Result<JsonCondition, &'static str> {
+        if let Some(("", comparison, number)) = Comparison::split(source) {
+            if let Ok(number) = number.trim().parse::<f64>() {
+                return Ok(JsonCondition::Compare(comparison, number));
+            }
+        }
+
+        Regex::new(source)
+            .map(JsonCondition::Matches)
+            .map_err(|_| "Invalid regular expression")
+    }
+
+    pub fn matches(&self, value: &Value) -> bool {
+        match self {
+            JsonCondition::Matches(regex) => regex.is_match(&render_value(value)),
+            JsonCondition::Compare(comparison, expected) => {
+                let actual = match value {
+                    Value::Number(number) => number.as_f64(),
+                    Value::String(text) => text.trim().parse().ok(),
+                    _ => None,
+                };
+
+                actual
+                    .and_then(|actual| actual.partial_cmp(expected))
+                    .is_some_and(|ordering| comparison.holds(ordering))
+            }
+        }
+    }
+}
+
+impl PartialEq for JsonCondition {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (JsonCondition::Matches(left), JsonCondition::Matches(right)) => {
+                left.as_str() == right.as_str()
+            }
+            (
+                JsonCondition::Compare(left_comparison, left_number),
+                JsonCondition::Compare(right_comparison, right_number),
+            ) => left_comparison == right_comparison && left_number == right_number,
+            _ => false,
+        }
+    }
+}
+
+impl JsonFilter {
+    pub fn new(path: JsonPath, condition: JsonCondition) -> JsonFilter {
+        JsonFilter { path, condition }
+    }
+
+    // Lines that are not JSON or lack the path never match.
+    pub fn matches(&self, line: &str) -> bool {
+        serde_json::from_str::<Value>(line)
+            .ok()
+            .as_ref()
+            .and_then(|document| self.path.lookup(document))
+            .is_some_and(|value| self.condition.matches(value))
+    }
+}
+
+impl PartialEq for JsonFilter {
+    fn eq(&self, other: &Self) -> bool {
+        self.path == other.path && self.condition == other.condition
+    }
+}
+
+pub fn render_value(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+// Writes a JSON line out again, either indented over several lines or with no
+// whitespace at all. Keys keep the order they came in.
+pub fn reformat(line: &str, pretty: bool) -> Option<String> {
+    let value: Value = serde_json::from_str(line).ok()?;
+
+    match pretty {
+        true => serde_json::to_string_pretty(&value).ok(),
+        false => serde_json::to_string(&value).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{reformat, JsonCondition, JsonFilter, JsonPath};
+
+    #[test]
+    fn reformat_keeps_key_order() {
+        //+ Arrange
+        let line = r#"{ "b": 1,  "a": [1, 2] }"#;
+
+        //+ Act + Assert
+        assert_eq!(reformat(line, false).unwrap(), r#"{"b":1,"a":[1,2]}"#);
+        assert_eq!(
+            reformat(line, true).unwrap(),
+            "{\n  \"b\": 1,\n  \"a\": [\n    1,\n    2\n  ]\n}"
+        );
+        assert_eq!(reformat("{", true), None);
+    }
+
+    #[test]
+    fn parse_rejects_malformed_paths() {
+        //+ Act + Assert
+        assert!(JsonPath::parse("request").is_err());
+        assert!(JsonPath::parse(".a[").is_err());
+        assert!(JsonPath::parse(".a[\"b\"").is_err());
+        assert!(JsonPath::parse(".a..b").is_err());
+        assert!(JsonPath::parse(".a[x]").is_err());
+    }
+
+    #[test]
+    fn extract_follows_keys_and_indices() {
+        //+ Arrange
+        let line =
+            r#"{"request":{"headers":{"user-agent":"curl/8"}},"items":[{"id":1},{"id":[2,3]}]}"#;
+
+        //+ Act + Assert
+        assert_eq!(
+            JsonPath::parse(r#".request.headers["user-agent"]"#)
+                .unwrap()
+                .extract(line),
+            Some("curl/8".to_string())
+        );
+        assert_eq!(
+            JsonPath::parse(".items[1].id").unwrap().extract(line),
+            Some("[2,3]".to_string())
+        );
+        assert_eq!(
+            JsonPath::parse(r#".["items"][0]"#).unwrap().extract(line),
+            Some(r#"{"id":1}"#.to_string())
+        );
+        assert_eq!(
+            JsonPath::parse(".").unwrap().extract("42"),
+            Some("42".to_string())
+        );
+        assert_eq!(JsonPath::parse(".missing").unwrap().extract(line), None);
+        assert_eq!(JsonPath::parse(".a").unwrap().extract("not json"), None);
+    }
+
+    #[test]
+    fn filter_matches_regex_or_number() {
+        //+ Arrange
+        let by_regex = JsonFilter::new(
+            JsonPath::parse(".level").unwrap(),
+            JsonCondition::parse("^(warn|error)$").unwrap(),
+        );
+        let by_number = JsonFilter::new(
+            JsonPath::parse(".status").unwrap(),
+            JsonCondition::parse(">=500").unwrap(),
+        );
+
+        //+ Act + Assert
+        assert!(by_regex.matches(r#"{"level":"error"}"#));
+        assert!(!by_regex.matches(r#"{"level":"info"}"#));
+        assert!(!by_regex.matches("level=error"));
+        assert!(by_number.matches(r#"{"status":503}"#));
+        assert!(by_number.matches(r#"{"status":"500"}"#));
+        assert!(!by_number.matches(r#"{"status":404}"#));
+        assert!(!by_number.matches(r#"{"code":503}"#));
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use regex::Regex;
+
+// Keeps one line per key, where the key is the first capture group of the
+// regex (or its whole match). Keeping the first line streams; keeping the last
+// buffers one line per key and emits them at the end of input, in the order
+// the keys were first seen. Lines without a key are dropped.
+#[derive(Debug)]
+pub struct PerKey {
+    regex: Regex,
+    keep_last: bool,
+    indices: HashMap<String, usize>,
+    lines: Vec<String>,
+}
+
+impl PerKey {
+    pub fn new(regex: Regex, keep_last: bool) -> PerKey {
+        PerKey {
+            regex,
+            keep_last,
+            indices: HashMap::new(),
+            lines: vec![],
+        }
+    }
+
+    pub fn keeps_last(&self) -> bool {
+        self.keep_last
+    }
+
+    // Returns the line when it should be emitted right away.
+    pub fn push(&mut self, line: String) -> Option<String> {
+        let key = {
+            let captures = self.regex.captures(&line)?;
+            captures.get(1).or(captures.get(0))?.as_str().to_string()
+        };
+
+        match (self.indices.get(&key), self.keep_last) {
+            (Some(&index), true) => {
+                self.lines[index] = line;
+                None
+            }
+            (Some(_), false) => None,
+            (None, true) => {
+                self.indices.insert(key, self.lines.len());
+                self.lines.push(line);
+                None
+            }
+            (None, false) => {
+                self.indices.insert(key, 0);
+                Some(line)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> Vec<String> {
+        self.indices.clear();
+        std::mem::take(&mut self.lines)
+    }
+
+    pub fn memory(&self) -> usize {
+        self.indices.keys().map(|key| key.len()).sum::<usize>()
+            + self.lines.iter().map(|line| line.len()).sum::<usize>()
+    }
+}
+
+impl PartialEq for PerKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+            && self.keep_last == other.keep_last
+            && self.indices == other.indices
+            && self.lines == other.lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::PerKey;
+
+    const LINES: [&str; 5] = [
+        "host=a up",
+        "host=b up",
+        "no host",
+        "host=a down",
+        "host=b degraded",
+    ];
+
+    #[test]
+    fn push_streams_first_line_per_key() {
+        //+ Arrange
+        let mut first = PerKey::new(Regex::new(r"host=(\w+)").unwrap(), false);
+
+        //+ Act
+        let kept: Vec<String> = LINES
+            .iter()
+            .filter_map(|line| first.push(line.to_string()))
+            .collect();
+
+        //+ Assert
+        assert_eq!(kept, vec!["host=a up", "host=b up"]);
+        assert!(first.flush().is_empty());
+    }
+
+    #[test]
+    fn flush_emits_last_line_per_key_in_first_seen_order() {
+        //+ Arrange
+        let mut last = PerKey::new(Regex::new(r"host=(\w+)").unwrap(), true);
+
+        //+ Act
+        for line in LINES {
+            assert_eq!(last.push(line.to_string()), None);
+        }
+
+        //+ Assert
+        assert_eq!(last.flush(), vec!["host=a down", "host=b degraded"]);
+    }
+}
@@ -0,0 +1,64 @@
+// Parses logfmt-style `key=value` pairs. Values may be double quoted with
+// backslash escapes; a bare `key` has an empty value and words without a key
+// are ignored.
+pub fn parse_pairs(line: &str) -> Vec<(String, String)> {
+    let mut pairs = vec![];
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && *c != '=') {
+            key.push(c);
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next()),
+                        '"' => break,
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    value.push(c);
+                }
+            }
+        }
+
+        if !key.is_empty() {
+            pairs.push((key, value));
+        }
+    }
+
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_pairs;
+
+    #[test]
+    fn parse_pairs_handles_quotes_and_bare_keys() {
+        //+ Act
+        let pairs = parse_pairs(r#"level=info msg="said \"hi\" twice" cached =x dur=5ms"#);
+
+        //+ Assert
+        assert_eq!(
+            pairs,
+            vec![
+                ("level".to_string(), "info".to_string()),
+                ("msg".to_string(), r#"said "hi" twice"#.to_string()),
+                ("cached".to_string(), "".to_string()),
+                ("dur".to_string(), "5ms".to_string()),
+            ]
+        );
+    }
+}
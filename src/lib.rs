@@ -0,0 +1,125 @@
+//! The streaming engine behind the `rangler` command line tool.
+//!
+//! A [`Pipeline`] is built from the same commands the tool takes and turns
+//! each input line into zero or more output lines; steps that buffer, such as
+//! `top` or `align`, hand over what they hold from [`Pipeline::finish`].
+//!
+//! ```
+//! use zeezey::Pipeline;
+//!
+//! let mut pipeline = Pipeline::build_pipeline(&["trim", "dedupe", "upper"]).unwrap();
+//! assert_eq!(pipeline.apply(" a ").unwrap(), vec!["A".to_string()]);
+//! assert!(pipeline.apply("a").unwrap().is_empty());
+//! assert!(pipeline.finish().unwrap().is_empty());
+//! ```
+//!
+//! To drive whole files the way the tool does, with its inputs, outputs and
+//! reporting, parse [`Options`] and hand them to [`run`].
+
+mod accesslog;
+mod align;
+mod bench;
+mod binary;
+mod buffers;
+mod builder;
+mod calc;
+mod checkpoint;
+mod chunk;
+mod codec;
+mod color;
+mod columns;
+mod completions;
+mod config;
+mod csv;
+mod dedupe;
+mod definition;
+mod degradation;
+mod distinct;
+mod encoding;
+mod error;
+mod exec;
+mod expand;
+mod expr;
+mod fields;
+mod follow;
+#[cfg(feature = "geoip")]
+mod geoip;
+mod group;
+mod hash;
+mod help;
+mod html;
+mod http;
+mod identifier;
+mod inputs;
+mod jobs;
+mod json;
+mod keyed;
+mod kv;
+mod lint;
+mod listen;
+mod locale;
+mod markdown;
+mod merge;
+mod normalize;
+mod optimize;
+pub mod options;
+pub mod output;
+mod parallel;
+mod partition;
+mod percentile;
+pub mod pipeline;
+mod plugin;
+mod presets;
+mod preview;
+mod records;
+mod redact;
+mod reference;
+mod reload;
+mod repl;
+mod route;
+mod run;
+#[cfg(feature = "s3")]
+mod s3;
+mod sample;
+#[cfg(feature = "script")]
+mod script;
+mod sink;
+mod sort;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod staged;
+pub mod stats;
+mod step;
+mod syntax;
+mod syslog;
+mod template;
+mod throttle;
+mod timestamp;
+mod top;
+mod trace;
+mod translate;
+mod units;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod width;
+mod window;
+
+pub use bench::{bench, Corpus, Profile};
+pub use builder::PipelineBuilder;
+pub use completions::completions;
+pub use config::load_config;
+pub use definition::load_definition;
+pub use error::RanglerError;
+pub use hash::HashAlgorithm;
+pub use help::{command_help, suggest_command, CommandHelp, COMMANDS};
+pub use normalize::NormalizationForm;
+pub use options::Options;
+pub use pipeline::{Pipeline, PipelineStep};
+pub use plugin::{load_plugin, Emit, PluginCommandV1};
+pub use presets::{load_preset, save_preset};
+pub use records::{RecordReader, RecordSeparator};
+pub use repl::{load_sample, Repl};
+pub use run::{run, Engine};
+pub use stats::RunSummary;
+pub use step::Step;
+pub use syntax::split_pipeline;
@@ -0,0 +1,180 @@
+use crate::{
+    options::Options,
+    pipeline::{Pipeline, PipelineStep},
+};
+
+// Orderings that build fine but probably do not do what was meant, found
+// once the pipeline is built so they can be pointed out before any input is
+// read.
+pub fn lint(pipeline: &Pipeline, options: &Options) -> Vec<String> {
+    let steps: Vec<(usize, &str, &PipelineStep)> = pipeline
+        .named_steps()
+        .enumerate()
+        .map(|(index, (name, step))| (index + 1, name, step))
+        .collect();
+    let mut warnings = vec![];
+
+    for pair in steps.windows(2) {
+        let ((first, first_name, first_step), (second, second_name, second_step)) =
+            (pair[0], pair[1]);
+        if is_case_change(first_step) && is_case_change(second_step) {
+            warnings.push(format!(
+                "step {} ({}) has no effect, step {} ({}) changes the case again",
+                first, first_name, second, second_name
+            ));
+        }
+    }
+
+    for &(index, name, step) in &steps {
+        if step.is_fancy_filter() {
+            warnings.push(format!(
+                "step {} ({}) matches with fancy-regex for lookaround or backreferences, which backtracks and can be far slower than other filters",
+                index, name
+            ));
+        }
+    }
+
+    for (position, &(dedupe, dedupe_name, step)) in steps.iter().enumerate() {
+        if !is_dedupe(step) {
+            continue;
+        }
+        if let Some((normalize, normalize_name, _)) = steps[position + 1..]
+            .iter()
+            .find(|(_, _, step)| is_normalization(step))
+        {
+            warnings.push(format!(
+                "step {} ({}) runs before step {} ({}), so lines that only differ until then are all kept; move it after?",
+                dedupe, dedupe_name, normalize, normalize_name
+            ));
+        }
+    }
+
+    if options.is_endless() {
+        for &(index, name, step) in &steps {
+            match step {
+                PipelineStep::Dedupe(..) if options.max_memory.is_none() => warnings.push(format!(
+                    "step {} ({}) remembers every distinct line and grows without bound on endless input; use --recent, --approx or --max-memory",
+                    index, name
+                )),
+                PipelineStep::Align(_)
+                | PipelineStep::DedupeCount(_)
+                | PipelineStep::Sort(_)
+                | PipelineStep::Top(_)
+                | PipelineStep::TopBy(_)
+                | PipelineStep::GroupBy(_)
+                | PipelineStep::Stats(_)
+                | PipelineStep::PerWindow(_) => warnings.push(format!(
+                    "step {} ({}) only emits at the end of input, which never comes when following",
+                    index, name
+                )),
+                PipelineStep::PerKey(per_key) if per_key.keeps_last() => warnings.push(format!(
+                    "step {} ({}) only emits at the end of input, which never comes when following",
+                    index, name
+                )),
+                _ => {}
+            }
+        }
+    }
+
+    warnings
+}
+
+fn is_case_change(step: &PipelineStep) -> bool {
+    matches!(
+        step,
+        PipelineStep::Lower
+            | PipelineStep::Upper
+            | PipelineStep::LocaleLower(_)
+            | PipelineStep::LocaleUpper(_)
+    )
+}
+
+fn is_dedupe(step: &PipelineStep) -> bool {
+    matches!(
+        step,
+        PipelineStep::Dedupe(..)
+            | PipelineStep::DedupeRecent(_)
+            | PipelineStep::DedupeWindow(_)
+            | PipelineStep::DedupeApprox(_)
+            | PipelineStep::DedupeSpill(_)
+            | PipelineStep::DedupeCount(_)
+    )
+}
+
+// Steps that make lines which looked different the same.
+fn is_normalization(step: &PipelineStep) -> bool {
+    matches!(
+        step,
+        PipelineStep::Trim
+            | PipelineStep::TrimStart
+            | PipelineStep::TrimEnd
+            | PipelineStep::TrimChars(..)
+            | PipelineStep::Lower
+            | PipelineStep::Upper
+            | PipelineStep::LocaleLower(_)
+            | PipelineStep::LocaleUpper(_)
+            | PipelineStep::Normalize(_)
+            | PipelineStep::Ascii
+            | PipelineStep::StripHtml(_)
+            | PipelineStep::UnescapeHtml
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lint;
+    use crate::{options::Options, pipeline::Pipeline};
+
+    #[test]
+    fn lint_warns_about_ineffective_orderings() {
+        //+ Arrange
+        let pipeline =
+            Pipeline::build_pipeline(&["upper", "lower", "dedupe", "trim", "upper"]).unwrap();
+
+        //+ Act
+        let warnings = lint(&pipeline, &Options::default());
+
+        //+ Assert
+        assert_eq!(
+            warnings,
+            vec![
+                "step 1 (upper) has no effect, step 2 (lower) changes the case again".to_string(),
+                "step 3 (dedupe) runs before step 4 (trim), so lines that only differ until then are all kept; move it after?".to_string(),
+            ]
+        );
+        assert!(lint(
+            &Pipeline::build_pipeline(&["trim", "lower", "dedupe"]).unwrap(),
+            &Options::default()
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn lint_warns_about_unbounded_steps_on_endless_input() {
+        //+ Arrange
+        let pipeline = Pipeline::build_pipeline(&["dedupe", "top", "3"]).unwrap();
+        let following = Options {
+            follow: Some("app.log".to_string()),
+            ..Options::default()
+        };
+
+        //+ Act
+        let unlimited = lint(&pipeline, &following);
+        let limited = Options {
+            max_memory: Some(1 << 20),
+            ..following
+        };
+        let warnings = lint(&pipeline, &limited);
+
+        //+ Assert
+        assert_eq!(unlimited.len(), 2);
+        assert_eq!(
+            warnings,
+            vec![
+                "step 2 (top) only emits at the end of input, which never comes when following"
+                    .to_string()
+            ]
+        );
+        assert!(lint(&pipeline, &Options::default()).is_empty());
+    }
+}
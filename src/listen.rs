@@ -0,0 +1,276 @@
+use std::{
+    io::{BufReader, Error, ErrorKind, Read, Result},
+    net::{TcpListener, UdpSocket},
+    sync::mpsc::{sync_channel, Receiver, SyncSender},
+    thread,
+};
+
+use crate::records::{RecordReader, RecordSeparator};
+
+// How many records can wait for the pipeline before the connections stop
+// being read, leaving the senders to wait on the socket.
+const CHANNEL_CAPACITY: usize = 1024;
+
+// A connection that sends a longer line than this without ending it is
+// closed, rather than buffered for as long as it goes on.
+const MAX_LINE_LENGTH: usize = 1 << 20;
+
+// Accepts connections on a TCP or Unix socket and reads them all at once,
+// each on its own thread, or receives datagrams on a UDP socket, each one a
+// record of its own. Connections are split into records with the run's own
+// separator and each is handed over whole, ended by the separator again, so
+// records from different connections never interleave, and the reader never
+// reaches EOF.
+pub struct Listener {
+    records: Receiver<Vec<u8>>,
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl Listener {
+    pub fn bind(
+        address: &str,
+        separator: &RecordSeparator,
+    ) -> std::result::Result<Listener, String> {
+        let (sender, records) = sync_channel(CHANNEL_CAPACITY);
+        let failed = |_| format!("Could not listen on {}", address);
+
+        if let Some(address) = address.strip_prefix("tcp://") {
+            let listener = TcpListener::bind(address).map_err(failed)?;
+            let separator = separator.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming().filter_map(Result::ok) {
+                    spawn_connection(stream, separator.clone(), sender.clone());
+                }
+            });
+        } else if let Some(address) = address.strip_prefix("udp://") {
+            let socket = UdpSocket::bind(address).map_err(failed)?;
+            spawn_datagrams(socket, terminator(separator).to_vec(), sender);
+        } else if let Some(path) = address.strip_prefix("unix://") {
+            #[cfg(unix)]
+            {
+                let listener = std::os::unix::net::UnixListener::bind(path).map_err(failed)?;
+                let separator = separator.clone();
+                thread::spawn(move || {
+                    for stream in listener.incoming().filter_map(Result::ok) {
+                        spawn_connection(stream, separator.clone(), sender.clone());
+                    }
+                });
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = (path, sender);
+                return Err("Unix sockets are not supported on this platform".to_string());
+            }
+        } else {
+            return Err(format!("Invalid listen address {}", address));
+        }
+
+        Ok(Listener {
+            records,
+            pending: vec![],
+            offset: 0,
+        })
+    }
+}
+
+// What ends a record handed over: a literal separator, or a newline, which
+// multiline and CSV records are split on again.
+fn terminator(separator: &RecordSeparator) -> &[u8] {
+    match separator {
+        RecordSeparator::Literal(separator) => separator,
+        _ => b"\n",
+    }
+}
+
+fn spawn_connection(
+    stream: impl Read + Send + 'static,
+    separator: RecordSeparator,
+    sender: SyncSender<Vec<u8>>,
+) {
+    thread::spawn(move || {
+        let end = terminator(&separator).to_vec();
+        let stream = LineLimit {
+            inner: stream,
+            end: *end.last().unwrap_or(&b'\n'),
+            length: 0,
+        };
+        let mut records = RecordReader::new(BufReader::new(stream), separator);
+        while let Ok(Some((mut record, _))) = records.next_record() {
+            record.extend_from_slice(&end);
+            if sender.send(record).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// Fails the read once a line runs past MAX_LINE_LENGTH. Lines end at the last
+// byte of the separator.
+struct LineLimit<R> {
+    inner: R,
+    end: u8,
+    length: usize,
+}
+
+impl<R: Read> Read for LineLimit<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let read = self.inner.read(buf)?;
+        for &byte in &buf[..read] {
+            self.length = match byte == self.end {
+                true => 0,
+                false => self.length + 1,
+            };
+            if self.length > MAX_LINE_LENGTH {
+                return Err(Error::new(ErrorKind::InvalidData, "Line too long"));
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+// Every datagram is ended by the separator, so one that lacks it still makes
+// a record of its own; a sender that packs several records into one passes
+// them on as separate records.
+fn spawn_datagrams(socket: UdpSocket, end: Vec<u8>, sender: SyncSender<Vec<u8>>) {
+    thread::spawn(move || {
+        let mut buffer = vec![0; 65536];
+        loop {
+            let received = match socket.recv(&mut buffer) {
+                Ok(received) => received,
+                Err(_) => continue,
+            };
+            let mut datagram = buffer[..received].to_vec();
+            if !datagram.ends_with(&end) {
+                datagram.extend_from_slice(&end);
+            }
+            if sender.send(datagram).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+impl Read for Listener {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        while self.offset == self.pending.len() {
+            // The accepting thread keeps a sender alive, so this only ends
+            // when that thread has died.
+            match self.records.recv() {
+                Ok(record) => {
+                    self.pending = record;
+                    self.offset = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let read = buf.len().min(self.pending.len() - self.offset);
+        buf[..read].copy_from_slice(&self.pending[self.offset..self.offset + read]);
+        self.offset += read;
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+
+    use super::{Listener, MAX_LINE_LENGTH};
+    use crate::records::RecordSeparator;
+
+    #[test]
+    fn bind_rejects_unknown_schemes() {
+        //+ Act + Assert
+        assert!(Listener::bind("sctp://127.0.0.1:5000", &RecordSeparator::Newline).is_err());
+        assert!(Listener::bind("127.0.0.1:5000", &RecordSeparator::Newline).is_err());
+    }
+
+    #[test]
+    fn read_takes_every_datagram_as_a_record() {
+        //+ Arrange
+        let probe = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = probe.local_addr().unwrap();
+        drop(probe);
+        let listener =
+            Listener::bind(&format!("udp://{}", address), &RecordSeparator::Newline).unwrap();
+        let mut reader = BufReader::new(listener);
+        let sender = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        //+ Act
+        sender
+            .send_to(b"<34>Oct 11 22:14:15 host su: failed", address)
+            .unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        sender.send_to(b"<13>second\n", address).unwrap();
+        reader.read_line(&mut line).unwrap();
+
+        //+ Assert
+        assert_eq!(line, "<34>Oct 11 22:14:15 host su: failed\n<13>second\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_collects_whole_lines_from_every_connection() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-listen-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = Listener::bind(
+            &format!("unix://{}", path.display()),
+            &RecordSeparator::Newline,
+        )
+        .unwrap();
+        let mut reader = BufReader::new(listener);
+
+        //+ Act
+        let mut first = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        first.write_all(b"one\n").unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+
+        let mut second = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        second.write_all(b"two without newline").unwrap();
+        drop(second);
+        reader.read_line(&mut line).unwrap();
+
+        //+ Assert
+        assert_eq!(line, "one\ntwo without newline\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn read_keeps_records_of_each_connection_whole() {
+        //+ Arrange
+        let path =
+            std::env::temp_dir().join(format!("rangler-listen-nul-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let separator = RecordSeparator::Literal(b"\0".to_vec());
+        let mut listener =
+            Listener::bind(&format!("unix://{}", path.display()), &separator).unwrap();
+
+        //+ Act
+        let mut first = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        first.write_all(b"one\nstill one").unwrap();
+        let mut second = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        second.write_all(b"two\0").unwrap();
+        let mut received = vec![0; 4];
+        listener.read_exact(&mut received).unwrap();
+        first.write_all(b"\0").unwrap();
+        let mut rest = vec![0; 14];
+        listener.read_exact(&mut rest).unwrap();
+
+        let mut long = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        let closed = long
+            .write_all(&vec![b'x'; MAX_LINE_LENGTH * 4])
+            .and_then(|_| long.read(&mut [0; 1]));
+
+        //+ Assert
+        assert_eq!(received, b"two\0");
+        assert_eq!(rest, b"one\nstill one\0");
+        assert!(matches!(closed, Ok(0) | Err(_)));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,70 @@
+// Case rules that differ from the Unicode defaults `lower` and `upper` use.
+// Turkish and Azerbaijani keep dotted and dotless i apart, so `I` lowers to
+// `ı` and `i` uppers to `İ`. Other languages, German included, already get
+// what they expect from the defaults, e.g. `ß` uppers to `SS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseRules {
+    Unicode,
+    Turkic,
+}
+
+impl CaseRules {
+    // Takes a locale such as `tr-TR`, `tr_TR` or `de`; only the language
+    // decides the rules.
+    pub fn parse(locale: &str) -> Result<CaseRules, &'static str> {
+        let language = locale
+            .split(['-', '_'])
+            .next()
+            .filter(|language| {
+                (2..=3).contains(&language.len())
+                    && language.chars().all(|c| c.is_ascii_alphabetic())
+            })
+            .ok_or("Invalid locale")?;
+
+        Ok(match language.to_ascii_lowercase().as_str() {
+            "tr" | "az" => CaseRules::Turkic,
+            _ => CaseRules::Unicode,
+        })
+    }
+
+    pub fn lower(self, text: &str) -> String {
+        match self {
+            CaseRules::Unicode => text.to_lowercase(),
+            // An I followed by a combining dot above is a dotted I written in
+            // two parts.
+            CaseRules::Turkic => text
+                .replace("I\u{307}", "i")
+                .replace('I', "ı")
+                .replace('İ', "i")
+                .to_lowercase(),
+        }
+    }
+
+    pub fn upper(self, text: &str) -> String {
+        match self {
+            CaseRules::Unicode => text.to_uppercase(),
+            CaseRules::Turkic => text.replace('i', "İ").to_uppercase(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CaseRules;
+
+    #[test]
+    fn turkic_rules_keep_dotted_and_dotless_i_apart() {
+        //+ Arrange
+        let turkish = CaseRules::parse("tr-TR").unwrap();
+
+        //+ Act + Assert
+        assert_eq!(turkish, CaseRules::Turkic);
+        assert_eq!(turkish.upper("istanbul ılık"), "İSTANBUL ILIK");
+        assert_eq!(turkish.lower("İSTANBUL ILIK"), "istanbul ılık");
+        assert_eq!(
+            CaseRules::parse("de_DE").unwrap().upper("straße"),
+            "STRASSE"
+        );
+        assert!(CaseRules::parse("not a locale").is_err());
+    }
+}
@@ -1,99 +1,379 @@
 use std::{
-    io::{stdin, stdout, BufRead, ErrorKind, Write},
+    fs::File,
+    io::{stdin, stdout, BufRead, BufReader, Write},
     process::exit,
 };
 
-use crate::pipeline::Pipeline;
-use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
-use regex::Regex;
+use clap::{Arg, ArgMatches, Command};
+use zeezey::{
+    bench, command_help, completions, load_config, load_definition, load_plugin, load_preset,
+    load_sample, output::BROKEN_PIPE, run, save_preset, split_pipeline, suggest_command, Corpus,
+    Engine, Options, RanglerError, Repl, COMMANDS,
+};
 
-mod pipeline;
+static USAGE: &str = r#"rangler [options] [commands] [-- <file>...]
+       rangler [options] '<command> [arguments] | <command> ...' // one quoted pipeline; words may be quoted, and /regex/flags are regex literals
+       rangler save-preset <name> [options] [commands] // saves them to ~/.config/rangler/presets/<name>.yaml
+       rangler run <name> [options] [-- <file>...] // runs a saved preset
+       rangler help [command] // shows how a command is used, with an example
+       rangler bench [--lines <n>] [--synthetic] [options] [commands] [-- <file>...] // times each step over the first <n> lines of the input, or of made-up access log lines, printing lines per second and each step's share of the time; steps run as written, once more for every later step, so side effects like tee repeat
+       rangler repl [--lines <n>] [file] // builds a pipeline step by step, showing the first lines of the input through it
+       rangler completions <bash|zsh|fish|powershell> // writes a shell completion script"#;
 
-static USAGE: &str = r#"Usage: rangler [commands]
-    filter <regex> // excludes lines that do not match",
-    append <quoted string> // appends the text in quotes to every line
-    prepend <quoted string> // prepends the text in quotes to every line
-    trim // removes whitespace at both ends of every line
-    lower // converts English letters to lower case
-    upper // converts English letters to upper case
-    dedupe // dedupes lines"#;
+static NOTES: &str = r#"Input files may also be http:// or https:// URLs, which are resumed with range requests
+when the connection drops, or s3://bucket/key objects when built with the s3 feature.
+Inputs compressed with gzip, bzip2 or zstd are decompressed transparently.
 
-fn main() {
-    match inner_main() {
-        Ok(()) => exit(0),
-        Err(message) => {
-            println!("{}", message);
-            println!("{}", USAGE);
-            exit(0)
-        }
+Defaults for any option can go in ~/.config/rangler/config.toml, one per key without
+its dashes, e.g. read-buffer = "4MiB" or no-progress = true; options given here win.
+RANGLER_CONFIG names another config file, or none when empty."#;
+
+fn cli() -> Command {
+    // The subcommands pass their options and commands on to be parsed with
+    // the preset's or the config file's, so clap only sees them as the
+    // arguments that follow, and the files after `--`.
+    let arguments = || {
+        Arg::new("arguments")
+            .value_name("ARGS")
+            .num_args(0..)
+            .allow_hyphen_values(true)
+    };
+    let files = || {
+        Arg::new("files")
+            .value_name("FILE")
+            .num_args(0..)
+            .last(true)
+    };
+    let commands: String = COMMANDS
+        .iter()
+        .map(|command| format!("\n    {} // {}", command.usage, command.about))
+        .collect();
+
+    Options::command()
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Streams lines of text through a pipeline of commands")
+        .override_usage(USAGE)
+        .after_help(format!("{}\n\nCommands:{}", NOTES, commands))
+        .subcommand_help_heading("Subcommands")
+        .disable_help_subcommand(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("help")
+                .about("Shows how a command is used, with an example")
+                .arg(Arg::new("command")),
+        )
+        .subcommand(
+            Command::new("completions")
+                .about("Writes a shell completion script")
+                .arg(Arg::new("shell").required(true).value_parser([
+                    "bash",
+                    "zsh",
+                    "fish",
+                    "powershell",
+                ])),
+        )
+        .subcommand(
+            Command::new("save-preset")
+                .about("Saves options and commands under a name")
+                .arg(Arg::new("name").required(true))
+                .arg(arguments())
+                .arg(files()),
+        )
+        .subcommand(
+            Command::new("repl")
+                .about("Builds a pipeline step by step against the first lines of the input")
+                .arg(
+                    Arg::new("lines")
+                        .long("lines")
+                        .value_name("N")
+                        .default_value("20")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(Arg::new("file")),
+        )
+        .subcommand(
+            Command::new("bench")
+                .about("Profiles how fast each step of a pipeline runs")
+                .arg(
+                    Arg::new("lines")
+                        .long("lines")
+                        .value_name("N")
+                        .default_value("1000000")
+                        .value_parser(clap::value_parser!(usize)),
+                )
+                .arg(
+                    Arg::new("synthetic")
+                        .long("synthetic")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(arguments())
+                .arg(files()),
+        )
+        .subcommand(
+            Command::new("run")
+                .about("Runs a saved preset, with any options given in front of its own")
+                .arg(Arg::new("name").required(true))
+                .arg(arguments())
+                .arg(files()),
+        )
+}
+
+fn values(matches: &ArgMatches, id: &str) -> Vec<String> {
+    matches
+        .get_many::<String>(id)
+        .map_or(vec![], |values| values.cloned().collect())
+}
+
+// Puts back the `--` clap takes off when the files come first; after other
+// arguments it is kept as one of them.
+fn files(matches: &ArgMatches) -> Vec<String> {
+    match values(matches, "files") {
+        files if files.is_empty() => files,
+        files => [vec!["--".to_string()], files].concat(),
     }
 }
 
-fn inner_main() -> Result<(), String> {
-    let args: Vec<String> = std::env::args().collect();
-    let mut pipeline = Pipeline::build_pipeline(&args[1..])?;
+fn arguments(matches: &ArgMatches) -> Vec<String> {
+    [values(matches, "arguments"), files(matches)].concat()
+}
 
-    let mut total_bytes_read = 0;
-    let mut bytes_at_last_message = 0;
+// Like print!, but a reader that went away early is not an error.
+fn print(text: &str) {
+    std::io::stdout().write_all(text.as_bytes()).ok();
+}
 
-    let progress = ProgressBar::new_spinner()
-        .with_style(ProgressStyle::with_template("[{elapsed_precise}] {msg}").unwrap());
+fn fail(message: &str) -> ! {
+    eprintln!("rangler: {}", message);
+    exit(2)
+}
 
-    let mut std_out = std::io::BufWriter::with_capacity(1_000_000, stdout());
-    let mut std_in = std::io::BufReader::with_capacity(1_000_000, stdin());
-    let mut line_of_bytes: Vec<u8> = Vec::new();
+// Mistakes in the commands also show where they are.
+fn usage_error(error: &RanglerError, commands: &[String]) -> ! {
+    let located = match error.locate(commands) {
+        Some(located) => format!("\n{}", located),
+        None => String::new(),
+    };
 
-    loop {
-        let mut buffer = std_in.fill_buf().map_err(|e| "IO Error")?;
+    fail(&format!(
+        "{}{}\n\nFor more information, try 'rangler --help'.",
+        error, located
+    ))
+}
 
-        if buffer.len() == 0 {
-            break;
+// The sample comes from the file, or from stdin, in which case the commands
+// are read from the terminal instead.
+fn repl(file: Option<&String>, lines: usize) -> ! {
+    let (sample, commands): (_, Box<dyn BufRead>) = match file {
+        Some(path) => {
+            let file = File::open(path)
+                .unwrap_or_else(|_| fail(&format!("Could not open input file {}", path)));
+            (
+                load_sample(BufReader::new(file), lines),
+                Box::new(stdin().lock()),
+            )
+        }
+        None => {
+            let sample = load_sample(stdin().lock(), lines);
+            let terminal = File::open("/dev/tty").unwrap_or_else(|_| {
+                fail("Reading the sample from stdin needs a terminal to type commands in")
+            });
+            (sample, Box::new(BufReader::new(terminal)))
         }
+    };
 
-        loop {
-            let consumed = buffer
-                .read_until(b'\n', &mut line_of_bytes)
-                .map_err(|e| "IO Error")?;
+    let sample = sample.unwrap_or_else(|message| fail(message));
+    match Repl::new(sample).run(commands, stdout().lock()) {
+        Ok(()) => exit(0),
+        Err(_) => fail("Could not read commands"),
+    }
+}
 
-            if consumed == 0 || line_of_bytes.last().unwrap() == &b'\n' {
-                break;
+// Exits like grep: 2 for errors, and with --grep-status 1 when nothing was
+// emitted. Mistakes on the command line point at --help.
+// A reader that went away early (`rangler ... | head`) is not an error.
+fn main() {
+    let given = Options::spell_out(&std::env::args().skip(1).collect::<Vec<_>>());
+    let matches =
+        cli().get_matches_from(std::iter::once("rangler".to_string()).chain(given.clone()));
+
+    let mut corpus = None;
+    let (preset, args) = match matches.subcommand() {
+        Some(("help", matches)) => match matches.get_one::<String>("command") {
+            Some(name) => match command_help(name) {
+                Some(help) => {
+                    print(&format!(
+                        "{}\n    {}\n\nUsage: {}\n\nExample:\n    {}\n",
+                        help.name, help.about, help.usage, help.example
+                    ));
+                    exit(0)
+                }
+                None => match suggest_command(name) {
+                    Some(suggestion) => fail(&format!(
+                        "Unknown command '{}', did you mean '{}'?",
+                        name, suggestion
+                    )),
+                    None => fail(&format!("Unknown command '{}', see rangler --help", name)),
+                },
+            },
+            None => {
+                cli().print_long_help().ok();
+                exit(0)
+            }
+        },
+        Some(("completions", matches)) => {
+            let shell = matches.get_one::<String>("shell").unwrap();
+            match completions(shell, cli()) {
+                Ok(script) => {
+                    print(&script);
+                    exit(0)
+                }
+                Err(message) => fail(message),
             }
         }
+        Some(("repl", matches)) => repl(
+            matches.get_one::<String>("file"),
+            *matches.get_one::<usize>("lines").unwrap(),
+        ),
+        Some(("bench", matches)) => {
+            let lines = *matches.get_one::<usize>("lines").unwrap();
+            corpus = Some(match matches.get_flag("synthetic") {
+                true => Corpus::Synthetic(lines),
+                false => Corpus::Input(lines),
+            });
+            (None, arguments(matches))
+        }
+        Some(("save-preset", matches)) => (
+            matches.get_one::<String>("name").cloned(),
+            arguments(matches),
+        ),
+        // Options given to `run` go in front of the preset's and files after
+        // `--` go after its commands.
+        Some(("run", matches)) => {
+            let name = matches.get_one::<String>("name").unwrap();
+            let preset = load_preset(name).unwrap_or_else(|message| fail(message));
+            let extra = arguments(matches);
+            let split = extra
+                .iter()
+                .position(|arg| arg == "--")
+                .unwrap_or(extra.len());
 
-        match std::str::from_utf8(&line_of_bytes) {
-            Ok(line_of_text) => {
-                let transforemd_line = pipeline.apply(line_of_text);
+            (None, [&extra[..split], &preset, &extra[split..]].concat())
+        }
+        _ => (None, given),
+    };
 
-                if let Some(line) = transforemd_line {
-                    std_out
-                        .write_all((line + "\n").as_bytes())
-                        .expect("IO Error");
-                }
+    // The config file's options go first, so any given here override them.
+    let config = load_config().unwrap_or_else(|message| fail(message));
+    let configured = [config, args.clone()].concat();
+
+    // Plugins register their commands before the pipeline is built.
+    let parsed = Options::parse(&configured)
+        .map_err(RanglerError::from)
+        .and_then(|(options, commands)| {
+            options
+                .plugins
+                .iter()
+                // Safety: a plugin runs as native code in this process, which
+                // is exactly the trust the user grants by passing --plugin.
+                .try_for_each(|path| unsafe { load_plugin(path) })?;
+            // A lone argument can hold the whole pipeline, e.g. 'trim | dedupe'.
+            let commands = match commands {
+                [pipeline] => split_pipeline(pipeline)?,
+                commands => commands.to_vec(),
+            };
+            // Steps from a pipeline file run before any given on the command line.
+            let commands = match &options.pipeline {
+                Some(path) => [load_definition(path)?, commands].concat(),
+                None => commands,
+            };
+            Ok((options, commands))
+        });
+    let (options, commands) = parsed.unwrap_or_else(|error| usage_error(&error, &[]));
+    let mut engine =
+        Engine::build(&options, &commands).unwrap_or_else(|error| usage_error(&error, &commands));
+
+    if !options.quiet {
+        for warning in engine.lint(&options) {
+            eprintln!("rangler: warning: {}", warning);
+        }
+    }
+
+    // Benchmarks time the steps as written, so they run unoptimized.
+    if let Some(corpus) = corpus {
+        match bench(&options, &commands, corpus) {
+            Ok(profile) => {
+                print(&format!("{}\n", profile.table().join("\n")));
+                exit(0)
             }
-            Err(_) => { /* todo ignore */ }
-        };
+            Err(error) => fail(&error.to_string()),
+        }
+    }
 
-        total_bytes_read += line_of_bytes.len();
+    // Warnings name the steps as written, so optimizing waits until after.
+    if !options.no_optimize {
+        engine.optimize();
+    }
 
-        if total_bytes_read > bytes_at_last_message + 256_000 {
-            progress.inc(line_of_bytes.len() as u64);
+    // Explaining stops before anything is saved, opened or read.
+    if options.explain {
+        print(&engine.explain());
+        exit(0)
+    }
 
-            let message = format!(
-                "{} read, {} stored",
-                HumanBytes(total_bytes_read as u64),
-                HumanBytes(pipeline.get_memory() as u64)
-            );
-            progress.set_message(message);
-            bytes_at_last_message = total_bytes_read;
+    if options.preview.is_some() {
+        match engine.preview(&options) {
+            Ok(preview) => {
+                print(&preview);
+                exit(0)
+            }
+            Err(error) => fail(&error.to_string()),
+        }
+    }
 
-            std_out.flush().expect("IO Error");
+    // A preset is only saved once it parses and builds like a real run.
+    if let Some(name) = preset {
+        match save_preset(name.as_str(), &args) {
+            Ok(path) => {
+                eprintln!("rangler: saved preset {} to {}", name, path.display());
+                exit(0)
+            }
+            Err(message) => fail(message),
         }
+    }
 
-        std_in.consume(line_of_bytes.len());
-        line_of_bytes.clear();
+    match run(&options, &commands, engine) {
+        Ok(summary) if options.grep_status && summary.lines_emitted == 0 => exit(1),
+        Ok(_) => exit(0),
+        Err(message) if message == BROKEN_PIPE => exit(0),
+        Err(error) => fail(&error.to_string()),
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{arguments, cli};
+
+    #[test]
+    fn cli_keeps_options_commands_and_files_apart() {
+        //+ Arrange
+        cli().debug_assert();
 
-    std_out.flush().expect("IO Error");
-    progress.finish();
-    Ok(())
+        //+ Act
+        let matches = cli()
+            .try_get_matches_from(["rangler", "--no-progress", "upper", "-o", "--", "a.log"])
+            .unwrap();
+        let preset = cli()
+            .try_get_matches_from(["rangler", "run", "clean", "-i.bak", "--", "a.log"])
+            .unwrap();
+        let unknown = cli().try_get_matches_from(["rangler", "--frobnicate", "upper"]);
+
+        //+ Assert
+        assert!(matches.get_flag("no-progress"));
+        let commands: Vec<&String> = matches.get_many("commands").unwrap().collect();
+        assert_eq!(commands, ["upper", "-o", "--", "a.log"]);
+        let (name, preset) = preset.subcommand().unwrap();
+        assert_eq!(name, "run");
+        assert_eq!(arguments(preset), ["-i.bak", "--", "a.log"]);
+        assert!(unknown.is_err());
+    }
 }
@@ -0,0 +1,329 @@
+/// Turns Markdown into plain text a line at a time: headings, quotes and list
+/// markers go, links and images keep their text, emphasis markers are
+/// dropped and code keeps its contents. Fence lines and link definitions are
+/// dropped, which is why lines between fences have to be tracked.
+#[derive(Debug, Default, PartialEq)]
+pub struct Markdown {
+    // The character and length of the open code fence, if inside one.
+    fence: Option<(char, usize)>,
+}
+
+impl Markdown {
+    pub fn new() -> Markdown {
+        Markdown::default()
+    }
+
+    /// The text of the line, or None for lines that are only markup.
+    pub fn apply(&mut self, line: &str) -> Option<String> {
+        let trimmed = line.trim_start();
+        if let Some((marker, length)) = fence(trimmed) {
+            match self.fence {
+                Some((open, open_length))
+                    if marker == open
+                        && length >= open_length
+                        && trimmed[length..].trim().is_empty() =>
+                {
+                    self.fence = None
+                }
+                Some(_) => return Some(line.to_string()),
+                None => self.fence = Some((marker, length)),
+            }
+            return None;
+        }
+        if self.fence.is_some() {
+            return Some(line.to_string());
+        }
+        if is_rule(trimmed) || is_link_definition(trimmed) {
+            return None;
+        }
+
+        Some(inline(strip_block_markers(trimmed)))
+    }
+}
+
+// The marker character and length of a fence line like ``` or ~~~~.
+fn fence(line: &str) -> Option<(char, usize)> {
+    let marker = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let length = line.chars().take_while(|c| *c == marker).count();
+
+    (length >= 3).then_some((marker, length))
+}
+
+// Thematic breaks like ---, * * * or ___, which also underline setext
+// headings.
+fn is_rule(line: &str) -> bool {
+    let mut marks = line.chars().filter(|c| !c.is_whitespace());
+    let first = marks.next();
+
+    matches!(first, Some('-' | '*' | '_' | '='))
+        && line.chars().filter(|c| Some(*c) == first).count() >= 3
+        && marks.all(|c| Some(c) == first)
+}
+
+// Reference definitions like `[docs]: https://example.com "Docs"`.
+fn is_link_definition(line: &str) -> bool {
+    line.starts_with('[')
+        && line
+            .find("]:")
+            .is_some_and(|end| end > 1 && !line[1..end].contains(']'))
+}
+
+// Drops the quote, heading and list markers that start a line.
+fn strip_block_markers(mut line: &str) -> &str {
+    while let Some(quoted) = line.strip_prefix('>') {
+        line = quoted.trim_start();
+    }
+
+    let hashes = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) && line[hashes..].starts_with([' ', '\t']) {
+        return line[hashes..].trim().trim_end_matches('#').trim_end();
+    }
+
+    let digits = line.chars().take_while(char::is_ascii_digit).count();
+    let item = if line.starts_with(['-', '*', '+']) {
+        Some(&line[1..])
+    } else if (1..=9).contains(&digits) && line[digits..].starts_with(['.', ')']) {
+        Some(&line[digits + 1..])
+    } else {
+        None
+    };
+    if let Some(rest) = item.filter(|rest| rest.starts_with([' ', '\t'])) {
+        line = rest.trim_start();
+        for task in ["[ ] ", "[x] ", "[X] "] {
+            line = line.strip_prefix(task).unwrap_or(line);
+        }
+    }
+
+    line
+}
+
+// Rewrites links, images, code spans and emphasis within a line.
+fn inline(line: &str) -> String {
+    let characters: Vec<char> = line.chars().collect();
+    let mut text = String::with_capacity(line.len());
+    let mut index = 0;
+
+    while index < characters.len() {
+        let character = characters[index];
+        match character {
+            '\\' if characters
+                .get(index + 1)
+                .is_some_and(|next| next.is_ascii_punctuation()) =>
+            {
+                text.push(characters[index + 1]);
+                index += 2;
+            }
+            '`' => {
+                let ticks = run_length(&characters, index, '`');
+                match find_run(&characters, index + ticks, '`', ticks) {
+                    Some(end) => {
+                        let code: String = characters[index + ticks..end].iter().collect();
+                        text.push_str(code.trim());
+                        index = end + ticks;
+                    }
+                    None => {
+                        text.extend(&characters[index..index + ticks]);
+                        index += ticks;
+                    }
+                }
+            }
+            '!' if characters.get(index + 1) == Some(&'[') => match link(&characters, index + 1) {
+                Some((label, end)) => {
+                    text.push_str(&inline(&label));
+                    index = end;
+                }
+                None => {
+                    text.push('!');
+                    index += 1;
+                }
+            },
+            '[' => match link(&characters, index) {
+                Some((label, end)) => {
+                    text.push_str(&inline(&label));
+                    index = end;
+                }
+                None => {
+                    text.push('[');
+                    index += 1;
+                }
+            },
+            '<' => match autolink(&characters, index) {
+                Some((address, end)) => {
+                    text.push_str(&address);
+                    index = end;
+                }
+                None => {
+                    text.push('<');
+                    index += 1;
+                }
+            },
+            '*' | '_' | '~' => {
+                let length = run_length(&characters, index, character);
+                let before = index.checked_sub(1).map(|at| characters[at]);
+                let after = characters.get(index + length).copied();
+                let flanking = after.is_some_and(|c| !c.is_whitespace())
+                    || before.is_some_and(|c| !c.is_whitespace());
+                // Underscores inside words, like snake_case, and a lone tilde
+                // are text rather than emphasis.
+                let in_word = character == '_'
+                    && before.is_some_and(char::is_alphanumeric)
+                    && after.is_some_and(char::is_alphanumeric);
+                let marker = flanking && !in_word && (character != '~' || length == 2);
+                if !marker {
+                    text.extend(&characters[index..index + length]);
+                }
+                index += length;
+            }
+            _ => {
+                text.push(character);
+                index += 1;
+            }
+        }
+    }
+
+    text
+}
+
+fn run_length(characters: &[char], start: usize, character: char) -> usize {
+    characters[start..]
+        .iter()
+        .take_while(|c| **c == character)
+        .count()
+}
+
+// Where a run of exactly `length` copies of `character` starts, from `start`.
+fn find_run(characters: &[char], start: usize, character: char, length: usize) -> Option<usize> {
+    let mut index = start;
+    while index < characters.len() {
+        if characters[index] == character {
+            let run = run_length(characters, index, character);
+            if run == length {
+                return Some(index);
+            }
+            index += run;
+        } else {
+            index += 1;
+        }
+    }
+
+    None
+}
+
+// The label of a link like [text](url), [text][ref] or [text][] starting at
+// `start`, and where the link ends.
+fn link(characters: &[char], start: usize) -> Option<(String, usize)> {
+    let mut depth = 0;
+    let mut close = None;
+    for (index, character) in characters.iter().enumerate().skip(start) {
+        match character {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(index);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let close = close?;
+    let label: String = characters[start + 1..close].iter().collect();
+
+    let (open, end) = match characters.get(close + 1) {
+        Some('(') => ('(', ')'),
+        Some('[') => ('[', ']'),
+        _ => return None,
+    };
+    let mut depth = 0;
+    for (index, character) in characters.iter().enumerate().skip(close + 1) {
+        if *character == open {
+            depth += 1;
+        } else if *character == end {
+            depth -= 1;
+            if depth == 0 {
+                return Some((label, index + 1));
+            }
+        }
+    }
+
+    None
+}
+
+// The address of an autolink like <https://example.com> starting at `start`,
+// and where it ends.
+fn autolink(characters: &[char], start: usize) -> Option<(String, usize)> {
+    let close = characters[start..].iter().position(|c| *c == '>')? + start;
+    let address: String = characters[start + 1..close].iter().collect();
+    let is_link = (address.contains("://") || address.starts_with("mailto:"))
+        && !address.contains(char::is_whitespace);
+
+    is_link.then_some((address, close + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Markdown;
+
+    #[test]
+    fn apply_strips_block_and_inline_markup() {
+        //+ Arrange
+        let mut markdown = Markdown::new();
+        let lines = [
+            "## Getting *started* ##",
+            "> - [x] Read the [guide](https://example.com/guide \"Guide\") first",
+            "1. Run `cargo build --release` and see ![the logo][logo]",
+            "---",
+            "[logo]: https://example.com/logo.png",
+            "Use **bold**, __strong__, ~~old~~ and snake_case; 2 * 3 is \\*six\\*.",
+            "Mail <mailto:team@example.com> or compare a <b> tag",
+        ];
+
+        //+ Act
+        let text: Vec<Option<String>> = lines.iter().map(|line| markdown.apply(line)).collect();
+
+        //+ Assert
+        assert_eq!(
+            text,
+            vec![
+                Some("Getting started".to_string()),
+                Some("Read the guide first".to_string()),
+                Some("Run cargo build --release and see the logo".to_string()),
+                None,
+                None,
+                Some("Use bold, strong, old and snake_case; 2 * 3 is *six*.".to_string()),
+                Some("Mail mailto:team@example.com or compare a <b> tag".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn apply_keeps_code_fences_verbatim() {
+        //+ Arrange
+        let mut markdown = Markdown::new();
+        let lines = [
+            "````md",
+            "# not a heading",
+            "```",
+            "**kept**",
+            "````",
+            "*done*",
+        ];
+
+        //+ Act
+        let text: Vec<Option<String>> = lines.iter().map(|line| markdown.apply(line)).collect();
+
+        //+ Assert
+        assert_eq!(
+            text,
+            vec![
+                None,
+                Some("# not a heading".to_string()),
+                Some("```".to_string()),
+                Some("**kept**".to_string()),
+                None,
+                Some("done".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use std::io::{BufRead, Read};
+
+use crate::inputs::Source;
+use crate::records::{strip_carriage_returns, RecordReader, RecordSeparator};
+
+// How several inputs are read as one stream.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MergeMode {
+    // A record from each input in turn; inputs that run out drop out.
+    Interleave,
+    // The next record of every input joined on the delimiter, like `paste`.
+    // Inputs that ran out add an empty field until the last one does.
+    Zip(String),
+}
+
+// Reads its inputs record by record in the merge order and hands them on as
+// one stream of records, each ending in the separator they were read with.
+pub struct MergedReader {
+    readers: Vec<Option<RecordReader<Box<dyn BufRead>>>>,
+    mode: MergeMode,
+    terminator: Vec<u8>,
+    next: usize,
+    pending: Vec<u8>,
+    position: usize,
+}
+
+impl MergedReader {
+    pub fn new(sources: Vec<Source>, mode: MergeMode, separator: &RecordSeparator) -> MergedReader {
+        // Start and CSV records are read back on newlines just as well.
+        let terminator = match separator {
+            RecordSeparator::Literal(separator) => separator.clone(),
+            _ => b"\n".to_vec(),
+        };
+
+        MergedReader {
+            readers: sources
+                .into_iter()
+                .map(|(_, source)| Some(RecordReader::new(source, separator.clone())))
+                .collect(),
+            mode,
+            terminator,
+            next: 0,
+            pending: vec![],
+            position: 0,
+        }
+    }
+
+    // Queues the next merged record, returning false once every input ran out.
+    fn refill(&mut self) -> Result<bool, &'static str> {
+        self.pending.clear();
+        self.position = 0;
+
+        match &self.mode {
+            MergeMode::Interleave => {
+                for _ in 0..self.readers.len() {
+                    let index = self.next;
+                    self.next = (self.next + 1) % self.readers.len();
+                    if let Some(mut record) = next_record(&mut self.readers[index])? {
+                        self.pending.append(&mut record);
+                        self.pending.extend_from_slice(&self.terminator);
+                        return Ok(true);
+                    }
+                }
+
+                Ok(false)
+            }
+            MergeMode::Zip(delimiter) => {
+                let mut fields = Vec::with_capacity(self.readers.len());
+                for reader in &mut self.readers {
+                    fields.push(next_record(reader)?);
+                }
+                if fields.iter().all(Option::is_none) {
+                    return Ok(false);
+                }
+
+                for (index, field) in fields.into_iter().enumerate() {
+                    if index > 0 {
+                        self.pending.extend_from_slice(delimiter.as_bytes());
+                    }
+                    // A field's carriage return would end up mid-line.
+                    if let Some(mut field) = field {
+                        strip_carriage_returns(&mut field);
+                        self.pending.append(&mut field);
+                    }
+                }
+                self.pending.extend_from_slice(&self.terminator);
+
+                Ok(true)
+            }
+        }
+    }
+}
+
+// Reads a record from an input, closing the input at its end.
+fn next_record(
+    reader: &mut Option<RecordReader<Box<dyn BufRead>>>,
+) -> Result<Option<Vec<u8>>, &'static str> {
+    let record = match reader {
+        Some(records) => records.next_record()?,
+        None => return Ok(None),
+    };
+    if record.is_none() {
+        *reader = None;
+    }
+
+    Ok(record.map(|(record, _)| record))
+}
+
+impl Read for MergedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.pending.len() && !self.refill().map_err(std::io::Error::other)? {
+            return Ok(0);
+        }
+
+        let count = buf.len().min(self.pending.len() - self.position);
+        buf[..count].copy_from_slice(&self.pending[self.position..self.position + count]);
+        self.position += count;
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, Cursor, Read};
+
+    use super::{MergeMode, MergedReader};
+    use crate::inputs::Source;
+    use crate::records::RecordSeparator;
+
+    fn sources(texts: &[&'static str]) -> Vec<Source> {
+        texts
+            .iter()
+            .map(|text| {
+                let reader: Box<dyn BufRead> = Box::new(Cursor::new(text.as_bytes()));
+                (text.to_string(), reader)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interleave_and_zip_keep_going_until_every_input_ends() {
+        //+ Arrange
+        let texts = ["a1\na2\na3\n", "b1\r\nb2"];
+        let mut interleaved = MergedReader::new(
+            sources(&texts),
+            MergeMode::Interleave,
+            &RecordSeparator::Newline,
+        );
+        let mut zipped = MergedReader::new(
+            sources(&texts),
+            MergeMode::Zip("\t".to_string()),
+            &RecordSeparator::Newline,
+        );
+
+        //+ Act
+        let (mut interleaved_text, mut zipped_text) = (String::new(), String::new());
+        interleaved.read_to_string(&mut interleaved_text).unwrap();
+        zipped.read_to_string(&mut zipped_text).unwrap();
+
+        //+ Assert
+        assert_eq!(interleaved_text, "a1\nb1\r\na2\nb2\na3\n");
+        assert_eq!(zipped_text, "a1\tb1\na2\tb2\na3\t\n");
+    }
+}
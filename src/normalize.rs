@@ -0,0 +1,55 @@
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalizationForm {
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd,
+}
+
+impl NormalizationForm {
+    pub fn parse(name: &str) -> Result<NormalizationForm, &'static str> {
+        match name.to_lowercase().as_str() {
+            "nfc" => Ok(NormalizationForm::Nfc),
+            "nfd" => Ok(NormalizationForm::Nfd),
+            "nfkc" => Ok(NormalizationForm::Nfkc),
+            "nfkd" => Ok(NormalizationForm::Nfkd),
+            _ => Err("Unknown normalization form"),
+        }
+    }
+
+    pub fn apply(&self, input: &str) -> String {
+        match self {
+            NormalizationForm::Nfc => input.nfc().collect(),
+            NormalizationForm::Nfd => input.nfd().collect(),
+            NormalizationForm::Nfkc => input.nfkc().collect(),
+            NormalizationForm::Nfkd => input.nfkd().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NormalizationForm;
+
+    #[test]
+    fn apply_composes_and_decomposes() {
+        //+ Arrange
+        let decomposed = "Cafe\u{301}";
+        let composed = "Caf\u{e9}";
+
+        //+ Act + Assert
+        assert_eq!(NormalizationForm::Nfc.apply(decomposed), composed);
+        assert_eq!(NormalizationForm::Nfd.apply(composed), decomposed);
+        assert_eq!(
+            NormalizationForm::Nfkc.apply("\u{fb01}le \u{2460}"),
+            "file 1"
+        );
+        assert_eq!(NormalizationForm::Nfkd.apply("\u{fb01}"), "fi");
+        assert_eq!(
+            NormalizationForm::parse("nfx"),
+            Err("Unknown normalization form")
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use regex::RegexSet;
+
+use crate::{
+    pipeline::{ParsedStep, PipelineStep},
+    sink::Sink,
+};
+
+// Rewrites built steps into ones that produce the same lines with less work.
+// Predicates move ahead of exact dedupes, so fewer lines are hashed and kept,
+// runs of filters become one RegexSet and runs of cheap transforms one step.
+pub(crate) fn optimize(steps: Vec<ParsedStep>) -> Vec<ParsedStep> {
+    let captures_needed = steps.iter().any(|(_, step, _)| step.uses_captures());
+    let mut steps = hoist_predicates(steps);
+
+    for (_, step, _) in steps.iter_mut() {
+        if let PipelineStep::Route(_, Sink::Pipeline(pipeline))
+        | PipelineStep::TeePipeline(pipeline, _)
+        | PipelineStep::If(_, pipeline)
+        | PipelineStep::Repeat(pipeline, _) = step
+        {
+            pipeline.optimize();
+        }
+    }
+
+    // A merged filter no longer knows which regex matched, so the captures a
+    // later step reads have to come from the filters as written.
+    if !captures_needed {
+        steps = merge_filters(steps);
+    }
+
+    fuse_transforms(steps)
+}
+
+// Dropping a line before or after an exact dedupe, or only-duplicates, leaves
+// the same lines, as they only ever compare whole lines. Recent and approximate dedupes
+// would remember different lines, so they stay where they are, as do dedupes
+// with a state file, which later runs with other steps read back.
+fn hoist_predicates(mut steps: Vec<ParsedStep>) -> Vec<ParsedStep> {
+    for index in 1..steps.len() {
+        let mut position = index;
+        while position > 0
+            && is_predicate(&steps[position].1)
+            && matches!(
+                steps[position - 1].1,
+                PipelineStep::Dedupe(..)
+                    | PipelineStep::DedupeSpill(_)
+                    | PipelineStep::OnlyDuplicates(_)
+            )
+        {
+            steps.swap(position - 1, position);
+            position -= 1;
+        }
+    }
+
+    steps
+}
+
+fn merge_filters(steps: Vec<ParsedStep>) -> Vec<ParsedStep> {
+    let mut merged: Vec<ParsedStep> = vec![];
+
+    for (name, step, arguments) in steps {
+        if let (Some(last), PipelineStep::Filter(regex)) = (merged.last_mut(), &step) {
+            let patterns = match &last.1 {
+                PipelineStep::Filter(previous) => Some(vec![previous.as_str().to_string()]),
+                PipelineStep::FilterSet(set) => Some(set.patterns().to_vec()),
+                _ => None,
+            };
+            // Patterns that only compile on their own are left unmerged.
+            let set = patterns.and_then(|mut patterns| {
+                patterns.push(regex.as_str().to_string());
+                RegexSet::new(patterns).ok()
+            });
+
+            if let Some(set) = set {
+                last.0 = format!("{}+{}", last.0, name);
+                last.1 = PipelineStep::FilterSet(set);
+                last.2.extend(arguments);
+                continue;
+            }
+        }
+
+        merged.push((name, step, arguments));
+    }
+
+    merged
+}
+
+fn fuse_transforms(steps: Vec<ParsedStep>) -> Vec<ParsedStep> {
+    let mut fused: Vec<ParsedStep> = vec![];
+
+    for (name, step, arguments) in steps {
+        let last = fused
+            .last_mut()
+            .filter(|(_, last, _)| is_transform(last) || matches!(last, PipelineStep::Fused(_)));
+
+        match last {
+            Some(last) if is_transform(&step) => {
+                let mut transforms =
+                    match std::mem::replace(&mut last.1, PipelineStep::Fused(vec![])) {
+                        PipelineStep::Fused(transforms) => transforms,
+                        previous => vec![previous],
+                    };
+                transforms.push(step);
+
+                last.0 = format!("{}+{}", last.0, name);
+                last.1 = PipelineStep::Fused(transforms);
+                last.2.extend(arguments);
+            }
+            _ => fused.push((name, step, arguments)),
+        }
+    }
+
+    fused
+}
+
+// Steps that only keep or drop a line, going by nothing but the line itself.
+fn is_predicate(step: &PipelineStep) -> bool {
+    matches!(
+        step,
+        PipelineStep::Filter(_)
+            | PipelineStep::FilterSet(_)
+            | PipelineStep::FilterAny(_)
+            | PipelineStep::FilterLiteral(_)
+            | PipelineStep::FilterLiterals(_)
+            | PipelineStep::MinLength(..)
+            | PipelineStep::MaxLength(..)
+            | PipelineStep::Where(_)
+            | PipelineStep::WhereField(_)
+    ) || step.is_fancy_filter()
+}
+
+fn is_transform(step: &PipelineStep) -> bool {
+    matches!(
+        step,
+        PipelineStep::Lower
+            | PipelineStep::Upper
+            | PipelineStep::LocaleLower(_)
+            | PipelineStep::LocaleUpper(_)
+            | PipelineStep::Trim
+            | PipelineStep::TrimStart
+            | PipelineStep::TrimEnd
+            | PipelineStep::TrimChars(..)
+            | PipelineStep::PadLeft(..)
+            | PipelineStep::PadRight(..)
+            | PipelineStep::Append(_)
+            | PipelineStep::Prepend(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::Pipeline;
+
+    fn step_names(pipeline: &Pipeline) -> Vec<&str> {
+        pipeline
+            .step_stats()
+            .iter()
+            .map(|stats| stats.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn optimize_merges_filters_and_fuses_transforms() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "filter", "a", "filter", "b", "trim", "upper", "append", "!",
+        ])
+        .unwrap();
+
+        //+ Act
+        pipeline.optimize();
+
+        //+ Assert
+        assert_eq!(
+            step_names(&pipeline),
+            ["filter+filter", "trim+upper+append"]
+        );
+        assert_eq!(pipeline.apply("  ab ").unwrap(), ["AB!"]);
+        assert!(pipeline.apply("a only").unwrap().is_empty());
+    }
+
+    #[test]
+    fn optimize_hoists_predicates_only_where_output_stays_the_same() {
+        //+ Arrange
+        let mut exact =
+            Pipeline::build_pipeline(&["dedupe", "filter", "x", "minlen", "2"]).unwrap();
+        let mut recent =
+            Pipeline::build_pipeline(&["dedupe", "--recent", "5", "filter", "x"]).unwrap();
+        let mut captured =
+            Pipeline::build_pipeline(&["filter", "a", "filter", "(x)", "format", "{1}"]).unwrap();
+        let state = std::env::temp_dir().join(format!("rangler-hoist-{}", std::process::id()));
+        let mut stateful = Pipeline::build_pipeline(&[
+            "dedupe",
+            "--state",
+            state.to_str().unwrap(),
+            "filter",
+            "x",
+        ])
+        .unwrap();
+
+        //+ Act
+        exact.optimize();
+        recent.optimize();
+        captured.optimize();
+        stateful.optimize();
+
+        //+ Assert
+        assert_eq!(step_names(&exact), ["filter", "minlen", "dedupe"]);
+        assert_eq!(step_names(&recent), ["dedupe", "filter"]);
+        assert_eq!(step_names(&stateful), ["dedupe", "filter"]);
+        assert!(!state.exists());
+        assert_eq!(step_names(&captured), ["filter", "filter", "format"]);
+        assert_eq!(captured.apply("ax").unwrap(), ["x"]);
+    }
+}
@@ -0,0 +1,1060 @@
+use std::time::Duration;
+
+use clap::{parser::ValueSource, value_parser, Arg, ArgAction, ArgMatches, Command};
+use encoding_rs::Encoding;
+use regex::Regex;
+
+use crate::{
+    color::ColorMode,
+    encoding::parse_encoding,
+    expand::{expand, expand_env},
+    hash::HashAlgorithm,
+    locale::CaseRules,
+    merge::MergeMode,
+    output::{OutputCompression, SplitLimit},
+    pipeline::ErrorPolicy,
+    records::{InvalidUtf8, RecordSeparator},
+    units::{parse_duration, parse_size},
+};
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Options {
+    pub output_partition: Option<String>,
+    pub output: Option<String>,
+    pub in_place: Option<String>,
+    pub compress: Option<OutputCompression>,
+    pub split: Option<SplitLimit>,
+    pub strict: bool,
+    pub grep_status: bool,
+    pub invalid_utf8: InvalidUtf8,
+    pub bytes: bool,
+    pub encoding: Option<&'static Encoding>,
+    pub output_encoding: Option<&'static Encoding>,
+    pub no_progress: bool,
+    pub max_memory: Option<usize>,
+    pub plugins: Vec<String>,
+    pub pipeline: Option<String>,
+    pub reload_keep_state: bool,
+    pub explain: bool,
+    pub preview: Option<usize>,
+    pub quiet: bool,
+    pub no_optimize: bool,
+    pub on_error: Option<ErrorPolicy>,
+    pub threads: usize,
+    pub jobs: usize,
+    pub pipeline_parallelism: bool,
+    pub read_buffer: Option<usize>,
+    pub mmap: bool,
+    pub write_buffer: Option<usize>,
+    pub line_buffered: bool,
+    pub flush_interval: Option<Duration>,
+    pub summary: bool,
+    pub stats_json: Option<String>,
+    pub checksum: Option<HashAlgorithm>,
+    pub checkpoint: Option<String>,
+    pub trace: Option<usize>,
+    pub trace_match: Option<String>,
+    pub seed: Option<u64>,
+    pub case_rules: Option<CaseRules>,
+    pub ignore_case: bool,
+    pub color: ColorMode,
+    pub record_separator: RecordSeparator,
+    pub print0: bool,
+    pub keep_eol: bool,
+    pub crlf_out: bool,
+    pub header: Option<String>,
+    pub keep_header: usize,
+    pub rejects: Option<String>,
+    pub annotate_rejects: bool,
+    pub footer: Option<String>,
+    pub inputs: Vec<String>,
+    pub globs: Vec<String>,
+    pub follow: Option<String>,
+    pub watch: Option<String>,
+    pub watch_glob: Option<String>,
+    pub listen: Option<String>,
+    pub with_filename: bool,
+    pub with_line_number: bool,
+    pub with_offset: bool,
+    pub merge: Option<MergeMode>,
+}
+
+impl Options {
+    /// The global options, for the command line and its help.
+    pub fn args() -> Vec<Arg> {
+        vec![
+            option("compress", "gzip|zstd", "compresses the output stream, whether it goes to stdout, a file or an in-place rewrite").value_parser(OutputCompression::parse),
+            option("follow", "path", "reads the file and keeps waiting for it to grow, like tail -f, reopening it when truncated or rotated").short('f'),
+            option("watch", "dir", "reads every file created in the directory from now on, in name order, following the newest one"),
+            option("watch-glob", "pattern", "only watches files whose names match the pattern, e.g. '*.log'"),
+            option("listen", "tcp://host:port|udp://host:port|unix://path", "reads lines from every connection to the socket, or every UDP datagram as a line of its own, e.g. syslog on udp://0.0.0.0:514, indefinitely; each connection is split into records on its own, and one that sends a line over 1 MiB is closed"),
+            option("glob", "pattern", "reads every file matching the pattern, e.g. 'logs/**/*.log', in sorted order; repeatable").action(ArgAction::Append),
+            Arg::new("in-place")
+                .short('i')
+                .long("in-place")
+                .value_name("suffix")
+                .num_args(0..=1)
+                .require_equals(true)
+                .default_missing_value("")
+                .help("rewrites each input file with the pipeline output, keeping a backup when a suffix such as .bak is given"),
+            option("split-lines", "n", "starts a new output file, <path>.0001, <path>.0002 and so on, every n lines").value_parser(count("Invalid split line count")),
+            option("split-bytes", "size", "starts a new output file before one would grow past the size, e.g. 100M, measured before compression").value_parser(positive_size),
+            option("output", "path", "writes to a file, replacing it atomically only once the run succeeds; ${VAR} and strftime tokens like %Y-%m-%d in the path are filled in from the environment and the date; sqlite://file.db#table inserts the lines into a SQLite table instead, one column per key of JSON objects and a line column otherwise, when built with the sqlite feature").short('o'),
+            option("input", "path", "reads this file instead of stdin; repeatable, and files after -- are read too").action(ArgAction::Append),
+            flag("with-filename", "prefixes every line with the name of the file it came from, like grep -H"),
+            flag("interleave", "reads a line from each input file in turn instead of one file after another"),
+            option("zip", "delimiter", "joins the next line of every input file on the delimiter, side by side like paste").allow_hyphen_values(true),
+            flag("with-line-number", "prefixes every line with its record number within its file, like grep -n"),
+            flag("with-offset", "prefixes every line with the byte offset its record starts at within its file, like grep -b, after the file name and line number when those are on too; offsets count decompressed bytes"),
+            flag("summary", "prints lines and bytes read and emitted, and per step lines in, out and dropped and peak memory, to stderr at the end"),
+            option("rejects", "file|stderr", "writes every line a filter, dedupe or failed parse dropped to the file, for auditing what the pipeline discarded; lines held back or routed elsewhere are not rejects"),
+            flag("annotate-rejects", "starts each rejected line with the number and name of the step that dropped it, e.g. 2:filter and a tab"),
+            option("header", "text", "writes a line of text before the output, e.g. a CSV header or BEGIN; with dates and ${VAR} filled in like append").allow_hyphen_values(true),
+            option("footer", "text", "writes a line of text after the output, e.g. COMMIT;").allow_hyphen_values(true),
+            option("keep-header", "n", "passes the first n lines straight to the output, past every step, e.g. a CSV header; later inputs' first n lines are dropped so concatenated files keep one header").value_parser(count("Invalid header line count")),
+            option("checksum", "md5|sha1|sha256|xxhash", "prints a digest of everything written to stderr at the end, and adds it to --stats-json, so a transfer can be checked without reading the output again; it covers the bytes before --compress or --output-encoding").value_parser(HashAlgorithm::parse),
+            option("stats-json", "path|stderr", "writes the same statistics as --summary as a JSON object, for scripts and CI checks"),
+            option("checkpoint", "file", "every 10 seconds records how far into the input files the run got, the output length and what dedupes have seen; run the same command again after a crash or Ctrl-C to carry on from there, with --output cut back to the checkpoint (stdout repeats the lines since it). The file is removed once the run completes"),
+            option("color", "auto|always|never", "highlights what filters matched in the output; auto, the default, only does so on a terminal and when NO_COLOR is unset").value_parser(ColorMode::parse),
+            flag("ignore-case", "makes filter, tag and if patterns match regardless of case; a pattern can opt out with (?-i)"),
+            option("locale", "locale", "makes lower and upper follow the language's case rules, e.g. tr-TR keeps Turkish dotted and dotless i apart").value_parser(CaseRules::parse),
+            option("seed", "number", "seeds sample, shuffle, uuid and ulid so the same input picks the same lines in the same order, and the same random UUIDs, on every run").value_parser(value_parser!(u64)),
+            option("trace", "count", "prints the first <count> lines to stderr after every step they go through, and the step that dropped or held back any that stop early").value_parser(count("Invalid trace count")),
+            option("trace-match", "regex", "traces the lines that match instead, or the first <count> of them with --trace").allow_hyphen_values(true).value_parser(pattern),
+            option("pipeline", "file.yaml|file.toml", "reads the steps from a YAML or TOML file, e.g. 'steps: [trim, {filter: ERROR}, {dedupe: {recent: 1000}}]', before any given as arguments; with --follow, --watch or --listen the file is read again when it changes, and the new steps take over between two lines, or the old ones keep running if it does not load"),
+            flag("reload-keep-state", "keeps what steps unchanged by a reload of the --pipeline file hold, such as the lines a dedupe has seen, instead of starting them afresh"),
+            option("preview", "n", "runs only the first <n> lines, showing each as it went in with - and what came out with +, or once if it came out unchanged, then exits").value_parser(count("Invalid preview line count")),
+            flag("explain", "prints each step with the arguments it took, what they compiled to, whether it buffers and how its memory grows, then exits without reading any input").visible_alias("dry-run"),
+            flag("quiet", "prints nothing but the data and errors: no progress line, no warnings about step orderings that probably do not do what was meant, like dedupe before trim, and no summary of lines handled lossily").short('q'),
+            option("plugin", "path", "loads commands from a shared library exporting rangler_plugin_v1; repeatable").action(ArgAction::Append),
+            option("max-memory", "size", "fails cleanly once steps hold more than this, e.g. 2GiB; plain dedupe spills to disk to stay under it").value_parser(parse_size),
+            option("read-buffer", "size", "buffer size for reading input, e.g. 64K or 4MiB; picked to suit the input by default").value_parser(positive_size),
+            flag("mmap", "maps plain local input files into memory and scans them for lines in place, faster for large files; a file truncated while it is read can crash the run"),
+            option("write-buffer", "size", "buffer size for writing output; picked to suit the output by default").value_parser(positive_size),
+            flag("line-buffered", "flushes the output after every line instead of in large blocks"),
+            option("flush-interval", "duration", "also flushes the output once this long has passed since the last flush, e.g. 500ms or 2s").value_parser(parse_duration),
+            option("jobs", "n", "runs n input files at once, each through its own copy of the pipeline, when every step handles each line on its own or is an exact dedupe, which the jobs share; each file's lines keep their order, but files interleave").value_parser(count("Invalid job count")),
+            option("threads", "n", "shares lines out between n threads, keeping their order, when every step handles each line on its own (filter, trim, case changes, json and the like) or is an exact dedupe, which the threads share; otherwise runs on one").value_parser(count("Invalid thread count")),
+            flag("pipeline-parallelism", "runs every step on a thread of its own, handing lines on over bounded channels, so slow steps like regex filters and json overlap with each other and with reading and writing"),
+            flag("no-optimize", "runs the steps exactly as written, instead of merging filters, fusing simple transforms and filtering ahead of dedupe, so --summary counts every step"),
+            flag("no-progress", "never shows the progress line, which is also hidden whenever stderr is not a terminal"),
+            flag("bytes", "runs the commands on raw bytes, leaving data that is not UTF-8 intact; supports filter, lower, upper, trim, dedupe, append, prepend, hash, base64, minlen and maxlen"),
+            option("encoding", "label", "decodes inputs from latin1, utf-16le, windows-1252, shift_jis and so on; a byte order mark overrides it").value_parser(parse_encoding),
+            option("output-encoding", "label", "encodes the output, starting UTF-16 with a byte order mark").value_parser(parse_encoding),
+            option("invalid-utf8", "lossy|skip|abort|raw", "what to do with records that are not valid UTF-8: replace the bad bytes with U+FFFD (the default), drop the record, fail naming the byte offset, or write the record out untouched, bypassing the commands; commands that only dedupe, throttle or limit lengths in bytes never look at the text, so records pass through them byte for byte and this does not apply").value_parser(InvalidUtf8::parse),
+            flag("grep-status", "exits with 1 when no lines were emitted, like grep when nothing matches; errors always exit with 2"),
+            option("on-error", "skip|annotate|abort", "what steps that fail on a line, like json, dateparse, base64 decode and exec, do unless given their own --on-error: drop it (the default), pass it on with a tab and [error: reason] appended, or stop naming the line and its number").value_parser(ErrorPolicy::parse),
+            flag("strict", "fails instead of silently degrading lines (e.g. skipping invalid UTF-8)"),
+            option("output-partition", "path format", "writes lines to files named by their timestamp, e.g. 'out/%Y-%m-%d.log', with ${VAR} filled in from the environment"),
+            option("record-sep", "string", "splits input into records on this string instead of newlines, e.g. '\\n\\n'").allow_hyphen_values(true).value_parser(RecordSeparator::literal),
+            flag("null", "reads NUL-terminated records, e.g. from find -print0").short('0'),
+            flag("print0", "terminates output records with NUL instead of a newline"),
+            flag("keep-eol", "keeps the \\r of CRLF line endings instead of normalizing input to LF"),
+            flag("crlf-out", "terminates output lines with CRLF"),
+            option("record-start", "regex", "joins lines into multiline records, starting a new record at each matching line").allow_hyphen_values(true).value_parser(RecordSeparator::start),
+            option("format", "lines|csv|json-array", "reads CSV records instead of lines, so a quoted field can hold commas and newlines and the whole record still goes through the commands as one; json-array streams the elements of one top-level JSON array, such as an API response, each compacted onto its own line without loading the whole document").value_parser(["lines", "csv", "json-array"]),
+        ]
+    }
+
+    /// The options followed by the commands, which take every argument from
+    /// the first one on.
+    pub fn command() -> Command {
+        Command::new("rangler")
+            .args_override_self(true)
+            .args(Options::args())
+            .arg(
+                Arg::new("commands")
+                    .value_name("COMMANDS")
+                    .num_args(0..)
+                    .trailing_var_arg(true)
+                    .hide(true),
+            )
+    }
+
+    /// clap has no optional value attached to a short flag, so sed's
+    /// `-i.bak` is spelled out as `--in-place=.bak` before the options are
+    /// parsed. The commands are left as they are.
+    pub fn spell_out<T: AsRef<str>>(args: &[T]) -> Vec<String> {
+        let options = Options::args();
+        let takes_value = |flag: &str| {
+            options.iter().any(|option| {
+                let named = match flag.strip_prefix("--") {
+                    Some(long) => option.get_long() == Some(long),
+                    None => flag.chars().nth(1) == option.get_short() && flag.len() == 2,
+                };
+                named
+                    && option.get_action().takes_values()
+                    && option
+                        .get_num_args()
+                        .is_none_or(|range| range.min_values() > 0)
+            })
+        };
+
+        let mut spelled: Vec<String> = args.iter().map(|arg| arg.as_ref().to_string()).collect();
+        let mut index = 0;
+        while let Some(arg) = spelled.get_mut(index) {
+            if arg == "--" || arg == "-" || !arg.starts_with('-') {
+                break;
+            }
+
+            match arg.strip_prefix("-i").filter(|suffix| !suffix.is_empty()) {
+                Some(suffix) if !arg.starts_with("--") => *arg = format!("--in-place={}", suffix),
+                _ if takes_value(arg) => index += 1,
+                _ => {}
+            }
+            index += 1;
+        }
+
+        spelled
+    }
+
+    /// Global options come before the first command; everything after them is
+    /// handed to the pipeline builder untouched, except for input files listed
+    /// after a `--` separator.
+    pub fn parse<T: AsRef<str>>(args: &[T]) -> Result<(Options, &[T]), String> {
+        let matches = Options::command()
+            .no_binary_name(true)
+            .disable_help_flag(true)
+            .try_get_matches_from(Options::spell_out(args))
+            .map_err(|error| message(&error))?;
+
+        // clap takes off a `--` that comes before any command, so the files
+        // after it are put back behind one.
+        let commands = matches
+            .get_many::<String>("commands")
+            .map_or(0, |c| c.len());
+        let mut start = args.len() - commands;
+        if start > 0 && args[start - 1].as_ref() == "--" {
+            start -= 1;
+        }
+        let mut args = &args[start..];
+
+        let string = |id: &str| matches.get_one::<String>(id).cloned();
+        let strings = |id: &str| {
+            matches
+                .get_many::<String>(id)
+                .map_or(vec![], |values| values.cloned().collect())
+        };
+        let flag = |id: &str| matches.get_flag(id);
+        let mut options = Options {
+            // Its dates are those of the lines, so only the environment is
+            // filled in now.
+            output_partition: string("output-partition").map(|format| expand_env(&format)),
+            output: string("output").map(|path| expand(&path)),
+            in_place: string("in-place"),
+            compress: matches.get_one("compress").copied(),
+            split: match last_of(&matches, &["split-lines", "split-bytes"]) {
+                Some("split-lines") => matches
+                    .get_one("split-lines")
+                    .copied()
+                    .map(SplitLimit::Lines),
+                Some(_) => matches
+                    .get_one("split-bytes")
+                    .copied()
+                    .map(SplitLimit::Bytes),
+                None => None,
+            },
+            strict: flag("strict"),
+            grep_status: flag("grep-status"),
+            invalid_utf8: matches.get_one("invalid-utf8").copied().unwrap_or_default(),
+            bytes: flag("bytes"),
+            encoding: matches.get_one("encoding").copied(),
+            output_encoding: matches.get_one("output-encoding").copied(),
+            no_progress: flag("no-progress"),
+            max_memory: matches.get_one("max-memory").copied(),
+            plugins: strings("plugin"),
+            pipeline: string("pipeline"),
+            reload_keep_state: flag("reload-keep-state"),
+            explain: flag("explain"),
+            preview: matches.get_one("preview").copied(),
+            quiet: flag("quiet"),
+            no_optimize: flag("no-optimize"),
+            on_error: matches.get_one("on-error").copied(),
+            threads: matches.get_one("threads").copied().unwrap_or_default(),
+            jobs: matches.get_one("jobs").copied().unwrap_or_default(),
+            pipeline_parallelism: flag("pipeline-parallelism"),
+            read_buffer: matches.get_one("read-buffer").copied(),
+            mmap: flag("mmap"),
+            write_buffer: matches.get_one("write-buffer").copied(),
+            line_buffered: flag("line-buffered"),
+            flush_interval: matches.get_one("flush-interval").copied(),
+            summary: flag("summary"),
+            stats_json: string("stats-json"),
+            checksum: matches.get_one("checksum").copied(),
+            checkpoint: string("checkpoint"),
+            trace: matches.get_one("trace").copied(),
+            trace_match: string("trace-match"),
+            seed: matches.get_one("seed").copied(),
+            case_rules: matches.get_one("locale").copied(),
+            ignore_case: flag("ignore-case"),
+            color: matches.get_one("color").copied().unwrap_or_default(),
+            record_separator: match last_of(
+                &matches,
+                &["record-sep", "null", "format", "record-start"],
+            ) {
+                Some("null") => RecordSeparator::Literal(vec![0]),
+                Some("format") => match string("format").as_deref() {
+                    Some("csv") => RecordSeparator::Csv,
+                    Some("json-array") => RecordSeparator::JsonArray,
+                    _ => RecordSeparator::Newline,
+                },
+                Some(id) => matches
+                    .get_one::<RecordSeparator>(id)
+                    .cloned()
+                    .unwrap_or_default(),
+                None => RecordSeparator::default(),
+            },
+            print0: flag("print0"),
+            keep_eol: flag("keep-eol"),
+            crlf_out: flag("crlf-out"),
+            header: string("header").map(|text| expand(&text)),
+            keep_header: matches.get_one("keep-header").copied().unwrap_or_default(),
+            rejects: string("rejects").map(|path| expand(&path)),
+            annotate_rejects: flag("annotate-rejects"),
+            footer: string("footer").map(|text| expand(&text)),
+            inputs: strings("input"),
+            globs: strings("glob"),
+            follow: string("follow"),
+            watch: string("watch"),
+            watch_glob: string("watch-glob"),
+            listen: string("listen"),
+            with_filename: flag("with-filename"),
+            with_line_number: flag("with-line-number"),
+            with_offset: flag("with-offset"),
+            merge: match last_of(&matches, &["interleave", "zip"]) {
+                Some("interleave") => Some(MergeMode::Interleave),
+                Some(_) => string("zip").map(MergeMode::Zip),
+                None => None,
+            },
+        };
+
+        if let Some(separator) = args.iter().position(|arg| arg.as_ref() == "--") {
+            options.inputs.extend(
+                args[separator + 1..]
+                    .iter()
+                    .map(|arg| arg.as_ref().to_string()),
+            );
+            args = &args[..separator];
+        }
+
+        options.check()?;
+
+        Ok((options, args))
+    }
+
+    // Options that make sense alone but not together.
+    fn check(&self) -> Result<(), &'static str> {
+        let options = self;
+        if options.bytes && options.output_partition.is_some() {
+            return Err("Byte mode cannot be combined with --output-partition");
+        }
+        if options.split.is_some() && options.output.is_none() {
+            return Err("Splitting the output needs --output");
+        }
+        // Each edited file gets its own header and footer, but partitions and
+        // resumed runs have no single start and end to frame.
+        if (options.header.is_some() || options.footer.is_some())
+            && (options.output_partition.is_some()
+                || options.checkpoint.is_some()
+                || options.is_sqlite_output())
+        {
+            return Err("Headers and footers need one plain output");
+        }
+        if options.keep_header > 0
+            && (options.output_partition.is_some() || options.is_sqlite_output())
+        {
+            return Err("Kept header lines need one plain output");
+        }
+        // Rows go straight into the database, so there is no byte stream to
+        // compress, encode, split or cut back to a checkpoint.
+        if options.is_sqlite_output()
+            && (options.split.is_some()
+                || options.compress.is_some()
+                || options.output_encoding.is_some()
+                || options.checkpoint.is_some())
+        {
+            return Err("SQLite output cannot be split, compressed, encoded or checkpointed");
+        }
+        if options.checksum.is_some()
+            && (options.in_place.is_some() || options.output_partition.is_some())
+        {
+            return Err("Checksums cover a single output stream, not partitions or edited files");
+        }
+        if options.in_place.is_some()
+            && (options.output.is_some() || options.output_partition.is_some())
+        {
+            return Err("In-place editing cannot be combined with other outputs");
+        }
+        let endless_sources = [&options.follow, &options.watch, &options.listen]
+            .iter()
+            .filter(|source| source.is_some())
+            .count();
+        if endless_sources > 1
+            || endless_sources == 1
+                && (!options.inputs.is_empty()
+                    || !options.globs.is_empty()
+                    || options.in_place.is_some())
+        {
+            return Err("Follow, watch and listen modes cannot be combined with other inputs");
+        }
+        // Every connection is split into records of its own, which a single
+        // array does not allow.
+        if options.listen.is_some() && options.record_separator == RecordSeparator::JsonArray {
+            return Err("JSON array input cannot be listened for");
+        }
+        if options.preview.is_some() && (options.bytes || endless_sources > 0) {
+            return Err("Previews need text mode and input that ends");
+        }
+        if options.merge.is_some()
+            && (endless_sources > 0 || options.in_place.is_some() || options.checkpoint.is_some())
+        {
+            return Err("Merged inputs cannot be followed, edited in place or checkpointed");
+        }
+        // A checkpoint resumes by skipping into input files and cutting the
+        // output back, so both have to be plain and read in one order.
+        if options.checkpoint.is_some() {
+            if options.inputs.is_empty() && options.globs.is_empty() {
+                return Err("Checkpoints need input files");
+            }
+            // Offsets into an array cannot be resumed from without its
+            // opening bracket.
+            if options.record_separator == RecordSeparator::JsonArray {
+                return Err("JSON array input cannot be checkpointed");
+            }
+            if options.in_place.is_some()
+                || options.split.is_some()
+                || options.output_partition.is_some()
+                || options.compress.is_some()
+                || options.output_encoding.is_some()
+                || options.bytes
+                || options.threads > 1
+                || options.jobs > 1
+                || options.pipeline_parallelism
+            {
+                return Err("Checkpoints need one plain output and a single thread");
+            }
+        }
+
+        // Dropped lines are only seen where one pipeline works through them.
+        if options.rejects.is_some()
+            && (options.bytes
+                || options.threads > 1
+                || options.jobs > 1
+                || options.pipeline_parallelism)
+        {
+            return Err("Rejected lines need text mode and a single thread");
+        }
+        if options.annotate_rejects && options.rejects.is_none() {
+            return Err("Annotating rejects needs --rejects");
+        }
+
+        // Traced lines print each step's output as it happens, which only
+        // reads in order with one pipeline working through the lines.
+        if options.is_tracing()
+            && (options.bytes
+                || options.threads > 1
+                || options.jobs > 1
+                || options.pipeline_parallelism)
+        {
+            return Err("Tracing needs text mode and a single thread");
+        }
+
+        // Only a pipeline file read alongside an endless input is reloaded.
+        if options.reload_keep_state && (options.pipeline.is_none() || endless_sources == 0) {
+            return Err(
+                "Keeping state on reload needs --pipeline with --follow, --watch or --listen",
+            );
+        }
+
+        // Jobs each take whole input files and send their lines back as text,
+        // already spread over the cores the other kinds of threads would use.
+        if options.jobs > 1 {
+            if (options.inputs.is_empty() && options.globs.is_empty())
+                || endless_sources > 0
+                || options.in_place.is_some()
+                || options.merge.is_some()
+                || options.bytes
+                || options.invalid_utf8 == InvalidUtf8::Raw
+            {
+                return Err("Per-file jobs need input files read as text, not followed, merged or edited in place");
+            }
+            if options.threads > 1
+                || options.pipeline_parallelism
+                || options.keep_header > 0
+                || options.max_memory.is_some()
+            {
+                return Err("Per-file jobs cannot be combined with --threads, --pipeline-parallelism, --keep-header or --max-memory");
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_sqlite_output(&self) -> bool {
+        self.output
+            .as_deref()
+            .is_some_and(|output| output.starts_with("sqlite://"))
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some() || self.trace_match.is_some()
+    }
+
+    // Following, watching and listening never reach the end of their input.
+    pub fn is_endless(&self) -> bool {
+        self.follow.is_some() || self.watch.is_some() || self.listen.is_some()
+    }
+}
+
+fn option(name: &'static str, value: &'static str, help: &'static str) -> Arg {
+    Arg::new(name).long(name).value_name(value).help(help)
+}
+
+fn flag(name: &'static str, help: &'static str) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .action(ArgAction::SetTrue)
+        .help(help)
+}
+
+// Reads a count of one or more.
+fn count(message: &'static str) -> impl Fn(&str) -> Result<usize, &'static str> + Clone {
+    move |value| value.parse().ok().filter(|count| *count > 0).ok_or(message)
+}
+
+fn positive_size(value: &str) -> Result<usize, &'static str> {
+    match parse_size(value)? {
+        0 => Err("Invalid size"),
+        size => Ok(size),
+    }
+}
+
+fn pattern(value: &str) -> Result<String, &'static str> {
+    Regex::new(value).map_err(|_| "Invalid trace pattern")?;
+    Ok(value.to_string())
+}
+
+// Of options that set the same thing, the one given last wins.
+fn last_of<'a>(matches: &ArgMatches, ids: &[&'a str]) -> Option<&'a str> {
+    ids.iter()
+        .filter(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        .max_by_key(|id| matches.index_of(id))
+        .copied()
+}
+
+// clap's first line, without its `error: ` in front.
+fn message(error: &clap::Error) -> String {
+    let rendered = error.to_string();
+    let line = rendered.lines().next().unwrap_or_default();
+    line.trim_start_matches("error: ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Options;
+    use crate::color::ColorMode;
+    use crate::hash::HashAlgorithm;
+    use crate::locale::CaseRules;
+    use crate::merge::MergeMode;
+    use crate::pipeline::ErrorPolicy;
+    use crate::records::{InvalidUtf8, RecordSeparator};
+
+    #[test]
+    fn parse_stops_at_first_command() {
+        //+ Arrange
+        let args = vec![
+            "--output-partition",
+            "out/%Y.log",
+            "--strict",
+            "filter",
+            "--x",
+        ];
+
+        //+ Act
+        let (options, rest) = Options::parse(&args).unwrap();
+
+        //+ Assert
+        assert_eq!(options.output_partition, Some("out/%Y.log".to_string()));
+        assert!(options.strict);
+        assert_eq!(rest, &["filter", "--x"]);
+    }
+
+    #[test]
+    fn parse_rejects_unknown_option() {
+        //+ Arrange
+        let args = vec!["--frobnicate", "filter", "x"];
+
+        //+ Act
+        let result = Options::parse(&args);
+
+        //+ Assert
+        assert!(result.is_err());
+        assert_eq!(
+            result.err().unwrap(),
+            "unexpected argument '--frobnicate' found"
+        );
+    }
+
+    #[test]
+    fn parse_lets_later_options_win_and_reads_files_without_commands() {
+        //+ Arrange
+        let args = [
+            "--read-buffer",
+            "4K",
+            "-0",
+            "--read-buffer",
+            "8K",
+            "--format",
+            "csv",
+            "trim",
+        ];
+
+        //+ Act
+        let (options, rest) = Options::parse(&args).unwrap();
+        let (files, none) = Options::parse(&["--strict", "--", "a.log"]).unwrap();
+
+        //+ Assert
+        assert_eq!(options.read_buffer, Some(8192));
+        assert_eq!(options.record_separator, RecordSeparator::Csv);
+        assert_eq!(rest, &["trim"]);
+        assert_eq!(files.inputs, ["a.log"]);
+        assert!(none.is_empty());
+    }
+
+    #[test]
+    fn parse_reads_record_separator() {
+        //+ Arrange
+        let args = vec!["--record-sep", r"\n\n", "trim"];
+
+        //+ Act
+        let (options, rest) = Options::parse(&args).unwrap();
+
+        //+ Assert
+        assert_eq!(
+            options.record_separator,
+            RecordSeparator::Literal(b"\n\n".to_vec())
+        );
+        assert_eq!(rest, &["trim"]);
+    }
+
+    #[test]
+    fn parse_reads_output_flags() {
+        //+ Arrange
+        let args = vec![
+            "-0",
+            "--print0",
+            "--with-line-number",
+            "--with-offset",
+            "--checksum",
+            "SHA256",
+            "--rejects",
+            "stderr",
+            "--annotate-rejects",
+            "--header",
+            "BEGIN;",
+            "--footer",
+            "COMMIT;",
+            "--keep-header",
+            "2",
+            "upper",
+        ];
+
+        //+ Act
+        let (options, rest) = Options::parse(&args).unwrap();
+
+        //+ Assert
+        assert_eq!(options.record_separator, RecordSeparator::Literal(vec![0]));
+        assert!(options.print0);
+        assert!(options.with_line_number);
+        assert!(options.with_offset);
+        assert_eq!(options.checksum, Some(HashAlgorithm::Sha256));
+        assert_eq!(options.header.as_deref(), Some("BEGIN;"));
+        assert_eq!(options.rejects.as_deref(), Some("stderr"));
+        assert!(options.annotate_rejects);
+        assert_eq!(options.footer.as_deref(), Some("COMMIT;"));
+        assert_eq!(options.keep_header, 2);
+        assert!(!options.with_filename);
+        assert_eq!(rest, &["upper"]);
+    }
+
+    #[test]
+    fn parse_collects_input_files() {
+        //+ Arrange
+        let args = vec!["--input", "a.log", "filter", "foo", "--", "b.log", "c.log"];
+
+        //+ Act
+        let (options, rest) = Options::parse(&args).unwrap();
+
+        //+ Assert
+        assert_eq!(options.inputs, vec!["a.log", "b.log", "c.log"]);
+        assert_eq!(rest, &["filter", "foo"]);
+    }
+
+    #[test]
+    fn parse_reads_in_place_backup_suffix() {
+        //+ Act
+        let (attached, _) = Options::parse(&["-i.bak", "trim"]).unwrap();
+        let (long, _) = Options::parse(&["--in-place=.orig", "trim"]).unwrap();
+        let (bare, _) = Options::parse(&["-i", "trim"]).unwrap();
+        let conflicting = Options::parse(&["-i", "-o", "out.txt", "trim"]);
+
+        //+ Assert
+        assert_eq!(attached.in_place, Some(".bak".to_string()));
+        assert_eq!(long.in_place, Some(".orig".to_string()));
+        assert_eq!(bare.in_place, Some(String::new()));
+        assert!(conflicting.is_err());
+    }
+
+    #[test]
+    fn parse_reads_invalid_utf8_policy() {
+        //+ Act
+        let (default, _) = Options::parse(&["trim"]).unwrap();
+        let (raw, _) = Options::parse(&["--invalid-utf8", "RAW", "trim"]).unwrap();
+        let unknown = Options::parse(&["--invalid-utf8", "replace", "trim"]);
+
+        //+ Assert
+        assert_eq!(default.invalid_utf8, InvalidUtf8::Lossy);
+        assert_eq!(raw.invalid_utf8, InvalidUtf8::Raw);
+        assert_eq!(unknown.err().as_deref(), Some("invalid value 'replace' for '--invalid-utf8 <lossy|skip|abort|raw>': Invalid UTF-8 policy"));
+    }
+
+    #[test]
+    fn parse_reads_line_buffering_and_flush_interval() {
+        //+ Act
+        let (options, _) =
+            Options::parse(&["--line-buffered", "--flush-interval", "500ms", "trim"]).unwrap();
+        let missing = Options::parse(&["--flush-interval"]);
+
+        //+ Assert
+        assert!(options.line_buffered);
+        assert_eq!(options.flush_interval, Some(Duration::from_millis(500)));
+        assert_eq!(
+            missing.err().as_deref(),
+            Some("a value is required for '--flush-interval <duration>' but none was supplied")
+        );
+    }
+
+    #[test]
+    fn parse_reads_buffer_sizes() {
+        //+ Act
+        let (options, _) =
+            Options::parse(&["--read-buffer", "4MiB", "--write-buffer", "64K", "trim"]).unwrap();
+        let empty = Options::parse(&["--write-buffer", "0", "trim"]);
+
+        //+ Assert
+        assert_eq!(options.read_buffer, Some(4 << 20));
+        assert_eq!(options.write_buffer, Some(64 << 10));
+        assert_eq!(
+            empty.err().as_deref(),
+            Some("invalid value '0' for '--write-buffer <size>': Invalid size")
+        );
+    }
+
+    #[test]
+    fn parse_reads_memory_limit() {
+        //+ Act
+        let (options, _) = Options::parse(&["--max-memory", "2GiB", "dedupe"]).unwrap();
+        let missing = Options::parse(&["--max-memory"]);
+
+        //+ Assert
+        assert_eq!(options.max_memory, Some(2 << 30));
+        assert_eq!(
+            missing.err().as_deref(),
+            Some("a value is required for '--max-memory <size>' but none was supplied")
+        );
+    }
+
+    #[test]
+    fn parse_collects_plugins() {
+        //+ Act
+        let (options, commands) =
+            Options::parse(&["--plugin", "a.so", "--plugin", "b.so", "reverse"]).unwrap();
+
+        //+ Assert
+        assert_eq!(
+            options.plugins,
+            vec!["a.so".to_string(), "b.so".to_string()]
+        );
+        assert_eq!(commands, &["reverse"]);
+    }
+
+    #[test]
+    fn parse_reads_pipeline_file() {
+        //+ Act
+        let (options, commands) = Options::parse(&["--pipeline", "clean.yaml", "upper"]).unwrap();
+
+        //+ Assert
+        assert_eq!(options.pipeline, Some("clean.yaml".to_string()));
+        assert_eq!(commands, &["upper"]);
+        assert_eq!(
+            Options::parse(&["--pipeline"]).err().as_deref(),
+            Some(
+                "a value is required for '--pipeline <file.yaml|file.toml>' but none was supplied"
+            )
+        );
+    }
+
+    #[test]
+    fn parse_refuses_json_arrays_from_listen() {
+        //+ Act
+        let listening =
+            Options::parse(&["--listen", "tcp://127.0.0.1:5140", "--format", "json-array"]);
+
+        //+ Assert
+        assert_eq!(
+            listening.err().as_deref(),
+            Some("JSON array input cannot be listened for")
+        );
+    }
+
+    #[test]
+    fn parse_keeps_state_on_reload_only_for_endless_pipeline_files() {
+        //+ Act
+        let (options, _) = Options::parse(&[
+            "--pipeline",
+            "clean.yaml",
+            "--reload-keep-state",
+            "-f",
+            "app.log",
+        ])
+        .unwrap();
+        let without_follow =
+            Options::parse(&["--pipeline", "clean.yaml", "--reload-keep-state", "upper"]);
+
+        //+ Assert
+        assert!(options.reload_keep_state);
+        assert_eq!(
+            without_follow.err().as_deref(),
+            Some("Keeping state on reload needs --pipeline with --follow, --watch or --listen")
+        );
+    }
+
+    #[test]
+    fn parse_reads_explain_flag() {
+        //+ Act
+        let (options, commands) = Options::parse(&["--dry-run", "upper"]).unwrap();
+
+        //+ Assert
+        assert!(options.explain);
+        assert!(!options.quiet);
+        assert_eq!(commands, &["upper"]);
+    }
+
+    #[test]
+    fn parse_reads_error_policy() {
+        //+ Act
+        let (options, commands) =
+            Options::parse(&["--on-error", "annotate", "json", ".a"]).unwrap();
+
+        //+ Assert
+        assert_eq!(options.on_error, Some(ErrorPolicy::Annotate));
+        assert_eq!(commands, &["json", ".a"]);
+        assert_eq!(
+            Options::parse(&["--on-error", "ignore", "trim"])
+                .err()
+                .as_deref(),
+            Some("invalid value 'ignore' for '--on-error <skip|annotate|abort>': Invalid error policy")
+        );
+    }
+
+    #[test]
+    fn parse_reads_thread_count() {
+        //+ Act
+        let (options, _) = Options::parse(&["--threads", "4", "trim"]).unwrap();
+
+        //+ Assert
+        assert_eq!(options.threads, 4);
+        assert!(!options.pipeline_parallelism);
+        assert_eq!(
+            Options::parse(&["--threads", "0", "trim"]).err().as_deref(),
+            Some("invalid value '0' for '--threads <n>': Invalid thread count")
+        );
+    }
+
+    #[test]
+    fn parse_reads_job_count_for_input_files_only() {
+        //+ Act
+        let (options, _) = Options::parse(&["--jobs", "8", "filter", "x", "--", "a.log"]).unwrap();
+        let from_stdin = Options::parse(&["--jobs", "8", "filter", "x"]);
+        let threaded = Options::parse(&["--jobs", "8", "--threads", "2", "upper", "--", "a.log"]);
+
+        //+ Assert
+        assert_eq!(options.jobs, 8);
+        assert_eq!(
+            from_stdin.err().as_deref(),
+            Some("Per-file jobs need input files read as text, not followed, merged or edited in place")
+        );
+        assert!(threaded.is_err());
+        assert!(Options::parse(&["--jobs", "0", "trim"]).is_err());
+    }
+
+    #[test]
+    fn parse_reads_checkpoint_for_input_files_only() {
+        //+ Act
+        let (options, _) =
+            Options::parse(&["--checkpoint", "run.ckpt", "trim", "--", "big.log"]).unwrap();
+        let from_stdin = Options::parse(&["--checkpoint", "run.ckpt", "trim"]);
+        let compressed = Options::parse(&[
+            "--checkpoint",
+            "run.ckpt",
+            "--compress",
+            "gzip",
+            "trim",
+            "--",
+            "big.log",
+        ]);
+
+        //+ Assert
+        assert_eq!(options.checkpoint.as_deref(), Some("run.ckpt"));
+        assert_eq!(
+            from_stdin.err().as_deref(),
+            Some("Checkpoints need input files")
+        );
+        assert_eq!(
+            compressed.err().as_deref(),
+            Some("Checkpoints need one plain output and a single thread")
+        );
+    }
+
+    #[test]
+    fn parse_reads_trace_count_and_pattern() {
+        //+ Arrange
+        let args = ["--trace", "5", "--trace-match", "WARN", "upper"];
+
+        //+ Act
+        let (options, commands) = Options::parse(&args).unwrap();
+
+        //+ Assert
+        assert_eq!(options.trace, Some(5));
+        assert_eq!(options.trace_match, Some("WARN".to_string()));
+        assert_eq!(commands, ["upper"]);
+        assert!(Options::parse(&["--trace-match", "(", "upper"]).is_err());
+        assert!(Options::parse(&["--trace", "5", "--threads", "2", "upper"]).is_err());
+    }
+
+    #[test]
+    fn parse_reads_seed() {
+        //+ Act
+        let (options, _) = Options::parse(&["--seed", "42", "sample", "0.1"]).unwrap();
+
+        //+ Assert
+        assert_eq!(options.seed, Some(42));
+        assert!(Options::parse(&["--seed", "-1", "shuffle"]).is_err());
+    }
+
+    #[test]
+    fn parse_reads_locale_and_ignore_case() {
+        //+ Act
+        let (options, commands) =
+            Options::parse(&["--ignore-case", "--locale", "tr-TR", "filter", "i"]).unwrap();
+
+        //+ Assert
+        assert!(options.ignore_case);
+        assert_eq!(options.case_rules, Some(CaseRules::Turkic));
+        assert_eq!(commands, ["filter", "i"]);
+    }
+
+    #[test]
+    fn parse_reads_color_mode() {
+        //+ Act
+        let (options, _) = Options::parse(&["--color", "always", "filter", "x"]).unwrap();
+
+        //+ Assert
+        assert_eq!(options.color, ColorMode::Always);
+        assert_eq!(Options::default().color, ColorMode::Auto);
+        assert!(Options::parse(&["--color", "sometimes", "filter", "x"]).is_err());
+    }
+
+    #[test]
+    fn parse_reads_input_format() {
+        //+ Act
+        let (options, _) = Options::parse(&["--format", "csv", "csv-select", "id"]).unwrap();
+
+        //+ Assert
+        assert_eq!(options.record_separator, RecordSeparator::Csv);
+        assert_eq!(
+            Options::parse(&["--format", "json-array", "json", ".id"])
+                .unwrap()
+                .0
+                .record_separator,
+            RecordSeparator::JsonArray
+        );
+        assert!(Options::parse(&["--format", "xml", "trim"]).is_err());
+    }
+
+    #[test]
+    fn parse_rejects_sqlite_output_with_byte_stream_options() {
+        //+ Act
+        let (options, _) = Options::parse(&["-o", "sqlite://out.db#events", "trim"]).unwrap();
+        let compressed = Options::parse(&["-o", "sqlite://out.db", "--compress", "gzip", "trim"]);
+
+        //+ Assert
+        assert!(options.is_sqlite_output());
+        assert_eq!(
+            compressed.err().as_deref(),
+            Some("SQLite output cannot be split, compressed, encoded or checkpointed")
+        );
+    }
+
+    #[test]
+    fn parse_reads_merge_modes() {
+        //+ Arrange
+        let args = ["--zip", ",", "upper", "--", "a.txt", "b.txt"];
+
+        //+ Act
+        let (options, _) = Options::parse(&args).unwrap();
+
+        //+ Assert
+        assert_eq!(options.merge, Some(MergeMode::Zip(",".to_string())));
+        assert_eq!(
+            Options::parse(&["--interleave", "-i", "trim"])
+                .err()
+                .as_deref(),
+            Some("Merged inputs cannot be followed, edited in place or checkpointed")
+        );
+    }
+
+    #[test]
+    fn parse_reads_preview_line_count() {
+        //+ Arrange
+        let args = ["--preview", "5", "trim"];
+
+        //+ Act
+        let (options, _) = Options::parse(&args).unwrap();
+
+        //+ Assert
+        assert_eq!(options.preview, Some(5));
+        assert!(Options::parse(&["--preview", "0", "trim"]).is_err());
+        assert_eq!(
+            Options::parse(&["--preview", "5", "--bytes", "trim"])
+                .err()
+                .as_deref(),
+            Some("Previews need text mode and input that ends")
+        );
+    }
+
+    #[test]
+    fn parse_rejects_kept_headers_without_one_plain_output() {
+        //+ Act
+        let zero = Options::parse(&["--keep-header", "0", "sort"]);
+        let partitioned = Options::parse(&[
+            "--keep-header",
+            "1",
+            "--output-partition",
+            "{1}.csv",
+            "sort",
+        ]);
+
+        //+ Assert
+        assert_eq!(
+            zero.err().as_deref(),
+            Some("invalid value '0' for '--keep-header <n>': Invalid header line count")
+        );
+        assert_eq!(
+            partitioned.err().as_deref(),
+            Some("Kept header lines need one plain output")
+        );
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    fs::{remove_file, rename, File, OpenOptions},
+    io::{stdout, BufWriter, ErrorKind, Seek, SeekFrom, Stdout, Write},
+    path::{Path, PathBuf},
+};
+
+use encoding_rs::Encoding;
+use flate2::write::GzEncoder;
+
+use crate::encoding::EncodedWriter;
+
+// Writes to a temporary file next to the destination and only renames it into
+// place on commit, so a failed or interrupted run never leaves a truncated
+// destination behind. Dropping it uncommitted removes the temporary file.
+pub struct AtomicFile {
+    path: PathBuf,
+    temp_path: PathBuf,
+    writer: BufWriter<File>,
+    committed: bool,
+}
+
+pub const BROKEN_PIPE: &str = "Broken pipe";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputCompression {
+    Gzip,
+    Zstd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitLimit {
+    Lines(usize),
+    Bytes(usize),
+}
+
+// Rotates through numbered files as each fills up. Every write is taken to be
+// one whole record, which is how lines are written, so records never straddle
+// two files. Each file is compressed and committed on its own.
+pub struct SplitOutput {
+    path: String,
+    compression: Option<OutputCompression>,
+    limit: SplitLimit,
+    encoding: Option<&'static Encoding>,
+    buffer: usize,
+    index: usize,
+    current: Box<Output>,
+    lines: usize,
+    bytes: usize,
+}
+
+pub enum Sink {
+    Stdout(BufWriter<Stdout>),
+    File(AtomicFile),
+    // Written in place, for runs that resume from a checkpoint.
+    Append(BufWriter<File>),
+}
+
+// Compression wraps the sink, so a compressed file is still only put in place
+// once the encoder has written its trailer.
+pub enum Output {
+    Plain(Sink),
+    Gzip(GzEncoder<Sink>),
+    Zstd(zstd::Encoder<'static, Sink>),
+    Split(SplitOutput),
+    Encoded(Box<EncodedWriter<Output>>),
+    #[cfg(feature = "sqlite")]
+    Sqlite(Box<crate::sqlite::SqliteOutput>),
+}
+
+impl OutputCompression {
+    pub fn parse(name: &str) -> Result<OutputCompression, &'static str> {
+        match name.to_lowercase().as_str() {
+            "gzip" | "gz" => Ok(OutputCompression::Gzip),
+            "zstd" | "zst" => Ok(OutputCompression::Zstd),
+            _ => Err("Invalid compression format"),
+        }
+    }
+}
+
+impl AtomicFile {
+    pub fn create(path: &str, capacity: usize) -> Result<AtomicFile, &'static str> {
+        let path = PathBuf::from(path);
+        let name = path
+            .file_name()
+            .ok_or("Invalid output path")?
+            .to_string_lossy()
+            .into_owned();
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let temp_path = directory.join(format!(".{}.rangler-{}.tmp", name, std::process::id()));
+
+        let file = File::create(&temp_path).map_err(|_| "Could not create output file")?;
+
+        Ok(AtomicFile {
+            path,
+            temp_path,
+            writer: BufWriter::with_capacity(capacity, file),
+            committed: false,
+        })
+    }
+
+    pub fn copy_permissions_from(&self, source: &str) -> Result<(), &'static str> {
+        let permissions = std::fs::metadata(source)
+            .map_err(|_| "IO Error")?
+            .permissions();
+
+        std::fs::set_permissions(&self.temp_path, permissions).map_err(|_| "IO Error")
+    }
+
+    pub fn commit(mut self) -> Result<(), &'static str> {
+        self.writer.flush().map_err(|_| "IO Error")?;
+        self.writer.get_ref().sync_all().map_err(|_| "IO Error")?;
+        rename(&self.temp_path, &self.path).map_err(|_| "Could not replace output file")?;
+        self.committed = true;
+
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = remove_file(&self.temp_path);
+        }
+    }
+}
+
+impl SplitOutput {
+    fn part(&self, index: usize) -> Result<Box<Output>, &'static str> {
+        SplitOutput::open_part(
+            &self.path,
+            index,
+            self.compression,
+            self.encoding,
+            self.buffer,
+        )
+    }
+
+    fn open_part(
+        path: &str,
+        index: usize,
+        compression: Option<OutputCompression>,
+        encoding: Option<&'static Encoding>,
+        buffer: usize,
+    ) -> Result<Box<Output>, &'static str> {
+        let path = format!("{}.{:04}", path, index);
+        let output = Output::open(Some(&path), compression, buffer)?;
+
+        Ok(Box::new(output.encoded(encoding)))
+    }
+
+    fn is_full(&self, incoming: usize) -> bool {
+        match self.limit {
+            SplitLimit::Lines(lines) => self.lines >= lines,
+            SplitLimit::Bytes(bytes) => self.bytes > 0 && self.bytes + incoming > bytes,
+        }
+    }
+}
+
+impl Write for SplitOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.is_full(buf.len()) {
+            let next = self.part(self.index + 1).map_err(std::io::Error::other)?;
+            std::mem::replace(&mut self.current, next)
+                .finish()
+                .map_err(std::io::Error::other)?;
+            self.index += 1;
+            self.lines = 0;
+            self.bytes = 0;
+        }
+
+        self.current.write_all(buf)?;
+        self.lines += 1;
+        self.bytes += buf.len();
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.current.flush()
+    }
+}
+
+// Broken pipes get their own message, so the caller can tell a reader that
+// stopped early from a real failure.
+pub fn write_error(error: std::io::Error) -> String {
+    match error.kind() {
+        ErrorKind::BrokenPipe => BROKEN_PIPE.to_string(),
+        _ => format!("Could not write output: {}", error),
+    }
+}
+
+impl Sink {
+    fn finish(self) -> Result<(), &'static str> {
+        match self {
+            Sink::Stdout(mut writer) => writer.flush().map_err(|error| match error.kind() {
+                ErrorKind::BrokenPipe => BROKEN_PIPE,
+                _ => "IO Error",
+            }),
+            Sink::File(file) => file.commit(),
+            Sink::Append(mut writer) => writer
+                .flush()
+                .and_then(|_| writer.get_ref().sync_all())
+                .map_err(|_| "IO Error"),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Sink::Stdout(writer) => writer.write(buf),
+            Sink::File(file) => file.write(buf),
+            Sink::Append(writer) => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Sink::Stdout(writer) => writer.flush(),
+            Sink::File(file) => file.flush(),
+            Sink::Append(writer) => writer.flush(),
+        }
+    }
+}
+
+impl Output {
+    pub fn open(
+        path: Option<&str>,
+        compression: Option<OutputCompression>,
+        buffer: usize,
+    ) -> Result<Output, &'static str> {
+        let sink = match path {
+            None => Sink::Stdout(BufWriter::with_capacity(buffer, stdout())),
+            Some(path) => Sink::File(AtomicFile::create(path, buffer)?),
+        };
+
+        Output::new(sink, compression)
+    }
+
+    // Opens the output of a checkpointed run in place, cut back to `length`,
+    // dropping whatever was written after the checkpoint the run resumes from.
+    pub fn append(path: &str, length: u64, buffer: usize) -> Result<Output, &'static str> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .map_err(|_| "Could not create output file")?;
+        file.set_len(length)
+            .and_then(|_| file.seek(SeekFrom::End(0)))
+            .map_err(|_| "Could not resume output file")?;
+
+        Output::new(Sink::Append(BufWriter::with_capacity(buffer, file)), None)
+    }
+
+    // Every part gets its own compression and encoding, so each one stands on
+    // its own, byte order mark included.
+    pub fn split(
+        path: &str,
+        compression: Option<OutputCompression>,
+        encoding: Option<&'static Encoding>,
+        limit: SplitLimit,
+        buffer: usize,
+    ) -> Result<Output, &'static str> {
+        Ok(Output::Split(SplitOutput {
+            path: path.to_string(),
+            compression,
+            limit,
+            encoding,
+            buffer,
+            index: 1,
+            current: SplitOutput::open_part(path, 1, compression, encoding, buffer)?,
+            lines: 0,
+            bytes: 0,
+        }))
+    }
+
+    // Rows are split out of what is written on the record terminator.
+    #[cfg(feature = "sqlite")]
+    pub fn sqlite(location: &str, terminator: &str) -> Result<Output, String> {
+        crate::sqlite::SqliteOutput::open(location, terminator)
+            .map(|output| Output::Sqlite(Box::new(output)))
+    }
+
+    #[cfg(not(feature = "sqlite"))]
+    pub fn sqlite(_: &str, _: &str) -> Result<Output, String> {
+        Err("Writing to SQLite needs rangler built with the sqlite feature".to_string())
+    }
+
+    // Encoding happens before compression, on the text itself.
+    pub fn encoded(self, encoding: Option<&'static Encoding>) -> Output {
+        match encoding {
+            Some(encoding) => Output::Encoded(Box::new(EncodedWriter::new(self, encoding))),
+            None => self,
+        }
+    }
+
+    pub fn new(sink: Sink, compression: Option<OutputCompression>) -> Result<Output, &'static str> {
+        match compression {
+            None => Ok(Output::Plain(sink)),
+            Some(OutputCompression::Gzip) => Ok(Output::Gzip(GzEncoder::new(
+                sink,
+                flate2::Compression::default(),
+            ))),
+            Some(OutputCompression::Zstd) => zstd::Encoder::new(sink, 0)
+                .map(Output::Zstd)
+                .map_err(|_| "IO Error"),
+        }
+    }
+
+    pub fn finish(self) -> Result<(), &'static str> {
+        let sink = match self {
+            Output::Plain(sink) => sink,
+            Output::Gzip(encoder) => encoder.finish().map_err(|_| "IO Error")?,
+            Output::Zstd(encoder) => encoder.finish().map_err(|_| "IO Error")?,
+            Output::Split(split) => return split.current.finish(),
+            Output::Encoded(writer) => return writer.into_inner().finish(),
+            #[cfg(feature = "sqlite")]
+            Output::Sqlite(output) => return output.finish(),
+        };
+
+        sink.finish()
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Plain(sink) => sink.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+            Output::Zstd(encoder) => encoder.write(buf),
+            Output::Split(split) => split.write(buf),
+            Output::Encoded(writer) => writer.write(buf),
+            #[cfg(feature = "sqlite")]
+            Output::Sqlite(output) => output.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(sink) => sink.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+            Output::Zstd(encoder) => encoder.flush(),
+            Output::Split(split) => split.flush(),
+            Output::Encoded(writer) => writer.flush(),
+            #[cfg(feature = "sqlite")]
+            Output::Sqlite(output) => output.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{AtomicFile, Output, OutputCompression, Sink, SplitLimit};
+
+    #[test]
+    fn commit_replaces_destination_only_on_success() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-atomic-{}.txt", std::process::id()));
+        std::fs::write(&path, "old\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        //+ Act
+        let mut abandoned = AtomicFile::create(path, 64).unwrap();
+        abandoned.write_all(b"partial").unwrap();
+        drop(abandoned);
+        let after_abandon = std::fs::read_to_string(path).unwrap();
+
+        let mut committed = AtomicFile::create(path, 64).unwrap();
+        committed.write_all(b"new\n").unwrap();
+        committed.commit().unwrap();
+
+        //+ Assert
+        assert_eq!(after_abandon, "old\n");
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "new\n");
+        assert_eq!(
+            std::fs::read_dir(std::env::temp_dir())
+                .unwrap()
+                .filter_map(Result::ok)
+                .filter(|entry| entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with(&format!(".rangler-atomic-{}", std::process::id())))
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn finish_writes_compressed_file() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-compress-{}.gz", std::process::id()));
+        let file = AtomicFile::create(path.to_str().unwrap(), 64).unwrap();
+        let mut output = Output::new(Sink::File(file), Some(OutputCompression::Gzip)).unwrap();
+
+        //+ Act
+        output.write_all(b"compressed\n").unwrap();
+        output.finish().unwrap();
+
+        //+ Assert
+        let mut text = String::new();
+        let file = std::fs::File::open(&path).unwrap();
+        std::io::Read::read_to_string(&mut flate2::read::GzDecoder::new(file), &mut text).unwrap();
+        assert_eq!(text, "compressed\n");
+    }
+
+    #[test]
+    fn split_rotates_files_at_record_boundaries() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-split-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut output = Output::split(path, None, None, SplitLimit::Bytes(8), 64).unwrap();
+
+        //+ Act
+        for line in ["one\n", "two\n", "three\n", "a much longer line\n"] {
+            output.write_all(line.as_bytes()).unwrap();
+        }
+        output.finish().unwrap();
+
+        //+ Assert
+        let part = |index: usize| std::fs::read_to_string(format!("{}.{:04}", path, index)).ok();
+        assert_eq!(part(1), Some("one\ntwo\n".to_string()));
+        assert_eq!(part(2), Some("three\n".to_string()));
+        assert_eq!(part(3), Some("a much longer line\n".to_string()));
+        assert_eq!(part(4), None);
+    }
+}
@@ -0,0 +1,308 @@
+use std::{
+    mem::take,
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+use crate::{
+    dedupe::ShardedSet, error::RanglerError, options::Options, pipeline::Pipeline, stats::StepStats,
+};
+
+// Lines are handed out this many at a time, so the threads spend their time
+// on the lines rather than on the channels.
+const BATCH_SIZE: usize = 1024;
+
+// The line number, prefix and text of every line in a batch.
+type Batch = Vec<(usize, String, String)>;
+
+/// The lines a batch turned into, each with its prefix, and the error that
+/// stopped the batch early, if any.
+pub type Processed = (Vec<(String, Vec<String>)>, Option<&'static str>);
+
+// What a worker sends back: every line's number, prefix and output, with the
+// line it claimed in a shared dedupe, and the error that stopped the batch.
+type Outcome = (
+    Vec<(usize, String, Vec<String>, Option<Vec<u8>>)>,
+    Option<&'static str>,
+);
+
+// Runs copies of a stateless pipeline on worker threads. Batches go to the
+// workers in turn and come back in the same turn, so the output keeps the
+// order of the input. An exact dedupe is the one state the copies can keep,
+// on a set they share.
+pub struct Workers {
+    workers: Vec<Worker>,
+    dedupe: ShardedSet,
+    batch: Batch,
+    line_number: usize,
+    next: usize,
+    pending: usize,
+}
+
+struct Worker {
+    batches: Sender<Batch>,
+    processed: Receiver<Outcome>,
+    thread: JoinHandle<Vec<StepStats>>,
+}
+
+impl Workers {
+    // Every worker builds its own pipeline from the commands, as pipelines
+    // stay on the thread they were built on.
+    pub fn start(
+        count: usize,
+        commands: &[String],
+        options: &Options,
+    ) -> Result<Workers, RanglerError> {
+        let mut workers = vec![];
+        let dedupe = ShardedSet::new();
+
+        for _ in 0..count {
+            let (batches, received_batches) = channel::<Batch>();
+            let (sent_processed, processed) = channel::<Outcome>();
+            let (sent_ready, ready) = channel();
+            let commands = commands.to_vec();
+            let (on_error, optimize) = (options.on_error, !options.no_optimize);
+            let (case_rules, ignore_case) = (options.case_rules, options.ignore_case);
+            let dedupe = dedupe.clone();
+
+            let thread = thread::spawn(move || {
+                let mut pipeline = match Pipeline::build_pipeline(&commands) {
+                    Ok(pipeline) => pipeline,
+                    Err(error) => {
+                        sent_ready.send(Err(error)).ok();
+                        return vec![];
+                    }
+                };
+                if let Some(policy) = on_error {
+                    pipeline.set_error_policy(policy);
+                }
+                if let Some(rules) = case_rules {
+                    pipeline.set_case_rules(rules);
+                }
+                if ignore_case {
+                    pipeline.set_ignore_case();
+                }
+                if optimize {
+                    pipeline.optimize();
+                }
+                if pipeline.can_share_dedupe() {
+                    pipeline.share_dedupe(&dedupe);
+                }
+                sent_ready.send(Ok(())).ok();
+
+                for batch in received_batches {
+                    let mut lines = vec![];
+                    let mut error = None;
+                    for (line_number, prefix, line) in batch {
+                        match pipeline.apply_numbered(line_number, line) {
+                            Ok(output) => {
+                                lines.push((line_number, prefix, output, pipeline.take_claim()))
+                            }
+                            Err(message) => {
+                                error = Some(message);
+                                break;
+                            }
+                        }
+                    }
+                    if sent_processed.send((lines, error)).is_err() {
+                        break;
+                    }
+                }
+
+                pipeline.step_stats().to_vec()
+            });
+
+            ready.recv().map_err(|_| "Worker thread stopped")??;
+            workers.push(Worker {
+                batches,
+                processed,
+                thread,
+            });
+        }
+
+        Ok(Workers {
+            workers,
+            dedupe,
+            batch: vec![],
+            line_number: 0,
+            next: 0,
+            pending: 0,
+        })
+    }
+
+    // Queues a line, handing back the batches that came back meanwhile.
+    pub fn push(&mut self, prefix: String, line: String) -> Result<Vec<Processed>, RanglerError> {
+        self.line_number += 1;
+        self.batch.push((self.line_number, prefix, line));
+
+        if self.batch.len() < BATCH_SIZE {
+            return Ok(vec![]);
+        }
+
+        // With every worker busy, the oldest batch has to come back first.
+        let mut processed = vec![];
+        if self.pending == self.workers.len() {
+            processed.push(self.collect()?);
+        }
+        self.send()?;
+
+        Ok(processed)
+    }
+
+    // Sends off the lines queued so far and waits for every batch to come
+    // back, at the end of each input.
+    pub fn flush(&mut self) -> Result<Vec<Processed>, RanglerError> {
+        let mut processed = vec![];
+        if !self.batch.is_empty() {
+            if self.pending == self.workers.len() {
+                processed.push(self.collect()?);
+            }
+            self.send()?;
+        }
+
+        while self.pending > 0 {
+            processed.push(self.collect()?);
+        }
+
+        Ok(processed)
+    }
+
+    // Stops the workers, giving back the step stats of each.
+    pub fn stop(self) -> Vec<Vec<StepStats>> {
+        self.workers
+            .into_iter()
+            .filter_map(|worker| {
+                drop(worker.batches);
+                worker.thread.join().ok()
+            })
+            .collect()
+    }
+
+    fn send(&mut self) -> Result<(), RanglerError> {
+        let batch = take(&mut self.batch);
+        self.workers[self.next]
+            .batches
+            .send(batch)
+            .map_err(|_| "Worker thread stopped")?;
+        self.next = (self.next + 1) % self.workers.len();
+        self.pending += 1;
+
+        Ok(())
+    }
+
+    fn collect(&mut self) -> Result<Processed, RanglerError> {
+        let count = self.workers.len();
+        let oldest = (self.next + count - self.pending) % count;
+        let (lines, error) = self.workers[oldest]
+            .processed
+            .recv()
+            .map_err(|_| "Worker thread stopped")?;
+        self.pending -= 1;
+
+        // Every earlier line has claimed its copy by now, so a line that lost
+        // its claim to one was a duplicate after all.
+        let lines = lines
+            .into_iter()
+            .map(|(line_number, prefix, output, claimed)| match claimed {
+                Some(line) if !self.dedupe.owns(&line, line_number) => (prefix, vec![]),
+                _ => (prefix, output),
+            })
+            .collect();
+
+        Ok((lines, error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Processed, Workers};
+    use crate::options::Options;
+
+    fn lines(processed: Vec<Processed>) -> Vec<String> {
+        processed
+            .into_iter()
+            .flat_map(|(lines, _)| lines)
+            .flat_map(|(prefix, lines)| {
+                lines
+                    .into_iter()
+                    .map(move |line| prefix.clone() + line.as_str())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn workers_keep_input_order() {
+        //+ Arrange
+        let commands = ["filter", "[02468]$", "prepend", "n"].map(String::from);
+        let mut workers = Workers::start(3, &commands, &Options::default()).unwrap();
+
+        //+ Act
+        let mut processed = vec![];
+        for number in 0..5000 {
+            processed.extend(workers.push(String::new(), number.to_string()).unwrap());
+        }
+        processed.extend(workers.flush().unwrap());
+        let stats = workers.stop();
+
+        //+ Assert
+        let expected: Vec<String> = (0..5000).step_by(2).map(|n| format!("n{}", n)).collect();
+        assert_eq!(lines(processed), expected);
+        let received: usize = stats.iter().map(|steps| steps[0].received).sum();
+        assert_eq!(received, 5000);
+    }
+
+    #[test]
+    fn workers_stop_a_batch_at_the_failing_line() {
+        //+ Arrange
+        let commands = ["json", ".a", "--on-error", "error"].map(String::from);
+        let mut workers = Workers::start(2, &commands, &Options::default()).unwrap();
+
+        //+ Act
+        workers
+            .push("a:".to_string(), r#"{"a":1}"#.to_string())
+            .unwrap();
+        workers.push("b:".to_string(), "plain".to_string()).unwrap();
+        workers
+            .push("c:".to_string(), r#"{"a":3}"#.to_string())
+            .unwrap();
+        let processed = workers.flush().unwrap();
+
+        //+ Assert
+        assert_eq!(
+            processed,
+            vec![(
+                vec![("a:".to_string(), vec!["1".to_string()])],
+                Some("Invalid JSON or missing path on line 2: plain")
+            )]
+        );
+    }
+
+    #[test]
+    fn workers_share_a_dedupe_keeping_first_occurrences() {
+        //+ Arrange
+        let commands = ["lower", "dedupe", "prepend", "n"].map(String::from);
+        let mut workers = Workers::start(4, &commands, &Options::default()).unwrap();
+
+        //+ Act
+        let mut processed = vec![];
+        for number in 0..20_000 {
+            let line = match number % 3 {
+                0 => format!("A{}", number % 700),
+                _ => format!("b{}", number % 1300),
+            };
+            processed.extend(workers.push(String::new(), line).unwrap());
+        }
+        processed.extend(workers.flush().unwrap());
+
+        //+ Assert
+        let mut seen = std::collections::HashSet::new();
+        let expected: Vec<String> = (0..20_000)
+            .map(|number| match number % 3 {
+                0 => format!("na{}", number % 700),
+                _ => format!("nb{}", number % 1300),
+            })
+            .filter(|line| seen.insert(line.clone()))
+            .collect();
+        assert_eq!(lines(processed), expected);
+    }
+}
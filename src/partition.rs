@@ -0,0 +1,163 @@
+use std::{
+    collections::HashSet,
+    fs::{create_dir_all, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use chrono::format::{Item, StrftimeItems};
+
+use crate::timestamp::find_timestamp;
+
+const MAX_OPEN_FILES: usize = 32;
+
+pub struct PartitionedOutput {
+    template: String,
+    current: Option<String>,
+    files: OpenFiles,
+    terminator: String,
+}
+
+// Keeps the most recently used handle at the end, evicting from the front
+// once MAX_OPEN_FILES are open. Files are truncated the first time a run
+// touches them and appended to when reopened after an eviction.
+#[derive(Debug, Default)]
+pub struct OpenFiles {
+    open: Vec<(String, BufWriter<File>)>,
+    created: HashSet<String>,
+}
+
+impl PartitionedOutput {
+    pub fn new(template: &str) -> Result<PartitionedOutput, &'static str> {
+        if StrftimeItems::new(template).any(|item| item == Item::Error) {
+            return Err("Invalid output partition format");
+        }
+
+        Ok(PartitionedOutput {
+            template: template.to_string(),
+            current: None,
+            files: OpenFiles::default(),
+            terminator: "\n".to_string(),
+        })
+    }
+
+    pub fn with_terminator(mut self, terminator: &str) -> PartitionedOutput {
+        self.terminator = terminator.to_string();
+        self
+    }
+
+    // Lines without a timestamp belong to the record before them (stack traces,
+    // wrapped messages), so they go wherever the last timestamped line went.
+    // Returns false when no timestamp has been seen yet.
+    pub fn write_line(&mut self, line: &str) -> Result<bool, &'static str> {
+        if let Some(timestamp) = find_timestamp(line) {
+            self.current = Some(timestamp.format(&self.template).to_string());
+        }
+
+        let path = match &self.current {
+            Some(path) => path.clone(),
+            None => return Ok(false),
+        };
+
+        let writer = self.files.writer_for(&path)?;
+        writer
+            .write_all(line.as_bytes())
+            .and_then(|_| writer.write_all(self.terminator.as_bytes()))
+            .map_err(|_| "IO Error")?;
+
+        Ok(true)
+    }
+
+    pub fn flush(&mut self) -> Result<(), &'static str> {
+        self.files.flush()
+    }
+}
+
+impl OpenFiles {
+    pub fn flush(&mut self) -> Result<(), &'static str> {
+        for (_, writer) in self.open.iter_mut() {
+            writer.flush().map_err(|_| "IO Error")?;
+        }
+
+        Ok(())
+    }
+
+    pub fn writer_for(&mut self, path: &str) -> Result<&mut BufWriter<File>, &'static str> {
+        if let Some(index) = self
+            .open
+            .iter()
+            .position(|(open_path, _)| open_path == path)
+        {
+            let entry = self.open.remove(index);
+            self.open.push(entry);
+        } else {
+            if self.open.len() >= MAX_OPEN_FILES {
+                let (_, mut evicted) = self.open.remove(0);
+                evicted.flush().map_err(|_| "IO Error")?;
+            }
+
+            if let Some(parent) = Path::new(path).parent() {
+                create_dir_all(parent).map_err(|_| "Could not create output directory")?;
+            }
+
+            let first_open = self.created.insert(path.to_string());
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(!first_open)
+                .truncate(first_open)
+                .open(path)
+                .map_err(|_| "Could not create output file")?;
+
+            self.open.push((path.to_string(), BufWriter::new(file)));
+        }
+
+        Ok(&mut self.open.last_mut().unwrap().1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, remove_dir_all};
+
+    use super::PartitionedOutput;
+
+    #[test]
+    fn new_rejects_invalid_format() {
+        //+ Act
+        let output = PartitionedOutput::new("out/%Q.log");
+
+        //+ Assert
+        assert!(output.is_err());
+        assert_eq!(output.err().unwrap(), "Invalid output partition format");
+    }
+
+    #[test]
+    fn write_line_partitions_by_timestamp() {
+        //+ Arrange
+        let directory =
+            std::env::temp_dir().join(format!("rangler-partition-{}", std::process::id()));
+        let template = format!("{}/%Y-%m-%d.log", directory.display());
+        let mut output = PartitionedOutput::new(&template).unwrap();
+
+        //+ Act
+        let before_any_timestamp = output.write_line("preamble").unwrap();
+        output.write_line("2024-05-01 10:00:00 first").unwrap();
+        output.write_line("  continuation").unwrap();
+        output.write_line("2024-05-02T09:00:00Z second").unwrap();
+        output.flush().unwrap();
+
+        //+ Assert
+        assert!(!before_any_timestamp);
+        assert_eq!(
+            read_to_string(directory.join("2024-05-01.log")).unwrap(),
+            "2024-05-01 10:00:00 first\n  continuation\n"
+        );
+        assert_eq!(
+            read_to_string(directory.join("2024-05-02.log")).unwrap(),
+            "2024-05-02T09:00:00Z second\n"
+        );
+
+        remove_dir_all(directory).unwrap();
+    }
+}
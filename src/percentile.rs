@@ -0,0 +1,303 @@
+use std::f64::consts::PI;
+
+use crate::calc::format_number;
+use crate::sort::{leading_number, SortKey};
+
+// How many centroids the digest aims for. A hundred keeps percentiles within
+// about 1% of their rank in the middle and much closer at the tails, where
+// p99 and p999 live, in a few KiB whatever the input size.
+const COMPRESSION: f64 = 100.0;
+const BUFFER: usize = 500;
+
+// A merging t-digest: values are buffered, then sorted into the centroids
+// once the buffer fills. Centroids near the middle may absorb many values,
+// ones at the tails only a few, so extreme percentiles stay accurate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Digest {
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<f64>,
+    count: f64,
+    min: f64,
+    max: f64,
+}
+
+impl Digest {
+    pub fn new() -> Digest {
+        Digest {
+            centroids: vec![],
+            buffer: Vec::with_capacity(BUFFER),
+            count: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    pub fn push(&mut self, value: f64) {
+        self.buffer.push(value);
+        self.count += 1.0;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if self.buffer.len() >= BUFFER {
+            self.merge();
+        }
+    }
+
+    // Whether some centroid stands for more than one value, which is when
+    // percentiles stop being exact.
+    pub fn is_compressed(&mut self) -> bool {
+        self.merge();
+        self.centroids.len() < self.count as usize
+    }
+
+    pub fn memory(&self) -> usize {
+        self.centroids.capacity() * std::mem::size_of::<(f64, f64)>()
+            + self.buffer.capacity() * std::mem::size_of::<f64>()
+    }
+
+    // Walks the sorted centroids, joining neighbours while the joined
+    // centroid stays within one unit of the scale function k.
+    fn merge(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let mut points = std::mem::take(&mut self.centroids);
+        points.extend(self.buffer.drain(..).map(|value| (value, 1.0)));
+        points.sort_by(|left, right| left.0.total_cmp(&right.0));
+
+        let mut merged: Vec<(f64, f64)> = Vec::with_capacity(COMPRESSION as usize * 2);
+        let mut before = 0.0;
+        let mut limit = scale_inverse(scale(0.0) + 1.0);
+        for (mean, weight) in points {
+            match merged.last_mut() {
+                Some(last) if (before + last.1 + weight) / self.count <= limit => {
+                    last.1 += weight;
+                    last.0 += (mean - last.0) * weight / last.1;
+                }
+                last => {
+                    if let Some(last) = last {
+                        before += last.1;
+                        limit = scale_inverse(scale(before / self.count) + 1.0);
+                    }
+                    merged.push((mean, weight));
+                }
+            }
+        }
+
+        self.centroids = merged;
+    }
+
+    // Interpolates between the centres of the centroids either side of the
+    // rank, and between the exact minimum or maximum at either end.
+    pub fn quantile(&mut self, q: f64) -> Option<f64> {
+        self.merge();
+        let (first, last) = (self.centroids.first()?, self.centroids.last()?);
+        if self.centroids.len() == 1 {
+            return Some(first.0);
+        }
+
+        let rank = q * self.count;
+        if rank <= first.1 / 2.0 {
+            return Some(self.min + (first.0 - self.min) * rank / (first.1 / 2.0));
+        }
+        if rank >= self.count - last.1 / 2.0 {
+            let past = rank - (self.count - last.1 / 2.0);
+            return Some(last.0 + (self.max - last.0) * past / (last.1 / 2.0));
+        }
+
+        let mut centre = first.1 / 2.0;
+        for pair in self.centroids.windows(2) {
+            let next = centre + (pair[0].1 + pair[1].1) / 2.0;
+            if rank <= next {
+                let share = (rank - centre) / (next - centre);
+                return Some(pair[0].0 + (pair[1].0 - pair[0].0) * share);
+            }
+            centre = next;
+        }
+
+        Some(self.max)
+    }
+}
+
+fn scale(q: f64) -> f64 {
+    COMPRESSION / (2.0 * PI) * (2.0 * q - 1.0).asin()
+}
+
+fn scale_inverse(k: f64) -> f64 {
+    ((k * 2.0 * PI / COMPRESSION)
+        .clamp(-PI / 2.0, PI / 2.0)
+        .sin()
+        + 1.0)
+        / 2.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Statistic {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Mean,
+    Percentile(f64),
+}
+
+impl Statistic {
+    // Takes count, sum, min, max, mean, median or a percentile like p95 or
+    // p99.9.
+    pub fn parse(name: &str) -> Result<Statistic, &'static str> {
+        match name.to_lowercase().as_str() {
+            "count" => Ok(Statistic::Count),
+            "sum" => Ok(Statistic::Sum),
+            "min" => Ok(Statistic::Min),
+            "max" => Ok(Statistic::Max),
+            "mean" | "avg" => Ok(Statistic::Mean),
+            "median" => Ok(Statistic::Percentile(50.0)),
+            name => name
+                .strip_prefix('p')
+                .and_then(|percent| percent.parse::<f64>().ok())
+                .filter(|percent| (0.0..=100.0).contains(percent))
+                .map(Statistic::Percentile)
+                .ok_or("Unknown statistic"),
+        }
+    }
+}
+
+// Summarizes the numbers in every line and emits one `name=value` line at the
+// end of input, e.g. `p50=12 p95=240 p99=910`. The number is the one the line,
+// or its key, starts with; lines without one are left out. Only the digest is
+// kept, so memory stays bounded however long the input.
+#[derive(Debug, PartialEq)]
+pub struct Stats {
+    names: Vec<String>,
+    statistics: Vec<Statistic>,
+    key: Option<SortKey>,
+    digest: Digest,
+    sum: f64,
+    estimated: bool,
+}
+
+impl Stats {
+    pub fn parse(list: &str, key: Option<SortKey>) -> Result<Stats, &'static str> {
+        let names: Vec<String> = list
+            .split(',')
+            .map(|name| name.trim().to_string())
+            .collect();
+        let statistics = names
+            .iter()
+            .map(|name| Statistic::parse(name))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Stats {
+            names,
+            statistics,
+            key,
+            digest: Digest::new(),
+            sum: 0.0,
+            estimated: false,
+        })
+    }
+
+    pub fn push(&mut self, line: &str) {
+        let text = match &self.key {
+            Some(key) => key.extract(line),
+            None => Some(line),
+        };
+        if let Some(value) = text.and_then(leading_number) {
+            self.digest.push(value);
+            self.sum += value;
+        }
+    }
+
+    // Whether a percentile it emitted was estimated rather than exact.
+    pub fn is_estimated(&self) -> bool {
+        self.estimated
+    }
+
+    // Nothing is emitted when no line held a number.
+    pub fn flush(&mut self) -> Vec<String> {
+        if self.digest.count == 0.0 {
+            return vec![];
+        }
+
+        let digest = &mut self.digest;
+        let percentiles = self
+            .statistics
+            .iter()
+            .any(|statistic| matches!(statistic, Statistic::Percentile(_)));
+        self.estimated |= percentiles && digest.is_compressed();
+        let values: Vec<String> = self
+            .statistics
+            .iter()
+            .zip(&self.names)
+            .map(|(statistic, name)| {
+                let value = match *statistic {
+                    Statistic::Count => digest.count,
+                    Statistic::Sum => self.sum,
+                    Statistic::Min => digest.min,
+                    Statistic::Max => digest.max,
+                    Statistic::Mean => self.sum / digest.count,
+                    Statistic::Percentile(percent) => {
+                        digest.quantile(percent / 100.0).unwrap_or(f64::NAN)
+                    }
+                };
+
+                format!("{}={}", name, format_number(value))
+            })
+            .collect();
+
+        self.digest = Digest::new();
+        self.sum = 0.0;
+        vec![values.join(" ")]
+    }
+
+    pub fn memory(&self) -> usize {
+        self.digest.memory()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Digest, Statistic, Stats};
+    use crate::sort::SortKey;
+
+    #[test]
+    fn digest_estimates_percentiles_in_bounded_memory() {
+        //+ Arrange
+        let mut digest = Digest::new();
+
+        //+ Act
+        for value in 1..=100_000 {
+            digest.push(value as f64);
+        }
+
+        //+ Assert
+        for (q, exact) in [(0.5, 50_000.0), (0.95, 95_000.0), (0.999, 99_900.0)] {
+            let estimate = digest.quantile(q).unwrap();
+            assert!(
+                (estimate - exact).abs() / exact < 0.01,
+                "p{} estimated as {}",
+                q * 100.0,
+                estimate
+            );
+        }
+        assert!(digest.is_compressed());
+        assert!(digest.memory() < 16 * 1024);
+    }
+
+    #[test]
+    fn flush_emits_each_statistic_by_name() {
+        //+ Arrange
+        let key = SortKey::parse("rt=(\\S+)").unwrap();
+        let mut stats = Stats::parse("count,min,median,p100", Some(key)).unwrap();
+
+        //+ Act
+        for line in ["GET rt=30ms", "GET rt=10ms", "GET", "GET rt=20ms"] {
+            stats.push(line);
+        }
+
+        //+ Assert
+        assert_eq!(stats.flush(), ["count=3 min=10 median=20 p100=30"]);
+        assert!(!stats.is_estimated());
+        assert_eq!(Statistic::parse("p101"), Err("Unknown statistic"));
+    }
+}
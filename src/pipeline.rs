@@ -1,278 +1,4872 @@
-use std::collections::HashSet;
+use std::{borrow::Cow, collections::HashSet};
 
-use regex::Regex;
+use chrono::{DateTime, FixedOffset, Utc};
+use indicatif::HumanBytes;
+use memchr::memmem::Finder;
+use regex::{Regex, RegexSet};
+use serde_json::{json, Value};
+
+use crate::accesslog::AccessLogParser;
+use crate::align::Align;
+use crate::builder::PipelineBuilder;
+use crate::calc::Calculation;
+use crate::chunk::{Chunk, ChunkMode};
+use crate::codec::{
+    base64_decode, base64_decode_bytes, base64_encode, base64_encode_bytes, url_decode, url_encode,
+};
+use crate::columns::Columns;
+use crate::csv::{parse_delimiter, CsvSelect, CsvToJsonl, CsvWhere, JsonlToCsv};
+use crate::dedupe::{
+    BloomFilter, DedupeCount, DuplicateSet, KeyTransform, LruSet, ShardedSet, SpillingSet,
+    StateSet, WindowSet,
+};
+use crate::degradation::LossyEvent;
+use crate::distinct::CountDistinct;
+use crate::error::RanglerError;
+use crate::exec::Exec;
+use crate::expand::expand;
+use crate::expr::{Expr, ExprContext};
+use crate::fields::{FieldCondition, FieldFilter, FieldQuery};
+use crate::group::{Aggregate, GroupBy};
+use crate::hash::HashAlgorithm;
+use crate::help::suggest_command;
+use crate::html::{strip_html, unescape_html};
+use crate::identifier::Identifier;
+use crate::json::{reformat, JsonCondition, JsonFilter, JsonPath};
+use crate::keyed::PerKey;
+use crate::kv::parse_pairs;
+use crate::locale::CaseRules;
+use crate::markdown::Markdown;
+use crate::normalize::NormalizationForm;
+use crate::percentile::Stats;
+use crate::plugin::plugin_step;
+use crate::redact::Redactor;
+use crate::reference::{read_lines, Diff, Literals, Lookup, LookupMiss};
+use crate::route::KeyRoute;
+use crate::sample::{Sample, Shuffle};
+use crate::sink::Sink;
+use crate::sort::{Sort, SortKey, SortOrder};
+use crate::stats::StepStats;
+use crate::step::Step;
+use crate::syslog::SyslogParser;
+use crate::template::{Template, TemplateContext};
+use crate::throttle::Throttle;
+use crate::timestamp::{
+    format_timestamp, humanize_epochs, parse_timestamp, validate_format, Stamp, TimestampFormat,
+};
+use crate::top::{Top, TopBy};
+use crate::trace::Trace;
+use crate::translate::{expand_set, Translate};
+use crate::units::{parse_duration, parse_size};
+use crate::width::{padding, MAX_PAD_WIDTH};
+use crate::window::Window;
+
+// How many times repeat-until-stable applies its steps before giving up on
+// the line settling.
+const DEFAULT_REPEAT_LIMIT: usize = 100;
 
 #[derive(Debug)]
 pub enum PipelineStep {
     Filter(Regex),
+    FilterSet(RegexSet),
+    FilterAny(RegexSet),
+    FilterLiteral(Finder<'static>),
+    FilterLiterals(Literals),
+    FilterMatches(Regex),
+    // Splits a record wherever the regex matches, each piece carrying on as
+    // a record of its own.
+    SplitRecords(Regex),
     Lower,
     Upper,
+    LocaleLower(CaseRules),
+    LocaleUpper(CaseRules),
     Trim,
-    Dedupe(HashSet<String>, usize),
+    TrimStart,
+    TrimEnd,
+    // The characters to trim, and whether to trim the start and the end.
+    TrimChars(Vec<char>, bool, bool),
+    // Pads lines narrower than the width, in terminal columns, with the fill.
+    PadLeft(usize, char),
+    PadRight(usize, char),
+    Dedupe(HashSet<Vec<u8>>, usize),
+    DedupeRecent(LruSet),
+    DedupeApprox(BloomFilter),
+    DedupeSpill(SpillingSet),
+    DedupeState(StateSet),
+    DedupeWindow(WindowSet),
+    // An exact dedupe shared between worker threads, with the line the last
+    // line claimed, so the claim can be checked once batches are in order.
+    DedupeShared(ShardedSet, Option<Vec<u8>>),
+    // Emits every distinct line with its count once the input ends.
+    DedupeCount(DedupeCount),
+    // Keeps only lines seen before, or with --once only their second copy.
+    OnlyDuplicates(DuplicateSet),
+    // Any of the dedupes above, comparing a normalized key of each line.
+    DedupeBy(Vec<KeyTransform>, Box<PipelineStep>),
     Append(String),
     Prepend(String),
+    Hash(HashAlgorithm, bool),
+    Tag(String, Regex),
+    Route(String, Sink),
+    RouteByKey(KeyRoute),
+    Base64Encode(bool),
+    Base64Decode(bool, ErrorPolicy),
+    UrlEncode,
+    UrlDecode(ErrorPolicy),
+    Calc(Calculation, Option<String>, ErrorPolicy),
+    Where(Expr),
+    WhereField(FieldFilter),
+    Map(Expr, ErrorPolicy),
+    Tee(Sink),
+    TeePipeline(Pipeline, Sink),
+    If(Regex, Pipeline),
+    Repeat(Pipeline, usize),
+    Exec(Exec, ErrorPolicy),
+    Format(Template),
+    DateParse(TimestampFormat, String, ErrorPolicy),
+    HumanizeEpoch(String, bool),
+    Timestamp(Stamp, String),
+    // The id, the separator and whether it goes after the line.
+    Id(Identifier, String, bool),
+    MinLength(usize, bool),
+    MaxLength(usize, bool),
+    Throttle(Throttle),
+    Sample(Sample),
+    // Every so many lines, a line of text after them: (every, text, seen).
+    Every(usize, String, usize),
+    Shuffle(Shuffle),
+    Sort(Sort),
+    CountDistinct(CountDistinct),
+    Chunk(Chunk),
+    Align(Align),
+    CsvSelect(CsvSelect),
+    CsvWhere(CsvWhere),
+    Json(JsonPath, ErrorPolicy),
+    JsonFormat(bool, ErrorPolicy),
+    JsonFilter(JsonFilter),
+    JsonlToCsv(JsonlToCsv),
+    CsvToJsonl(CsvToJsonl),
+    Kv(FieldQuery),
+    Syslog(SyslogParser, FieldQuery),
+    AccessLog(AccessLogParser, FieldQuery),
+    Redact(Redactor),
+    Diff(Diff),
+    OnlyIn(HashSet<String>),
+    NotIn(HashSet<String>),
+    Lookup(Lookup),
+    Translate(Translate),
+    Columns(Columns),
+    Normalize(NormalizationForm),
+    Ascii,
+    StripHtml(bool),
+    UnescapeHtml,
+    StripMarkdown(Markdown),
+    Since(DateTime<FixedOffset>, TimestampFormat, ErrorPolicy),
+    Until(DateTime<FixedOffset>, TimestampFormat, ErrorPolicy),
+    PerWindow(Window),
+    Top(Top),
+    TopBy(TopBy),
+    GroupBy(GroupBy),
+    Stats(Stats),
+    PerKey(PerKey),
+    Custom(Box<dyn Step>),
+    Fused(Vec<PipelineStep>),
+    #[cfg(feature = "script")]
+    Script(Box<crate::script::Script>),
+    // A filter pattern with lookaround or backreferences, which the regex
+    // crate turns down. fancy-regex backtracks to match it.
+    #[cfg(feature = "fancy")]
+    FilterFancy(fancy_regex::Regex),
+}
+
+/// What a step does with a line it fails on, such as invalid JSON for `json`:
+/// drop it, pass it on unchanged, pass it on annotated with the reason, or end
+/// the run naming the line. `Inherit` leaves it to the pipeline, which skips
+/// unless told otherwise with [`Pipeline::set_error_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    Inherit,
+    Skip,
+    PassThrough,
+    Annotate,
+    Error,
+}
+
+impl ErrorPolicy {
+    pub fn or(self, default: ErrorPolicy) -> ErrorPolicy {
+        match self {
+            ErrorPolicy::Inherit => default,
+            policy => policy,
+        }
+    }
+
+    /// Reads `skip`, `pass`, `annotate` or `error`, also known as `abort`.
+    pub fn parse(policy: &str) -> Result<ErrorPolicy, &'static str> {
+        match policy {
+            "skip" => Ok(ErrorPolicy::Skip),
+            "pass" => Ok(ErrorPolicy::PassThrough),
+            "annotate" => Ok(ErrorPolicy::Annotate),
+            "error" | "abort" => Ok(ErrorPolicy::Error),
+            _ => Err("Invalid error policy"),
+        }
+    }
 }
+
+/// A chain of steps built from rangler commands, run on one line at a time.
+#[derive(Debug)]
 pub struct Pipeline {
     steps: Vec<PipelineStep>,
+    stats: Vec<StepStats>,
+    line_number: usize,
+    // The input the lines are being read from, for `{file}`.
+    source: String,
+    captures_needed: bool,
+    on_error: ErrorPolicy,
+    // The tokens each step was built from, after its command, for --explain.
+    arguments: Vec<Vec<String>>,
+    trace: Option<Trace>,
+    // The furthest step the last line applied got to, for --rejects.
+    reached: usize,
 }
 
-impl Pipeline {
-    pub fn build_pipeline<T: AsRef<str>>(mut tokens: &[T]) -> Result<Pipeline, &'static str> {
-        let mut steps: Vec<PipelineStep> = vec![];
+pub(crate) type ParsedStep = (String, PipelineStep, Vec<String>);
+
+impl Pipeline {
+    /// Parses commands as they are written on the command line, e.g.
+    /// `["filter", "error", "dedupe"]`.
+    ///
+    /// Errors about a particular token record its index in `commands`.
+    pub fn build_pipeline<T: AsRef<str>>(commands: &[T]) -> Result<Pipeline, RanglerError> {
+        let mut tokens = commands;
+        let steps = Self::parse_steps(&mut tokens, false)
+            .map_err(|error| error.at(commands.len() - tokens.len()))?;
+
+        if steps.is_empty() {
+            Err("No commands specified")?
+        } else {
+            Ok(Pipeline::parsed(steps))
+        }
+    }
+
+    /// Starts a pipeline built from typed steps rather than command tokens.
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder::default()
+    }
+
+    pub(crate) fn new(named_steps: Vec<(String, PipelineStep)>) -> Pipeline {
+        let (names, steps): (Vec<String>, Vec<PipelineStep>) = named_steps.into_iter().unzip();
+        let captures_needed = steps.iter().any(PipelineStep::uses_captures);
+
+        Pipeline {
+            arguments: vec![vec![]; steps.len()],
+            steps,
+            stats: names.iter().map(|name| StepStats::new(name)).collect(),
+            line_number: 0,
+            source: String::new(),
+            captures_needed,
+            on_error: ErrorPolicy::Skip,
+            trace: None,
+            reached: 0,
+        }
+    }
+
+    fn parsed(parsed_steps: Vec<ParsedStep>) -> Pipeline {
+        let mut arguments = vec![];
+        let mut named_steps = vec![];
+        for (name, step, step_arguments) in parsed_steps {
+            named_steps.push((name, step));
+            arguments.push(step_arguments);
+        }
+
+        Pipeline {
+            arguments,
+            ..Pipeline::new(named_steps)
+        }
+    }
+
+    fn parse_steps<T: AsRef<str>>(
+        tokens: &mut &[T],
+        nested: bool,
+    ) -> Result<Vec<ParsedStep>, RanglerError> {
+        let mut steps: Vec<ParsedStep> = vec![];
+
+        loop {
+            let remaining = *tokens;
+            let command = match next_argument(tokens) {
+                Some(command) => command,
+                None if nested => Err("Missing end")?,
+                None => break,
+            };
+
+            let step = match command.to_lowercase().as_str() {
+                "end" if nested => break,
+                "filter" => {
+                    let fancy = next_flag(tokens, "--fancy");
+                    let mut flags = next_regex_flags(tokens);
+                    let fancy = fancy || next_flag(tokens, "--fancy");
+                    let pattern = next_argument(tokens).ok_or("Missing regular expression")?;
+                    let only_matching = flags.contains('o');
+                    flags.retain(|flag| flag != 'o');
+
+                    // A fixed string on its own is searched for as bytes,
+                    // without a regex at all. Patterns the regex crate turns
+                    // down go to fancy-regex, when built with it.
+                    match flags.as_str() {
+                        _ if only_matching => {
+                            PipelineStep::FilterMatches(flagged_regex(&flags, pattern)?)
+                        }
+                        "F" => PipelineStep::FilterLiteral(Finder::new(pattern).into_owned()),
+                        _ if fancy => fancy_filter(&flags, pattern)?,
+                        _ => match flagged_regex(&flags, pattern) {
+                            Ok(regex) => PipelineStep::Filter(regex),
+                            Err(error) => fancy_filter(&flags, pattern).map_err(|_| error)?,
+                        },
+                    }
+                }
+                "filter-any" => PipelineStep::FilterAny(next_regex_set(tokens)?),
+                "records" => {
+                    let flags = next_regex_flags(tokens);
+                    let pattern = next_argument(tokens).ok_or("Missing regular expression")?;
+
+                    PipelineStep::SplitRecords(flagged_regex(&flags, pattern)?)
+                }
+                "only-duplicates" => {
+                    PipelineStep::OnlyDuplicates(DuplicateSet::new(next_flag(tokens, "--once")))
+                }
+                "filter-literals" => {
+                    let ignore_case = next_flag(tokens, "-i");
+                    let path = next_argument(tokens).ok_or("Missing literals file")?;
+
+                    PipelineStep::FilterLiterals(Literals::load(path, ignore_case)?)
+                }
+                "filter-all" => PipelineStep::FilterSet(next_regex_set(tokens)?),
+                "lower" => PipelineStep::Lower,
+                "upper" => PipelineStep::Upper,
+                "trim" => PipelineStep::Trim,
+                "ltrim" => PipelineStep::TrimStart,
+                "rtrim" => PipelineStep::TrimEnd,
+                "trim-chars" => {
+                    let set = expand_set(next_argument(tokens).ok_or("Missing character set")?)?;
+                    if set.is_empty() {
+                        Err("Empty character set")?
+                    }
+
+                    match (next_flag(tokens, "--left"), next_flag(tokens, "--right")) {
+                        (true, false) => PipelineStep::TrimChars(set, true, false),
+                        (false, true) => PipelineStep::TrimChars(set, false, true),
+                        _ => PipelineStep::TrimChars(set, true, true),
+                    }
+                }
+                // --key-transform and --count may come before or after the
+                // options that pick the kind of dedupe.
+                "dedupe" => {
+                    let mut count = next_flag(tokens, "--count");
+                    let mut transforms = match next_option(tokens, "--key-transform")? {
+                        Some(list) => Some(KeyTransform::parse_list(list)?),
+                        None => None,
+                    };
+                    let dedupe = match next_option(tokens, "--recent")? {
+                        Some(capacity) => {
+                            let capacity = capacity
+                                .parse::<usize>()
+                                .ok()
+                                .filter(|capacity| *capacity > 0)
+                                .ok_or("Invalid dedupe capacity")?;
+
+                            PipelineStep::DedupeRecent(LruSet::new(capacity))
+                        }
+                        None if next_flag(tokens, "--window") => {
+                            let size = next_argument(tokens)
+                                .and_then(|size| size.parse::<usize>().ok())
+                                .filter(|size| *size > 0)
+                                .ok_or("Invalid dedupe window")?;
+
+                            PipelineStep::DedupeWindow(WindowSet::new(size))
+                        }
+                        None if next_flag(tokens, "--approx") => {
+                            let expected_items = next_argument(tokens)
+                                .and_then(|count| count.parse::<usize>().ok())
+                                .filter(|count| *count > 0)
+                                .ok_or("Invalid expected item count")?;
+                            let false_positive_rate = next_argument(tokens)
+                                .and_then(|rate| rate.parse::<f64>().ok())
+                                .filter(|rate| *rate > 0.0 && *rate < 1.0)
+                                .ok_or("Invalid false positive rate")?;
+
+                            PipelineStep::DedupeApprox(BloomFilter::new(
+                                expected_items,
+                                false_positive_rate,
+                            ))
+                        }
+                        None if next_flag(tokens, "--spill") => {
+                            let memory_limit =
+                                parse_size(next_argument(tokens).ok_or("Missing memory limit")?)?;
+
+                            PipelineStep::DedupeSpill(SpillingSet::new(memory_limit))
+                        }
+                        None => match next_option(tokens, "--state")? {
+                            Some(path) => PipelineStep::DedupeState(StateSet::load(path)?),
+                            None => PipelineStep::Dedupe(HashSet::new(), 0),
+                        },
+                    };
+                    count = count || next_flag(tokens, "--count");
+                    if transforms.is_none() {
+                        if let Some(list) = next_option(tokens, "--key-transform")? {
+                            transforms = Some(KeyTransform::parse_list(list)?);
+                            count = count || next_flag(tokens, "--count");
+                        }
+                    }
+
+                    match (count, transforms) {
+                        (true, _) if !matches!(dedupe, PipelineStep::Dedupe(..)) => {
+                            Err("Only exact dedupes can --count")?
+                        }
+                        (true, transforms) => PipelineStep::DedupeCount(DedupeCount::new(
+                            transforms.unwrap_or_default(),
+                        )),
+                        (false, Some(transforms)) => {
+                            PipelineStep::DedupeBy(transforms, Box::new(dedupe))
+                        }
+                        (false, None) => dedupe,
+                    }
+                }
+                // Text naming the file, host or line number is filled in
+                // like a format template.
+                "every" => {
+                    let every = next_argument(tokens)
+                        .ok_or("Missing line count")?
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|every| *every > 0)
+                        .ok_or("Invalid line count")?;
+                    let text = expand(next_argument(tokens).ok_or("Missing text to insert")?);
+
+                    PipelineStep::Every(every, text, 0)
+                }
+                "append" => {
+                    let suffix = expand(next_argument(tokens).ok_or("Missing suffix")?);
+                    match Template::annotation(&suffix, false) {
+                        Some(template) => PipelineStep::Format(template),
+                        None => PipelineStep::Append(suffix),
+                    }
+                }
+                "prepend" => {
+                    let prefix = expand(next_argument(tokens).ok_or("Missing prefix")?);
+                    match Template::annotation(&prefix, true) {
+                        Some(template) => PipelineStep::Format(template),
+                        None => PipelineStep::Prepend(prefix),
+                    }
+                }
+                name @ ("pad-left" | "pad-right") => {
+                    let width = next_argument(tokens)
+                        .ok_or("Missing pad width")?
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|width| *width <= MAX_PAD_WIDTH)
+                        .ok_or("Invalid pad width")?;
+                    let mut fill = next_argument(tokens)
+                        .ok_or("Missing pad character")?
+                        .chars();
+                    let fill = match (fill.next(), fill.next()) {
+                        (Some(fill), None) => fill,
+                        _ => Err("Invalid pad character")?,
+                    };
+
+                    match name {
+                        "pad-left" => PipelineStep::PadLeft(width, fill),
+                        _ => PipelineStep::PadRight(width, fill),
+                    }
+                }
+                "hash" => {
+                    let algorithm = HashAlgorithm::parse(
+                        next_argument(tokens).ok_or("Missing hash algorithm")?,
+                    )?;
+
+                    PipelineStep::Hash(algorithm, next_flag(tokens, "--append"))
+                }
+                "tag" => {
+                    let name = next_argument(tokens).ok_or("Missing tag name")?.to_string();
+                    if next_argument(tokens) != Some("when") {
+                        Err("Expected when")?;
+                    }
+                    let regex = next_regex(tokens)?;
+
+                    PipelineStep::Tag(name, regex)
+                }
+                "route" => {
+                    let name = next_argument(tokens).ok_or("Missing tag name")?.to_string();
+
+                    // `route <tag> to <sink>` sends tagged lines to a sink, while
+                    // `route <regex> <template>` writes lines to a file per key.
+                    match next_argument(tokens).ok_or("Expected to")? {
+                        "to" => {
+                            let sink = match next_argument(tokens).ok_or("Missing sink")? {
+                                "pipeline" => {
+                                    let steps = Self::parse_steps(tokens, true)?;
+                                    if steps.is_empty() {
+                                        Err("No commands specified")?;
+                                    }
+
+                                    Sink::Pipeline(Pipeline::parsed(steps))
+                                }
+                                target => Sink::parse(target)?,
+                            };
+
+                            PipelineStep::Route(name, sink)
+                        }
+                        template => {
+                            let regex =
+                                Regex::new(&name).map_err(|_| "Invalid regular expression")?;
+
+                            PipelineStep::RouteByKey(KeyRoute::new(regex, template)?)
+                        }
+                    }
+                }
+                "base64" => {
+                    let mode = next_argument(tokens).ok_or("Missing base64 mode")?;
+                    let url_safe = next_flag(tokens, "--url");
+
+                    match mode {
+                        "encode" => PipelineStep::Base64Encode(url_safe),
+                        "decode" => {
+                            PipelineStep::Base64Decode(url_safe, next_error_policy(tokens)?)
+                        }
+                        _ => Err("Invalid base64 mode")?,
+                    }
+                }
+                "urlencode" => PipelineStep::UrlEncode,
+                "urldecode" => PipelineStep::UrlDecode(next_error_policy(tokens)?),
+                "calc" => {
+                    let calculation =
+                        Calculation::parse(next_argument(tokens).ok_or("Missing expression")?)?;
+                    let delimiter = next_option(tokens, "--delimiter")?.map(str::to_string);
+
+                    PipelineStep::Calc(calculation, delimiter, next_error_policy(tokens)?)
+                }
+                "where" => PipelineStep::Where(next_expression(tokens)?),
+                "where-field" => {
+                    let delimiter = next_argument(tokens).ok_or("Missing field delimiter")?;
+                    let index = next_argument(tokens).ok_or("Missing field number")?;
+                    let operator = next_argument(tokens).ok_or("Missing comparison operator")?;
+                    let value = next_argument(tokens).ok_or("Missing comparison value")?;
+
+                    PipelineStep::WhereField(FieldFilter::new(delimiter, index, operator, value)?)
+                }
+                "map" => PipelineStep::Map(next_expression(tokens)?, next_error_policy(tokens)?),
+                "tee" => {
+                    PipelineStep::Tee(Sink::parse(next_argument(tokens).ok_or("Missing sink")?)?)
+                }
+                "tee-pipeline" => {
+                    let sink = Sink::parse(next_argument(tokens).ok_or("Missing sink")?)?;
+                    let steps = Self::parse_steps(tokens, true)?;
+                    if steps.is_empty() {
+                        Err("No commands specified")?;
+                    }
+
+                    PipelineStep::TeePipeline(Pipeline::parsed(steps), sink)
+                }
+                "if" => {
+                    let regex = next_regex(tokens)?;
+                    if next_argument(tokens) != Some("then") {
+                        Err("Expected then")?;
+                    }
+                    let steps = Self::parse_steps(tokens, true)?;
+                    if steps.is_empty() {
+                        Err("No commands specified")?;
+                    }
+
+                    PipelineStep::If(regex, Pipeline::parsed(steps))
+                }
+                "repeat-until-stable" => {
+                    let limit = match next_option(tokens, "--max")? {
+                        Some(limit) => limit
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|limit| *limit > 0)
+                            .ok_or("Invalid repeat limit")?,
+                        None => DEFAULT_REPEAT_LIMIT,
+                    };
+                    let branch = Pipeline::parsed(Self::parse_steps(tokens, true)?);
+                    if branch.steps.is_empty() {
+                        Err("No commands specified")?;
+                    }
+                    // Steps that remember lines would see each one again on
+                    // every pass.
+                    if !branch.is_stateless() {
+                        Err(
+                            "repeat-until-stable only takes steps that handle each line on its own",
+                        )?;
+                    }
+
+                    PipelineStep::Repeat(branch, limit)
+                }
+                "exec" => {
+                    let command = next_argument(tokens).ok_or("Missing command")?;
+                    let exec = Exec::new(command, next_flag(tokens, "--coprocess"))?;
+
+                    PipelineStep::Exec(exec, next_error_policy(tokens)?)
+                }
+                "format" => PipelineStep::Format(Template::parse(&expand(
+                    next_argument(tokens).ok_or("Missing template")?,
+                ))?),
+                "dateparse" => {
+                    let input = TimestampFormat::parse(
+                        next_argument(tokens).ok_or("Missing input date format")?,
+                    )?;
+                    let output = next_argument(tokens).ok_or("Missing output date format")?;
+                    validate_format(output)?;
+
+                    PipelineStep::DateParse(input, output.to_string(), next_error_policy(tokens)?)
+                }
+                "since" => {
+                    let (bound, input, policy) = next_time_bound(tokens)?;
+
+                    PipelineStep::Since(bound, input, policy)
+                }
+                "until" => {
+                    let (bound, input, policy) = next_time_bound(tokens)?;
+
+                    PipelineStep::Until(bound, input, policy)
+                }
+                "count-distinct" => {
+                    PipelineStep::CountDistinct(match next_flag(tokens, "--approx") {
+                        true => CountDistinct::approximate(),
+                        false => CountDistinct::exact(),
+                    })
+                }
+                "top" => {
+                    let k = next_argument(tokens)
+                        .ok_or("Missing top size")?
+                        .parse::<usize>()
+                        .map_err(|_| "Invalid top size")?;
+                    let capacity = match next_option(tokens, "--approx")? {
+                        Some(capacity) => Some(
+                            capacity
+                                .parse::<usize>()
+                                .map_err(|_| "Invalid top capacity")?,
+                        ),
+                        None => None,
+                    };
+
+                    PipelineStep::Top(Top::new(k, capacity)?)
+                }
+                "top-by" => {
+                    let k = next_argument(tokens)
+                        .ok_or("Missing top size")?
+                        .parse::<usize>()
+                        .map_err(|_| "Invalid top size")?;
+                    let key = SortKey::parse(next_argument(tokens).ok_or("Missing sort key")?)?;
+
+                    PipelineStep::TopBy(TopBy::new(k, key, next_flag(tokens, "--smallest"))?)
+                }
+                "first-per-key" | "last-per-key" => {
+                    let regex = next_regex(tokens)?;
+
+                    PipelineStep::PerKey(PerKey::new(
+                        regex,
+                        command.eq_ignore_ascii_case("last-per-key"),
+                    ))
+                }
+                "group-by" => {
+                    let regex = next_regex(tokens)?;
+                    let aggregate =
+                        Aggregate::parse(next_argument(tokens).ok_or("Missing aggregate")?)?;
+
+                    PipelineStep::GroupBy(GroupBy::new(regex, aggregate)?)
+                }
+                "stats" => {
+                    let list = next_argument(tokens).ok_or("Missing statistics")?;
+                    let key = match next_option(tokens, "--key")? {
+                        Some(key) => Some(SortKey::parse(key)?),
+                        None => None,
+                    };
+
+                    PipelineStep::Stats(Stats::parse(list, key)?)
+                }
+                "per-window" => {
+                    let width = parse_duration(next_argument(tokens).ok_or("Missing window")?)?;
+                    if next_argument(tokens) != Some("count") {
+                        Err("Unsupported window aggregation")?
+                    }
+                    let key = match next_option(tokens, "--by")? {
+                        Some(key) => {
+                            Some(Regex::new(key).map_err(|_| "Invalid regular expression")?)
+                        }
+                        None => None,
+                    };
+                    let input =
+                        TimestampFormat::parse(next_option(tokens, "--format")?.unwrap_or("iso"))?;
+
+                    PipelineStep::PerWindow(Window::new(width, input, key)?)
+                }
+                "humanize-epoch" => {
+                    let format = next_option(tokens, "--format")?.unwrap_or("iso");
+                    validate_format(format)?;
+
+                    PipelineStep::HumanizeEpoch(format.to_string(), next_flag(tokens, "--relative"))
+                }
+                "timestamp" => {
+                    let format = next_option(tokens, "--format")?;
+                    let stamp = match (format, next_flag(tokens, "--elapsed")) {
+                        (Some(_), true) => Err("Cannot combine --format with --elapsed")?,
+                        (format, false) => Stamp::clock(format.unwrap_or("%Y-%m-%d %H:%M:%S"))?,
+                        (None, true) => Stamp::elapsed(),
+                    };
+                    let separator = next_option(tokens, "--separator")?.unwrap_or(" ");
+
+                    PipelineStep::Timestamp(stamp, separator.to_string())
+                }
+                name @ ("uuid" | "ulid") => {
+                    let identifier = match (name, next_option(tokens, "--v5")?) {
+                        ("uuid", Some(namespace)) => Identifier::named_uuid(namespace)?,
+                        ("uuid", None) => Identifier::uuid(),
+                        _ => Identifier::ulid(),
+                    };
+                    let append = next_flag(tokens, "--append");
+                    let separator = next_option(tokens, "--separator")?.unwrap_or(" ");
+
+                    PipelineStep::Id(identifier, separator.to_string(), append)
+                }
+                "minlen" | "maxlen" => {
+                    let length = next_argument(tokens)
+                        .ok_or("Missing length")?
+                        .parse::<usize>()
+                        .map_err(|_| "Invalid length")?;
+                    let bytes = next_flag(tokens, "--bytes");
+
+                    if command.eq_ignore_ascii_case("minlen") {
+                        PipelineStep::MinLength(length, bytes)
+                    } else {
+                        PipelineStep::MaxLength(length, bytes)
+                    }
+                }
+                "throttle" => {
+                    let lines_per_second = next_argument(tokens)
+                        .ok_or("Missing rate")?
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|rate| *rate > 0.0 && rate.is_finite())
+                        .ok_or("Invalid rate")?;
+
+                    PipelineStep::Throttle(Throttle::new(lines_per_second)?)
+                }
+                "sample" => {
+                    let rate = next_argument(tokens)
+                        .ok_or("Missing sample rate")?
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|rate| *rate > 0.0 && *rate <= 1.0)
+                        .ok_or("Invalid sample rate")?;
+
+                    PipelineStep::Sample(Sample::new(rate))
+                }
+                "shuffle" => PipelineStep::Shuffle(Shuffle::new()),
+                "sort" => {
+                    let (mut order, mut reverse, mut key) = (SortOrder::Text, false, None);
+                    loop {
+                        let chosen = if next_flag(tokens, "--numeric") {
+                            SortOrder::Numeric
+                        } else if next_flag(tokens, "--natural") {
+                            SortOrder::Natural
+                        } else if next_flag(tokens, "--reverse") {
+                            reverse = true;
+                            continue;
+                        } else if let Some(field) = next_option(tokens, "--key")? {
+                            key = Some(SortKey::parse(field)?);
+                            continue;
+                        } else {
+                            break;
+                        };
+                        if order != SortOrder::Text && order != chosen {
+                            Err("Choose one of --numeric and --natural")?
+                        }
+                        order = chosen;
+                    }
+
+                    PipelineStep::Sort(Sort::new(order, reverse, key))
+                }
+                "chunk" => {
+                    let size = next_argument(tokens)
+                        .ok_or("Missing chunk size")?
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|size| *size > 0)
+                        .ok_or("Invalid chunk size")?;
+                    let mode = if let Some(delimiter) = next_option(tokens, "--join")? {
+                        ChunkMode::Join(delimiter.to_string())
+                    } else {
+                        ChunkMode::Separator(
+                            next_option(tokens, "--separator")?
+                                .unwrap_or("")
+                                .to_string(),
+                        )
+                    };
+
+                    PipelineStep::Chunk(Chunk::new(size, mode))
+                }
+                "align" => PipelineStep::Align(Align::new(
+                    next_argument(tokens).ok_or("Missing delimiter")?,
+                )),
+                "csv-select" => {
+                    let columns = next_argument(tokens).ok_or("Missing columns")?;
+                    let delimiter = parse_delimiter(next_option(tokens, "--delimiter")?)?;
+
+                    PipelineStep::CsvSelect(CsvSelect::new(columns, delimiter))
+                }
+                "csv-where" => {
+                    let condition = next_argument(tokens).ok_or("Missing condition")?;
+                    let delimiter = parse_delimiter(next_option(tokens, "--delimiter")?)?;
+
+                    PipelineStep::CsvWhere(CsvWhere::new(condition, delimiter)?)
+                }
+                "json" => {
+                    let path = JsonPath::parse(next_argument(tokens).ok_or("Missing JSON path")?)?;
+
+                    PipelineStep::Json(path, next_error_policy(tokens)?)
+                }
+                "json-pretty" | "json-compact" => PipelineStep::JsonFormat(
+                    command.eq_ignore_ascii_case("json-pretty"),
+                    next_error_policy(tokens)?,
+                ),
+                "json-filter" => {
+                    let path = JsonPath::parse(next_argument(tokens).ok_or("Missing JSON path")?)?;
+                    let condition =
+                        JsonCondition::parse(next_argument(tokens).ok_or("Missing condition")?)?;
+
+                    PipelineStep::JsonFilter(JsonFilter::new(path, condition))
+                }
+                "jsonl2csv" => {
+                    let columns = next_option(tokens, "--columns")?;
+                    let delimiter = parse_delimiter(next_option(tokens, "--delimiter")?)?;
+
+                    PipelineStep::JsonlToCsv(JsonlToCsv::new(columns, delimiter))
+                }
+                "csv2jsonl" => {
+                    let delimiter = parse_delimiter(next_option(tokens, "--delimiter")?)?;
+
+                    PipelineStep::CsvToJsonl(CsvToJsonl::new(delimiter))
+                }
+                "kv" => PipelineStep::Kv(next_field_query(tokens)?),
+                "syslog" => PipelineStep::Syslog(SyslogParser::new(), next_field_query(tokens)?),
+                "diff" => PipelineStep::Diff(Diff::load(
+                    next_argument(tokens).ok_or("Missing reference file")?,
+                )?),
+                "only-in" => PipelineStep::OnlyIn(HashSet::from_iter(read_lines(
+                    next_argument(tokens).ok_or("Missing reference file")?,
+                )?)),
+                "not-in" => PipelineStep::NotIn(HashSet::from_iter(read_lines(
+                    next_argument(tokens).ok_or("Missing reference file")?,
+                )?)),
+                "lookup" => {
+                    let path = next_argument(tokens).ok_or("Missing mapping file")?;
+                    let key = match next_option(tokens, "--key")? {
+                        Some(key) => {
+                            Some(Regex::new(key).map_err(|_| "Invalid regular expression")?)
+                        }
+                        None => None,
+                    };
+                    let delimiter = match next_option(tokens, "--delimiter")? {
+                        Some(delimiter) => Some(parse_delimiter(Some(delimiter))?),
+                        None => None,
+                    };
+                    let annotate = next_flag(tokens, "--annotate");
+                    let miss = match next_option(tokens, "--on-miss")? {
+                        None | Some("pass") => LookupMiss::Pass,
+                        Some("drop") => LookupMiss::Drop,
+                        Some("default") => LookupMiss::Default(
+                            next_argument(tokens)
+                                .ok_or("Missing default value")?
+                                .to_string(),
+                        ),
+                        Some(_) => Err("Invalid miss policy")?,
+                    };
+
+                    PipelineStep::Lookup(Lookup::load(path, delimiter, key, annotate, miss)?)
+                }
+                "tr" => {
+                    let from = next_argument(tokens).ok_or("Missing character set")?;
+                    let to = if next_flag(tokens, "--delete") {
+                        None
+                    } else {
+                        Some(next_argument(tokens).ok_or("Missing character set")?)
+                    };
+
+                    PipelineStep::Translate(Translate::new(from, to)?)
+                }
+                "cols" => {
+                    let ranges = next_argument(tokens).ok_or("Missing column ranges")?;
+                    let bytes = next_flag(tokens, "--bytes");
+                    let delimiter = next_option(tokens, "--delimiter")?.unwrap_or("");
+
+                    PipelineStep::Columns(Columns::parse(ranges, bytes, delimiter)?)
+                }
+                "normalize" => PipelineStep::Normalize(NormalizationForm::parse(
+                    next_argument(tokens).ok_or("Missing normalization form")?,
+                )?),
+                "ascii" => PipelineStep::Ascii,
+                "strip-html" => PipelineStep::StripHtml(next_flag(tokens, "--squeeze")),
+                "unescape-html" => PipelineStep::UnescapeHtml,
+                "strip-markdown" => PipelineStep::StripMarkdown(Markdown::new()),
+                "redact" => {
+                    let detectors = next_option(tokens, "--only")?;
+                    let mut custom = vec![];
+                    while let Some(pattern) = next_option(tokens, "--pattern")? {
+                        custom.push(pattern);
+                    }
+
+                    PipelineStep::Redact(Redactor::new(
+                        detectors,
+                        &custom,
+                        next_flag(tokens, "--hash"),
+                    )?)
+                }
+                "script" => script_step(next_argument(tokens).ok_or("Missing script")?)?,
+                "wasm" => PipelineStep::Custom(wasm_step(
+                    next_argument(tokens).ok_or("Missing module path")?,
+                )?),
+                "geoip" => PipelineStep::Custom(geoip_step(
+                    next_argument(tokens).ok_or("Missing database path")?,
+                )?),
+                "accesslog" => {
+                    PipelineStep::AccessLog(AccessLogParser::new(), next_field_query(tokens)?)
+                }
+                _ => match plugin_step(command, tokens) {
+                    Some(step) => PipelineStep::Custom(Box::new(step?)),
+                    None => Err(unknown_command(command))?,
+                },
+            };
+
+            let arguments = remaining[1..remaining.len() - tokens.len()]
+                .iter()
+                .map(|token| token.as_ref().to_string())
+                .collect();
+            steps.push((command.to_lowercase(), step, arguments));
+        }
+
+        Ok(steps)
+    }
+
+    /// Runs one line through every step. A line can come out unchanged,
+    /// dropped, split into several, or held back until [`Pipeline::finish`].
+    /// Lines that only pass filters and dedupes come out borrowed from the
+    /// input, so they are never copied.
+    pub fn apply<'a>(&mut self, line: &'a str) -> Result<Vec<Cow<'a, str>>, &'static str> {
+        let mut lines = vec![];
+
+        self.line_number += 1;
+        self.reached = 0;
+        if let Some(trace) = &mut self.trace {
+            trace.start(self.line_number, line);
+        }
+        let result = self.run_from(0, Cow::Borrowed(line), &mut lines);
+        if let Some(trace) = &mut self.trace {
+            trace.stop();
+        }
+        result?;
+
+        Ok(lines)
+    }
+
+    /// Runs one line read as bytes. The steps before the first one that reads
+    /// text see the bytes as they are, so the line is only checked for UTF-8
+    /// if it gets that far.
+    pub fn apply_bytes<'a>(&mut self, line: &'a [u8]) -> Result<Vec<Cow<'a, [u8]>>, &'static str> {
+        self.line_number += 1;
+        self.reached = 0;
+
+        let text_from = self
+            .steps
+            .iter()
+            .position(PipelineStep::needs_text)
+            .unwrap_or(self.steps.len());
+        for index in 0..text_from {
+            self.stats[index].received += 1;
+            self.reached = index;
+            if !keeps_bytes(&mut self.steps[index], line) {
+                return Ok(vec![]);
+            }
+        }
+        if text_from == self.steps.len() {
+            return Ok(vec![Cow::Borrowed(line)]);
+        }
+
+        let text = std::str::from_utf8(line).map_err(|_| "Invalid UTF-8 input")?;
+        let mut lines = vec![];
+        self.run_from(text_from, Cow::Borrowed(text), &mut lines)?;
+
+        Ok(lines
+            .into_iter()
+            .map(|line| match line {
+                Cow::Borrowed(line) => Cow::Borrowed(line.as_bytes()),
+                Cow::Owned(line) => Cow::Owned(line.into_bytes()),
+            })
+            .collect())
+    }
+
+    // Like apply, for a line whose number is already known, as when lines are
+    // shared out between copies of a pipeline.
+    pub(crate) fn apply_numbered(
+        &mut self,
+        line_number: usize,
+        line: String,
+    ) -> Result<Vec<String>, &'static str> {
+        let mut lines = vec![];
+
+        self.line_number = line_number;
+        self.run_from(0, Cow::Owned(line), &mut lines)?;
+
+        Ok(lines.into_iter().map(Cow::into_owned).collect())
+    }
+
+    // Runs a line through the steps starting at `start`. Steps that buffer or
+    // fan out hand each line they produce to the steps after them, so a single
+    // input line can yield any number of output lines.
+    // Traced lines print what each step made of them, see [`Trace`].
+    fn run_from<'a>(
+        &mut self,
+        start: usize,
+        line: Cow<'a, str>,
+        lines: &mut Vec<Cow<'a, str>>,
+    ) -> Result<(), &'static str> {
+        let trace = match &mut self.trace {
+            Some(trace) if trace.is_active() => trace,
+            _ => return self.run_steps(start, line, lines),
+        };
+        // A line a buffering step released carries on from the next step.
+        if start > 0 {
+            trace.step(start - 1, &self.stats[start - 1].name, &line);
+        }
+        trace.reached = start;
+
+        self.run_steps(start, line, lines)?;
+        let end = self.steps.len();
+        if let Some(trace) = &mut self.trace {
+            if trace.reached < end {
+                trace.stopped(trace.reached, &self.stats[trace.reached].name, end);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_steps<'a>(
+        &mut self,
+        start: usize,
+        line: Cow<'a, str>,
+        lines: &mut Vec<Cow<'a, str>>,
+    ) -> Result<(), &'static str> {
+        let mut output = line;
+        let mut tags: Vec<String> = Vec::new();
+        let mut captures: Vec<(Option<String>, Option<String>)> = Vec::new();
+
+        for index in start..self.steps.len() {
+            self.stats[index].received += 1;
+            self.reached = self.reached.max(index);
+            if index > start {
+                self.trace_step(index - 1, &output);
+            }
+
+            output = match &mut self.steps[index] {
+                PipelineStep::Filter(regex) if self.captures_needed => {
+                    let matched = match regex.captures(&output) {
+                        Some(matched) => matched,
+                        None => return Ok(()),
+                    };
+
+                    captures = regex
+                        .capture_names()
+                        .zip(matched.iter())
+                        .map(|(name, value)| {
+                            (
+                                name.map(str::to_string),
+                                value.map(|v| v.as_str().to_string()),
+                            )
+                        })
+                        .collect();
+
+                    output
+                }
+                PipelineStep::Filter(regex) => {
+                    if !regex.is_match(&output) {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::FilterSet(set) => {
+                    if set.matches(&output).iter().count() < set.len() {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::FilterAny(set) => {
+                    if !set.is_match(&output) {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::FilterLiteral(finder) => {
+                    if finder.find(output.as_bytes()).is_none() {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::FilterLiterals(literals) => {
+                    if !literals.is_match(output.as_bytes()) {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                // Every match carries on as a line of its own, skipping the
+                // empty ones a pattern like `a*` finds between the others.
+                PipelineStep::FilterMatches(regex) => {
+                    let mut matches: Vec<String> = regex
+                        .find_iter(&output)
+                        .filter(|found| found.start() < found.end())
+                        .map(|found| found.as_str().to_string())
+                        .collect();
+                    match matches.pop() {
+                        Some(found) if matches.is_empty() => found.into(),
+                        last => {
+                            for found in matches.into_iter().chain(last) {
+                                self.run_from(index + 1, found.into(), lines)?;
+                            }
+
+                            return Ok(());
+                        }
+                    }
+                }
+                // Empty pieces, between two separators in a row or at either
+                // end, are dropped.
+                PipelineStep::SplitRecords(regex) => {
+                    let mut pieces: Vec<String> = regex
+                        .split(&output)
+                        .filter(|piece| !piece.is_empty())
+                        .map(str::to_string)
+                        .collect();
+                    match pieces.pop() {
+                        Some(piece) if pieces.is_empty() => piece.into(),
+                        last => {
+                            for piece in pieces.into_iter().chain(last) {
+                                self.run_from(index + 1, piece.into(), lines)?;
+                            }
+
+                            return Ok(());
+                        }
+                    }
+                }
+                PipelineStep::Fused(transforms) => transforms
+                    .iter()
+                    .fold(output, |line, step| transform(step, line)),
+                step @ (PipelineStep::Dedupe(..)
+                | PipelineStep::DedupeState(_)
+                | PipelineStep::DedupeRecent(_)
+                | PipelineStep::DedupeWindow(_)
+                | PipelineStep::DedupeApprox(_)
+                | PipelineStep::DedupeSpill(_)
+                | PipelineStep::OnlyDuplicates(_)) => {
+                    if !keeps_unseen(step, &output)? {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::DedupeShared(set, claimed) => {
+                    if !set.claim(output.as_bytes(), self.line_number) {
+                        return Ok(());
+                    }
+                    *claimed = Some(output.as_bytes().to_vec());
+
+                    output
+                }
+                PipelineStep::DedupeBy(transforms, step) => {
+                    if !keeps_unseen(step, &KeyTransform::key(transforms, &output))? {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::MinLength(length, bytes) => {
+                    if line_length(&output, *bytes) < *length {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::MaxLength(length, bytes) => {
+                    if line_length(&output, *bytes) > *length {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::Throttle(throttle) => {
+                    throttle.wait();
+
+                    output
+                }
+                PipelineStep::Timestamp(stamp, separator) => {
+                    format!("{}{}{}", stamp.next(), separator, output).into()
+                }
+                PipelineStep::Id(identifier, separator, append) => {
+                    let id = identifier.next(&output);
+                    if *append {
+                        format!("{}{}{}", output, separator, id).into()
+                    } else {
+                        format!("{}{}{}", id, separator, output).into()
+                    }
+                }
+                PipelineStep::Every(every, text, seen) => {
+                    *seen += 1;
+                    if *seen % *every != 0 {
+                        output
+                    } else {
+                        let text = text.clone();
+                        self.run_from(index + 1, output, lines)?;
+                        self.run_from(index + 1, text.into(), lines)?;
+
+                        return Ok(());
+                    }
+                }
+                PipelineStep::Sample(sample) => match sample.keeps() {
+                    true => output,
+                    false => return Ok(()),
+                },
+                PipelineStep::CsvSelect(select) => match select.apply(&output)? {
+                    Some(projected) => projected.into(),
+                    None => return Ok(()),
+                },
+                PipelineStep::CsvWhere(condition) => {
+                    if !condition.apply(&output)? {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::Json(path, policy) => match path.extract(&output) {
+                    Some(value) => value.into(),
+                    None => match failed(
+                        policy.or(self.on_error),
+                        "Invalid JSON or missing path",
+                        self.line_number,
+                        output,
+                    )? {
+                        Some(line) => line,
+                        None => return Ok(()),
+                    },
+                },
+                PipelineStep::JsonFormat(pretty, policy) => match reformat(&output, *pretty) {
+                    Some(formatted) => formatted.into(),
+                    None => match failed(
+                        policy.or(self.on_error),
+                        "Invalid JSON",
+                        self.line_number,
+                        output,
+                    )? {
+                        Some(line) => line,
+                        None => return Ok(()),
+                    },
+                },
+                PipelineStep::JsonFilter(filter) => {
+                    if !filter.matches(&output) {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::JsonlToCsv(convert) => {
+                    for line in convert.push(&output) {
+                        self.run_from(index + 1, line.into(), lines)?;
+                    }
+
+                    return Ok(());
+                }
+                PipelineStep::CsvToJsonl(convert) => match convert.apply(&output) {
+                    Some(object) => object.into(),
+                    None => return Ok(()),
+                },
+                PipelineStep::Kv(query) => match query.apply(&output, &parse_pairs(&output)) {
+                    Some(output) => output.into(),
+                    None => return Ok(()),
+                },
+                PipelineStep::Syslog(parser, query) => {
+                    match parser
+                        .fields(&output)
+                        .and_then(|fields| query.apply(&output, &fields))
+                    {
+                        Some(output) => output.into(),
+                        None => return Ok(()),
+                    }
+                }
+                PipelineStep::AccessLog(parser, query) => {
+                    match parser
+                        .fields(&output)
+                        .and_then(|fields| query.apply(&output, &fields))
+                    {
+                        Some(output) => output.into(),
+                        None => return Ok(()),
+                    }
+                }
+                PipelineStep::Redact(redactor) => redactor.apply(&output).into(),
+                PipelineStep::OnlyIn(set) => {
+                    if !set.contains(output.as_ref()) {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::NotIn(set) => {
+                    if set.contains(output.as_ref()) {
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::Lookup(lookup) => match lookup.apply(&output) {
+                    Some(enriched) => enriched.into(),
+                    None => return Ok(()),
+                },
+                PipelineStep::Translate(translate) => translate.apply(&output).into(),
+                PipelineStep::Columns(columns) => columns.apply(&output).into(),
+                PipelineStep::Normalize(form) => form.apply(&output).into(),
+                PipelineStep::Ascii => deunicode::deunicode(&output).into(),
+                PipelineStep::StripHtml(squeeze) => strip_html(&output, *squeeze).into(),
+                PipelineStep::UnescapeHtml => unescape_html(&output).into(),
+                PipelineStep::StripMarkdown(markdown) => match markdown.apply(&output) {
+                    Some(text) => text.into(),
+                    None => return Ok(()),
+                },
+                step @ (PipelineStep::Lower
+                | PipelineStep::Upper
+                | PipelineStep::LocaleLower(_)
+                | PipelineStep::LocaleUpper(_)
+                | PipelineStep::Trim
+                | PipelineStep::TrimStart
+                | PipelineStep::TrimEnd
+                | PipelineStep::TrimChars(..)
+                | PipelineStep::PadLeft(..)
+                | PipelineStep::PadRight(..)
+                | PipelineStep::Append(_)
+                | PipelineStep::Prepend(_)) => transform(step, output),
+                PipelineStep::Hash(algorithm, append) => {
+                    let digest = algorithm.digest(&output);
+                    if *append {
+                        (output.into_owned() + " " + digest.as_str()).into()
+                    } else {
+                        digest.into()
+                    }
+                }
+                PipelineStep::Tag(name, regex) => {
+                    if regex.is_match(&output) {
+                        tags.push(name.clone());
+                    }
+
+                    output
+                }
+                PipelineStep::Route(name, sink) => {
+                    if tags.contains(name) {
+                        lines.extend(
+                            sink.receive(output.into_owned())?
+                                .into_iter()
+                                .map(Cow::Owned),
+                        );
+                        return Ok(());
+                    }
+
+                    output
+                }
+                PipelineStep::RouteByKey(route) => {
+                    match route.apply(output.into_owned(), &self.source)? {
+                        Some(unrouted) => unrouted.into(),
+                        None => return Ok(()),
+                    }
+                }
+                PipelineStep::Base64Encode(url_safe) => base64_encode(&output, *url_safe).into(),
+                PipelineStep::Base64Decode(url_safe, policy) => {
+                    match base64_decode(&output, *url_safe) {
+                        Some(decoded) => decoded.into(),
+                        None => match failed(
+                            policy.or(self.on_error),
+                            "Invalid base64 input",
+                            self.line_number,
+                            output,
+                        )? {
+                            Some(line) => line,
+                            None => return Ok(()),
+                        },
+                    }
+                }
+                PipelineStep::UrlEncode => url_encode(&output).into(),
+                PipelineStep::UrlDecode(policy) => match url_decode(&output) {
+                    Some(decoded) => decoded.into(),
+                    None => match failed(
+                        policy.or(self.on_error),
+                        "Invalid percent-encoded input",
+                        self.line_number,
+                        output,
+                    )? {
+                        Some(line) => line,
+                        None => return Ok(()),
+                    },
+                },
+                PipelineStep::Calc(calculation, delimiter, policy) => {
+                    match calculation.apply(&output, delimiter.as_deref()) {
+                        Some(calculated) => calculated.into(),
+                        None => match failed(
+                            policy.or(self.on_error),
+                            "Could not evaluate expression",
+                            self.line_number,
+                            output,
+                        )? {
+                            Some(line) => line,
+                            None => return Ok(()),
+                        },
+                    }
+                }
+                PipelineStep::Where(expression) => {
+                    let context = ExprContext {
+                        line: &output,
+                        number: self.line_number,
+                    };
+                    // Lines the expression cannot be evaluated for, e.g.
+                    // comparing text with a number, do not match.
+                    match expression.evaluate(&context) {
+                        Some(value) if value.is_truthy() => output,
+                        _ => return Ok(()),
+                    }
+                }
+                PipelineStep::WhereField(filter) => match filter.matches(&output) {
+                    true => output,
+                    false => return Ok(()),
+                },
+                PipelineStep::Map(expression, policy) => {
+                    let context = ExprContext {
+                        line: &output,
+                        number: self.line_number,
+                    };
+                    match expression.evaluate_text(&context) {
+                        Some(mapped) => mapped.into(),
+                        None => match failed(
+                            policy.or(self.on_error),
+                            "Could not evaluate expression",
+                            self.line_number,
+                            output,
+                        )? {
+                            Some(line) => line,
+                            None => return Ok(()),
+                        },
+                    }
+                }
+                PipelineStep::Tee(sink) => {
+                    sink.receive(output.to_string())?;
+
+                    output
+                }
+                PipelineStep::TeePipeline(branch, sink) => {
+                    for line in branch.apply(&output)? {
+                        sink.receive(line.into_owned())?;
+                    }
+
+                    output
+                }
+                // Lines the regex does not match skip the branch untouched.
+                PipelineStep::If(regex, branch) if regex.is_match(&output) => {
+                    let mut released: Vec<String> = branch
+                        .apply(&output)?
+                        .into_iter()
+                        .map(Cow::into_owned)
+                        .collect();
+                    match released.pop() {
+                        Some(line) if released.is_empty() => line.into(),
+                        last => {
+                            for line in released.into_iter().chain(last) {
+                                self.run_from(index + 1, line.into(), lines)?;
+                            }
+
+                            return Ok(());
+                        }
+                    }
+                }
+                PipelineStep::If(..) => output,
+                PipelineStep::Repeat(branch, limit) => {
+                    let mut line = output.into_owned();
+                    for _ in 0..*limit {
+                        let mut released = branch.apply(&line)?;
+                        let next = match released.pop() {
+                            Some(next) if released.is_empty() => next.into_owned(),
+                            Some(_) => Err("Repeated steps must leave one line")?,
+                            None => return Ok(()),
+                        };
+                        if next == line {
+                            break;
+                        }
+
+                        line = next;
+                    }
+
+                    line.into()
+                }
+                PipelineStep::Format(template) => template
+                    .render(&TemplateContext {
+                        line: &output,
+                        number: self.line_number,
+                        file: &self.source,
+                        captures: &captures,
+                    })
+                    .into(),
+                PipelineStep::DateParse(input, format, policy) => match input.find(&output) {
+                    Some((range, timestamp)) => {
+                        let mut rewritten = output.into_owned();
+                        rewritten.replace_range(range, &format_timestamp(&timestamp, format));
+
+                        rewritten.into()
+                    }
+                    None => match failed(
+                        policy.or(self.on_error),
+                        "No timestamp found",
+                        self.line_number,
+                        output,
+                    )? {
+                        Some(line) => line,
+                        None => return Ok(()),
+                    },
+                },
+                PipelineStep::Since(bound, input, policy) => match input.find(&output) {
+                    Some((_, timestamp)) if timestamp >= *bound => output,
+                    Some(_) => return Ok(()),
+                    None => match failed(
+                        policy.or(self.on_error),
+                        "No timestamp found",
+                        self.line_number,
+                        output,
+                    )? {
+                        Some(line) => line,
+                        None => return Ok(()),
+                    },
+                },
+                PipelineStep::Until(bound, input, policy) => match input.find(&output) {
+                    Some((_, timestamp)) if timestamp < *bound => output,
+                    Some(_) => return Ok(()),
+                    None => match failed(
+                        policy.or(self.on_error),
+                        "No timestamp found",
+                        self.line_number,
+                        output,
+                    )? {
+                        Some(line) => line,
+                        None => return Ok(()),
+                    },
+                },
+                PipelineStep::HumanizeEpoch(format, relative) => {
+                    humanize_epochs(&output, format, *relative, Utc::now()).into()
+                }
+                PipelineStep::Exec(exec, policy) => match exec.apply(&output)? {
+                    Some(transformed) => transformed.into(),
+                    None => match failed(
+                        policy.or(self.on_error),
+                        "Command failed",
+                        self.line_number,
+                        output,
+                    )? {
+                        Some(line) => line,
+                        None => return Ok(()),
+                    },
+                },
+                #[cfg(feature = "fancy")]
+                PipelineStep::FilterFancy(regex) => {
+                    let matched = match regex.captures(&output) {
+                        Ok(Some(matched)) => matched,
+                        Ok(None) => return Ok(()),
+                        Err(_) => return Err("Pattern backtracked too far to match"),
+                    };
+                    if self.captures_needed {
+                        captures = regex
+                            .capture_names()
+                            .zip(matched.iter())
+                            .map(|(name, value)| {
+                                (
+                                    name.map(str::to_string),
+                                    value.map(|v| v.as_str().to_string()),
+                                )
+                            })
+                            .collect();
+                    }
+                    drop(matched);
+
+                    output
+                }
+                #[cfg(feature = "script")]
+                PipelineStep::Script(script) => {
+                    let mut released =
+                        script.run(output.into_owned(), self.line_number, &captures)?;
+                    match released.pop() {
+                        Some(line) if released.is_empty() => line.into(),
+                        last => {
+                            for line in released.into_iter().chain(last) {
+                                self.run_from(index + 1, line.into(), lines)?;
+                            }
+
+                            return Ok(());
+                        }
+                    }
+                }
+                PipelineStep::Chunk(chunk) => {
+                    for line in chunk.push(output.into_owned()) {
+                        self.run_from(index + 1, line.into(), lines)?;
+                    }
+
+                    return Ok(());
+                }
+                PipelineStep::Align(align) => {
+                    align.push(output.into_owned());
+
+                    return Ok(());
+                }
+                PipelineStep::Shuffle(shuffle) => {
+                    shuffle.push(output.into_owned());
+
+                    return Ok(());
+                }
+                PipelineStep::Sort(sort) => {
+                    sort.push(output.into_owned());
+
+                    return Ok(());
+                }
+                PipelineStep::CountDistinct(distinct) => {
+                    distinct.push(&output);
+
+                    return Ok(());
+                }
+                PipelineStep::DedupeCount(counts) => {
+                    counts.push(output.into_owned());
+
+                    return Ok(());
+                }
+                PipelineStep::Diff(diff) => match diff.apply(&output) {
+                    Some(added) => added.into(),
+                    None => return Ok(()),
+                },
+                PipelineStep::PerWindow(window) => {
+                    window.push(&output);
+
+                    return Ok(());
+                }
+                PipelineStep::Top(top) => {
+                    top.push(output.into_owned());
+
+                    return Ok(());
+                }
+                PipelineStep::TopBy(top) => {
+                    top.push(output.into_owned());
+
+                    return Ok(());
+                }
+                PipelineStep::GroupBy(group) => {
+                    group.push(&output);
+
+                    return Ok(());
+                }
+                PipelineStep::Stats(stats) => {
+                    stats.push(&output);
+
+                    return Ok(());
+                }
+                PipelineStep::PerKey(per_key) => match per_key.push(output.into_owned()) {
+                    Some(first) => first.into(),
+                    None => return Ok(()),
+                },
+                // A single line carries on with the tags and captures gathered
+                // so far; anything else is handed to the later steps one by one.
+                PipelineStep::Custom(step) => {
+                    let mut released = step.apply(output.into_owned())?;
+                    match released.pop() {
+                        Some(line) if released.is_empty() => line.into(),
+                        last => {
+                            for line in released.into_iter().chain(last) {
+                                self.run_from(index + 1, line.into(), lines)?;
+                            }
+
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+
+        if self.steps.len() > start {
+            self.trace_step(self.steps.len() - 1, &output);
+        }
+        lines.push(output);
+        Ok(())
+    }
+
+    fn trace_step(&mut self, index: usize, line: &str) {
+        if let Some(trace) = self.trace.as_mut().filter(|trace| trace.is_active()) {
+            trace.step(index, &self.stats[index].name, line);
+        }
+    }
+
+    /// The number and name of the step that dropped the last line applied,
+    /// for a line nothing came out of. Steps that hold lines back or route
+    /// them elsewhere have not dropped them.
+    pub(crate) fn rejected_by(&self) -> Option<(usize, &str)> {
+        let step = self.steps.get(self.reached)?;
+        let elsewhere = matches!(
+            step,
+            PipelineStep::Chunk(_)
+                | PipelineStep::Align(_)
+                | PipelineStep::Shuffle(_)
+                | PipelineStep::Sort(_)
+                | PipelineStep::CountDistinct(_)
+                | PipelineStep::DedupeCount(_)
+                | PipelineStep::Diff(_)
+                | PipelineStep::PerWindow(_)
+                | PipelineStep::Top(_)
+                | PipelineStep::TopBy(_)
+                | PipelineStep::GroupBy(_)
+                | PipelineStep::Stats(_)
+                | PipelineStep::PerKey(_)
+                | PipelineStep::Custom(_)
+                | PipelineStep::Route(..)
+                | PipelineStep::RouteByKey(_)
+        );
+
+        (!elsewhere).then(|| (self.reached + 1, self.stats[self.reached].name.as_str()))
+    }
+
+    /// Prints lines chosen by `trace` to stderr as they go through each step.
+    pub(crate) fn set_trace(&mut self, trace: Trace) {
+        self.trace = Some(trace);
+    }
+
+    // Swaps every plain `dedupe` for one that spills to disk, sharing `budget`
+    // between them, so a memory limit can be kept without losing exactness.
+    pub fn spill_dedupes(&mut self, budget: usize) {
+        let dedupes = self
+            .steps
+            .iter()
+            .map(|step| match step {
+                PipelineStep::DedupeBy(_, step) => step,
+                step => step,
+            })
+            .filter(|step| matches!(step, PipelineStep::Dedupe(..)))
+            .count();
+
+        for step in self.steps.iter_mut() {
+            let step = match step {
+                PipelineStep::DedupeBy(_, step) => step.as_mut(),
+                step => step,
+            };
+            if matches!(step, PipelineStep::Dedupe(..)) {
+                *step = PipelineStep::DedupeSpill(SpillingSet::new(budget / dedupes));
+            }
+        }
+    }
+
+    /// Swaps `lower` and `upper` for ones that follow a language's own case
+    /// rules, here and in nested pipelines, where those differ from the
+    /// Unicode defaults.
+    pub fn set_case_rules(&mut self, rules: CaseRules) {
+        if rules == CaseRules::Unicode {
+            return;
+        }
+
+        for step in self.steps.iter_mut() {
+            match step {
+                PipelineStep::Lower => *step = PipelineStep::LocaleLower(rules),
+                PipelineStep::Upper => *step = PipelineStep::LocaleUpper(rules),
+                PipelineStep::Route(_, Sink::Pipeline(pipeline))
+                | PipelineStep::TeePipeline(pipeline, _)
+                | PipelineStep::If(_, pipeline)
+                | PipelineStep::Repeat(pipeline, _) => pipeline.set_case_rules(rules),
+                _ => {}
+            }
+        }
+    }
+
+    /// The patterns of the filters, for highlighting what they matched in the
+    /// output.
+    pub(crate) fn match_patterns(&self) -> Vec<Regex> {
+        let mut patterns = vec![];
+        for step in self.steps.iter() {
+            match step {
+                PipelineStep::Filter(regex) | PipelineStep::FilterMatches(regex) => {
+                    patterns.push(regex.clone())
+                }
+                PipelineStep::FilterLiteral(finder) => patterns.extend(
+                    std::str::from_utf8(finder.needle())
+                        .ok()
+                        .and_then(|literal| Regex::new(&regex::escape(literal)).ok()),
+                ),
+                PipelineStep::FilterSet(set) | PipelineStep::FilterAny(set) => patterns.extend(
+                    set.patterns()
+                        .iter()
+                        .filter_map(|pattern| Regex::new(pattern).ok()),
+                ),
+                _ => {}
+            }
+        }
+
+        patterns
+    }
+
+    /// Makes the patterns of filters, tags and ifs match regardless of case,
+    /// here and in nested pipelines. A pattern can still turn it off for
+    /// itself with `(?-i)`.
+    pub fn set_ignore_case(&mut self) {
+        let ignoring_case = |regex: &Regex| Regex::new(&format!("(?i){}", regex.as_str())).unwrap();
+        let set_ignoring_case = |set: &RegexSet| {
+            RegexSet::new(
+                set.patterns()
+                    .iter()
+                    .map(|pattern| format!("(?i){}", pattern)),
+            )
+            .unwrap()
+        };
+
+        for step in self.steps.iter_mut() {
+            match step {
+                PipelineStep::Filter(regex)
+                | PipelineStep::FilterMatches(regex)
+                | PipelineStep::Tag(_, regex) => *regex = ignoring_case(regex),
+                PipelineStep::FilterSet(set) | PipelineStep::FilterAny(set) => {
+                    *set = set_ignoring_case(set)
+                }
+                PipelineStep::FilterLiterals(literals) => *literals = literals.ignoring_case(),
+                #[cfg(feature = "fancy")]
+                PipelineStep::FilterFancy(regex) => {
+                    *regex = fancy_regex::Regex::new(&format!("(?i){}", regex.as_str())).unwrap()
+                }
+                PipelineStep::FilterLiteral(finder) => {
+                    let literal = String::from_utf8_lossy(finder.needle());
+                    *step = PipelineStep::Filter(
+                        Regex::new(&format!("(?i){}", regex::escape(&literal))).unwrap(),
+                    );
+                }
+                PipelineStep::If(regex, pipeline) => {
+                    *regex = ignoring_case(regex);
+                    pipeline.set_ignore_case();
+                }
+                PipelineStep::Route(_, Sink::Pipeline(pipeline))
+                | PipelineStep::TeePipeline(pipeline, _)
+                | PipelineStep::Repeat(pipeline, _) => pipeline.set_ignore_case(),
+                _ => {}
+            }
+        }
+    }
+
+    pub fn get_memory(&self) -> usize {
+        self.steps.iter().map(step_memory).sum()
+    }
+
+    /// Describes every step as built: the arguments it took, what they were
+    /// compiled to, whether it holds lines back and how its memory grows.
+    pub fn explain(&self) -> String {
+        let mut text = String::new();
+        let steps = self.steps.iter().zip(&self.stats).zip(&self.arguments);
+
+        for (index, ((step, stats), arguments)) in steps.enumerate() {
+            let (buffers, memory) = step_behaviour(step);
+            let arguments: String = arguments
+                .iter()
+                .map(|argument| format!(" {:?}", argument))
+                .collect();
+
+            text += &format!("{}. {}{}\n", index + 1, stats.name, arguments);
+            text += &format!("   compiled: {}\n", compiled(step));
+            text += &format!("   buffers:  {}\n", buffers);
+            text += &format!("   memory:   {}\n", memory);
+            if let PipelineStep::Route(_, Sink::Pipeline(pipeline))
+            | PipelineStep::TeePipeline(pipeline, _)
+            | PipelineStep::If(_, pipeline)
+            | PipelineStep::Repeat(pipeline, _) = step
+            {
+                for line in pipeline.explain().lines() {
+                    text += &format!("      {}\n", line);
+                }
+            }
+        }
+
+        text
+    }
+
+    // Like get_memory, but also remembers each step's peak for the run summary.
+    pub fn sample_memory(&mut self) -> usize {
+        let mut memory = 0;
+        for (step, stats) in self.steps.iter().zip(self.stats.iter_mut()) {
+            let step_memory = step_memory(step);
+            stats.peak_memory = stats.peak_memory.max(step_memory);
+            memory += step_memory;
+        }
+
+        memory
+    }
+
+    /// Moves filters ahead of exact dedupes, merges runs of filters into one
+    /// `RegexSet` and fuses runs of cheap transforms, wherever the output stays
+    /// the same. Stats are kept for the optimized steps, so this is meant to
+    /// run before any input.
+    pub fn optimize(&mut self) {
+        let steps = std::mem::take(&mut self.steps);
+        let arguments = std::mem::take(&mut self.arguments);
+        let parsed_steps = self
+            .stats
+            .iter()
+            .map(|stats| stats.name.clone())
+            .zip(steps)
+            .zip(arguments)
+            .map(|((name, step), arguments)| (name, step, arguments))
+            .collect();
+
+        *self = Pipeline {
+            on_error: self.on_error,
+            trace: self.trace.take(),
+            ..Pipeline::parsed(crate::optimize::optimize(parsed_steps))
+        };
+    }
+
+    /// Sets what steps not given their own `--on-error` do with lines they
+    /// fail on, here and in the pipelines lines are routed to.
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.on_error = policy;
+        for step in self.steps.iter_mut() {
+            if let PipelineStep::Route(_, Sink::Pipeline(pipeline))
+            | PipelineStep::TeePipeline(pipeline, _)
+            | PipelineStep::If(_, pipeline)
+            | PipelineStep::Repeat(pipeline, _) = step
+            {
+                pipeline.set_error_policy(policy);
+            }
+        }
+    }
+
+    /// Names the input the next lines come from, for steps that fill in
+    /// `{file}`, here and in nested pipelines.
+    pub fn set_source(&mut self, name: &str) {
+        self.source = name.to_string();
+        for step in self.steps.iter_mut() {
+            if let PipelineStep::Route(_, Sink::Pipeline(pipeline))
+            | PipelineStep::TeePipeline(pipeline, _)
+            | PipelineStep::If(_, pipeline)
+            | PipelineStep::Repeat(pipeline, _) = step
+            {
+                pipeline.set_source(name);
+            }
+        }
+    }
+
+    /// Whether any step fills in the name of the input, which copies of the
+    /// pipeline on other threads are never told.
+    pub fn uses_source(&self) -> bool {
+        self.steps.iter().any(PipelineStep::uses_source)
+    }
+
+    /// Ends the lines `route` writes to its files with the run's record
+    /// terminator, here and in nested pipelines.
+    pub fn set_terminator(&mut self, terminator: &str) {
+        for step in self.steps.iter_mut() {
+            match step {
+                PipelineStep::RouteByKey(route) => route.set_terminator(terminator),
+                PipelineStep::Route(_, Sink::Pipeline(pipeline))
+                | PipelineStep::TeePipeline(pipeline, _)
+                | PipelineStep::If(_, pipeline)
+                | PipelineStep::Repeat(pipeline, _) => pipeline.set_terminator(terminator),
+                _ => {}
+            }
+        }
+    }
+
+    /// Seeds every step that picks lines at random, here and in nested
+    /// pipelines, so runs with the same seed pick the same lines. Each step
+    /// gets its own seed derived from this one.
+    pub fn set_seed(&mut self, seed: u64) {
+        for (index, step) in self.steps.iter_mut().enumerate() {
+            let seed = seed.wrapping_add(index as u64);
+            match step {
+                PipelineStep::Sample(sample) => sample.reseed(seed),
+                PipelineStep::Shuffle(shuffle) => shuffle.reseed(seed),
+                PipelineStep::Id(identifier, ..) => identifier.reseed(seed),
+                PipelineStep::Route(_, Sink::Pipeline(pipeline))
+                | PipelineStep::TeePipeline(pipeline, _)
+                | PipelineStep::If(_, pipeline)
+                | PipelineStep::Repeat(pipeline, _) => pipeline.set_seed(seed.rotate_left(32)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether any step reads lines as text. When none does, lines can go
+    /// through [`Pipeline::apply_bytes`] without ever being checked for UTF-8.
+    pub fn needs_text(&self) -> bool {
+        self.steps.iter().any(PipelineStep::needs_text)
+    }
+
+    /// Whether every step handles each line on its own, keeping nothing from
+    /// one line to the next, so lines can be shared out between copies.
+    pub fn is_stateless(&self) -> bool {
+        self.steps.iter().all(PipelineStep::is_stateless)
+    }
+
+    // Whether worker threads can share the lines out with one exact dedupe
+    // between them: it is the only step keeping state, and nothing before it
+    // turns a line into several, so a line claims no more than one entry.
+    pub(crate) fn can_share_dedupe(&self) -> bool {
+        let Some(dedupe) = self
+            .steps
+            .iter()
+            .position(|step| matches!(step, PipelineStep::Dedupe(..)))
+        else {
+            return false;
+        };
+
+        self.steps.iter().enumerate().all(|(index, step)| {
+            index == dedupe
+                || (step.is_stateless()
+                    && (index > dedupe
+                        || !matches!(
+                            step,
+                            PipelineStep::FilterMatches(_)
+                                | PipelineStep::SplitRecords(_)
+                                | PipelineStep::If(..)
+                                | PipelineStep::Repeat(..)
+                        )))
+        })
+    }
+
+    // Swaps the exact dedupe for one on the set the workers share.
+    pub(crate) fn share_dedupe(&mut self, set: &ShardedSet) {
+        for step in self.steps.iter_mut() {
+            if matches!(step, PipelineStep::Dedupe(..)) {
+                *step = PipelineStep::DedupeShared(set.clone(), None);
+            }
+        }
+    }
+
+    // The line the shared dedupe let through for the last line, if any.
+    pub(crate) fn take_claim(&mut self) -> Option<Vec<u8>> {
+        self.steps.iter_mut().find_map(|step| match step {
+            PipelineStep::DedupeShared(_, claimed) => claimed.take(),
+            _ => None,
+        })
+    }
+
+    // The name of the first step that keeps state a checkpoint cannot save.
+    pub(crate) fn unsaved_step(&self) -> Option<&str> {
+        self.named_steps()
+            .find(|(_, step)| {
+                !step.is_stateless()
+                    && !matches!(
+                        step,
+                        PipelineStep::Dedupe(..)
+                            | PipelineStep::DedupeState(_)
+                            | PipelineStep::Throttle(_)
+                            | PipelineStep::Timestamp(..)
+                            | PipelineStep::Id(..)
+                    )
+            })
+            .map(|(name, _)| name)
+    }
+
+    // Takes over from the pipeline a reloaded one replaces: the line number
+    // goes on, and when state is kept every step built from the same command
+    // and arguments as one of the old steps swaps in that step, in order,
+    // with all it holds.
+    pub(crate) fn take_over(&mut self, old: &mut Pipeline, keep_state: bool) {
+        self.line_number = old.line_number;
+        if !keep_state {
+            return;
+        }
+
+        let old_commands = old.step_commands();
+        let mut taken = vec![false; old_commands.len()];
+        for (index, command) in self.step_commands().iter().enumerate() {
+            let found = old_commands
+                .iter()
+                .zip(taken.iter())
+                .position(|(old_command, taken)| !taken && old_command == command);
+            if let Some(found) = found {
+                taken[found] = true;
+                std::mem::swap(&mut self.steps[index], &mut old.steps[found]);
+            }
+        }
+    }
+
+    // What a checkpoint keeps of the pipeline: the line number and the lines
+    // every exact dedupe has seen, base64 encoded as they need not be text.
+    // Dedupes with a state file save it in place instead.
+    pub(crate) fn save_state(&mut self) -> Result<Value, &'static str> {
+        let mut steps = vec![];
+        for step in self.steps.iter_mut() {
+            steps.push(match step {
+                PipelineStep::Dedupe(dupes, _) => dupes
+                    .iter()
+                    .map(|line| Value::from(base64_encode_bytes(line, false)))
+                    .collect(),
+                PipelineStep::DedupeState(set) => {
+                    set.save()?;
+                    Value::Null
+                }
+                _ => Value::Null,
+            });
+        }
+
+        Ok(json!({ "line_number": self.line_number, "steps": steps }))
+    }
+
+    pub(crate) fn restore_state(&mut self, state: &Value) -> Result<(), &'static str> {
+        let invalid = "Invalid checkpoint";
+        let steps = state["steps"]
+            .as_array()
+            .filter(|steps| steps.len() == self.steps.len())
+            .ok_or(invalid)?;
+        self.line_number = state["line_number"].as_u64().ok_or(invalid)? as usize;
+
+        for (step, saved) in self.steps.iter_mut().zip(steps) {
+            if let PipelineStep::Dedupe(dupes, stored) = step {
+                for line in saved.as_array().ok_or(invalid)? {
+                    let line = line
+                        .as_str()
+                        .and_then(|line| base64_decode_bytes(line.as_bytes(), false))
+                        .ok_or(invalid)?;
+                    *stored += line.len();
+                    dupes.insert(line);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // The command and arguments of every step, as each could be built again.
+    pub(crate) fn step_commands(&self) -> Vec<Vec<String>> {
+        self.stats
+            .iter()
+            .zip(&self.arguments)
+            .map(|(stats, arguments)| [vec![stats.name.clone()], arguments.clone()].concat())
+            .collect()
+    }
+
+    pub(crate) fn named_steps(&self) -> impl Iterator<Item = (&str, &PipelineStep)> {
+        self.stats
+            .iter()
+            .map(|stats| stats.name.as_str())
+            .zip(&self.steps)
+    }
+
+    pub fn step_stats(&self) -> &[StepStats] {
+        &self.stats
+    }
+
+    pub fn lossy_events(&self) -> Vec<(LossyEvent, usize)> {
+        let mut events = vec![];
+        for step in self.steps.iter() {
+            match step {
+                PipelineStep::Top(top) => events.push((LossyEvent::ApproximateTop, top.evicted())),
+                PipelineStep::CountDistinct(distinct) if distinct.is_approximate() => {
+                    events.push((LossyEvent::ApproximateCount, 1))
+                }
+                PipelineStep::Stats(stats) if stats.is_estimated() => {
+                    events.push((LossyEvent::ApproximatePercentile, 1))
+                }
+                PipelineStep::Route(_, Sink::Pipeline(pipeline))
+                | PipelineStep::TeePipeline(pipeline, _)
+                | PipelineStep::If(_, pipeline)
+                | PipelineStep::Repeat(pipeline, _) => events.extend(pipeline.lossy_events()),
+                _ => {}
+            }
+        }
+
+        events
+    }
+
+    /// Drains buffering steps at the end of input, in order, so whatever an
+    /// earlier step releases still passes through the later ones before they
+    /// are drained in turn.
+    pub fn finish(&mut self) -> Result<Vec<String>, &'static str> {
+        let mut lines = vec![];
+
+        for index in 0..self.steps.len() {
+            let released = match &mut self.steps[index] {
+                PipelineStep::Route(_, sink) | PipelineStep::Tee(sink) => {
+                    lines.extend(sink.finish()?.into_iter().map(Cow::Owned));
+                    vec![]
+                }
+                // What the branch releases at the end still goes to its sink.
+                PipelineStep::TeePipeline(branch, sink) => {
+                    for line in branch.finish()? {
+                        sink.receive(line)?;
+                    }
+                    sink.finish()?;
+                    vec![]
+                }
+                PipelineStep::If(_, branch) => branch.finish()?,
+                PipelineStep::Exec(exec, _) => {
+                    exec.finish()?;
+                    vec![]
+                }
+                PipelineStep::DedupeState(set) => {
+                    set.save()?;
+                    vec![]
+                }
+                PipelineStep::RouteByKey(route) => {
+                    route.flush()?;
+                    vec![]
+                }
+                PipelineStep::Chunk(chunk) => chunk.flush(),
+                PipelineStep::Align(align) => align.flush(),
+                PipelineStep::Shuffle(shuffle) => shuffle.flush(),
+                PipelineStep::Sort(sort) => sort.flush(),
+                PipelineStep::CountDistinct(distinct) => vec![distinct.count().to_string()],
+                PipelineStep::DedupeCount(counts) => counts.flush(),
+                PipelineStep::Diff(diff) => diff.finish(),
+                PipelineStep::PerWindow(window) => window.flush(),
+                PipelineStep::Top(top) => top.flush(),
+                PipelineStep::TopBy(top) => top.flush(),
+                PipelineStep::GroupBy(group) => group.flush(),
+                PipelineStep::Stats(stats) => stats.flush(),
+                PipelineStep::PerKey(per_key) => per_key.flush(),
+                PipelineStep::Custom(step) => step.flush()?,
+                _ => vec![],
+            };
+
+            for line in released {
+                self.run_from(index + 1, Cow::Owned(line), &mut lines)?;
+            }
+        }
+
+        Ok(lines.into_iter().map(Cow::into_owned).collect())
+    }
+}
+
+impl PipelineStep {
+    // Whether the step handles each line on its own, keeping nothing from one
+    // line to the next.
+    pub(crate) fn is_stateless(&self) -> bool {
+        matches!(
+            self,
+            PipelineStep::Filter(_)
+                | PipelineStep::FilterSet(_)
+                | PipelineStep::FilterAny(_)
+                | PipelineStep::FilterLiteral(_)
+                | PipelineStep::FilterLiterals(_)
+                | PipelineStep::FilterMatches(_)
+                | PipelineStep::SplitRecords(_)
+                | PipelineStep::Lower
+                | PipelineStep::Upper
+                | PipelineStep::LocaleLower(_)
+                | PipelineStep::LocaleUpper(_)
+                | PipelineStep::Trim
+                | PipelineStep::TrimStart
+                | PipelineStep::TrimEnd
+                | PipelineStep::TrimChars(..)
+                | PipelineStep::PadLeft(..)
+                | PipelineStep::PadRight(..)
+                | PipelineStep::Append(_)
+                | PipelineStep::Prepend(_)
+                | PipelineStep::Fused(_)
+                | PipelineStep::Hash(..)
+                | PipelineStep::Base64Encode(_)
+                | PipelineStep::Base64Decode(..)
+                | PipelineStep::UrlEncode
+                | PipelineStep::UrlDecode(_)
+                | PipelineStep::Calc(..)
+                | PipelineStep::Where(_)
+                | PipelineStep::WhereField(_)
+                | PipelineStep::Map(..)
+                | PipelineStep::Format(_)
+                | PipelineStep::DateParse(..)
+                | PipelineStep::HumanizeEpoch(..)
+                | PipelineStep::MinLength(..)
+                | PipelineStep::MaxLength(..)
+                | PipelineStep::Json(..)
+                | PipelineStep::JsonFormat(..)
+                | PipelineStep::JsonFilter(_)
+                | PipelineStep::Kv(_)
+                | PipelineStep::Syslog(..)
+                | PipelineStep::AccessLog(..)
+                | PipelineStep::Redact(_)
+                | PipelineStep::OnlyIn(_)
+                | PipelineStep::NotIn(_)
+                | PipelineStep::Lookup(_)
+                | PipelineStep::Translate(_)
+                | PipelineStep::Columns(_)
+                | PipelineStep::Normalize(_)
+                | PipelineStep::Ascii
+                | PipelineStep::StripHtml(_)
+                | PipelineStep::UnescapeHtml
+                | PipelineStep::Since(..)
+                | PipelineStep::Until(..)
+        ) || matches!(self, PipelineStep::If(_, branch) if branch.is_stateless())
+            || matches!(self, PipelineStep::Repeat(..))
+            || matches!(self, PipelineStep::Id(identifier, ..) if identifier.is_named())
+            || self.is_fancy_filter()
+    }
+
+    pub(crate) fn is_fancy_filter(&self) -> bool {
+        #[cfg(feature = "fancy")]
+        if matches!(self, PipelineStep::FilterFancy(_)) {
+            return true;
+        }
+
+        false
+    }
+
+    // Whether the step has to see the line as text; the others only compare
+    // or measure its bytes.
+    pub(crate) fn needs_text(&self) -> bool {
+        !matches!(
+            self,
+            PipelineStep::Dedupe(..)
+                | PipelineStep::DedupeState(_)
+                | PipelineStep::OnlyDuplicates(_)
+                | PipelineStep::FilterLiteral(_)
+                | PipelineStep::FilterLiterals(_)
+                | PipelineStep::MinLength(_, true)
+                | PipelineStep::MaxLength(_, true)
+                | PipelineStep::Throttle(_)
+        )
+    }
+
+    pub(crate) fn uses_source(&self) -> bool {
+        match self {
+            PipelineStep::Format(template) => template.uses_file(),
+            PipelineStep::RouteByKey(route) => route.uses_file(),
+            PipelineStep::Route(_, Sink::Pipeline(pipeline))
+            | PipelineStep::TeePipeline(pipeline, _)
+            | PipelineStep::If(_, pipeline)
+            | PipelineStep::Repeat(pipeline, _) => pipeline.uses_source(),
+            _ => false,
+        }
+    }
+
+    // Whether the step reads the captures of the last filter.
+    pub(crate) fn uses_captures(&self) -> bool {
+        match self {
+            PipelineStep::Format(template) => template.uses_captures(),
+            #[cfg(feature = "script")]
+            PipelineStep::Script(_) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "script")]
+fn script_step(script: &str) -> Result<PipelineStep, &'static str> {
+    Ok(PipelineStep::Script(Box::new(crate::script::Script::load(
+        script,
+    )?)))
+}
+
+#[cfg(not(feature = "script"))]
+fn script_step(_: &str) -> Result<PipelineStep, &'static str> {
+    Err("The script step needs rangler built with the script feature")
+}
+
+#[cfg(feature = "fancy")]
+fn fancy_filter(flags: &str, pattern: &str) -> Result<PipelineStep, &'static str> {
+    let inline = match flags.is_empty() {
+        true => pattern.to_string(),
+        false => format!("(?{}){}", flags, pattern),
+    };
+
+    fancy_regex::Regex::new(&inline)
+        .map(PipelineStep::FilterFancy)
+        .map_err(|_| "Invalid regular expression")
+}
+
+#[cfg(not(feature = "fancy"))]
+fn fancy_filter(_: &str, _: &str) -> Result<PipelineStep, &'static str> {
+    Err("Lookaround and backreferences need rangler built with the fancy feature")
+}
+
+#[cfg(feature = "wasm")]
+fn wasm_step(path: &str) -> Result<Box<dyn Step>, &'static str> {
+    Ok(Box::new(crate::wasm::WasmStep::load(path)?))
+}
+
+#[cfg(not(feature = "wasm"))]
+fn wasm_step(_: &str) -> Result<Box<dyn Step>, &'static str> {
+    Err("The wasm step needs rangler built with the wasm feature")
+}
+
+#[cfg(feature = "geoip")]
+fn geoip_step(path: &str) -> Result<Box<dyn Step>, &'static str> {
+    Ok(Box::new(crate::geoip::GeoIp::load(path)?))
+}
+
+#[cfg(not(feature = "geoip"))]
+fn geoip_step(_: &str) -> Result<Box<dyn Step>, &'static str> {
+    Err("The geoip step needs rangler built with the geoip feature")
+}
+
+// The steps that rewrite a line without looking at anything else, which is
+// what lets the optimizer fuse them.
+// A borrowed line stays borrowed through a trim, as that only narrows it.
+fn transform<'a>(step: &PipelineStep, line: Cow<'a, str>) -> Cow<'a, str> {
+    match (step, line) {
+        (PipelineStep::Lower, line) => line.to_lowercase().into(),
+        (PipelineStep::Upper, line) => line.to_uppercase().into(),
+        (PipelineStep::LocaleLower(rules), line) => rules.lower(&line).into(),
+        (PipelineStep::LocaleUpper(rules), line) => rules.upper(&line).into(),
+        (PipelineStep::Trim, Cow::Borrowed(line)) => Cow::Borrowed(line.trim()),
+        (PipelineStep::Trim, line) => line.trim().to_string().into(),
+        (PipelineStep::TrimStart, Cow::Borrowed(line)) => Cow::Borrowed(line.trim_start()),
+        (PipelineStep::TrimStart, line) => line.trim_start().to_string().into(),
+        (PipelineStep::TrimEnd, Cow::Borrowed(line)) => Cow::Borrowed(line.trim_end()),
+        (PipelineStep::TrimEnd, line) => line.trim_end().to_string().into(),
+        (PipelineStep::TrimChars(set, start, end), line) => {
+            let trim = |line: &str| -> (usize, usize) {
+                let mut trimmed = line;
+                if *start {
+                    trimmed = trimmed.trim_start_matches(set.as_slice());
+                }
+                let from = line.len() - trimmed.len();
+                if *end {
+                    trimmed = trimmed.trim_end_matches(set.as_slice());
+                }
+                (from, from + trimmed.len())
+            };
+
+            match line {
+                Cow::Borrowed(line) => {
+                    let (from, to) = trim(line);
+                    Cow::Borrowed(&line[from..to])
+                }
+                Cow::Owned(line) => {
+                    let (from, to) = trim(&line);
+                    line[from..to].to_string().into()
+                }
+            }
+        }
+        (PipelineStep::PadLeft(width, fill), line) => match padding(&line, *width, *fill) {
+            Some(padding) => (padding + line.as_ref()).into(),
+            None => line,
+        },
+        (PipelineStep::PadRight(width, fill), line) => match padding(&line, *width, *fill) {
+            Some(padding) => (line.into_owned() + padding.as_str()).into(),
+            None => line,
+        },
+        (PipelineStep::Append(suffix), line) => (line.into_owned() + suffix.as_str()).into(),
+        (PipelineStep::Prepend(prefix), line) => (prefix.to_owned() + line.as_ref()).into(),
+        (_, line) => line,
+    }
+}
+
+// Runs a dedupe on a line, or on the key standing in for it, returning
+// whether it was the first of its kind.
+fn keeps_unseen(step: &mut PipelineStep, line: &str) -> Result<bool, &'static str> {
+    match step {
+        PipelineStep::DedupeRecent(recent) => Ok(recent.insert(line)),
+        PipelineStep::DedupeWindow(window) => Ok(window.insert(line)),
+        PipelineStep::DedupeApprox(filter) => Ok(filter.insert(line)),
+        PipelineStep::DedupeSpill(set) => set.insert(line),
+        step => Ok(keeps_bytes(step, line.as_bytes())),
+    }
+}
+
+// Runs a step that does not need text on the bytes of a line, returning
+// whether the line carries on.
+fn keeps_bytes(step: &mut PipelineStep, line: &[u8]) -> bool {
+    match step {
+        PipelineStep::Dedupe(dupes, stored) => {
+            if dupes.contains(line) {
+                return false;
+            }
+
+            dupes.insert(line.to_vec());
+            *stored += line.len();
+            true
+        }
+        PipelineStep::DedupeState(set) => set.insert(line),
+        PipelineStep::OnlyDuplicates(set) => set.insert(line),
+        PipelineStep::FilterLiteral(finder) => finder.find(line).is_some(),
+        PipelineStep::FilterLiterals(literals) => literals.is_match(line),
+        PipelineStep::MinLength(length, true) => line.len() >= *length,
+        PipelineStep::MaxLength(length, true) => line.len() <= *length,
+        PipelineStep::Throttle(throttle) => {
+            throttle.wait();
+            true
+        }
+        _ => true,
+    }
+}
+
+fn line_length(line: &str, bytes: bool) -> usize {
+    if bytes {
+        line.len()
+    } else {
+        line.chars().count()
+    }
+}
+
+pub fn next_argument<'a, T: AsRef<str>>(tokens: &mut &'a [T]) -> Option<&'a str> {
+    let (first, rest) = tokens.split_first()?;
+    *tokens = rest;
+
+    Some(first.as_ref())
+}
+
+// Names the command and the one that was probably meant; like expression
+// errors, the message ends the run.
+// The position is filled in once parsing stops, see RanglerError::at.
+fn unknown_command(command: &str) -> RanglerError {
+    RanglerError::UnknownCommand {
+        command: command.to_string(),
+        suggestion: suggest_command(command),
+        position: 0,
+    }
+}
+
+// A pattern may follow -i (ignore case), -m (^ and $ match at line breaks
+// inside a record), -s (. matches line breaks too) and -F (the pattern is a
+// fixed string). They become inline flags and escapes, so the regex's own
+// text still says how it matches.
+fn next_regex<T: AsRef<str>>(tokens: &mut &[T]) -> Result<Regex, RanglerError> {
+    let flags = next_regex_flags(tokens);
+    let pattern = next_argument(tokens).ok_or("Missing regular expression")?;
+
+    flagged_regex(&flags, pattern)
+}
+
+// The patterns up to `end`, all taking the flags given before the first.
+fn next_regex_set<T: AsRef<str>>(tokens: &mut &[T]) -> Result<RegexSet, RanglerError> {
+    let flags = next_regex_flags(tokens);
+    let mut patterns = vec![];
+    loop {
+        match next_argument(tokens).ok_or("Missing end")? {
+            "end" => break,
+            pattern => patterns.push(flagged_regex(&flags, pattern)?.as_str().to_string()),
+        }
+    }
+    if patterns.is_empty() {
+        Err("Missing regular expression")?;
+    }
+
+    Ok(RegexSet::new(patterns).map_err(|_| "Invalid regular expression")?)
+}
+
+fn next_regex_flags<T: AsRef<str>>(tokens: &mut &[T]) -> String {
+    let mut flags = String::new();
+    while tokens.len() > 1 {
+        match tokens[0].as_ref() {
+            "-i" => flags.push('i'),
+            "-m" => flags.push('m'),
+            "-s" => flags.push('s'),
+            "-F" => flags.push('F'),
+            "-o" => flags.push('o'),
+            _ => break,
+        }
+        *tokens = &tokens[1..];
+    }
+
+    flags
+}
+
+fn flagged_regex(flags: &str, pattern: &str) -> Result<Regex, RanglerError> {
+    if flags.contains('o') {
+        Err("Only filter takes -o")?;
+    }
+    let (flags, escaped) = match flags.contains('F') {
+        true => (flags.replace('F', ""), regex::escape(pattern)),
+        false => (flags.to_string(), pattern.to_string()),
+    };
+    let inline = match flags.is_empty() {
+        true => escaped,
+        false => format!("(?{}){}", flags, escaped),
+    };
+
+    Regex::new(&inline).map_err(|source| RanglerError::InvalidRegex {
+        pattern: pattern.to_string(),
+        position: 0,
+        source,
+    })
+}
+
+// Expression errors quote the expression to point at the bad token. They end
+// the run, so leaking the message is fine.
+fn next_expression<T: AsRef<str>>(tokens: &mut &[T]) -> Result<Expr, &'static str> {
+    let source = next_argument(tokens).ok_or("Missing expression")?;
+
+    Expr::parse(source).map_err(|message| &*Box::leak(message.into_boxed_str()))
+}
+
+fn next_error_policy<T: AsRef<str>>(tokens: &mut &[T]) -> Result<ErrorPolicy, &'static str> {
+    if !next_flag(tokens, "--on-error") {
+        return Ok(ErrorPolicy::Inherit);
+    }
+
+    ErrorPolicy::parse(next_argument(tokens).ok_or("Missing error policy")?)
+}
+
+// Settles a line a step failed on by its policy, giving the line to carry on
+// with, if any. Errors end the run, so leaking the message is fine.
+fn failed<'a>(
+    policy: ErrorPolicy,
+    reason: &'static str,
+    line_number: usize,
+    line: Cow<'a, str>,
+) -> Result<Option<Cow<'a, str>>, &'static str> {
+    match policy {
+        ErrorPolicy::Inherit | ErrorPolicy::Skip => Ok(None),
+        ErrorPolicy::PassThrough => Ok(Some(line)),
+        ErrorPolicy::Annotate => Ok(Some(format!("{}\t[error: {}]", line, reason).into())),
+        ErrorPolicy::Error => {
+            let message = format!("{} on line {}: {}", reason, line_number, line);
+            Err(Box::leak(message.into_boxed_str()))
+        }
+    }
+}
+
+// Reads `<datetime> [--format <input format>] [--on-error ...]` for the
+// since and until steps. A datetime without an offset is taken as UTC.
+fn next_time_bound<T: AsRef<str>>(
+    tokens: &mut &[T],
+) -> Result<(DateTime<FixedOffset>, TimestampFormat, ErrorPolicy), &'static str> {
+    let bound = parse_timestamp(next_argument(tokens).ok_or("Missing datetime")?)
+        .ok_or("Invalid datetime")?;
+    let input = TimestampFormat::parse(next_option(tokens, "--format")?.unwrap_or("iso"))?;
+
+    Ok((bound, input, next_error_policy(tokens)?))
+}
+
+fn next_field_query<T: AsRef<str>>(tokens: &mut &[T]) -> Result<FieldQuery, &'static str> {
+    match next_argument(tokens).ok_or("Missing field query")? {
+        "get" => {
+            let names = next_argument(tokens).ok_or("Missing field names")?;
+
+            Ok(FieldQuery::Get(
+                names
+                    .split(',')
+                    .map(|name| name.trim().to_string())
+                    .collect(),
+            ))
+        }
+        "where" => Ok(FieldQuery::Where(FieldCondition::parse(
+            next_argument(tokens).ok_or("Missing condition")?,
+        )?)),
+        _ => Err("Invalid field query"),
+    }
+}
+
+fn next_option<'a, T: AsRef<str>>(
+    tokens: &mut &'a [T],
+    flag: &str,
+) -> Result<Option<&'a str>, &'static str> {
+    if !next_flag(tokens, flag) {
+        return Ok(None);
+    }
+
+    next_argument(tokens)
+        .map(Some)
+        .ok_or("Missing option value")
+}
+
+pub fn next_flag<T: AsRef<str>>(tokens: &mut &[T], flag: &str) -> bool {
+    let present = tokens.first().map(|t| t.as_ref()) == Some(flag);
+    if present {
+        *tokens = &tokens[1..];
+    }
+
+    present
+}
+
+impl PartialEq for Pipeline {
+    fn eq(&self, other: &Self) -> bool {
+        self.steps == other.steps
+    }
+}
+
+// A step's Debug output, which shows what its arguments were compiled to,
+// cut short where it would dump a whole lookup table or filter.
+pub fn compiled<T: std::fmt::Debug>(step: &T) -> String {
+    let debug = format!("{:?}", step);
+    match debug.char_indices().nth(100) {
+        Some((end, _)) => format!("{}…", &debug[..end]),
+        None => debug,
+    }
+}
+
+// Whether a step holds lines back, and how its memory grows with the input.
+fn step_behaviour(step: &PipelineStep) -> (&'static str, String) {
+    let end = "everything until the end of input";
+    let held = |what: &str| format!("holds {}, {}", what, HumanBytes(step_memory(step) as u64));
+
+    match step {
+        PipelineStep::Dedupe(..) => (
+            "no",
+            "grows with every distinct line, unless --max-memory makes it spill".to_string(),
+        ),
+        PipelineStep::DedupeRecent(_) => ("no", "bounded by the --recent count".to_string()),
+        PipelineStep::DedupeWindow(_) => ("no", "bounded by the --window size".to_string()),
+        PipelineStep::DedupeApprox(_) => ("no", held("a fixed-size Bloom filter")),
+        PipelineStep::DedupeSpill(_) => (
+            "no",
+            "bounded, spilling to disk past its budget".to_string(),
+        ),
+        PipelineStep::DedupeState(_) => ("no", held("a hash of every line seen in any run")),
+        PipelineStep::OnlyDuplicates(_) => ("no", "grows with every distinct line".to_string()),
+        PipelineStep::DedupeBy(_, step) => step_behaviour(step),
+        PipelineStep::Throttle(_) => ("no, but delays lines", "constant".to_string()),
+        PipelineStep::Chunk(_) => ("up to one chunk", "bounded by the chunk size".to_string()),
+        PipelineStep::Align(_) | PipelineStep::Shuffle(_) | PipelineStep::Sort(_) => {
+            (end, "grows with the input".to_string())
+        }
+        PipelineStep::Top(top) if top.is_bounded() => {
+            (end, "bounded by the --approx counters".to_string())
+        }
+        PipelineStep::Top(_) => (end, "grows with every distinct line".to_string()),
+        PipelineStep::TopBy(_) => (end, "bounded by k lines".to_string()),
+        PipelineStep::CountDistinct(distinct) if distinct.is_approximate() => {
+            (end, "constant, a 16 KiB sketch".to_string())
+        }
+        PipelineStep::CountDistinct(_) => {
+            (end, "grows by a 16-byte hash per distinct line".to_string())
+        }
+        PipelineStep::DedupeCount(_) => (end, "grows with every distinct line".to_string()),
+        PipelineStep::GroupBy(_) => (end, "grows with every distinct key".to_string()),
+        PipelineStep::Stats(_) => (end, "bounded, a t-digest of a few KiB".to_string()),
+        PipelineStep::PerWindow(_) => (end, "grows with every window and key".to_string()),
+        PipelineStep::PerKey(per_key) if per_key.keeps_last() => {
+            (end, "grows with every distinct key".to_string())
+        }
+        PipelineStep::PerKey(_) => ("no", "grows with every distinct key".to_string()),
+        PipelineStep::Diff(_) => (
+            "no, but emits the missing lines at the end",
+            held("the reference file"),
+        ),
+        PipelineStep::Lookup(_)
+        | PipelineStep::OnlyIn(_)
+        | PipelineStep::NotIn(_)
+        | PipelineStep::FilterLiterals(_) => ("no", held("the file")),
+        PipelineStep::Route(_, Sink::Pipeline(_)) => {
+            ("as its pipeline", "as its pipeline".to_string())
+        }
+        PipelineStep::TeePipeline(..) => ("no", "as its pipeline".to_string()),
+        PipelineStep::If(..) => ("as its pipeline", "as its pipeline".to_string()),
+        PipelineStep::Exec(..) => (
+            "no",
+            "constant, not counting the command itself".to_string(),
+        ),
+        PipelineStep::Custom(_) => (
+            "as the step decides",
+            "as the step reports while running".to_string(),
+        ),
+        _ => ("no", "constant".to_string()),
+    }
+}
+
+fn step_memory(step: &PipelineStep) -> usize {
+    match step {
+        PipelineStep::Dedupe(_, bytes) => *bytes,
+        PipelineStep::DedupeRecent(recent) => recent.memory(),
+        PipelineStep::DedupeWindow(window) => window.memory(),
+        PipelineStep::OnlyDuplicates(set) => set.memory(),
+        PipelineStep::DedupeApprox(filter) => filter.memory(),
+        PipelineStep::DedupeSpill(set) => set.memory(),
+        PipelineStep::DedupeState(set) => set.memory(),
+        PipelineStep::DedupeBy(_, step) => step_memory(step),
+        PipelineStep::Lookup(lookup) => lookup.memory(),
+        PipelineStep::FilterLiterals(literals) => literals.memory(),
+        PipelineStep::OnlyIn(set) | PipelineStep::NotIn(set) => {
+            set.iter().map(|line| line.len()).sum()
+        }
+        PipelineStep::Route(_, Sink::Pipeline(pipeline))
+        | PipelineStep::TeePipeline(pipeline, _)
+        | PipelineStep::If(_, pipeline)
+        | PipelineStep::Repeat(pipeline, _) => pipeline.get_memory(),
+        PipelineStep::Chunk(chunk) => chunk.memory(),
+        PipelineStep::Align(align) => align.memory(),
+        PipelineStep::Shuffle(shuffle) => shuffle.memory(),
+        PipelineStep::Sort(sort) => sort.memory(),
+        PipelineStep::CountDistinct(distinct) => distinct.memory(),
+        PipelineStep::DedupeCount(counts) => counts.memory(),
+        PipelineStep::Diff(diff) => diff.memory(),
+        PipelineStep::PerWindow(window) => window.memory(),
+        PipelineStep::Top(top) => top.memory(),
+        PipelineStep::TopBy(top) => top.memory(),
+        PipelineStep::GroupBy(group) => group.memory(),
+        PipelineStep::Stats(stats) => stats.memory(),
+        PipelineStep::PerKey(per_key) => per_key.memory(),
+        PipelineStep::Custom(step) => step.memory(),
+        _ => 0,
+    }
+}
+
+impl PartialEq for PipelineStep {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::RouteByKey(left), Self::RouteByKey(right)) => left == right,
+            #[cfg(feature = "script")]
+            (Self::Script(left), Self::Script(right)) => left == right,
+            (Self::Filter(left_regex), Self::Filter(right_regex))
+            | (Self::SplitRecords(left_regex), Self::SplitRecords(right_regex)) => {
+                left_regex.as_str() == right_regex.as_str()
+            }
+            (Self::FilterSet(left_set), Self::FilterSet(right_set))
+            | (Self::FilterAny(left_set), Self::FilterAny(right_set)) => {
+                left_set.patterns() == right_set.patterns()
+            }
+            (Self::FilterLiteral(left_finder), Self::FilterLiteral(right_finder)) => {
+                left_finder.needle() == right_finder.needle()
+            }
+            (Self::FilterLiterals(left), Self::FilterLiterals(right)) => left == right,
+            #[cfg(feature = "fancy")]
+            (Self::FilterFancy(left), Self::FilterFancy(right)) => left.as_str() == right.as_str(),
+            (Self::Fused(left_steps), Self::Fused(right_steps)) => left_steps == right_steps,
+            (
+                Self::DedupeBy(left_transforms, left_step),
+                Self::DedupeBy(right_transforms, right_step),
+            ) => left_transforms == right_transforms && left_step == right_step,
+            (Self::Append(left_suffix), Self::Append(right_suffix)) => left_suffix == right_suffix,
+            (Self::WhereField(left), Self::WhereField(right)) => left == right,
+            (Self::Every(left_every, left_text, _), Self::Every(right_every, right_text, _)) => {
+                left_every == right_every && left_text == right_text
+            }
+            (Self::StripHtml(left_squeeze), Self::StripHtml(right_squeeze)) => {
+                left_squeeze == right_squeeze
+            }
+            (
+                Self::TrimChars(left_set, left_start, left_end),
+                Self::TrimChars(right_set, right_start, right_end),
+            ) => left_set == right_set && left_start == right_start && left_end == right_end,
+            (Self::PadLeft(left_width, left_fill), Self::PadLeft(right_width, right_fill))
+            | (Self::PadRight(left_width, left_fill), Self::PadRight(right_width, right_fill)) => {
+                left_width == right_width && left_fill == right_fill
+            }
+            (
+                Self::Timestamp(left_stamp, left_separator),
+                Self::Timestamp(right_stamp, right_separator),
+            ) => left_stamp == right_stamp && left_separator == right_separator,
+            (
+                Self::Id(left_identifier, left_separator, left_append),
+                Self::Id(right_identifier, right_separator, right_append),
+            ) => {
+                left_identifier == right_identifier
+                    && left_separator == right_separator
+                    && left_append == right_append
+            }
+            (Self::Prepend(left_prefix), Self::Prepend(right_prefix)) => {
+                left_prefix == right_prefix
+            }
+            (
+                Self::Hash(left_algorithm, left_append),
+                Self::Hash(right_algorithm, right_append),
+            ) => left_algorithm == right_algorithm && left_append == right_append,
+            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, collections::HashSet};
+
+    use regex::Regex;
+
+    use super::{ErrorPolicy, Pipeline, PipelineStep};
+    use crate::calc::Calculation;
+    use crate::chunk::{Chunk, ChunkMode};
+    use crate::dedupe::{BloomFilter, LruSet, SpillingSet};
+    use crate::degradation::LossyEvent;
+    use crate::hash::HashAlgorithm;
+    use crate::locale::CaseRules;
+    use crate::sink::Sink;
+    use crate::step::Step;
+
+    #[test]
+    fn build_pipeline_rejects_zero_commands() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec![];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens);
+
+        //+ Assert
+        assert!(pipeline.is_err());
+        assert_eq!(pipeline.err().unwrap(), "No commands specified");
+    }
+
+    #[test]
+    fn build_pipeline_parses_filter_command() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["filter", ".+"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[PipelineStep::Filter(Regex::new(".+").unwrap())],
+        )
+    }
+
+    #[test]
+    fn build_pipeline_rejects_invalid_regex() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["filter", r"\"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens);
+
+        //+ Assert
+        assert!(pipeline.is_err());
+        assert_eq!(pipeline.err().unwrap(), "Invalid regular expression");
+    }
+
+    #[test]
+    fn build_pipeline_parses_append_command() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["append", "foo"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(&pipeline, &[PipelineStep::Append("foo".to_string())])
+    }
+
+    #[test]
+    fn build_pipeline_rejects_missing_suffix() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["append"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens);
+
+        //+ Assert
+        assert!(pipeline.is_err());
+        assert_eq!(pipeline.err().unwrap(), "Missing suffix");
+    }
+
+    #[test]
+    fn build_pipeline_parses_prepend_command() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["prepend", "foo"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(&pipeline, &[PipelineStep::Prepend("foo".to_string())])
+    }
+
+    #[test]
+    fn build_pipeline_rejects_missing_prefix() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["prepend"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens);
+
+        //+ Assert
+        assert!(pipeline.is_err());
+        assert_eq!(pipeline.err().unwrap(), "Missing prefix");
+    }
+
+    #[test]
+    fn build_pipeline_parses_dedupe_command() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["dedupe"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(&pipeline, &[PipelineStep::Dedupe(HashSet::new(), 0)])
+    }
+
+    #[test]
+    fn build_pipeline_parses_lower_command() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["lower"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(&pipeline, &[PipelineStep::Lower])
+    }
+
+    #[test]
+    fn build_pipeline_parses_upper_command() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["upper"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(&pipeline, &[PipelineStep::Upper])
+    }
+
+    #[test]
+    fn build_pipeline_parses_trim_command() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["trim"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(&pipeline, &[PipelineStep::Trim])
+    }
+
+    #[test]
+    fn build_pipeline_parses_multiple_commands() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["lower", "upper", "filter", ".+", "prepend", "hello"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[
+                PipelineStep::Lower,
+                PipelineStep::Upper,
+                PipelineStep::Filter(Regex::new(".+").unwrap()),
+                PipelineStep::Prepend("hello".to_string()),
+            ],
+        )
+    }
+
+    #[test]
+    fn build_pipeline_parses_hash_command() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["hash", "sha256", "hash", "md5", "--append"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[
+                PipelineStep::Hash(HashAlgorithm::Sha256, false),
+                PipelineStep::Hash(HashAlgorithm::Md5, true),
+            ],
+        )
+    }
+
+    #[test]
+    fn build_pipeline_rejects_unknown_hash_algorithm() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["hash", "crc32"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens);
+
+        //+ Assert
+        assert!(pipeline.is_err());
+        assert_eq!(pipeline.err().unwrap(), "Unknown hash algorithm");
+    }
+
+    #[test]
+    fn apply_hash_appends_digest() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["hash", "md5", "--append"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply("abc"),
+            Ok(vec!["abc 900150983cd24fb0d6963f7d28e17f72".into()])
+        );
+    }
+
+    #[test]
+    fn build_pipeline_parses_tag_and_route_commands() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec![
+            "tag", "err", "when", "ERROR", "route", "err", "to", "pipeline", "upper", "end", "trim",
+        ];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[
+                PipelineStep::Tag("err".to_string(), Regex::new("ERROR").unwrap()),
+                PipelineStep::Route(
+                    "err".to_string(),
+                    Sink::Pipeline(Pipeline::build_pipeline(&["upper"]).unwrap()),
+                ),
+                PipelineStep::Trim,
+            ],
+        )
+    }
+
+    #[test]
+    fn build_pipeline_rejects_unterminated_sub_pipeline() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec![
+            "tag", "a", "when", "a", "route", "a", "to", "pipeline", "upper",
+        ];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens);
+
+        //+ Assert
+        assert!(pipeline.is_err());
+        assert_eq!(pipeline.err().unwrap(), "Missing end");
+    }
+
+    #[test]
+    fn apply_route_sends_tagged_lines_to_sink() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "tag", "noise", "when", "DEBUG", "tag", "err", "when", "ERROR", "route", "noise", "to",
+            "drop", "route", "err", "to", "pipeline", "prepend", "! ", "end", "lower",
+        ])
+        .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(pipeline.apply("DEBUG x"), Ok(vec![]));
+        assert_eq!(pipeline.apply("ERROR x"), Ok(vec!["! ERROR x".into()]));
+        assert_eq!(pipeline.apply("INFO x"), Ok(vec!["info x".into()]));
+    }
+
+    #[test]
+    fn build_pipeline_parses_base64_commands() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec![
+            "base64",
+            "encode",
+            "base64",
+            "decode",
+            "--url",
+            "--on-error",
+            "pass",
+        ];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[
+                PipelineStep::Base64Encode(false),
+                PipelineStep::Base64Decode(true, ErrorPolicy::PassThrough),
+            ],
+        )
+    }
+
+    #[test]
+    fn build_pipeline_rejects_invalid_error_policy() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["base64", "decode", "--on-error", "ignore"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens);
+
+        //+ Assert
+        assert!(pipeline.is_err());
+        assert_eq!(pipeline.err().unwrap(), "Invalid error policy");
+    }
+
+    #[test]
+    fn apply_base64_decode_honors_error_policy() {
+        //+ Arrange
+        let mut skip = Pipeline::build_pipeline(&["base64", "decode"]).unwrap();
+        let mut pass =
+            Pipeline::build_pipeline(&["base64", "decode", "--on-error", "pass"]).unwrap();
+        let mut error =
+            Pipeline::build_pipeline(&["base64", "decode", "--on-error", "error"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(skip.apply("Zm9v"), Ok(vec!["foo".into()]));
+        assert_eq!(skip.apply("!!"), Ok(vec![]));
+        assert_eq!(pass.apply("!!"), Ok(vec!["!!".into()]));
+        assert_eq!(error.apply("!!"), Err("Invalid base64 input on line 1: !!"));
+    }
+
+    #[test]
+    fn apply_urldecode_normalizes_before_dedupe() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["urldecode", "dedupe", "urlencode"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(pipeline.apply("q=a+b"), Ok(vec!["q%3Da%20b".into()]));
+        assert_eq!(pipeline.apply("q%3Da%20b"), Ok(vec![]));
+        assert_eq!(pipeline.apply("q=%zz"), Ok(vec![]));
+    }
+
+    #[test]
+    fn build_pipeline_parses_calc_command() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec![
+            "calc",
+            "f3 = f1 * 2",
+            "--delimiter",
+            ",",
+            "--on-error",
+            "pass",
+        ];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[PipelineStep::Calc(
+                Calculation::parse("f3 = f1 * 2").unwrap(),
+                Some(",".to_string()),
+                ErrorPolicy::PassThrough,
+            )],
+        )
+    }
+
+    #[test]
+    fn apply_calc_skips_non_numeric_lines() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["calc", "{1} * 1000"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(pipeline.apply("0.25 s"), Ok(vec!["250".into()]));
+        assert_eq!(pipeline.apply("n/a"), Ok(vec![]));
+    }
+
+    #[test]
+    fn apply_where_and_map_evaluate_expressions() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "where",
+            "field(1) > 2 && line contains 'ERROR'",
+            "map",
+            "upper(field(2, ','))",
+        ])
+        .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(pipeline.apply("3 a,error"), Ok(vec![]));
+        assert_eq!(pipeline.apply("3 ERROR,x"), Ok(vec!["X".into()]));
+        assert_eq!(pipeline.apply("1 ERROR,x"), Ok(vec![]));
+        assert_eq!(
+            Pipeline::build_pipeline(&["where", "len >"]).err().unwrap(),
+            "Unexpected end in expression:\n    len >\n         ^"
+        );
+    }
+
+    #[test]
+    fn apply_tee_writes_intermediate_lines() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-tee-{}.log", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut pipeline =
+            Pipeline::build_pipeline(&["filter", "a", "tee", path, "dedupe", "upper"]).unwrap();
+
+        //+ Act
+        let outputs = ["a", "b", "a"].map(|line| pipeline.apply(line));
+        pipeline.finish().unwrap();
+
+        //+ Assert
+        assert_eq!(outputs, [Ok(vec!["A".into()]), Ok(vec![]), Ok(vec![])]);
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "a\na\n");
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn apply_tee_pipeline_sends_branch_output_to_its_sink() {
+        //+ Arrange
+        let path =
+            std::env::temp_dir().join(format!("rangler-tee-pipeline-{}.log", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "tee-pipeline",
+            path,
+            "filter",
+            "ERROR",
+            "dedupe",
+            "align",
+            " ",
+            "end",
+            "lower",
+        ])
+        .unwrap();
+
+        //+ Act
+        let mut outputs = vec![];
+        for line in ["ERROR a", "INFO b", "ERROR a", "ERROR bc"] {
+            outputs.extend(
+                pipeline
+                    .apply(line)
+                    .unwrap()
+                    .into_iter()
+                    .map(Cow::into_owned),
+            );
+        }
+        outputs.extend(pipeline.finish().unwrap());
+
+        //+ Assert
+        assert_eq!(outputs, ["error a", "info b", "error a", "error bc"]);
+        assert_eq!(
+            std::fs::read_to_string(path).unwrap(),
+            "ERROR  a\nERROR  bc\n"
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn apply_if_runs_steps_only_on_matching_lines() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "if", "WARN", "then", "dedupe", "upper", "end", "prepend", "> ",
+        ])
+        .unwrap();
+
+        //+ Act
+        let outputs = ["WARN a", "info b", "WARN a", "info b"].map(|line| pipeline.apply(line));
+
+        //+ Assert
+        assert_eq!(
+            outputs,
+            [
+                Ok(vec!["> WARN A".into()]),
+                Ok(vec!["> info b".into()]),
+                Ok(vec![]),
+                Ok(vec!["> info b".into()]),
+            ]
+        );
+        assert!(Pipeline::build_pipeline(&["if", "WARN", "upper", "end"]).is_err());
+    }
+
+    #[test]
+    fn apply_repeat_until_stable_reapplies_steps_until_the_line_settles() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "repeat-until-stable",
+            "map",
+            "replace(line, 'aa', 'a')",
+            "end",
+        ])
+        .unwrap();
+        let mut capped =
+            Pipeline::build_pipeline(&["repeat-until-stable", "--max", "2", "append", "!", "end"])
+                .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(pipeline.apply("aaaaaaaa b"), Ok(vec!["a b".into()]));
+        assert_eq!(capped.apply("hi"), Ok(vec!["hi!!".into()]));
+        assert!(Pipeline::build_pipeline(&["repeat-until-stable", "dedupe", "end"]).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn apply_exec_honors_error_policy() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["exec", "grep -v bad", "--on-error", "pass", "upper"])
+                .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(pipeline.apply("good"), Ok(vec!["GOOD".into()]));
+        assert_eq!(pipeline.apply("bad"), Ok(vec!["BAD".into()]));
+    }
+
+    #[test]
+    fn build_pipeline_rejects_invalid_template() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["format", "{line"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens);
+
+        //+ Assert
+        assert!(pipeline.is_err());
+        assert_eq!(pipeline.err().unwrap(), "Unterminated placeholder");
+    }
+
+    #[test]
+    fn apply_format_uses_preceding_captures() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["filter", r"(\d+)", "format", "count={1} raw={line} n={n}"])
+                .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(pipeline.apply("no digits"), Ok(vec![]));
+        assert_eq!(
+            pipeline.apply("got 42 items"),
+            Ok(vec!["count=42 raw=got 42 items n=2".into()])
+        );
+    }
+
+    #[test]
+    fn apply_dateparse_rewrites_timestamp_in_place() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "dateparse",
+            "%d/%b/%Y:%H:%M:%S %z",
+            "iso",
+            "--on-error",
+            "pass",
+        ])
+        .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply("[10/Oct/2000:13:55:36 -0700] GET /"),
+            Ok(vec!["[2000-10-10T20:55:36Z] GET /".into()])
+        );
+        assert_eq!(pipeline.apply("no date"), Ok(vec!["no date".into()]));
+    }
+
+    #[test]
+    fn build_pipeline_parses_humanize_epoch_command() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec![
+            "humanize-epoch",
+            "--format",
+            "%F",
+            "humanize-epoch",
+            "--relative",
+        ];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[
+                PipelineStep::HumanizeEpoch("%F".to_string(), false),
+                PipelineStep::HumanizeEpoch("iso".to_string(), true),
+            ],
+        )
+    }
+
+    #[test]
+    fn build_pipeline_rejects_invalid_dedupe_capacity() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["dedupe", "--recent", "0"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens);
+
+        //+ Assert
+        assert!(pipeline.is_err());
+        assert_eq!(pipeline.err().unwrap(), "Invalid dedupe capacity");
+    }
+
+    #[test]
+    fn apply_dedupe_recent_forgets_old_lines() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["dedupe", "--recent", "1"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(pipeline.apply("a"), Ok(vec!["a".into()]));
+        assert_eq!(pipeline.apply("a"), Ok(vec![]));
+        assert_eq!(pipeline.apply("b"), Ok(vec!["b".into()]));
+        assert_eq!(pipeline.apply("a"), Ok(vec!["a".into()]));
+    }
+
+    #[test]
+    fn build_pipeline_parses_approximate_dedupe() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["dedupe", "--approx", "1000", "0.001"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[PipelineStep::DedupeApprox(BloomFilter::new(1000, 0.001))],
+        )
+    }
+
+    #[test]
+    fn build_pipeline_rejects_invalid_false_positive_rate() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["dedupe", "--approx", "1000", "1.5"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens);
+
+        //+ Assert
+        assert!(pipeline.is_err());
+        assert_eq!(pipeline.err().unwrap(), "Invalid false positive rate");
+    }
+
+    #[test]
+    fn apply_approximate_dedupe_drops_repeats_without_degrading() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["dedupe", "--approx", "100", "0.01"]).unwrap();
+
+        //+ Act
+        let outputs = ["a", "b", "a"].map(|line| pipeline.apply(line));
+
+        //+ Assert
+        assert_eq!(
+            outputs,
+            [Ok(vec!["a".into()]), Ok(vec!["b".into()]), Ok(vec![])]
+        );
+        // A repeat dropped is not a degradation, and which drops were
+        // false positives cannot be told apart from it.
+        assert!(pipeline.lossy_events().is_empty());
+    }
+
+    #[test]
+    fn build_pipeline_parses_spilling_dedupe() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["dedupe", "--spill", "64MiB"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[PipelineStep::DedupeSpill(SpillingSet::new(64 << 20))],
+        )
+    }
+
+    #[test]
+    fn spill_dedupes_shares_budget_between_plain_dedupes() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["dedupe", "lower", "dedupe", "--recent", "5", "dedupe"];
+        let mut pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Act
+        pipeline.spill_dedupes(1 << 20);
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[
+                PipelineStep::DedupeSpill(SpillingSet::new(512 << 10)),
+                PipelineStep::Lower,
+                PipelineStep::DedupeRecent(LruSet::new(5)),
+                PipelineStep::DedupeSpill(SpillingSet::new(512 << 10)),
+            ],
+        )
+    }
+
+    #[test]
+    fn spill_dedupes_stays_exact_for_multiline_records() -> Result<(), String> {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["dedupe"])?;
+        pipeline.spill_dedupes(1);
+
+        //+ Act
+        let mut kept = vec![];
+        for record in ["a\nb", "c", "d", "e", "a\nb", "c"] {
+            kept.extend(pipeline.apply(record)?);
+        }
+
+        //+ Assert
+        assert_eq!(kept, ["a\nb", "c", "d", "e"]);
+        Ok(())
+    }
+
+    #[test]
+    fn build_pipeline_reads_regex_flags_before_patterns() {
+        //+ Arrange
+        let mut flagged = Pipeline::build_pipeline(&["filter", "-s", "-i", "^error.x$"]).unwrap();
+        let mut ignoring_case =
+            Pipeline::build_pipeline(&["filter", "error", "filter", "(?-i)Disk"]).unwrap();
+        ignoring_case.set_ignore_case();
+
+        //+ Act + Assert
+        assert_eq!(flagged.apply("ERROR\nx"), Ok(vec!["ERROR\nx".into()]));
+        assert_eq!(flagged.apply("error"), Ok(vec![]));
+        assert_eq!(
+            ignoring_case.apply("ERROR Disk"),
+            Ok(vec!["ERROR Disk".into()])
+        );
+        assert_eq!(ignoring_case.apply("ERROR disk"), Ok(vec![]));
+        assert!(Pipeline::build_pipeline(&["filter", "-i"]).is_ok());
+    }
+
+    #[test]
+    fn apply_filter_any_and_all_match_pattern_sets() {
+        //+ Arrange
+        let mut any =
+            Pipeline::build_pipeline(&["filter-any", "-i", "error", "warn", "end"]).unwrap();
+        let mut all = Pipeline::build_pipeline(&["filter-all", "disk", "full", "end"]).unwrap();
+
+        //+ Act
+        let kept_by_any = ["ERROR x", "Warn y", "info z"].map(|line| any.apply(line));
+        let kept_by_all = ["disk full", "disk ok"].map(|line| all.apply(line));
+
+        //+ Assert
+        assert_eq!(
+            kept_by_any,
+            [
+                Ok(vec!["ERROR x".into()]),
+                Ok(vec!["Warn y".into()]),
+                Ok(vec![])
+            ]
+        );
+        assert_eq!(kept_by_all, [Ok(vec!["disk full".into()]), Ok(vec![])]);
+        assert!(Pipeline::build_pipeline(&["filter-any", "a", "("]).is_err());
+    }
+
+    #[test]
+    fn apply_filter_with_fixed_string_matches_it_literally() {
+        //+ Arrange
+        let mut literal = Pipeline::build_pipeline(&["filter", "-F", "a.b(", "upper"]).unwrap();
+        let mut ignoring_case = Pipeline::build_pipeline(&["filter", "-F", "-i", "A.B("]).unwrap();
+
+        //+ Act
+        let outputs = ["x a.b( y", "x aXb( y"].map(|line| literal.apply(line));
+        let bytes = literal.apply_bytes(b"\xff a.b(");
+
+        //+ Assert
+        assert_steps(
+            &literal,
+            &[
+                PipelineStep::FilterLiteral(memchr::memmem::Finder::new("a.b(").into_owned()),
+                PipelineStep::Upper,
+            ],
+        )
+        .unwrap();
+        assert_eq!(outputs, [Ok(vec!["X A.B( Y".into()]), Ok(vec![])]);
+        assert_eq!(bytes, Err("Invalid UTF-8 input"));
+        assert_eq!(ignoring_case.apply("a.b("), Ok(vec!["a.b(".into()]));
+    }
+
+    #[test]
+    fn apply_filter_only_matching_emits_every_match() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["filter", "-o", "id=\\d+", "upper"]).unwrap();
+
+        //+ Act
+        let outputs = ["id=1 and id=22", "no ids", "id=3"].map(|line| pipeline.apply(line));
+
+        //+ Assert
+        assert_eq!(
+            outputs,
+            [
+                Ok(vec!["ID=1".into(), "ID=22".into()]),
+                Ok(vec![]),
+                Ok(vec!["ID=3".into()]),
+            ]
+        );
+        assert!(Pipeline::build_pipeline(&["tag", "x", "when", "-o", "a"]).is_err());
+    }
+
+    #[test]
+    fn count_distinct_emits_only_the_count_at_the_end() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["lower", "count-distinct"]).unwrap();
+        let mut approximate = Pipeline::build_pipeline(&["count-distinct", "--approx"]).unwrap();
+
+        //+ Act
+        let outputs = ["a", "A", "b"].map(|line| pipeline.apply(line));
+        approximate.apply("a").unwrap();
+
+        //+ Assert
+        assert_eq!(outputs, [Ok(vec![]), Ok(vec![]), Ok(vec![])]);
+        assert_eq!(pipeline.finish(), Ok(vec!["2".to_string()]));
+        assert_eq!(pipeline.lossy_events(), []);
+        assert_eq!(approximate.finish(), Ok(vec!["1".to_string()]));
+        assert_eq!(
+            approximate.lossy_events(),
+            [(LossyEvent::ApproximateCount, 1)]
+        );
+    }
+
+    #[test]
+    fn sort_orders_by_key_at_the_end() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["sort", "--key", "rt=(\\S+)", "--numeric", "--reverse"];
+        let mut pipeline = Pipeline::build_pipeline(&tokens).unwrap();
+
+        //+ Act
+        let outputs = ["GET / rt=0.25", "GET /a rt=1.5", "GET /b"].map(|line| pipeline.apply(line));
+
+        //+ Assert
+        assert_eq!(outputs, [Ok(vec![]), Ok(vec![]), Ok(vec![])]);
+        assert_eq!(
+            pipeline.finish(),
+            Ok(vec![
+                "GET /a rt=1.5".to_string(),
+                "GET / rt=0.25".to_string(),
+                "GET /b".to_string(),
+            ])
+        );
+        assert!(Pipeline::build_pipeline(&["sort", "--key", "0"]).is_err());
+        assert!(Pipeline::build_pipeline(&["sort", "--numeric", "--natural"]).is_err());
+    }
+
+    #[test]
+    fn top_by_emits_the_slowest_lines_at_the_end() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["top-by", "1", "2"]).unwrap();
+
+        //+ Act
+        let outputs = ["/a 30ms", "/b 250ms"].map(|line| pipeline.apply(line));
+
+        //+ Assert
+        assert_eq!(outputs, [Ok(vec![]), Ok(vec![])]);
+        assert_eq!(pipeline.finish(), Ok(vec!["/b 250ms".to_string()]));
+        assert!(Pipeline::build_pipeline(&["top-by", "0", "2"]).is_err());
+    }
+
+    #[test]
+    fn stats_emits_percentiles_at_the_end() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["stats", "p50,max", "--key", "2"]).unwrap();
+
+        //+ Act
+        let outputs = ["/a 30ms", "/b 10ms", "/c 20ms"].map(|line| pipeline.apply(line));
+
+        //+ Assert
+        assert_eq!(outputs, [Ok(vec![]), Ok(vec![]), Ok(vec![])]);
+        assert_eq!(pipeline.finish(), Ok(vec!["p50=20 max=30".to_string()]));
+        assert_eq!(pipeline.lossy_events(), []);
+        assert!(Pipeline::build_pipeline(&["stats", "p50,p200"]).is_err());
+    }
+
+    #[test]
+    fn cols_cuts_fixed_width_fields() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["cols", "1-4,10-", "--delimiter", " "];
+        let mut pipeline = Pipeline::build_pipeline(&tokens).unwrap();
+
+        //+ Act
+        let output = pipeline.apply("root     1234 sshd");
+
+        //+ Assert
+        assert_eq!(output, Ok(vec!["root 1234 sshd".into()]));
+        assert!(pipeline.is_stateless());
+        assert!(Pipeline::build_pipeline(&["cols"]).is_err());
+    }
+
+    #[test]
+    fn dedupe_key_transform_keeps_the_first_original_line() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["dedupe", "--key-transform", "lower,trim"];
+        let mut pipeline = Pipeline::build_pipeline(&tokens).unwrap();
+        let mut recent =
+            Pipeline::build_pipeline(&["dedupe", "--recent", "1", "--key-transform", "upper"])
+                .unwrap();
+
+        //+ Act
+        let outputs = ["Alice", " alice ", "ALICE", "Bob"].map(|line| pipeline.apply(line));
+        let recent_outputs = ["a", "A", "b", "a"].map(|line| recent.apply(line));
+
+        //+ Assert
+        assert_eq!(
+            outputs,
+            [
+                Ok(vec!["Alice".into()]),
+                Ok(vec![]),
+                Ok(vec![]),
+                Ok(vec!["Bob".into()])
+            ]
+        );
+        assert_eq!(
+            recent_outputs,
+            [
+                Ok(vec!["a".into()]),
+                Ok(vec![]),
+                Ok(vec!["b".into()]),
+                Ok(vec!["a".into()])
+            ]
+        );
+        assert!(Pipeline::build_pipeline(&["dedupe", "--key-transform", "sort"]).is_err());
+    }
+
+    #[test]
+    fn dedupe_window_lets_repeats_back_after_a_quiet_stretch() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["dedupe", "--window", "2"]).unwrap();
+
+        //+ Act
+        let outputs = ["disk full", "disk full", "ok", "ok", "disk full"]
+            .map(|line| pipeline.apply(line).unwrap().len());
+
+        //+ Assert
+        assert_eq!(outputs, [1, 0, 1, 0, 1]);
+        assert!(Pipeline::build_pipeline(&["dedupe", "--window", "0"]).is_err());
+    }
+
+    #[test]
+    fn trim_variants_trim_one_side_or_a_character_set() {
+        //+ Arrange
+        let mut left = Pipeline::build_pipeline(&["ltrim", "append", "|"]).unwrap();
+        let mut right = Pipeline::build_pipeline(&["rtrim", "append", "|"]).unwrap();
+        let mut quotes = Pipeline::build_pipeline(&["trim-chars", "\"[]"]).unwrap();
+        let mut commas = Pipeline::build_pipeline(&["trim-chars", ",", "--right"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(left.apply("  a  "), Ok(vec!["a  |".into()]));
+        assert_eq!(right.apply("  a  "), Ok(vec!["  a|".into()]));
+        assert_eq!(quotes.apply("[\"x\"]"), Ok(vec!["x".into()]));
+        assert_eq!(commas.apply(",a,b,,"), Ok(vec![",a,b".into()]));
+        assert!(Pipeline::build_pipeline(&["trim-chars"]).is_err());
+    }
+
+    #[test]
+    fn pad_steps_fill_short_lines_to_the_width() {
+        //+ Arrange
+        let mut left = Pipeline::build_pipeline(&["pad-left", "5", "0"]).unwrap();
+        let mut right = Pipeline::build_pipeline(&["pad-right", "4", ".", "append", "|"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(left.apply("42"), Ok(vec!["00042".into()]));
+        assert_eq!(left.apply("123456"), Ok(vec!["123456".into()]));
+        assert_eq!(right.apply("né"), Ok(vec!["né..|".into()]));
+        assert!(Pipeline::build_pipeline(&["pad-left", "5", "ab"]).is_err());
+        assert!(Pipeline::build_pipeline(&["pad-right", "-1", " "]).is_err());
+        assert!(Pipeline::build_pipeline(&["pad-left", "18446744073709551615", "x"]).is_err());
+    }
+
+    #[test]
+    fn timestamp_prefixes_lines_with_the_time() {
+        //+ Arrange
+        let mut clock = Pipeline::build_pipeline(&["timestamp", "--format", "[%Y]"]).unwrap();
+        let mut elapsed =
+            Pipeline::build_pipeline(&["timestamp", "--elapsed", "--separator", "|"]).unwrap();
+
+        //+ Act
+        let stamped = clock.apply("started").unwrap();
+        let timed = elapsed.apply("started").unwrap();
+
+        //+ Assert
+        assert!(stamped[0].starts_with('[') && stamped[0].ends_with("] started"));
+        assert!(timed[0].starts_with("0.00") && timed[0].ends_with("|started"));
+        assert!(Pipeline::build_pipeline(&["timestamp", "--format", "%F", "--elapsed"]).is_err());
+    }
+
+    #[test]
+    fn append_and_prepend_fill_in_where_lines_came_from() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["prepend", "{file}:{n}: ", "append", " {x}"]).unwrap();
+        pipeline.set_source("app.log");
+
+        //+ Act
+        let first = pipeline.apply("started").unwrap();
+        pipeline.set_source("worker.log");
+        let second = pipeline.apply("stopped").unwrap();
+
+        //+ Assert
+        assert_eq!(first, vec![Cow::from("app.log:1: started {x}")]);
+        assert_eq!(second, vec![Cow::from("worker.log:2: stopped {x}")]);
+        assert!(pipeline.uses_source());
+        assert!(!Pipeline::build_pipeline(&["append", "{n}"])
+            .unwrap()
+            .uses_source());
+    }
+
+    #[test]
+    fn apply_filter_literals_keeps_lines_containing_any_literal() {
+        //+ Arrange
+        let path =
+            std::env::temp_dir().join(format!("rangler-indicators-{}.txt", std::process::id()));
+        std::fs::write(&path, "evil.example\n10.6.6.6\n").unwrap();
+        let path = path.to_str().unwrap();
+        let mut pipeline = Pipeline::build_pipeline(&["filter-literals", path]).unwrap();
+        let mut ignoring_case = Pipeline::build_pipeline(&["filter-literals", "-i", path]).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply("query evil.example from 10.0.0.1"),
+            Ok(vec!["query evil.example from 10.0.0.1".into()])
+        );
+        assert_eq!(
+            pipeline.apply("connect 10.6.6.6:443"),
+            Ok(vec!["connect 10.6.6.6:443".into()])
+        );
+        assert_eq!(pipeline.apply("query EVIL.example"), Ok(vec![]));
+        assert_eq!(
+            ignoring_case.apply("query EVIL.example"),
+            Ok(vec!["query EVIL.example".into()])
+        );
+        assert!(
+            Pipeline::build_pipeline(&["filter-literals", "/nonexistent/indicators.txt"]).is_err()
+        );
+    }
+
+    #[cfg(feature = "fancy")]
+    #[test]
+    fn apply_filter_falls_back_to_fancy_regex_for_lookaround() {
+        //+ Arrange
+        let mut lookahead = Pipeline::build_pipeline(&["filter", r"user=(?!root\b)\w+"]).unwrap();
+        let mut repeated =
+            Pipeline::build_pipeline(&["filter", "-i", r"\b(\w+) \1\b", "format", "{1}"]).unwrap();
+        let plain = Pipeline::build_pipeline(&["filter", "--fancy", "error"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            lookahead.apply("login user=alice"),
+            Ok(vec!["login user=alice".into()])
+        );
+        assert_eq!(lookahead.apply("login user=root"), Ok(vec![]));
+        assert_eq!(repeated.apply("so the the cat"), Ok(vec!["the".into()]));
+        assert_eq!(repeated.apply("the cat"), Ok(vec![]));
+        assert!(plain.steps[0].is_fancy_filter());
+    }
+
+    #[test]
+    fn apply_only_duplicates_keeps_lines_seen_before() {
+        //+ Arrange
+        let lines = ["id=1", "id=2", "id=1", "id=1", "id=2"];
+        let mut every = Pipeline::build_pipeline(&["only-duplicates"]).unwrap();
+        let mut once = Pipeline::build_pipeline(&["only-duplicates", "--once"]).unwrap();
+
+        //+ Act
+        let mut kept = vec![];
+        let mut kept_once = vec![];
+        for line in lines {
+            kept.extend(every.apply(line).unwrap().into_iter().map(String::from));
+            kept_once.extend(once.apply(line).unwrap().into_iter().map(String::from));
+        }
+
+        //+ Assert
+        assert_eq!(kept, ["id=1", "id=1", "id=2"]);
+        assert_eq!(kept_once, ["id=1", "id=2"]);
+    }
+
+    #[test]
+    fn finish_dedupe_count_emits_counts_in_first_seen_order() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["dedupe", "--count"]).unwrap();
+        let mut by_key =
+            Pipeline::build_pipeline(&["dedupe", "--key-transform", "trim", "--count"]).unwrap();
+
+        //+ Act
+        let outputs = ["b", "a", "b", " a"].map(|line| pipeline.apply(line));
+        for line in ["b", "a", "b", " a"] {
+            by_key.apply(line).unwrap();
+        }
+
+        //+ Assert
+        assert_eq!(outputs, [Ok(vec![]), Ok(vec![]), Ok(vec![]), Ok(vec![])]);
+        assert_eq!(
+            pipeline.finish(),
+            Ok(vec![
+                "2 b".to_string(),
+                "1 a".to_string(),
+                "1  a".to_string()
+            ])
+        );
+        assert_eq!(
+            by_key.finish(),
+            Ok(vec!["2 b".to_string(), "2 a".to_string()])
+        );
+        assert!(Pipeline::build_pipeline(&["dedupe", "--recent", "5", "--count"]).is_err());
+        assert_eq!(
+            Pipeline::build_pipeline(&[
+                "dedupe",
+                "--recent",
+                "5",
+                "--key-transform",
+                "trim",
+                "--count"
+            ])
+            .err()
+            .map(|error| error.to_string()),
+            Some("Only exact dedupes can --count".to_string())
+        );
+    }
+
+    #[test]
+    fn apply_records_splits_a_record_into_several() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "json",
+            ".items",
+            "records",
+            r"^\[\{|\},\s*\{|\}\]$",
+            "prepend",
+            "{",
+            "append",
+            "}",
+        ])
+        .unwrap();
+        let mut fixed = Pipeline::build_pipeline(&["records", "-F", "||", "upper"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply(r#"{"items":[{"id":1},{"id":2}]}"#),
+            Ok(vec![r#"{"id":1}"#.into(), r#"{"id":2}"#.into()])
+        );
+        assert_eq!(
+            fixed.apply("a||b||||c"),
+            Ok(vec!["A".into(), "B".into(), "C".into()])
+        );
+        assert_eq!(fixed.apply("||"), Ok(vec![]));
+        assert_eq!(fixed.apply("one"), Ok(vec!["ONE".into()]));
+    }
+
+    #[test]
+    fn set_case_rules_swaps_lower_and_upper_here_and_in_branches() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["upper", "if", "X", "then", "lower", "end"]).unwrap();
+
+        //+ Act
+        pipeline.set_case_rules(CaseRules::Turkic);
+
+        //+ Assert
+        assert_eq!(pipeline.apply("istanbul"), Ok(vec!["İSTANBUL".into()]));
+        assert_eq!(pipeline.apply("IXI"), Ok(vec!["ıxı".into()]));
+    }
+
+    #[test]
+    fn build_pipeline_parses_length_commands() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["minlen", "2", "maxlen", "10", "--bytes"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[
+                PipelineStep::MinLength(2, false),
+                PipelineStep::MaxLength(10, true),
+            ],
+        )
+    }
+
+    #[test]
+    fn apply_length_filters_count_characters_or_bytes() {
+        //+ Arrange
+        let mut characters = Pipeline::build_pipeline(&["minlen", "2", "maxlen", "3"]).unwrap();
+        let mut bytes = Pipeline::build_pipeline(&["maxlen", "3", "--bytes"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(characters.apply("a"), Ok(vec![]));
+        assert_eq!(characters.apply("héé"), Ok(vec!["héé".into()]));
+        assert_eq!(characters.apply("abcd"), Ok(vec![]));
+        assert_eq!(bytes.apply("héé"), Ok(vec![]));
+    }
+
+    #[test]
+    fn build_pipeline_rejects_invalid_rate() {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["throttle", "0"];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens);
+
+        //+ Assert
+        assert!(pipeline.is_err());
+        assert_eq!(pipeline.err().unwrap(), "Invalid rate");
+    }
+
+    #[test]
+    fn build_pipeline_parses_chunk_command() -> Result<(), String> {
+        //+ Arrange
+        let tokens: Vec<&str> = vec!["chunk", "3", "chunk", "2", "--join", ","];
+
+        //+ Act
+        let pipeline = Pipeline::build_pipeline(&tokens)?;
+
+        //+ Assert
+        assert_steps(
+            &pipeline,
+            &[
+                PipelineStep::Chunk(Chunk::new(3, ChunkMode::Separator(String::new()))),
+                PipelineStep::Chunk(Chunk::new(2, ChunkMode::Join(",".to_string()))),
+            ],
+        )
+    }
+
+    #[test]
+    fn finish_flushes_buffered_lines_through_later_steps() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "chunk",
+            "2",
+            "--join",
+            " ",
+            "upper",
+            "chunk",
+            "2",
+            "--separator",
+            "--",
+        ])
+        .unwrap();
+
+        //+ Act
+        let mut lines = vec![];
+        for line in ["a", "b", "c", "d", "e"] {
+            lines.extend(
+                pipeline
+                    .apply(line)
+                    .unwrap()
+                    .into_iter()
+                    .map(Cow::into_owned),
+            );
+        }
+        lines.extend(pipeline.finish().unwrap());
+
+        //+ Assert
+        assert_eq!(lines, vec!["A B", "C D", "--", "E"]);
+    }
+
+    #[test]
+    fn finish_releases_aligned_columns() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["align", " ", "prepend", "> "]).unwrap();
+
+        //+ Act
+        let during = pipeline.apply("a bb").unwrap();
+        pipeline.apply("ccc    d").unwrap();
+        let after = pipeline.finish().unwrap();
+
+        //+ Assert
+        assert!(during.is_empty());
+        assert_eq!(after, vec!["> a    bb", "> ccc  d"]);
+    }
+
+    #[test]
+    fn apply_csv_steps_filter_then_project() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["csv-where", "status=error", "csv-select", "message,id"])
+                .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply("id,status,message"),
+            Ok(vec!["message,id".into()])
+        );
+        assert_eq!(pipeline.apply(r#"1,ok,"fine, really""#), Ok(vec![]));
+        assert_eq!(
+            pipeline.apply(r#"2,error,"disk full, again""#),
+            Ok(vec![r#""disk full, again",2"#.into()])
+        );
+    }
+
+    #[test]
+    fn apply_json_honors_error_policy() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["json", ".level", "--on-error", "pass", "upper"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply(r#"{"level":"warn"}"#),
+            Ok(vec!["WARN".into()])
+        );
+        assert_eq!(pipeline.apply("plain text"), Ok(vec!["PLAIN TEXT".into()]));
+    }
+
+    #[test]
+    fn apply_json_filter_keeps_matching_lines() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["json-filter", ".status", ">=500", "json", ".path"])
+                .unwrap();
 
-        loop {
-            if tokens.len() == 0 {
-                break;
-            }
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply(r#"{"status":502,"path":"/api"}"#),
+            Ok(vec!["/api".into()])
+        );
+        assert_eq!(pipeline.apply(r#"{"status":200,"path":"/"}"#), Ok(vec![]));
+    }
 
-            let command = tokens[0].as_ref();
-            let argument = tokens.get(1).map(|r| r.as_ref());
+    #[test]
+    fn apply_csv_round_trips_through_jsonl() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["csv2jsonl", "jsonl2csv"]).unwrap();
 
-            let step = match command.to_lowercase().as_str() {
-                "filter" => {
-                    tokens = &tokens[1..];
-                    let regex = Regex::new(argument.ok_or("Missing regular expression")?)
-                        .map_err(|_| "Invalid regular expression")?;
+        //+ Act + Assert
+        assert_eq!(pipeline.apply("id,name"), Ok(vec![]));
+        assert_eq!(
+            pipeline.apply(r#"1,"a, b""#),
+            Ok(vec!["id,name".into(), r#"1,"a, b""#.into()])
+        );
+        assert_eq!(pipeline.apply("2,c"), Ok(vec!["2,c".into()]));
+    }
 
-                    PipelineStep::Filter(regex)
-                }
-                "lower" => PipelineStep::Lower,
-                "upper" => PipelineStep::Upper,
-                "trim" => PipelineStep::Trim,
-                "dedupe" => PipelineStep::Dedupe(HashSet::new(), 0),
-                "append" => {
-                    tokens = &tokens[1..];
-                    PipelineStep::Append(argument.ok_or("Missing suffix")?.to_string())
-                }
-                "prepend" => {
-                    tokens = &tokens[1..];
-                    PipelineStep::Prepend(argument.ok_or("Missing prefix")?.to_string())
-                }
-                _ => Err("Invalid command specified")?,
-            };
+    #[test]
+    fn apply_kv_filters_then_projects() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["kv", "where", "level=error", "kv", "get", "msg,level"])
+                .unwrap();
 
-            tokens = &tokens[1..];
-            steps.push(step);
-        }
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply(r#"ts=1 level=error msg="disk full""#),
+            Ok(vec![r#"msg="disk full" level=error"#.into()])
+        );
+        assert_eq!(pipeline.apply("ts=2 level=info msg=ok"), Ok(vec![]));
+        assert!(Pipeline::build_pipeline(&["kv", "pick", "a"]).is_err());
+    }
 
-        if steps.len() < 0 {
-            Err("No commands specified")
-        } else {
-            Ok(Pipeline { steps })
-        }
+    #[test]
+    fn apply_syslog_keeps_severe_lines() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "syslog",
+            "where",
+            "severity<=3",
+            "syslog",
+            "get",
+            "host,message",
+        ])
+        .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply("<11>Oct 11 22:14:15 db1 postgres[7]: out of memory"),
+            Ok(vec![r#"host=db1 message="out of memory""#.into()])
+        );
+        assert_eq!(
+            pipeline.apply("<14>Oct 11 22:14:15 db1 postgres[7]: checkpoint"),
+            Ok(vec![])
+        );
+        assert_eq!(pipeline.apply("plain text"), Ok(vec![]));
     }
 
-    pub fn apply(&mut self, line: &str) -> Option<String> {
-        let mut output = line.to_string();
+    #[test]
+    fn apply_accesslog_keeps_server_errors() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "accesslog",
+            "where",
+            "status>=500",
+            "accesslog",
+            "get",
+            "path,status",
+        ])
+        .unwrap();
 
-        for step in self.steps.iter_mut() {
-            output = match step {
-                PipelineStep::Filter(regex) => {
-                    if !regex.is_match(&output) {
-                        return None;
-                    }
+        //+ Act + Assert
+        assert_eq!(
+            pipeline
+                .apply(r#"1.2.3.4 - - [01/May/2024:13:04:05 +0000] "POST /api HTTP/1.1" 502 12"#),
+            Ok(vec!["path=/api status=502".into()])
+        );
+        assert_eq!(
+            pipeline.apply(r#"1.2.3.4 - - [01/May/2024:13:04:05 +0000] "GET / HTTP/1.1" 200 12"#),
+            Ok(vec![])
+        );
+    }
 
-                    output
-                }
-                PipelineStep::Append(suffix) => output + suffix,
-                PipelineStep::Prepend(prefix) => prefix.to_owned() + &output,
-                PipelineStep::Dedupe(ref mut dupes, stored) => {
-                    if dupes.contains(&output) {
-                        return None;
-                    } else {
-                        dupes.insert(output.to_string());
-                        *stored = *stored + output.len();
+    #[test]
+    fn apply_redact_masks_custom_patterns() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "redact",
+            "--only",
+            "ipv4",
+            "--pattern",
+            "secret=(\\S+)",
+            "--pattern",
+            "acct-\\d+",
+        ])
+        .unwrap();
 
-                        output.to_string()
-                    }
-                }
-                PipelineStep::Lower => output.to_lowercase(),
-                PipelineStep::Upper => output.to_uppercase(),
-                PipelineStep::Trim => output.trim().to_string(),
-            }
-        }
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply("10.0.0.1 secret=hunter2 acct-991 bob@example.com"),
+            Ok(vec![
+                "[REDACTED] secret=[REDACTED] [REDACTED] bob@example.com".into()
+            ])
+        );
+    }
+
+    #[test]
+    fn finish_diff_emits_removed_lines_through_later_steps() {
+        //+ Arrange
+        let path =
+            std::env::temp_dir().join(format!("rangler-diff-step-{}.txt", std::process::id()));
+        std::fs::write(&path, "kept\ngone\n").unwrap();
+        let mut pipeline =
+            Pipeline::build_pipeline(&["diff", path.to_str().unwrap(), "upper"]).unwrap();
+
+        //+ Act
+        let outputs = ["new", "kept"].map(|line| pipeline.apply(line));
+        let finished = pipeline.finish();
 
-        Some(output)
+        //+ Assert
+        assert_eq!(outputs, [Ok(vec!["+NEW".into()]), Ok(vec![])]);
+        assert_eq!(finished, Ok(vec!["-GONE".to_string()]));
     }
 
-    pub fn get_memory(&self) -> usize {
-        let mut memory = 0;
-        for step in self.steps.iter() {
-            memory += match step {
-                PipelineStep::Dedupe(_, bytes) => *bytes,
-                _ => 0,
-            }
-        }
+    #[test]
+    fn apply_only_in_and_not_in_use_file_as_set() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-set-{}.txt", std::process::id()));
+        std::fs::write(&path, "alice\nbob\n").unwrap();
+        let path = path.to_str().unwrap();
+        let mut only_in = Pipeline::build_pipeline(&["only-in", path]).unwrap();
+        let mut not_in = Pipeline::build_pipeline(&["not-in", path]).unwrap();
 
-        memory
+        //+ Act + Assert
+        assert_eq!(only_in.apply("bob"), Ok(vec!["bob".into()]));
+        assert_eq!(only_in.apply("carol"), Ok(vec![]));
+        assert_eq!(not_in.apply("bob"), Ok(vec![]));
+        assert_eq!(not_in.apply("carol"), Ok(vec!["carol".into()]));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashSet;
+    #[test]
+    fn apply_lookup_uses_default_on_miss() {
+        //+ Arrange
+        let path =
+            std::env::temp_dir().join(format!("rangler-lookup-step-{}.csv", std::process::id()));
+        std::fs::write(&path, "id,name\n42,alice\n").unwrap();
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "lookup",
+            path.to_str().unwrap(),
+            "--key",
+            "^\\d+",
+            "--annotate",
+            "--on-miss",
+            "default",
+            "unknown",
+        ])
+        .unwrap();
 
-    use regex::Regex;
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply("42 login"),
+            Ok(vec!["42 alice login".into()])
+        );
+        assert_eq!(
+            pipeline.apply("9 login"),
+            Ok(vec!["9 unknown login".into()])
+        );
+    }
+
+    #[test]
+    fn apply_tr_translates_then_deletes() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["tr", "_", " ", "tr", "aeiou", "--delete"]).unwrap();
 
-    use super::{Pipeline, PipelineStep};
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply("snake_case_name"),
+            Ok(vec!["snk cs nm".into()])
+        );
+    }
 
     #[test]
-    fn build_pipeline_rejects_zero_commands() {
+    fn apply_normalize_lets_dedupe_match_equivalent_text() {
         //+ Arrange
-        let tokens: Vec<&str> = vec![];
+        let mut pipeline = Pipeline::build_pipeline(&["normalize", "nfc", "dedupe"]).unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens);
+        let outputs = ["Caf\u{e9}", "Cafe\u{301}"].map(|line| pipeline.apply(line));
 
         //+ Assert
-        assert!(pipeline.is_err());
-        assert_eq!(pipeline.err().unwrap(), "No commands specified");
+        assert_eq!(outputs, [Ok(vec!["Caf\u{e9}".into()]), Ok(vec![])]);
     }
 
     #[test]
-    fn build_pipeline_parses_filter_command() -> Result<(), String> {
+    fn apply_ascii_transliterates() {
         //+ Arrange
-        let tokens: Vec<&str> = vec!["filter", ".+"];
+        let mut pipeline = Pipeline::build_pipeline(&["ascii"]).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply("Jos\u{e9} Stra\u{df}e \u{201c}ok\u{201d}"),
+            Ok(vec!["Jose Strasse \"ok\"".into()])
+        );
+    }
+
+    #[test]
+    fn apply_unescape_html_lets_dedupe_match_escaped_copies() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["unescape-html", "dedupe"]).unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens)?;
+        let first = pipeline.apply("Fish & chips");
+        let escaped = pipeline.apply("Fish &amp; chips");
 
         //+ Assert
-        assert_steps(
-            &pipeline,
-            &[PipelineStep::Filter(Regex::new(".+").unwrap())],
-        )
+        assert_eq!(first, Ok(vec!["Fish & chips".into()]));
+        assert_eq!(escaped, Ok(vec![]));
     }
 
     #[test]
-    fn build_pipeline_rejects_invalid_regex() {
+    fn apply_where_field_compares_a_whitespace_field() {
         //+ Arrange
-        let tokens: Vec<&str> = vec!["filter", r"\"];
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "where-field",
+            " ",
+            "2",
+            ">=",
+            "500",
+            "where-field",
+            " ",
+            "3",
+            "!=",
+            "/health",
+        ])
+        .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            pipeline.apply("GET  503 /orders"),
+            Ok(vec!["GET  503 /orders".into()])
+        );
+        assert_eq!(pipeline.apply("GET 200 /orders"), Ok(vec![]));
+        assert_eq!(pipeline.apply("GET 503 /health"), Ok(vec![]));
+        assert!(Pipeline::build_pipeline(&["where-field", " ", "x", ">", "1"]).is_err());
+    }
+
+    #[test]
+    fn apply_every_inserts_text_after_each_batch() {
+        //+ Arrange
+        let mut pipeline = Pipeline::build_pipeline(&["every", "2", "---", "upper"]).unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens);
+        let first = pipeline.apply("a");
+        let second = pipeline.apply("b");
+        let third = pipeline.apply("c");
 
         //+ Assert
-        assert!(pipeline.is_err());
-        assert_eq!(pipeline.err().unwrap(), "Invalid regular expression");
+        assert_eq!(first, Ok(vec!["A".into()]));
+        assert_eq!(second, Ok(vec!["B".into(), "---".into()]));
+        assert_eq!(third, Ok(vec!["C".into()]));
+        assert!(Pipeline::build_pipeline(&["every", "0", "---"]).is_err());
     }
 
     #[test]
-    fn build_pipeline_parses_append_command() -> Result<(), String> {
+    fn rejected_by_names_the_step_that_dropped_the_line() {
         //+ Arrange
-        let tokens: Vec<&str> = vec!["append", "foo"];
+        let mut pipeline = Pipeline::build_pipeline(&["filter", "^ok", "dedupe", "sort"]).unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens)?;
+        pipeline.apply("fail").unwrap();
+        let filtered = pipeline
+            .rejected_by()
+            .map(|(number, name)| (number, name.to_string()));
+        pipeline.apply("ok").unwrap();
+        let held = pipeline.rejected_by().is_none();
+        pipeline.apply("ok").unwrap();
+        let deduped = pipeline
+            .rejected_by()
+            .map(|(number, name)| (number, name.to_string()));
 
         //+ Assert
-        assert_steps(&pipeline, &[PipelineStep::Append("foo".to_string())])
+        assert_eq!(filtered, Some((1, "filter".to_string())));
+        assert!(held);
+        assert_eq!(deduped, Some((2, "dedupe".to_string())));
     }
 
     #[test]
-    fn build_pipeline_rejects_missing_suffix() {
+    fn apply_uuid_v5_gives_the_same_line_the_same_id() {
         //+ Arrange
-        let tokens: Vec<&str> = vec!["append"];
+        let mut pipeline =
+            Pipeline::build_pipeline(&["uuid", "--v5", "url", "--append", "--separator", ","])
+                .unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens);
+        let first = pipeline.apply("GET /index.html 200");
+        let again = pipeline.apply("GET /index.html 200");
 
         //+ Assert
-        assert!(pipeline.is_err());
-        assert_eq!(pipeline.err().unwrap(), "Missing suffix");
+        assert_eq!(
+            first,
+            Ok(vec![
+                "GET /index.html 200,975865bf-e2f7-55dd-a3cc-f4accf0f3d0d".into()
+            ])
+        );
+        assert_eq!(first, again);
+        assert!(pipeline.is_stateless());
+        assert!(!Pipeline::build_pipeline(&["ulid"]).unwrap().is_stateless());
+        assert!(Pipeline::build_pipeline(&["uuid", "--v5", "example"]).is_err());
     }
 
     #[test]
-    fn build_pipeline_parses_prepend_command() -> Result<(), String> {
+    fn apply_strip_html_leaves_text_for_later_steps() {
         //+ Arrange
-        let tokens: Vec<&str> = vec!["prepend", "foo"];
+        let mut pipeline =
+            Pipeline::build_pipeline(&["strip-html", "--squeeze", "filter", "^Fish"]).unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens)?;
+        let kept = pipeline.apply("<li><a href=\"/menu?fish\">Fish &amp; chips</a></li>");
+        let dropped = pipeline.apply("<p>Chips</p>");
 
         //+ Assert
-        assert_steps(&pipeline, &[PipelineStep::Prepend("foo".to_string())])
+        assert_eq!(kept, Ok(vec!["Fish & chips".into()]));
+        assert_eq!(dropped, Ok(vec![]));
     }
 
     #[test]
-    fn build_pipeline_rejects_missing_prefix() {
+    fn apply_since_and_until_keep_lines_in_range() {
         //+ Arrange
-        let tokens: Vec<&str> = vec!["prepend"];
+        let mut pipeline = Pipeline::build_pipeline(&[
+            "since",
+            "2024-05-01T14:00",
+            "--on-error",
+            "pass",
+            "until",
+            "2024-05-01 17:00:00+02:00",
+            "--format",
+            "%Y-%m-%d %H:%M:%S",
+        ])
+        .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(pipeline.apply("2024-05-01 13:59:59 early"), Ok(vec![]));
+        assert_eq!(
+            pipeline.apply("2024-05-01 14:00:00 start"),
+            Ok(vec!["2024-05-01 14:00:00 start".into()])
+        );
+        assert_eq!(pipeline.apply("2024-05-01 15:00:00 late"), Ok(vec![]));
+        assert_eq!(pipeline.apply("no timestamp"), Ok(vec![]));
+        assert!(Pipeline::build_pipeline(&["since", "yesterday"]).is_err());
+    }
+
+    #[test]
+    fn finish_per_window_emits_counts() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["per-window", "1h", "count", "--format", "clf"]).unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens);
+        let outputs = [
+            "[01/May/2024:13:04:05 +0000] GET /",
+            "[01/May/2024:13:59:59 +0000] GET /",
+            "[01/May/2024:16:30:00 +0200] GET /",
+        ]
+        .map(|line| pipeline.apply(line));
+        let finished = pipeline.finish();
 
         //+ Assert
-        assert!(pipeline.is_err());
-        assert_eq!(pipeline.err().unwrap(), "Missing prefix");
+        assert_eq!(outputs, [Ok(vec![]), Ok(vec![]), Ok(vec![])]);
+        assert_eq!(
+            finished,
+            Ok(vec![
+                "2024-05-01T13:00:00Z 2".to_string(),
+                "2024-05-01T14:00:00Z 1".to_string()
+            ])
+        );
+        assert!(Pipeline::build_pipeline(&["per-window", "1m", "sum"]).is_err());
     }
 
     #[test]
-    fn build_pipeline_parses_dedupe_command() -> Result<(), String> {
+    fn finish_top_emits_most_frequent_lines() {
         //+ Arrange
-        let tokens: Vec<&str> = vec!["dedupe"];
+        let mut pipeline =
+            Pipeline::build_pipeline(&["lower", "top", "2", "prepend", "#"]).unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens)?;
+        let outputs = ["A", "b", "a", "c", "B", "a"].map(|line| pipeline.apply(line));
+        let finished = pipeline.finish();
 
         //+ Assert
-        assert_steps(&pipeline, &[PipelineStep::Dedupe(HashSet::new())])
+        assert!(outputs.iter().all(|output| output == &Ok(vec![])));
+        assert_eq!(finished, Ok(vec!["#3 a".to_string(), "#2 b".to_string()]));
+        assert_eq!(
+            pipeline.lossy_events(),
+            vec![(LossyEvent::ApproximateTop, 0)]
+        );
     }
 
     #[test]
-    fn build_pipeline_parses_lower_command() -> Result<(), String> {
+    fn finish_group_by_sums_per_key() {
         //+ Arrange
-        let tokens: Vec<&str> = vec!["lower"];
+        let mut pipeline =
+            Pipeline::build_pipeline(&["group-by", r"user=(\w+) bytes=(\d+)", "sum"]).unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens)?;
+        for line in ["user=bob bytes=10", "user=amy bytes=5", "user=bob bytes=7"] {
+            assert_eq!(pipeline.apply(line), Ok(vec![]));
+        }
 
         //+ Assert
-        assert_steps(&pipeline, &[PipelineStep::Lower])
+        assert_eq!(
+            pipeline.finish(),
+            Ok(vec!["amy 5".to_string(), "bob 17".to_string()])
+        );
     }
 
     #[test]
-    fn build_pipeline_parses_upper_command() -> Result<(), String> {
+    fn finish_last_per_key_emits_latest_status() {
         //+ Arrange
-        let tokens: Vec<&str> = vec!["upper"];
+        let mut pipeline = Pipeline::build_pipeline(&["last-per-key", r"^(\S+)", "upper"]).unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens)?;
+        let outputs = ["web1 ok", "web2 ok", "web1 down"].map(|line| pipeline.apply(line));
+        let finished = pipeline.finish();
 
         //+ Assert
-        assert_steps(&pipeline, &[PipelineStep::Upper])
+        assert_eq!(outputs, [Ok(vec![]), Ok(vec![]), Ok(vec![])]);
+        assert_eq!(
+            finished,
+            Ok(vec!["WEB1 DOWN".to_string(), "WEB2 OK".to_string()])
+        );
     }
 
     #[test]
-    fn build_pipeline_parses_trim_command() -> Result<(), String> {
+    fn apply_route_by_key_consumes_matching_lines() {
         //+ Arrange
-        let tokens: Vec<&str> = vec!["trim"];
+        let directory =
+            std::env::temp_dir().join(format!("rangler-route-step-{}", std::process::id()));
+        let template = format!("{}/{{1}}.log", directory.display());
+        let mut pipeline =
+            Pipeline::build_pipeline(&["route", r"^(\d{4}-\d{2}-\d{2})", &template, "upper"])
+                .unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens)?;
+        let routed = pipeline.apply("2024-05-01 started").unwrap();
+        let unrouted = pipeline.apply("no date").unwrap();
+        pipeline.finish().unwrap();
 
         //+ Assert
-        assert_steps(&pipeline, &[PipelineStep::Trim])
+        assert_eq!(routed, Vec::<String>::new());
+        assert_eq!(unrouted, vec!["NO DATE".to_string()]);
+        assert_eq!(
+            std::fs::read_to_string(directory.join("2024-05-01.log")).unwrap(),
+            "2024-05-01 started\n"
+        );
+
+        std::fs::remove_dir_all(directory).unwrap();
     }
 
     #[test]
-    fn build_pipeline_parses_multiple_commands() -> Result<(), String> {
+    fn step_stats_count_lines_reaching_each_step() {
         //+ Arrange
-        let tokens: Vec<&str> = vec!["lower", "upper", "filter", ".+", "prepend", "hello"];
+        let mut pipeline = Pipeline::build_pipeline(&["filter", "a", "DEDUPE", "upper"]).unwrap();
 
         //+ Act
-        let pipeline = Pipeline::build_pipeline(&tokens)?;
+        for line in ["a", "b", "a", "ab"] {
+            pipeline.apply(line).unwrap();
+        }
+        pipeline.sample_memory();
 
         //+ Assert
-        assert_steps(
-            &pipeline,
-            &[
-                PipelineStep::Lower,
-                PipelineStep::Upper,
-                PipelineStep::Filter(Regex::new(".+").unwrap()),
-                PipelineStep::Prepend("hello".to_string()),
-            ],
-        )
+        let stats = pipeline.step_stats();
+        let names: Vec<&str> = stats.iter().map(|step| step.name.as_str()).collect();
+        let received: Vec<usize> = stats.iter().map(|step| step.received).collect();
+        assert_eq!(names, vec!["filter", "dedupe", "upper"]);
+        assert_eq!(received, vec![4, 3, 2]);
+        assert!(stats[1].peak_memory > 0);
+    }
+
+    #[test]
+    fn apply_runs_custom_steps_like_built_ins() {
+        //+ Arrange
+        // Joins every two lines and hands back an unpaired one at the end.
+        #[derive(Debug)]
+        struct Pairs(Option<String>);
+
+        impl Step for Pairs {
+            fn apply(&mut self, line: String) -> Result<Vec<String>, &'static str> {
+                Ok(match self.0.take() {
+                    Some(first) => vec![first + "+" + line.as_str()],
+                    None => {
+                        self.0 = Some(line);
+                        vec![]
+                    }
+                })
+            }
+
+            fn flush(&mut self) -> Result<Vec<String>, &'static str> {
+                Ok(self.0.take().into_iter().collect())
+            }
+
+            fn memory(&self) -> usize {
+                self.0.as_ref().map_or(0, String::len)
+            }
+        }
+
+        let mut pipeline = Pipeline::builder()
+            .custom("pairs", Pairs(None))
+            .upper()
+            .build();
+
+        //+ Act
+        let mut lines = vec![];
+        for line in ["a", "b", "c"] {
+            lines.extend(
+                pipeline
+                    .apply(line)
+                    .unwrap()
+                    .into_iter()
+                    .map(Cow::into_owned),
+            );
+        }
+        let memory = pipeline.get_memory();
+        lines.extend(pipeline.finish().unwrap());
+
+        //+ Assert
+        assert_eq!(lines, vec!["A+B".to_string(), "C".to_string()]);
+        assert_eq!(memory, 1);
+    }
+
+    #[test]
+    fn explain_lists_arguments_buffering_and_memory() {
+        //+ Arrange
+        let pipeline =
+            Pipeline::build_pipeline(&["filter", "a b", "top", "3", "--approx", "10", "upper"])
+                .unwrap();
+
+        //+ Act
+        let explanation = pipeline.explain();
+
+        //+ Assert
+        let lines: Vec<&str> = explanation.lines().collect();
+        assert_eq!(lines[0], "1. filter \"a b\"");
+        assert_eq!(lines[1], "   compiled: Filter(a b)");
+        assert_eq!(lines[4], "2. top \"3\" \"--approx\" \"10\"");
+        assert_eq!(lines[6], "   buffers:  everything until the end of input");
+        assert_eq!(lines[7], "   memory:   bounded by the --approx counters");
+        assert_eq!(lines[8], "3. upper");
+        assert_eq!(lines.len(), 12);
+    }
+
+    #[test]
+    fn apply_uses_pipeline_error_policy_unless_step_sets_its_own() {
+        //+ Arrange
+        let mut pipeline =
+            Pipeline::build_pipeline(&["json", ".a", "base64", "decode", "--on-error", "skip"])
+                .unwrap();
+        let mut aborting = Pipeline::build_pipeline(&["json", ".a"]).unwrap();
+
+        //+ Act
+        pipeline.set_error_policy(ErrorPolicy::Annotate);
+        aborting.set_error_policy(ErrorPolicy::Error);
+
+        //+ Assert
+        assert_eq!(
+            pipeline.apply("plain"),
+            Ok(vec![]),
+            "base64 skips the annotated line itself"
+        );
+        assert_eq!(
+            Pipeline::build_pipeline(&["json", ".a", "--on-error", "annotate"])
+                .unwrap()
+                .apply("plain"),
+            Ok(vec!["plain\t[error: Invalid JSON or missing path]".into()])
+        );
+        assert_eq!(aborting.apply(r#"{"a":"x"}"#), Ok(vec!["x".into()]));
+        assert_eq!(
+            aborting.apply("plain"),
+            Err("Invalid JSON or missing path on line 2: plain")
+        );
+    }
+
+    #[test]
+    fn apply_borrows_lines_that_only_pass_filters_dedupes_and_trims() {
+        //+ Arrange
+        let mut borrowing = Pipeline::build_pipeline(&["filter", "a", "dedupe", "trim"]).unwrap();
+        let mut upper = Pipeline::build_pipeline(&["filter", "a", "upper"]).unwrap();
+
+        //+ Act
+        let borrowed = borrowing.apply(" a ").unwrap();
+        let owned = upper.apply("a").unwrap();
+
+        //+ Assert
+        assert!(matches!(borrowed[..], [Cow::Borrowed("a")]));
+        assert!(matches!(owned[..], [Cow::Owned(_)]));
+        assert_eq!(owned, ["A"]);
+    }
+
+    #[test]
+    fn apply_bytes_checks_utf8_only_for_steps_that_read_text() {
+        //+ Arrange
+        let mut bytes_only =
+            Pipeline::build_pipeline(&["dedupe", "minlen", "2", "--bytes"]).unwrap();
+        let mut then_text = Pipeline::build_pipeline(&["dedupe", "upper"]).unwrap();
+
+        //+ Act
+        let kept = bytes_only.apply_bytes(b"\xffa").unwrap();
+        let repeated = bytes_only.apply_bytes(b"\xffa").unwrap();
+        let upper = then_text.apply_bytes(b"a").unwrap();
+        let invalid = then_text.apply_bytes(b"\xffb");
+        let duplicate = then_text.apply_bytes(b"a").unwrap();
+
+        //+ Assert
+        assert!(matches!(kept[..], [Cow::Borrowed(b"\xffa")]));
+        assert!(repeated.is_empty());
+        assert_eq!(upper, [b"A".to_vec()]);
+        assert_eq!(invalid, Err("Invalid UTF-8 input"));
+        assert!(duplicate.is_empty());
+        assert!(!bytes_only.needs_text());
+    }
+
+    #[test]
+    fn dedupe_state_skips_lines_seen_in_earlier_runs() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-dedupe-{}", std::process::id()));
+        let commands = ["dedupe", "--state", path.to_str().unwrap()];
+        let mut first = Pipeline::build_pipeline(&commands).unwrap();
+        for line in ["a", "b"] {
+            first.apply(line).unwrap();
+        }
+        first.finish().unwrap();
+
+        //+ Act
+        let mut second = Pipeline::build_pipeline(&commands).unwrap();
+        let outputs = ["b", "c", "c"].map(|line| second.apply(line).unwrap().len());
+        second.finish().unwrap();
+        let mut third = Pipeline::build_pipeline(&commands).unwrap();
+        let repeated = third.apply("c").unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        //+ Assert
+        assert_eq!(outputs, [0, 1, 0]);
+        assert!(repeated.is_empty());
+    }
+
+    #[test]
+    fn restore_state_carries_dedupes_over_to_a_new_pipeline() {
+        //+ Arrange
+        let mut first = Pipeline::build_pipeline(&["trim", "dedupe", "format", "{n}"]).unwrap();
+        for line in ["a", "b", "a"] {
+            first.apply(line).unwrap();
+        }
+        let state = first.save_state().unwrap();
+        let mut resumed = Pipeline::build_pipeline(&["trim", "dedupe", "format", "{n}"]).unwrap();
+
+        //+ Act
+        resumed.restore_state(&state).unwrap();
+        let outputs = ["b", "c"].map(|line| resumed.apply(line).unwrap());
+        let mismatched = Pipeline::build_pipeline(&["dedupe"])
+            .unwrap()
+            .restore_state(&state);
+
+        //+ Assert
+        assert_eq!(outputs, [vec![], vec!["5"]]);
+        assert_eq!(mismatched, Err("Invalid checkpoint"));
+        assert_eq!(
+            Pipeline::build_pipeline(&["trim", "top", "3"])
+                .unwrap()
+                .unsaved_step(),
+            Some("top")
+        );
+    }
+
+    #[test]
+    fn take_over_keeps_unchanged_steps_when_asked() {
+        //+ Arrange
+        let mut old = Pipeline::build_pipeline(&["dedupe", "upper", "format", "{n}"]).unwrap();
+        for line in ["a", "b"] {
+            old.apply(line).unwrap();
+        }
+        let mut kept = Pipeline::build_pipeline(&["trim", "dedupe", "format", "{n}"]).unwrap();
+        let mut reset = Pipeline::build_pipeline(&["trim", "dedupe", "format", "{n}"]).unwrap();
+
+        //+ Act
+        kept.take_over(&mut old, true);
+        reset.take_over(&mut old, false);
+        let kept_outputs = ["a", "c"].map(|line| kept.apply(line).unwrap());
+        let reset_outputs = ["a", "c"].map(|line| reset.apply(line).unwrap());
+
+        //+ Assert
+        assert_eq!(kept_outputs, [vec![], vec!["4"]]);
+        assert_eq!(reset_outputs, [vec!["3"], vec!["4"]]);
     }
 
     #[test]
@@ -281,32 +4875,17 @@ mod tests {
         let mut pipeline = Pipeline::build_pipeline(&["lower", "dedupe"]).unwrap();
 
         //+ Act + Assert
-        assert_eq!(pipeline.apply("fOo"), Some("foo".to_string()));
-        assert_eq!(pipeline.apply("fOo"), None);
+        assert_eq!(pipeline.apply("fOo"), Ok(vec!["foo".into()]));
+        assert_eq!(pipeline.apply("fOo"), Ok(vec![]));
     }
 
     fn assert_steps(pipeline: &Pipeline, expected_steps: &[PipelineStep]) -> Result<(), String> {
         assert_eq!(pipeline.steps.len(), expected_steps.len());
 
-        for (index, actual_step) in expected_steps.iter().enumerate() {
+        for (index, actual_step) in pipeline.steps.iter().enumerate() {
             assert_eq!(actual_step, &expected_steps[index]);
         }
 
         Ok(())
     }
 }
-
-impl PartialEq for PipelineStep {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Self::Filter(left_regex), Self::Filter(right_regex)) => {
-                left_regex.as_str() == right_regex.as_str()
-            }
-            (Self::Append(left_suffix), Self::Append(right_suffix)) => left_suffix == right_suffix,
-            (Self::Prepend(left_prefix), Self::Prepend(right_prefix)) => {
-                left_prefix == right_prefix
-            }
-            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
-        }
-    }
-}
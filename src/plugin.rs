@@ -0,0 +1,283 @@
+use std::{
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    fmt,
+    sync::Mutex,
+};
+
+use libloading::Library;
+
+use crate::step::Step;
+
+/// Hands one output line back to rangler. Only valid during the call it was
+/// passed to.
+pub type Emit = unsafe extern "C" fn(context: *mut c_void, line: *const u8, length: usize);
+
+/// One command a plugin adds, as a table of C functions, so plugins can be
+/// written in any language and built with any compiler version.
+///
+/// A plugin library exports `rangler_plugin_v1`, which stores how many
+/// commands it has in `count` and returns a pointer to that many of these.
+/// The table and the strings in it must live as long as the library.
+///
+/// * `create` gets the command's `arguments` tokens as NUL-terminated strings
+///   and returns the state of a new step, or null when they are invalid.
+/// * `apply` gets one line, which is not NUL-terminated, and calls `emit`
+///   for every line to pass on: never to drop it, more than once to fan out.
+/// * `flush` calls `emit` for whatever is still held at the end of input.
+/// * `destroy` frees the state.
+///
+/// `apply` and `flush` return 0 on success.
+#[repr(C)]
+pub struct PluginCommandV1 {
+    pub name: *const c_char,
+    pub arguments: usize,
+    pub create: unsafe extern "C" fn(arguments: *const *const c_char) -> *mut c_void,
+    pub apply: unsafe extern "C" fn(
+        state: *mut c_void,
+        line: *const u8,
+        length: usize,
+        emit: Emit,
+        context: *mut c_void,
+    ) -> c_int,
+    pub flush: unsafe extern "C" fn(state: *mut c_void, emit: Emit, context: *mut c_void) -> c_int,
+    pub destroy: unsafe extern "C" fn(state: *mut c_void),
+}
+
+type Entry = unsafe extern "C" fn(count: *mut usize) -> *const PluginCommandV1;
+
+struct Command {
+    name: String,
+    table: &'static PluginCommandV1,
+}
+
+// The tables are plain data that stays put for as long as the process runs,
+// since loaded libraries are never unloaded.
+unsafe impl Send for Command {}
+
+static COMMANDS: Mutex<Vec<Command>> = Mutex::new(vec![]);
+
+/// Loads a plugin library and registers its commands for every pipeline built
+/// from then on. Commands are looked up by name after the built-in ones, so a
+/// plugin cannot change what a built-in command does.
+///
+/// # Safety
+///
+/// Loading runs the library's initialisers, and its commands run as native
+/// code in this process. The library must be a rangler plugin whose
+/// `rangler_plugin_v1` returns a table of `count` valid [`PluginCommandV1`]s
+/// that stay valid, with the functions they point to, for the rest of the
+/// process.
+pub unsafe fn load_plugin(path: &str) -> Result<(), &'static str> {
+    let library = unsafe { Library::new(path) }.map_err(|_| "Could not load plugin")?;
+
+    let mut count = 0;
+    let table = unsafe {
+        let entry = library
+            .get::<Entry>(b"rangler_plugin_v1\0")
+            .map_err(|_| "Not a rangler plugin")?;
+        entry(&mut count)
+    };
+    if table.is_null() && count > 0 {
+        return Err("Not a rangler plugin");
+    }
+
+    let tables: &'static [PluginCommandV1] = match count {
+        0 => &[],
+        _ => unsafe { std::slice::from_raw_parts(table, count) },
+    };
+    std::mem::forget(library);
+
+    tables.iter().try_for_each(register)
+}
+
+// Makes a command table available to build_pipeline.
+pub fn register(table: &'static PluginCommandV1) -> Result<(), &'static str> {
+    if table.name.is_null() {
+        return Err("Plugin command without a name");
+    }
+    let name = unsafe { CStr::from_ptr(table.name) }
+        .to_str()
+        .map_err(|_| "Plugin command without a name")?
+        .to_lowercase();
+
+    let mut commands = COMMANDS.lock().unwrap();
+    commands.retain(|command| command.name != name);
+    commands.push(Command { name, table });
+
+    Ok(())
+}
+
+// Builds a step for a plugin command, taking its arguments from the tokens.
+// None means no plugin has a command by that name.
+pub fn plugin_step<T: AsRef<str>>(
+    command: &str,
+    tokens: &mut &[T],
+) -> Option<Result<PluginStep, &'static str>> {
+    let table = COMMANDS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|registered| registered.name.eq_ignore_ascii_case(command))
+        .map(|registered| registered.table)?;
+
+    Some(PluginStep::create(command, table, tokens))
+}
+
+pub struct PluginStep {
+    name: String,
+    table: &'static PluginCommandV1,
+    state: *mut c_void,
+}
+
+impl PluginStep {
+    fn create<T: AsRef<str>>(
+        name: &str,
+        table: &'static PluginCommandV1,
+        tokens: &mut &[T],
+    ) -> Result<PluginStep, &'static str> {
+        if tokens.len() < table.arguments {
+            return Err("Missing plugin command arguments");
+        }
+        let (arguments, rest) = tokens.split_at(table.arguments);
+        *tokens = rest;
+
+        let arguments = arguments
+            .iter()
+            .map(|argument| CString::new(argument.as_ref()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| "Invalid plugin command arguments")?;
+        let pointers: Vec<*const c_char> =
+            arguments.iter().map(|argument| argument.as_ptr()).collect();
+
+        let state = unsafe { (table.create)(pointers.as_ptr()) };
+        if state.is_null() {
+            return Err("Invalid plugin command arguments");
+        }
+
+        Ok(PluginStep {
+            name: name.to_string(),
+            table,
+            state,
+        })
+    }
+}
+
+unsafe extern "C" fn collect(context: *mut c_void, line: *const u8, length: usize) {
+    let lines = &mut *(context as *mut Vec<String>);
+    let bytes = std::slice::from_raw_parts(line, length);
+    lines.push(String::from_utf8_lossy(bytes).into_owned());
+}
+
+impl Step for PluginStep {
+    fn apply(&mut self, line: String) -> Result<Vec<String>, &'static str> {
+        let mut lines: Vec<String> = vec![];
+        let context = &mut lines as *mut Vec<String> as *mut c_void;
+
+        match unsafe { (self.table.apply)(self.state, line.as_ptr(), line.len(), collect, context) }
+        {
+            0 => Ok(lines),
+            _ => Err("Plugin command failed"),
+        }
+    }
+
+    fn flush(&mut self) -> Result<Vec<String>, &'static str> {
+        let mut lines: Vec<String> = vec![];
+        let context = &mut lines as *mut Vec<String> as *mut c_void;
+
+        match unsafe { (self.table.flush)(self.state, collect, context) } {
+            0 => Ok(lines),
+            _ => Err("Plugin command failed"),
+        }
+    }
+}
+
+impl Drop for PluginStep {
+    fn drop(&mut self) {
+        unsafe { (self.table.destroy)(self.state) }
+    }
+}
+
+impl fmt::Debug for PluginStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PluginStep")
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{c_char, c_int, c_void, CStr};
+
+    use super::{register, Emit, PluginCommandV1};
+    use crate::pipeline::Pipeline;
+
+    // Repeats every line as many times as its argument says.
+    unsafe extern "C" fn create(arguments: *const *const c_char) -> *mut c_void {
+        match CStr::from_ptr(*arguments)
+            .to_str()
+            .ok()
+            .and_then(|n| n.parse::<usize>().ok())
+        {
+            Some(times) => Box::into_raw(Box::new(times)) as *mut c_void,
+            None => std::ptr::null_mut(),
+        }
+    }
+
+    unsafe extern "C" fn apply(
+        state: *mut c_void,
+        line: *const u8,
+        length: usize,
+        emit: Emit,
+        context: *mut c_void,
+    ) -> c_int {
+        for _ in 0..*(state as *mut usize) {
+            emit(context, line, length);
+        }
+        0
+    }
+
+    unsafe extern "C" fn flush(_: *mut c_void, emit: Emit, context: *mut c_void) -> c_int {
+        emit(context, b"done".as_ptr(), 4);
+        0
+    }
+
+    unsafe extern "C" fn destroy(state: *mut c_void) {
+        drop(Box::from_raw(state as *mut usize));
+    }
+
+    struct Table(PluginCommandV1);
+    unsafe impl Sync for Table {}
+
+    static REPEAT: Table = Table(PluginCommandV1 {
+        name: c"repeat".as_ptr(),
+        arguments: 1,
+        create,
+        apply,
+        flush,
+        destroy,
+    });
+
+    #[test]
+    fn build_pipeline_finds_registered_plugin_commands() {
+        //+ Arrange
+        register(&REPEAT.0).unwrap();
+        let mut pipeline = Pipeline::build_pipeline(&["repeat", "2", "upper"]).unwrap();
+
+        //+ Act
+        let lines = pipeline.apply("a").unwrap();
+        let finished = pipeline.finish().unwrap();
+
+        //+ Assert
+        assert_eq!(lines, vec!["A".to_string(), "A".to_string()]);
+        assert_eq!(finished, vec!["DONE".to_string()]);
+        assert_eq!(
+            Pipeline::build_pipeline(&["repeat", "x"]).err().unwrap(),
+            "Invalid plugin command arguments"
+        );
+        assert_eq!(
+            Pipeline::build_pipeline(&["repeat"]).err().unwrap(),
+            "Missing plugin command arguments"
+        );
+    }
+}
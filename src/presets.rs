@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+use serde_json::json;
+
+use crate::definition::load_definition;
+
+// Presets are saved command lines, kept one per file as
+// `$XDG_CONFIG_HOME/rangler/presets/<name>.yaml`, falling back to
+// `~/.config`, so they can be edited by hand or passed to --pipeline.
+fn preset_path(name: &str) -> Result<PathBuf, &'static str> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err("Invalid preset name");
+    }
+
+    Ok(config_directory()?
+        .join("presets")
+        .join(format!("{}.yaml", name)))
+}
+
+// Where rangler keeps its presets and config file.
+pub(crate) fn config_directory() -> Result<PathBuf, &'static str> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME").ok_or("No home directory for presets")?)
+            .join(".config"),
+    };
+
+    Ok(config.join("rangler"))
+}
+
+/// Saves options and commands under a name, replacing any preset already
+/// saved by that name, and returns where it was written.
+pub fn save_preset<T: AsRef<str>>(name: &str, args: &[T]) -> Result<PathBuf, &'static str> {
+    let path = preset_path(name)?;
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_ref()).collect();
+    let document =
+        serde_yaml::to_string(&json!({ "args": args })).map_err(|_| "Could not save preset")?;
+
+    std::fs::create_dir_all(path.parent().unwrap()).map_err(|_| "Could not save preset")?;
+    std::fs::write(&path, document).map_err(|_| "Could not save preset")?;
+
+    Ok(path)
+}
+
+/// Loads the options and commands saved under a name.
+pub fn load_preset(name: &str) -> Result<Vec<String>, &'static str> {
+    let path = preset_path(name)?;
+    if !path.exists() {
+        return Err("No preset by that name");
+    }
+
+    load_definition(path.to_str().ok_or("Invalid preset name")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_preset, save_preset};
+
+    #[test]
+    fn save_preset_round_trips_through_config_directory() {
+        //+ Arrange
+        let config = std::env::temp_dir().join(format!("rangler-config-{}", std::process::id()));
+        std::env::set_var("XDG_CONFIG_HOME", &config);
+        let args = [
+            "--no-progress",
+            "filter",
+            "ERROR|WARN",
+            "append",
+            " !",
+            "dedupe",
+        ];
+
+        //+ Act
+        let path = save_preset("clean-logs", &args).unwrap();
+        let loaded = load_preset("clean-logs");
+
+        //+ Assert
+        assert_eq!(path, config.join("rangler/presets/clean-logs.yaml"));
+        assert_eq!(loaded, Ok(args.map(String::from).to_vec()));
+        assert_eq!(load_preset("missing"), Err("No preset by that name"));
+        assert_eq!(load_preset("../secrets"), Err("Invalid preset name"));
+        std::fs::remove_dir_all(config).unwrap();
+    }
+}
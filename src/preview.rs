@@ -0,0 +1,58 @@
+use crate::pipeline::Pipeline;
+
+// Shows what the pipeline makes of each line, like a unified diff: a line
+// that comes out as it went in is shown once, otherwise the line goes in
+// with `-` and what came out of it with `+`. Lines held back until the end of
+// input show up after all the others, under a marker. Lines come with the
+// name of the input they were read from.
+pub(crate) fn preview(
+    pipeline: &mut Pipeline,
+    lines: &[(String, String)],
+) -> Result<String, &'static str> {
+    let mut text = vec![];
+    for (source, line) in lines {
+        pipeline.set_source(source);
+        let output = pipeline.apply(line)?;
+        match output.as_slice() {
+            [same] if same == line => text.push(format!("  {}", line)),
+            output => {
+                text.push(format!("- {}", line));
+                text.extend(output.iter().map(|line| format!("+ {}", line)));
+            }
+        }
+    }
+
+    let held = pipeline.finish()?;
+    if !held.is_empty() {
+        text.push("@@ end of input".to_string());
+        text.extend(held.iter().map(|line| format!("+ {}", line)));
+    }
+
+    Ok(text.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::preview;
+    use crate::pipeline::Pipeline;
+
+    #[test]
+    fn preview_marks_changed_dropped_and_held_lines() {
+        //+ Arrange
+        let mut changed = Pipeline::build_pipeline(&["filter", "a", "append", "!"]).unwrap();
+        let mut same = Pipeline::build_pipeline(&["filter", "a"]).unwrap();
+        let mut held = Pipeline::build_pipeline(&["sort"]).unwrap();
+        let lines = [
+            ("-".to_string(), "b".to_string()),
+            ("-".to_string(), "a".to_string()),
+        ];
+
+        //+ Act + Assert
+        assert_eq!(preview(&mut changed, &lines).unwrap(), "- b\n- a\n+ a!\n");
+        assert_eq!(preview(&mut same, &lines).unwrap(), "- b\n  a\n");
+        assert_eq!(
+            preview(&mut held, &lines).unwrap(),
+            "- b\n- a\n@@ end of input\n+ a\n+ b\n"
+        );
+    }
+}
@@ -0,0 +1,551 @@
+use std::io::BufRead;
+
+use memchr::{memchr, memmem::Finder};
+use regex::bytes::Regex;
+
+// How stdin is split into the records the pipeline sees.
+#[derive(Debug, Default, Clone)]
+pub enum RecordSeparator {
+    #[default]
+    Newline,
+    // Records end at this byte sequence, e.g. `\n\n` for paragraphs.
+    Literal(Vec<u8>),
+    // Multiline records: every line matching the regex starts a new record
+    // and the lines that follow it are joined on `\n`.
+    Start(Regex),
+    // CSV records: a newline inside a quoted field is part of the record,
+    // so a record only ends at a newline after an even number of quotes.
+    Csv,
+    // The elements of one top-level JSON array, each compacted onto a line
+    // of its own.
+    JsonArray,
+}
+
+// What happens to records that are not valid UTF-8.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum InvalidUtf8 {
+    #[default]
+    Lossy,
+    Skip,
+    Abort,
+    Raw,
+}
+
+/// Splits a reader into records. Records are found by scanning each block
+/// the reader buffers with memchr (or memmem for multi-byte separators),
+/// carrying on into the next block when a record does not end in this one.
+pub struct RecordReader<R> {
+    reader: R,
+    separator: RecordSeparator,
+    pending: Option<Vec<u8>>,
+    array: ArrayPosition,
+}
+
+// Where a JSON array reader is relative to the array's brackets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArrayPosition {
+    Before,
+    Inside,
+    After,
+}
+
+impl InvalidUtf8 {
+    pub fn parse(name: &str) -> Result<InvalidUtf8, &'static str> {
+        match name.to_lowercase().as_str() {
+            "lossy" => Ok(InvalidUtf8::Lossy),
+            "skip" => Ok(InvalidUtf8::Skip),
+            "abort" => Ok(InvalidUtf8::Abort),
+            "raw" => Ok(InvalidUtf8::Raw),
+            _ => Err("Invalid UTF-8 policy"),
+        }
+    }
+}
+
+impl RecordSeparator {
+    // Accepts `\n`, `\r`, `\t`, `\0` and `\\` escapes.
+    pub fn literal(text: &str) -> Result<RecordSeparator, &'static str> {
+        let mut bytes = vec![];
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            let c = match c {
+                '\\' => match chars.next() {
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('0') => '\0',
+                    Some('\\') | None => '\\',
+                    Some(other) => other,
+                },
+                c => c,
+            };
+            bytes.extend(c.to_string().as_bytes());
+        }
+
+        if bytes.is_empty() {
+            return Err("Empty record separator");
+        }
+
+        Ok(RecordSeparator::Literal(bytes))
+    }
+
+    pub fn start(pattern: &str) -> Result<RecordSeparator, &'static str> {
+        Regex::new(pattern)
+            .map(RecordSeparator::Start)
+            .map_err(|_| "Invalid record start expression")
+    }
+}
+
+impl PartialEq for RecordSeparator {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (RecordSeparator::Newline, RecordSeparator::Newline) => true,
+            (RecordSeparator::Csv, RecordSeparator::Csv) => true,
+            (RecordSeparator::JsonArray, RecordSeparator::JsonArray) => true,
+            (RecordSeparator::Literal(left), RecordSeparator::Literal(right)) => left == right,
+            (RecordSeparator::Start(left), RecordSeparator::Start(right)) => {
+                left.as_str() == right.as_str()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl<R: BufRead> RecordReader<R> {
+    pub fn new(reader: R, separator: RecordSeparator) -> RecordReader<R> {
+        RecordReader {
+            reader,
+            separator,
+            pending: None,
+            array: ArrayPosition::Before,
+        }
+    }
+
+    /// Returns the next record without its separator, along with the number
+    /// of bytes read for it, or None at the end of input.
+    pub fn next_record(&mut self) -> Result<Option<(Vec<u8>, usize)>, &'static str> {
+        match &self.separator {
+            RecordSeparator::Newline => {
+                let mut record = vec![];
+                let read = self.read_until(b'\n', &mut record)?;
+                if read == 0 {
+                    return Ok(None);
+                }
+                if record.last() == Some(&b'\n') {
+                    record.pop();
+                }
+
+                Ok(Some((record, read)))
+            }
+            RecordSeparator::Literal(separator) => {
+                let mut record = vec![];
+                let (total, found) = read_through(&mut self.reader, separator, &mut record)?;
+                if found {
+                    record.truncate(record.len() - separator.len());
+                    return Ok(Some((record, total)));
+                }
+
+                if total == 0 {
+                    return Ok(None);
+                }
+                if record.last() == Some(&b'\n') {
+                    record.pop();
+                }
+
+                Ok(Some((record, total)))
+            }
+            RecordSeparator::Csv => {
+                let mut record = vec![];
+                let mut total = 0;
+                let mut quotes = 0;
+                loop {
+                    let start = record.len();
+                    let read = self.read_until(b'\n', &mut record)?;
+                    total += read;
+                    quotes += record[start..].iter().filter(|&&byte| byte == b'"').count();
+
+                    if read == 0 || quotes % 2 == 0 {
+                        break;
+                    }
+                }
+
+                if total == 0 {
+                    return Ok(None);
+                }
+                if record.last() == Some(&b'\n') {
+                    record.pop();
+                }
+
+                Ok(Some((record, total)))
+            }
+            RecordSeparator::JsonArray => self.next_element(),
+            RecordSeparator::Start(_) => {
+                let mut record = vec![];
+                let mut total = 0;
+
+                if let Some(line) = self.pending.take() {
+                    total += line.len();
+                    record = line;
+                }
+
+                loop {
+                    let mut line = vec![];
+                    let read = self.read_until(b'\n', &mut line)?;
+                    if read == 0 {
+                        break;
+                    }
+
+                    let starts_record = match &self.separator {
+                        RecordSeparator::Start(regex) => {
+                            regex.is_match(line.strip_suffix(b"\n").unwrap_or(&line))
+                        }
+                        _ => false,
+                    };
+                    if starts_record && total > 0 {
+                        self.pending = Some(line);
+                        break;
+                    }
+
+                    total += read;
+                    record.extend(line);
+                }
+
+                if total == 0 {
+                    return Ok(None);
+                }
+                if record.last() == Some(&b'\n') {
+                    record.pop();
+                }
+
+                Ok(Some((record, total)))
+            }
+        }
+    }
+
+    // Reads the next element of a top-level JSON array, dropping whitespace
+    // outside strings so that pretty-printed elements fit on one line. Only
+    // the nesting of brackets and strings is followed; the element itself is
+    // left for the steps to parse.
+    fn next_element(&mut self) -> Result<Option<(Vec<u8>, usize)>, &'static str> {
+        let mut record = vec![];
+        let mut total = 0;
+        let mut depth = 0usize;
+        let mut in_string = false;
+        let mut escaped = false;
+
+        loop {
+            let block = self.reader.fill_buf().map_err(|_| "IO Error")?;
+            if block.is_empty() {
+                return match self.array {
+                    ArrayPosition::Inside => Err("JSON array ended early"),
+                    _ => Ok(None),
+                };
+            }
+
+            let mut used = 0;
+            let mut ended = false;
+            for &byte in block {
+                used += 1;
+                if in_string {
+                    record.push(byte);
+                    match byte {
+                        _ if escaped => escaped = false,
+                        b'\\' => escaped = true,
+                        b'"' => in_string = false,
+                        _ => {}
+                    }
+                    continue;
+                }
+                if byte.is_ascii_whitespace() {
+                    continue;
+                }
+
+                match (self.array, byte) {
+                    (ArrayPosition::Before, b'[') => self.array = ArrayPosition::Inside,
+                    (ArrayPosition::Before, _) => return Err("Input is not a JSON array"),
+                    (ArrayPosition::After, _) => return Err("Unexpected data after JSON array"),
+                    (ArrayPosition::Inside, b',') if depth == 0 => {
+                        if record.is_empty() {
+                            return Err("Empty element in JSON array");
+                        }
+                        ended = true;
+                        break;
+                    }
+                    (ArrayPosition::Inside, b']') if depth == 0 => {
+                        self.array = ArrayPosition::After;
+                        ended = true;
+                        break;
+                    }
+                    (ArrayPosition::Inside, _) => {
+                        match byte {
+                            b'"' => in_string = true,
+                            b'[' | b'{' => depth += 1,
+                            b']' | b'}' => depth = depth.saturating_sub(1),
+                            _ => {}
+                        }
+                        record.push(byte);
+                    }
+                }
+            }
+            self.reader.consume(used);
+            total += used;
+
+            // An empty array ends without an element to pass on.
+            if ended && !record.is_empty() {
+                return Ok(Some((record, total)));
+            }
+        }
+    }
+
+    // Appends everything up to and including the next `byte`, block by
+    // block, returning how many bytes that was.
+    fn read_until(&mut self, byte: u8, record: &mut Vec<u8>) -> Result<usize, &'static str> {
+        let mut total = 0;
+        loop {
+            let block = self.reader.fill_buf().map_err(|_| "IO Error")?;
+            if block.is_empty() {
+                return Ok(total);
+            }
+
+            let (length, done) = match memchr(byte, block) {
+                Some(end) => (end + 1, true),
+                None => (block.len(), false),
+            };
+            record.extend_from_slice(&block[..length]);
+            self.reader.consume(length);
+            total += length;
+            if done {
+                return Ok(total);
+            }
+        }
+    }
+}
+
+// Appends everything up to and including the next separator, returning how
+// many bytes that was and whether the separator was found before the end of
+// input. A separator split across two blocks is looked for in the last few
+// bytes of the record joined to the first few of the new block.
+fn read_through<R: BufRead>(
+    reader: &mut R,
+    separator: &[u8],
+    record: &mut Vec<u8>,
+) -> Result<(usize, bool), &'static str> {
+    let finder = Finder::new(separator);
+    let start = record.len();
+    loop {
+        let block = reader.fill_buf().map_err(|_| "IO Error")?;
+        if block.is_empty() {
+            return Ok((record.len() - start, false));
+        }
+
+        let tail = (record.len() - start).min(separator.len() - 1);
+        let straddling = match tail {
+            0 => None,
+            _ => {
+                let head = &block[..block.len().min(separator.len() - 1)];
+                let window = [&record[record.len() - tail..], head].concat();
+                finder
+                    .find(&window)
+                    .filter(|&found| found < tail)
+                    .map(|found| found + separator.len() - tail)
+            }
+        };
+
+        let (length, done) =
+            match straddling.or_else(|| finder.find(block).map(|found| found + separator.len())) {
+                Some(end) => (end, true),
+                None => (block.len(), false),
+            };
+        record.extend_from_slice(&block[..length]);
+        reader.consume(length);
+        if done {
+            return Ok((record.len() - start, true));
+        }
+    }
+}
+
+// Normalizes Windows line endings to LF: drops a `\r` before every `\n` and
+// at the end of the record.
+pub fn strip_carriage_returns(record: &mut Vec<u8>) {
+    let mut kept = 0;
+    for index in 0..record.len() {
+        let byte = record[index];
+        let before_newline = match record.get(index + 1) {
+            Some(next) => *next == b'\n',
+            None => true,
+        };
+        if byte == b'\r' && before_newline {
+            continue;
+        }
+
+        record[kept] = byte;
+        kept += 1;
+    }
+
+    record.truncate(kept);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{strip_carriage_returns, RecordReader, RecordSeparator};
+
+    fn read_all(input: &str, separator: RecordSeparator) -> Vec<String> {
+        let mut reader = RecordReader::new(input.as_bytes(), separator);
+        let mut records = vec![];
+        while let Some((record, _)) = reader.next_record().unwrap() {
+            records.push(String::from_utf8(record).unwrap());
+        }
+
+        records
+    }
+
+    fn read_json_array(input: &str) -> Result<Vec<String>, &'static str> {
+        let mut reader = RecordReader::new(input.as_bytes(), RecordSeparator::JsonArray);
+        let mut records = vec![];
+        while let Some((record, _)) = reader.next_record()? {
+            records.push(String::from_utf8(record).unwrap());
+        }
+
+        Ok(records)
+    }
+
+    #[test]
+    fn next_record_splits_on_literal_separator() {
+        //+ Act + Assert
+        assert_eq!(
+            read_all("a\nb\n\nc\n", RecordSeparator::literal(r"\n\n").unwrap()),
+            vec!["a\nb".to_string(), "c".to_string()]
+        );
+        assert_eq!(
+            read_all("x;y;", RecordSeparator::literal(";").unwrap()),
+            vec!["x".to_string(), "y".to_string()]
+        );
+        assert_eq!(
+            read_all("a\n\nb", RecordSeparator::Newline),
+            vec!["a", "", "b"]
+        );
+    }
+
+    #[test]
+    fn next_record_groups_lines_after_start_pattern() {
+        //+ Arrange
+        let input = "trailing\n2024-05-01 error\n  at a()\n  at b()\n2024-05-01 ok\n";
+
+        //+ Act
+        let records = read_all(input, RecordSeparator::start(r"^\d{4}-").unwrap());
+
+        //+ Assert
+        assert_eq!(
+            records,
+            vec![
+                "trailing".to_string(),
+                "2024-05-01 error\n  at a()\n  at b()".to_string(),
+                "2024-05-01 ok".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn next_record_keeps_quoted_newlines_in_csv_records() {
+        //+ Arrange
+        let input = "id,note\n1,\"two\nlines, \"\"quoted\"\"\"\n2,plain\n3,\"unterminated\n";
+
+        //+ Act
+        let records = read_all(input, RecordSeparator::Csv);
+
+        //+ Assert
+        assert_eq!(
+            records,
+            vec![
+                "id,note",
+                "1,\"two\nlines, \"\"quoted\"\"\"",
+                "2,plain",
+                "3,\"unterminated"
+            ]
+        );
+    }
+
+    #[test]
+    fn next_record_reads_lines_longer_than_the_buffer() {
+        //+ Arrange
+        let input = format!("{}\nshort\n", "x".repeat(100));
+        let reader = std::io::BufReader::with_capacity(16, input.as_bytes());
+        let mut records = RecordReader::new(reader, RecordSeparator::Newline);
+
+        //+ Act + Assert
+        assert_eq!(records.next_record().unwrap().unwrap().0.len(), 100);
+        assert_eq!(records.next_record().unwrap(), Some((b"short".to_vec(), 6)));
+        assert_eq!(records.next_record().unwrap(), None);
+    }
+
+    #[test]
+    fn strip_carriage_returns_only_removes_line_endings() {
+        //+ Arrange
+        let mut record = b"a\r\nb\rc\r".to_vec();
+
+        //+ Act
+        strip_carriage_returns(&mut record);
+
+        //+ Assert
+        assert_eq!(record, b"a\nb\rc".to_vec());
+    }
+
+    #[test]
+    fn next_record_finds_records_spanning_buffer_boundaries() {
+        //+ Arrange
+        let input = "first-->second record-->-->third";
+        let records = |separator: RecordSeparator| {
+            // Buffers three bytes at a time, so separators straddle blocks.
+            let reader = std::io::BufReader::with_capacity(3, input.as_bytes());
+            let mut reader = RecordReader::new(reader, separator);
+            let mut records = vec![];
+            while let Some((record, read)) = reader.next_record().unwrap() {
+                records.push((String::from_utf8(record).unwrap(), read));
+            }
+            records
+        };
+
+        //+ Act
+        let literal = records(RecordSeparator::literal("-->").unwrap());
+        let newline = records(RecordSeparator::Newline);
+
+        //+ Assert
+        assert_eq!(
+            literal,
+            vec![
+                ("first".to_string(), 8),
+                ("second record".to_string(), 16),
+                ("".to_string(), 3),
+                ("third".to_string(), 5)
+            ]
+        );
+        assert_eq!(newline, vec![(input.to_string(), input.len())]);
+    }
+
+    #[test]
+    fn next_record_reads_json_array_elements_one_by_one() {
+        //+ Arrange
+        let input =
+            "[\n  {\"id\": 1, \"tags\": [\"a\", \"b, c\"]},\n  \"say \\\"]\\\"\",\n  3\n]\n";
+        let reader = std::io::BufReader::with_capacity(4, input.as_bytes());
+        let mut records = RecordReader::new(reader, RecordSeparator::JsonArray);
+
+        //+ Act
+        let mut elements = vec![];
+        let mut total = 0;
+        while let Some((record, read)) = records.next_record().unwrap() {
+            elements.push(String::from_utf8(record).unwrap());
+            total += read;
+        }
+
+        //+ Assert
+        assert_eq!(
+            elements,
+            vec![r#"{"id":1,"tags":["a","b, c"]}"#, r#""say \"]\"""#, "3",]
+        );
+        assert_eq!(total, input.trim_end().len());
+        assert!(read_json_array("[1,,2]").is_err());
+        assert!(read_json_array("[1, 2").is_err());
+        assert!(read_json_array("{\"id\": 1}").is_err());
+        assert_eq!(read_json_array(" [ ] ").unwrap(), Vec::<String>::new());
+    }
+}
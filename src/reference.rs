@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::fs::read_to_string;
+
+use aho_corasick::{AhoCorasick, AhoCorasickBuilder};
+use regex::Regex;
+
+use crate::csv::parse_record;
+
+// Steps that compare the stream against a file loaded up front.
+
+// A streaming, order-insensitive diff. Incoming lines that the reference file
+// does not account for are emitted as `+line` straight away; reference lines
+// never seen are emitted as `-line`, in file order, once the input ends.
+// Duplicates are counted, so a line appearing twice must arrive twice.
+#[derive(Debug, PartialEq)]
+pub struct Diff {
+    reference: Vec<String>,
+    remaining: HashMap<String, usize>,
+}
+
+impl Diff {
+    pub fn load(path: &str) -> Result<Diff, &'static str> {
+        let reference = read_lines(path)?;
+
+        let mut remaining = HashMap::new();
+        for line in reference.iter() {
+            *remaining.entry(line.clone()).or_insert(0) += 1;
+        }
+
+        Ok(Diff {
+            reference,
+            remaining,
+        })
+    }
+
+    pub fn apply(&mut self, line: &str) -> Option<String> {
+        match self.remaining.get_mut(line) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                None
+            }
+            _ => Some(format!("+{}", line)),
+        }
+    }
+
+    pub fn finish(&mut self) -> Vec<String> {
+        let mut removed = vec![];
+        for line in self.reference.iter() {
+            if let Some(count) = self.remaining.get_mut(line).filter(|count| **count > 0) {
+                *count -= 1;
+                removed.push(format!("-{}", line));
+            }
+        }
+
+        removed
+    }
+
+    pub fn memory(&self) -> usize {
+        self.reference.iter().map(|line| line.len() * 2).sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LookupMiss {
+    Pass,
+    Drop,
+    Default(String),
+}
+
+// Enriches lines from a two-column mapping file. The key is the first capture
+// group of the key regex (or its whole match, or the whole line without one)
+// and is replaced by its mapped value, or kept with the value appended after
+// it when annotating.
+#[derive(Debug)]
+pub struct Lookup {
+    mapping: HashMap<String, String>,
+    key: Option<Regex>,
+    annotate: bool,
+    miss: LookupMiss,
+}
+
+impl Lookup {
+    // Mapping files ending in .tsv default to tab separated columns.
+    pub fn load(
+        path: &str,
+        delimiter: Option<u8>,
+        key: Option<Regex>,
+        annotate: bool,
+        miss: LookupMiss,
+    ) -> Result<Lookup, &'static str> {
+        let delimiter = delimiter.unwrap_or(if path.ends_with(".tsv") { b'\t' } else { b',' });
+
+        let mut mapping = HashMap::new();
+        for line in read_lines(path)? {
+            match parse_record(&line, delimiter).as_deref() {
+                Some([key, value, ..]) => {
+                    mapping.entry(key.clone()).or_insert_with(|| value.clone());
+                }
+                Some([key]) if key.is_empty() => {}
+                _ => return Err("Mapping file needs two columns"),
+            }
+        }
+
+        Ok(Lookup {
+            mapping,
+            key,
+            annotate,
+            miss,
+        })
+    }
+
+    pub fn apply(&self, line: &str) -> Option<String> {
+        let span = match &self.key {
+            None => Some(0..line.len()),
+            Some(regex) => regex.captures(line).map(|captures| {
+                let found = captures.get(1).or(captures.get(0)).unwrap();
+                found.range()
+            }),
+        };
+
+        let value = match span.clone().and_then(|span| self.mapping.get(&line[span])) {
+            Some(value) => value,
+            None => match &self.miss {
+                LookupMiss::Pass => return Some(line.to_string()),
+                LookupMiss::Drop => return None,
+                LookupMiss::Default(value) => value,
+            },
+        };
+
+        let span = match span {
+            Some(span) => span,
+            None => return Some(line.to_string()),
+        };
+
+        let replacement = if self.annotate {
+            format!("{} {}", &line[span.clone()], value)
+        } else {
+            value.clone()
+        };
+
+        Some(format!(
+            "{}{}{}",
+            &line[..span.start],
+            replacement,
+            &line[span.end..]
+        ))
+    }
+
+    pub fn memory(&self) -> usize {
+        self.mapping
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum()
+    }
+}
+
+impl PartialEq for Lookup {
+    fn eq(&self, other: &Self) -> bool {
+        self.mapping == other.mapping
+            && self.key.as_ref().map(Regex::as_str) == other.key.as_ref().map(Regex::as_str)
+            && self.annotate == other.annotate
+            && self.miss == other.miss
+    }
+}
+
+// Keeps lines containing any of the literals in a file, IDs or hostnames by
+// the thousand, found in one Aho-Corasick pass rather than through a regex
+// alternating between all of them. Blank lines in the file are skipped, as
+// the empty string would be in every line.
+#[derive(Debug)]
+pub struct Literals {
+    literals: Vec<String>,
+    ignore_case: bool,
+    matcher: AhoCorasick,
+}
+
+impl Literals {
+    pub fn load(path: &str, ignore_case: bool) -> Result<Literals, &'static str> {
+        let literals = read_lines(path)?
+            .into_iter()
+            .filter(|literal| !literal.is_empty())
+            .collect();
+
+        Literals::new(literals, ignore_case)
+    }
+
+    fn new(literals: Vec<String>, ignore_case: bool) -> Result<Literals, &'static str> {
+        let matcher = AhoCorasickBuilder::new()
+            .ascii_case_insensitive(ignore_case)
+            .build(&literals)
+            .map_err(|_| "Too many literals")?;
+
+        Ok(Literals {
+            literals,
+            ignore_case,
+            matcher,
+        })
+    }
+
+    // Case is only ignored for ASCII letters.
+    pub fn ignoring_case(&self) -> Literals {
+        Literals::new(self.literals.clone(), true).unwrap()
+    }
+
+    pub fn is_match(&self, line: &[u8]) -> bool {
+        self.matcher.is_match(line)
+    }
+
+    pub fn memory(&self) -> usize {
+        self.matcher.memory_usage() + self.literals.iter().map(String::len).sum::<usize>()
+    }
+}
+
+impl PartialEq for Literals {
+    fn eq(&self, other: &Self) -> bool {
+        self.literals == other.literals && self.ignore_case == other.ignore_case
+    }
+}
+
+pub fn read_lines(path: &str) -> Result<Vec<String>, &'static str> {
+    let contents = read_to_string(path).map_err(|_| "Could not read reference file")?;
+
+    Ok(contents
+        .lines()
+        .map(|line| line.strip_suffix('\r').unwrap_or(line).to_string())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::{Diff, Literals, Lookup, LookupMiss};
+
+    #[test]
+    fn diff_reports_added_and_removed_lines() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-diff-{}.txt", std::process::id()));
+        std::fs::write(&path, "a\nb\nb\nc\n").unwrap();
+        let mut diff = Diff::load(path.to_str().unwrap()).unwrap();
+
+        //+ Act
+        let applied: Vec<Option<String>> = ["c", "b", "d", "c"]
+            .iter()
+            .map(|line| diff.apply(line))
+            .collect();
+        let removed = diff.finish();
+
+        //+ Assert
+        assert_eq!(
+            applied,
+            vec![None, None, Some("+d".to_string()), Some("+c".to_string())]
+        );
+        assert_eq!(removed, vec!["-a".to_string(), "-b".to_string()]);
+        assert_eq!(
+            Diff::load("/nonexistent/rangler/reference").err(),
+            Some("Could not read reference file")
+        );
+    }
+
+    #[test]
+    fn lookup_replaces_or_annotates_keys() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-lookup-{}.tsv", std::process::id()));
+        std::fs::write(&path, "42\talice\n7\tbob\n").unwrap();
+        let path = path.to_str().unwrap();
+        let key = Regex::new(r"user=(\d+)").unwrap();
+        let replace = Lookup::load(path, None, Some(key.clone()), false, LookupMiss::Drop).unwrap();
+        let annotate = Lookup::load(
+            path,
+            None,
+            Some(key),
+            true,
+            LookupMiss::Default("?".to_string()),
+        )
+        .unwrap();
+        let whole = Lookup::load(path, None, None, false, LookupMiss::Pass).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            replace.apply("user=42 ok"),
+            Some("user=alice ok".to_string())
+        );
+        assert_eq!(replace.apply("user=1 ok"), None);
+        assert_eq!(
+            annotate.apply("user=7 ok"),
+            Some("user=7 bob ok".to_string())
+        );
+        assert_eq!(annotate.apply("user=1 ok"), Some("user=1 ? ok".to_string()));
+        assert_eq!(whole.apply("7"), Some("bob".to_string()));
+        assert_eq!(whole.apply("8"), Some("8".to_string()));
+    }
+
+    #[test]
+    fn literals_match_any_of_them_in_one_pass() {
+        //+ Arrange
+        let path =
+            std::env::temp_dir().join(format!("rangler-literals-{}.txt", std::process::id()));
+        std::fs::write(&path, "10.0.0.7\r\n\nevil.example\nID-4242\n").unwrap();
+
+        //+ Act
+        let literals = Literals::load(path.to_str().unwrap(), false).unwrap();
+        let ignoring_case = literals.ignoring_case();
+        std::fs::remove_file(&path).unwrap();
+
+        //+ Assert
+        assert!(literals.is_match(b"GET / from 10.0.0.7"));
+        assert!(literals.is_match(b"resolved evil.example."));
+        assert!(!literals.is_match(b"id-4242 retried"));
+        assert!(ignoring_case.is_match(b"id-4242 retried"));
+        assert!(!literals.is_match(b""));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    fs::metadata,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{definition::load_definition, error::RanglerError};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// Watches the --pipeline file of a run that never ends, so the steps can be
+// retuned without a restart. The file is looked at once a second at most, and
+// whenever its modification time changes it is read again, with the steps
+// given on the command line still after its own.
+pub struct Reload {
+    path: String,
+    commands: Vec<String>,
+    modified: Option<SystemTime>,
+    checked: Instant,
+}
+
+impl Reload {
+    // The run's commands are the file's followed by the command line's, so
+    // the file is read once more to tell them apart.
+    pub fn start(path: &str, commands: &[String]) -> Result<Reload, RanglerError> {
+        let modified = modified(path);
+        let definition = load_definition(path)?;
+        let commands = commands
+            .strip_prefix(definition.as_slice())
+            .ok_or("The pipeline file changed while starting")?;
+
+        Ok(Reload {
+            path: path.to_string(),
+            commands: commands.to_vec(),
+            modified,
+            checked: Instant::now(),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // The commands the file now holds, once it changed. A file caught half
+    // written fails to load, and is read again once written in full.
+    pub fn poll(&mut self) -> Option<Result<Vec<String>, RanglerError>> {
+        if self.checked.elapsed() < CHECK_INTERVAL {
+            return None;
+        }
+        self.checked = Instant::now();
+
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return None;
+        }
+        self.modified = modified;
+
+        Some(
+            load_definition(&self.path)
+                .map(|definition| [definition, self.commands.clone()].concat())
+                .map_err(RanglerError::from),
+        )
+    }
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    metadata(path).and_then(|file| file.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{Reload, CHECK_INTERVAL};
+
+    #[test]
+    fn poll_reads_the_file_again_once_it_changes() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-reload-{}.yaml", std::process::id()));
+        std::fs::write(&path, "[trim]").unwrap();
+        let path = path.to_string_lossy().into_owned();
+        let commands = ["trim", "upper"].map(String::from);
+        let mut reload = Reload::start(&path, &commands).unwrap();
+
+        //+ Act
+        let too_soon = reload.poll().is_none();
+        reload.checked -= CHECK_INTERVAL;
+        let unchanged = reload.poll().is_none();
+        std::fs::write(&path, "[lower, {filter: x}]").unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        reload.checked -= CHECK_INTERVAL;
+        let changed = reload.poll();
+        std::fs::remove_file(&path).unwrap();
+
+        //+ Assert
+        assert!(too_soon && unchanged);
+        assert_eq!(changed.unwrap().unwrap(), ["lower", "filter", "x", "upper"]);
+    }
+}
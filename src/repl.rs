@@ -0,0 +1,187 @@
+use std::io::{BufRead, Write};
+
+use crate::{pipeline::Pipeline, syntax::split_pipeline};
+
+const HELP: &str = "\
+<step>                  adds a step at the end, written as in a pipeline, e.g. filter /error/i
+insert <n> <step>       adds a step before step n
+remove <n>              removes step n
+move <from> <to>        moves a step to another position
+clear                   removes every step
+show                    shows the steps and the sample through them again
+pipeline                prints the pipeline as a command line
+quit                    leaves, printing the pipeline";
+
+/// Reads up to `count` lines from the start of an input, the sample
+/// `rangler repl` runs its pipeline over.
+pub fn load_sample(input: impl BufRead, count: usize) -> Result<Vec<String>, &'static str> {
+    input
+        .lines()
+        .take(count)
+        .map(|line| line.map_err(|_| "Could not read the sample"))
+        .collect()
+}
+
+/// Builds a pipeline a step at a time against a sample of the input. Every
+/// change is checked by building the pipeline again, so a step that does not
+/// parse is never added, and shows the sample as the steps now leave it.
+pub struct Repl {
+    sample: Vec<String>,
+    steps: Vec<String>,
+}
+
+impl Repl {
+    pub fn new(sample: Vec<String>) -> Repl {
+        Repl {
+            sample,
+            steps: vec![],
+        }
+    }
+
+    /// Reads commands until the input ends or `quit`, then prints the
+    /// pipeline built so far.
+    pub fn run(&mut self, commands: impl BufRead, mut screen: impl Write) -> std::io::Result<()> {
+        writeln!(
+            screen,
+            "{} sample lines loaded; type a step to add it, or help",
+            self.sample.len()
+        )?;
+        write!(screen, "> ")?;
+        screen.flush()?;
+
+        for command in commands.lines() {
+            let command = command?;
+            match command.trim() {
+                "quit" | "exit" => break,
+                "" => {}
+                command => match self.execute(command) {
+                    Ok(text) => writeln!(screen, "{}", text)?,
+                    Err(message) => writeln!(screen, "error: {}", message)?,
+                },
+            }
+            write!(screen, "> ")?;
+            screen.flush()?;
+        }
+
+        writeln!(screen)?;
+        writeln!(screen, "{}", self.command_line())
+    }
+
+    // Carries out one command, returning what to show for it.
+    fn execute(&mut self, command: &str) -> Result<String, String> {
+        let (name, rest) = command
+            .split_once(char::is_whitespace)
+            .map_or((command, ""), |(name, rest)| (name, rest.trim()));
+        let position = |text: &str, limit: usize| match text.parse::<usize>() {
+            Ok(position) if (1..=limit).contains(&position) => Ok(position - 1),
+            _ => Err(format!("Step numbers go from 1 to {}", limit)),
+        };
+
+        let mut steps = self.steps.clone();
+        match name {
+            "help" => return Ok(HELP.to_string()),
+            "show" => return Ok(self.show()),
+            "pipeline" => return Ok(self.command_line()),
+            "clear" => steps.clear(),
+            "remove" | "rm" => {
+                steps.remove(position(rest, steps.len())?);
+            }
+            "insert" => {
+                let (at, step) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let at = position(at, steps.len() + 1)?;
+                steps.insert(at, step.trim().to_string());
+            }
+            "move" => {
+                let (from, to) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                let from = position(from, steps.len())?;
+                let to = position(to.trim(), steps.len())?;
+                let step = steps.remove(from);
+                steps.insert(to, step);
+            }
+            _ => steps.push(command.to_string()),
+        }
+
+        if !steps.is_empty() {
+            build(&steps)?;
+        }
+        self.steps = steps;
+
+        Ok(self.show())
+    }
+
+    // The steps, numbered, then the sample as they leave it.
+    fn show(&self) -> String {
+        let mut text: Vec<String> = self
+            .steps
+            .iter()
+            .enumerate()
+            .map(|(index, step)| format!("  {}. {}", index + 1, step))
+            .collect();
+        if text.is_empty() {
+            text.push("  (no steps)".to_string());
+        }
+        text.push("--".to_string());
+
+        match self.transformed() {
+            Ok(lines) => text.extend(lines),
+            Err(message) => text.push(format!("error: {}", message)),
+        }
+
+        text.join("\n")
+    }
+
+    fn transformed(&self) -> Result<Vec<String>, String> {
+        if self.steps.is_empty() {
+            return Ok(self.sample.clone());
+        }
+
+        let mut pipeline = build(&self.steps)?;
+        let mut lines = vec![];
+        for line in &self.sample {
+            lines.extend(pipeline.apply(line)?.into_iter().map(String::from));
+        }
+        lines.extend(pipeline.finish()?);
+
+        Ok(lines)
+    }
+
+    fn command_line(&self) -> String {
+        match self.steps.is_empty() {
+            true => "rangler".to_string(),
+            false => format!(
+                "rangler '{}'",
+                self.steps.join(" | ").replace('\'', "'\\''")
+            ),
+        }
+    }
+}
+
+fn build(steps: &[String]) -> Result<Pipeline, String> {
+    let commands = split_pipeline(&steps.join(" | "))?;
+    Pipeline::build_pipeline(&commands).map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_sample, Repl};
+
+    #[test]
+    fn run_edits_steps_and_shows_the_sample_through_them() {
+        //+ Arrange
+        let sample = load_sample("b 2\na 1\nb 2\n".as_bytes(), 20).unwrap();
+        let mut repl = Repl::new(sample);
+        let commands = "upper\nfilter 2\nnonsense\ninsert 1 dedupe\nmove 3 1\nremove 3\nquit\n";
+        let mut screen = vec![];
+
+        //+ Act
+        repl.run(commands.as_bytes(), &mut screen).unwrap();
+
+        //+ Assert
+        let screen = String::from_utf8(screen).unwrap();
+        assert!(screen.contains("  1. upper\n--\nB 2\nA 1\nB 2\n"));
+        assert!(screen.contains("  1. upper\n  2. filter 2\n--\nB 2\nB 2\n"));
+        assert!(screen.contains("error: Unknown command"));
+        assert!(screen.contains("  1. filter 2\n  2. dedupe\n--\nb 2\n"));
+        assert!(screen.ends_with("rangler 'filter 2 | dedupe'\n"));
+    }
+}
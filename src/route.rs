@@ -0,0 +1,183 @@
+use std::{io::Write, path::Path};
+
+use regex::Regex;
+
+use crate::{
+    partition::OpenFiles,
+    template::{Template, TemplateContext},
+};
+
+// Writes each matching line to the file named by filling the template in with
+// the expression's capture groups, e.g. `logs/{service}.log`. Lines that do
+// not match carry on down the pipeline.
+#[derive(Debug)]
+pub struct KeyRoute {
+    regex: Regex,
+    template: Template,
+    files: OpenFiles,
+    routed: usize,
+    terminator: String,
+}
+
+impl KeyRoute {
+    pub fn new(regex: Regex, template: &str) -> Result<KeyRoute, &'static str> {
+        Ok(KeyRoute {
+            regex,
+            template: Template::parse(template)?,
+            files: OpenFiles::default(),
+            routed: 0,
+            terminator: "\n".to_string(),
+        })
+    }
+
+    pub fn set_terminator(&mut self, terminator: &str) {
+        self.terminator = terminator.to_string();
+    }
+
+    // The file is the input the line was read from, for `{file}`. The
+    // captures come from the lines themselves, so one the template uses may
+    // not name another directory.
+    pub fn apply(&mut self, line: String, file: &str) -> Result<Option<String>, &'static str> {
+        let captures: Vec<(Option<String>, Option<String>)> = match self.regex.captures(&line) {
+            Some(matched) => self
+                .regex
+                .capture_names()
+                .zip(matched.iter())
+                .map(|(name, value)| {
+                    (
+                        name.map(str::to_string),
+                        value.map(|v| v.as_str().to_string()),
+                    )
+                })
+                .collect(),
+            None => return Ok(Some(line)),
+        };
+        let escapes = captures.iter().enumerate().any(|(index, (name, value))| {
+            value.as_deref().is_some_and(|value| {
+                self.template.uses_group(index, name.as_deref()) && !is_plain_name(value)
+            })
+        });
+        if escapes {
+            return Err("Route key is not a plain file name");
+        }
+
+        self.routed += 1;
+        let path = self.template.render(&TemplateContext {
+            line: &line,
+            number: self.routed,
+            file,
+            captures: &captures,
+        });
+        if path.is_empty() {
+            return Err("Route produced an empty file name");
+        }
+
+        let writer = self.files.writer_for(&path)?;
+        writer
+            .write_all(line.as_bytes())
+            .and_then(|_| writer.write_all(self.terminator.as_bytes()))
+            .map_err(|_| "IO Error")?;
+
+        Ok(None)
+    }
+
+    pub fn uses_file(&self) -> bool {
+        self.template.uses_file()
+    }
+
+    pub fn flush(&mut self) -> Result<(), &'static str> {
+        self.files.flush()
+    }
+}
+
+fn is_plain_name(value: &str) -> bool {
+    !value.contains(['/', '\\']) && !value.contains("..") && !Path::new(value).is_absolute()
+}
+
+impl PartialEq for KeyRoute {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str() && self.template == other.template
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read_to_string, remove_dir_all};
+
+    use regex::Regex;
+
+    use super::KeyRoute;
+
+    #[test]
+    fn apply_writes_lines_to_file_per_key() {
+        //+ Arrange
+        let directory = std::env::temp_dir().join(format!("rangler-route-{}", std::process::id()));
+        let template = format!("{}/{{service}}.log", directory.display());
+        let regex = Regex::new(r"service=(?P<service>\w+)").unwrap();
+        let mut route = KeyRoute::new(regex, &template).unwrap();
+
+        //+ Act
+        let unmatched = route
+            .apply("no service here".to_string(), "events.log")
+            .unwrap();
+        route
+            .apply("service=api a".to_string(), "events.log")
+            .unwrap();
+        route
+            .apply("service=db b".to_string(), "events.log")
+            .unwrap();
+        route
+            .apply("service=api c".to_string(), "events.log")
+            .unwrap();
+        route.flush().unwrap();
+
+        //+ Assert
+        assert_eq!(unmatched, Some("no service here".to_string()));
+        assert_eq!(
+            read_to_string(directory.join("api.log")).unwrap(),
+            "service=api a\nservice=api c\n"
+        );
+        assert_eq!(
+            read_to_string(directory.join("db.log")).unwrap(),
+            "service=db b\n"
+        );
+
+        remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn apply_rejects_keys_that_leave_the_directory() {
+        //+ Arrange
+        let directory =
+            std::env::temp_dir().join(format!("rangler-route-escape-{}", std::process::id()));
+        let template = format!("{}/{{service}}.log", directory.display());
+        let regex = Regex::new(r"service=(?P<service>\S+)").unwrap();
+        let mut route = KeyRoute::new(regex, &template).unwrap();
+        route.set_terminator("\0");
+
+        //+ Act
+        let escaped = [
+            "service=../x",
+            "service=a/b",
+            "service=a\\b",
+            "service=/tmp/x",
+        ]
+        .map(|line| route.apply(line.to_string(), "events.log"));
+        route
+            .apply("service=api a".to_string(), "events.log")
+            .unwrap();
+        route.flush().unwrap();
+
+        //+ Assert
+        for result in escaped {
+            assert_eq!(result, Err("Route key is not a plain file name"));
+        }
+        assert_eq!(
+            read_to_string(directory.join("api.log")).unwrap(),
+            "service=api a\0"
+        );
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+
+        remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,1151 @@
+use std::{
+    borrow::Cow,
+    fs::{copy, write},
+    io::{stderr, BufRead, BufReader, IsTerminal, Read, Write},
+    thread,
+    time::Instant,
+};
+
+use crate::{
+    binary::BytePipeline,
+    buffers::StreamKind,
+    checkpoint::{skip_bytes, Checkpoint, Checkpointer},
+    color::Highlighter,
+    degradation::{DegradationReport, LossyEvent},
+    encoding::decode_input,
+    error::RanglerError,
+    follow::{Follow, Watch},
+    hash::StreamDigest,
+    inputs::{expand_globs, open_inputs, Source},
+    jobs::Jobs,
+    listen::Listener,
+    merge::MergedReader,
+    options::Options,
+    output::{write_error, AtomicFile, Output, Sink},
+    parallel::{Processed, Workers},
+    partition::PartitionedOutput,
+    pipeline::Pipeline,
+    records::{strip_carriage_returns, InvalidUtf8, RecordReader},
+    reload::Reload,
+    sink,
+    staged::{Received, Stages},
+    stats::{top_dropper, RunSummary, StepStats},
+    trace::Trace,
+};
+use indicatif::{HumanBytes, ProgressBar, ProgressStyle};
+use regex::Regex;
+use serde_json::Value;
+
+/// Drives one invocation: opens the inputs and the output the options ask for,
+/// streams every record through the engine and reports on the run. A reader
+/// that went away early comes back as an error showing
+/// [`BROKEN_PIPE`](crate::output::BROKEN_PIPE).
+pub fn run(
+    options: &Options,
+    commands: &[String],
+    mut engine: Engine,
+) -> Result<RunSummary, RanglerError> {
+    let terminator = terminator(options);
+    let mut partitions = options
+        .output_partition
+        .as_deref()
+        .map(|template| PartitionedOutput::new(template).map(|p| p.with_terminator(terminator)))
+        .transpose()?;
+
+    let mut paths = options.inputs.clone();
+    paths.extend(expand_globs(&options.globs)?);
+    if options.in_place.is_some() && paths.is_empty() {
+        Err("In-place editing needs input files")?;
+    }
+    let endless: Option<(&String, Box<dyn Read>)> =
+        match (&options.follow, &options.watch, &options.listen) {
+            (Some(path), _, _) => Some((path, Box::new(Follow::open(path)?))),
+            (_, Some(directory), _) => {
+                let pattern = options.watch_glob.as_deref().unwrap_or("*");
+                Some((directory, Box::new(Watch::open(directory, pattern)?)))
+            }
+            (_, _, Some(address)) => Some((
+                address,
+                Box::new(Listener::bind(address, &options.record_separator)?),
+            )),
+            (None, None, None) => None,
+        };
+    // Pipelines that keep nothing between lines, or only an exact dedupe, can
+    // take whole files at a time on jobs that open the files themselves.
+    let jobs = match &engine {
+        Engine::Text(pipeline) => {
+            options.jobs > 1 && (pipeline.is_stateless() || pipeline.can_share_dedupe())
+        }
+        Engine::Bytes(_) => false,
+    };
+    let (sources, total_size) = match endless {
+        Some((name, reader)) => {
+            let capacity = options
+                .read_buffer
+                .unwrap_or(StreamKind::Pipe.buffer_size());
+            let source: Box<dyn BufRead> = Box::new(BufReader::with_capacity(capacity, reader));
+            (vec![(name.clone(), source)], None)
+        }
+        None if jobs => (vec![], None),
+        None => open_inputs(&paths, options.read_buffer, options.mmap)?,
+    };
+    let sources: Vec<Source> = match options.encoding {
+        Some(encoding) => sources
+            .into_iter()
+            .map(|(name, source)| {
+                let capacity = options
+                    .read_buffer
+                    .unwrap_or(StreamKind::File.buffer_size());
+                (name, decode_input(source, encoding, capacity))
+            })
+            .collect(),
+        None => sources,
+    };
+    // Merged inputs read as one, named after all of them.
+    let sources: Vec<Source> = match &options.merge {
+        Some(_) if sources.len() < 2 => Err("Merging inputs needs at least two of them")?,
+        Some(mode) => {
+            let names: Vec<&str> = sources.iter().map(|(name, _)| name.as_str()).collect();
+            let name = names.join("+");
+            let merged = MergedReader::new(sources, mode.clone(), &options.record_separator);
+            let capacity = options
+                .read_buffer
+                .unwrap_or(StreamKind::File.buffer_size());
+            vec![(name, Box::new(BufReader::with_capacity(capacity, merged)))]
+        }
+        None => sources,
+    };
+
+    // A checkpoint left by a run that stopped part way picks up where it was,
+    // with the steps holding what they held then.
+    let inputs: Vec<String> = sources.iter().map(|(name, _)| name.clone()).collect();
+    let resume = match &options.checkpoint {
+        Some(path) => Checkpoint::load(path)?,
+        None => None,
+    };
+    if let (Some(_), Engine::Text(pipeline)) = (&options.checkpoint, &mut engine) {
+        if let Some(name) = pipeline.unsaved_step() {
+            Err(format!(
+                "The {} step keeps state a checkpoint cannot save",
+                name
+            ))?;
+        }
+        if let Some(checkpoint) = &resume {
+            checkpoint.check(commands, &inputs)?;
+            pipeline.restore_state(&checkpoint.pipeline)?;
+        }
+    }
+    let output_start = resume
+        .as_ref()
+        .map_or(0, |checkpoint| checkpoint.output_bytes);
+    let checkpointer = options
+        .checkpoint
+        .as_deref()
+        .map(|path| Checkpointer::new(path, commands, inputs, output_start));
+
+    // Without a known input size (stdin, endless or compressed inputs) there is
+    // no percentage or ETA, only a spinner. Progress is never drawn when stderr
+    // is redirected, so it cannot end up mixed into a log file, nor when
+    // --quiet asks for nothing but the data and errors.
+    let progress = match total_size {
+        _ if options.no_progress || options.quiet || !stderr().is_terminal() => {
+            ProgressBar::hidden()
+        }
+        Some(total_size) => ProgressBar::new(total_size).with_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] {percent}% ETA {eta} {binary_bytes_per_sec} {msg}",
+            )
+            .unwrap(),
+        ),
+        None => ProgressBar::new_spinner().with_style(
+            ProgressStyle::with_template("[{elapsed_precise}] {binary_bytes_per_sec} {msg}")
+                .unwrap(),
+        ),
+    };
+
+    // Output to a file gets a large buffer, stdout one to suit whatever it is
+    // attached to.
+    let write_buffer = match (options.write_buffer, &options.output, &options.in_place) {
+        (Some(size), _, _) => size,
+        (None, None, None) => StreamKind::stdout().buffer_size(),
+        _ => StreamKind::File.buffer_size(),
+    };
+
+    // Pipelines that keep nothing between lines can share them out between
+    // threads; anything else, and input that never ends, stays on this one.
+    // An exact dedupe is shared by the threads, unless a checkpoint or the
+    // memory limit has to see its lines, or every file edited in place needs
+    // a dedupe of its own.
+    let shares_dedupe = matches!(&engine, Engine::Text(pipeline) if pipeline.can_share_dedupe())
+        && options.checkpoint.is_none()
+        && options.max_memory.is_none()
+        && options.in_place.is_none();
+    let workers = match &engine {
+        Engine::Text(pipeline)
+            if options.threads > 1
+                && (pipeline.is_stateless() || shares_dedupe)
+                && !pipeline.uses_source()
+                && !options.is_endless() =>
+        {
+            Some(Workers::start(options.threads, commands, options)?)
+        }
+        _ => None,
+    };
+    // Otherwise every step can get a thread of its own, as long as the steps
+    // share nothing but the line and one pipeline runs for the whole input.
+    let stages = match &engine {
+        Engine::Text(pipeline)
+            if options.pipeline_parallelism
+                && workers.is_none()
+                && Stages::can_stage(pipeline)
+                && !options.is_endless()
+                && options.in_place.is_none()
+                && options.max_memory.is_none() =>
+        {
+            Some(Stages::start(commands, options)?)
+        }
+        _ => None,
+    };
+
+    // Matches are highlighted in what is written, after every step has run.
+    let to_terminal = options.output.is_none()
+        && options.in_place.is_none()
+        && partitions.is_none()
+        && StreamKind::stdout() == StreamKind::Terminal;
+    let color = options.color.enabled(to_terminal);
+    let highlighter = match &engine {
+        Engine::Text(pipeline) if color => Highlighter::new(pipeline.match_patterns()),
+        _ => None,
+    };
+
+    // A pipeline file is read again whenever it changes, for as long as the
+    // input goes on.
+    let reload = match &options.pipeline {
+        Some(path) if options.is_endless() => Some(Reload::start(path, commands)?),
+        _ => None,
+    };
+
+    // A pipeline that never reads text takes records as they were read,
+    // without checking them for UTF-8. Partitioning, tracing and highlighting
+    // read the text, so they still need the check.
+    let skip_decoding = matches!(&engine, Engine::Text(pipeline) if !pipeline.needs_text())
+        && !options.is_tracing()
+        && highlighter.is_none()
+        && partitions.is_none()
+        && workers.is_none()
+        && stages.is_none();
+
+    let mut run = Run {
+        options,
+        workers,
+        stages,
+        skip_decoding,
+        color,
+        highlighter,
+        reload,
+        checkpointer,
+        input: 0,
+        terminator,
+        degradations: DegradationReport::new(options.strict),
+        progress,
+        summary: RunSummary::default(),
+        checksum: options.checksum.map(|algorithm| algorithm.stream()),
+        rejects: options
+            .rejects
+            .as_deref()
+            .map(sink::Sink::parse)
+            .transpose()?,
+        started: Instant::now(),
+        last_flush: Instant::now(),
+        bytes_at_last_message: 0,
+        record: vec![],
+    };
+
+    match &options.in_place {
+        // Every file gets a fresh pipeline and replaces itself atomically,
+        // after the original is copied aside when a backup suffix is given.
+        Some(backup_suffix) => {
+            for (name, source) in sources {
+                let mut engine = Engine::build(options, commands)?;
+                if !options.no_optimize {
+                    engine.optimize();
+                }
+                let file = AtomicFile::create(&name, write_buffer)?;
+                file.copy_permissions_from(&name)?;
+                let mut output = Output::new(Sink::File(file), options.compress)?
+                    .encoded(options.output_encoding);
+
+                run.frame(options.header.as_deref(), &mut output)?;
+                run.process(&name, source, (0, 0), &mut engine, &mut None, &mut output)?;
+                run.finish(&mut engine, &mut None, &mut output)?;
+                run.frame(options.footer.as_deref(), &mut output)?;
+
+                if !backup_suffix.is_empty() {
+                    copy(&name, format!("{}{}", name, backup_suffix)).map_err(|source| {
+                        RanglerError::Io {
+                            message: "Could not write backup of",
+                            path: name.clone(),
+                            source,
+                        }
+                    })?;
+                }
+                output.finish()?;
+            }
+        }
+        None => {
+            let mut output = match (options.split, options.output.as_deref()) {
+                (Some(limit), Some(path)) => Output::split(
+                    path,
+                    options.compress,
+                    options.output_encoding,
+                    limit,
+                    write_buffer,
+                )?,
+                (None, Some(path)) if options.checkpoint.is_some() => {
+                    Output::append(path, output_start, write_buffer)?
+                }
+                (None, Some(location)) if location.starts_with("sqlite://") => {
+                    Output::sqlite(location, terminator)?
+                }
+                _ => Output::open(options.output.as_deref(), options.compress, write_buffer)?
+                    .encoded(options.output_encoding),
+            };
+            run.frame(options.header.as_deref(), &mut output)?;
+            if jobs {
+                run.process_files(&paths, commands, &mut partitions, &mut output)?;
+            }
+            let mut resume_at = resume.map(|checkpoint| {
+                (
+                    checkpoint.input,
+                    checkpoint.offset,
+                    checkpoint.record_number,
+                )
+            });
+            for (index, (name, mut source)) in sources.into_iter().enumerate() {
+                let start = match resume_at {
+                    Some((input, ..)) if index < input => continue,
+                    Some((_, offset, record_number)) => {
+                        resume_at = None;
+                        skip_bytes(&mut source, offset)?;
+                        (offset, record_number)
+                    }
+                    None => (0, 0),
+                };
+                run.input = index;
+                run.process(
+                    &name,
+                    source,
+                    start,
+                    &mut engine,
+                    &mut partitions,
+                    &mut output,
+                )?;
+            }
+            run.finish(&mut engine, &mut partitions, &mut output)?;
+            run.frame(options.footer.as_deref(), &mut output)?;
+            output.finish()?;
+            if let Some(checkpointer) = run.checkpointer.take() {
+                checkpointer.finish()?;
+            }
+        }
+    }
+
+    if let Some(rejects) = run.rejects.as_mut() {
+        rejects.finish()?;
+    }
+    if let Some(workers) = run.workers.take() {
+        for steps in workers.stop() {
+            run.summary.add_steps(&steps);
+        }
+    }
+    run.progress.finish();
+    run.summary.elapsed = run.started.elapsed();
+    if let (Some(algorithm), Some(digest)) = (options.checksum, run.checksum.take()) {
+        let checksum = format!("{}:{}", algorithm.name(), digest.finish());
+        eprintln!("rangler: checksum {}", checksum);
+        run.summary.checksum = Some(checksum);
+    }
+    if options.summary {
+        for line in run.summary.table() {
+            eprintln!("rangler: {}", line);
+        }
+    }
+    match options.stats_json.as_deref() {
+        Some("stderr") => eprintln!("{}", run.summary.to_json()),
+        Some(path) => write(path, run.summary.to_json().to_string() + "\n").map_err(|source| {
+            RanglerError::Io {
+                message: "Could not write statistics to",
+                path: path.to_string(),
+                source,
+            }
+        })?,
+        None => {}
+    }
+    if !options.quiet {
+        for line in run.degradations.summary() {
+            eprintln!("rangler: {}", line);
+        }
+    }
+
+    Ok(run.summary)
+}
+
+// State shared by all the inputs of one invocation.
+struct Run<'a> {
+    options: &'a Options,
+    workers: Option<Workers>,
+    stages: Option<Stages>,
+    skip_decoding: bool,
+    color: bool,
+    highlighter: Option<Highlighter>,
+    reload: Option<Reload>,
+    checkpointer: Option<Checkpointer>,
+    input: usize,
+    terminator: &'static str,
+    degradations: DegradationReport,
+    progress: ProgressBar,
+    summary: RunSummary,
+    checksum: Option<StreamDigest>,
+    rejects: Option<sink::Sink>,
+    started: Instant,
+    last_flush: Instant,
+    bytes_at_last_message: usize,
+    // Reused to put each record together before it is written.
+    record: Vec<u8>,
+}
+
+impl Run<'_> {
+    fn process(
+        &mut self,
+        name: &str,
+        source: Box<dyn BufRead>,
+        (mut offset, mut record_number): (usize, usize),
+        engine: &mut Engine,
+        partitions: &mut Option<PartitionedOutput>,
+        output: &mut impl Write,
+    ) -> Result<(), RanglerError> {
+        let options = self.options;
+        let mut records = RecordReader::new(source, options.record_separator.clone());
+        if let Engine::Text(pipeline) = engine {
+            pipeline.set_source(name);
+        }
+
+        while let Some((mut record, bytes_read)) = records.next_record()? {
+            record_number += 1;
+            self.summary.lines_read += 1;
+            if self.reload.is_some() {
+                self.reload(name, engine, partitions, output)?;
+            }
+
+            if !options.keep_eol {
+                strip_carriage_returns(&mut record);
+            }
+
+            let prefix = record_prefix(options, name, record_number, offset);
+
+            // Kept header lines skip the steps. Each edited file keeps its
+            // own, while later inputs' would repeat the first one's.
+            if record_number <= options.keep_header {
+                if self.input == 0 || options.in_place.is_some() {
+                    self.emit_bytes(&prefix, &record, output)?;
+                }
+            } else {
+                match engine {
+                    Engine::Bytes(pipeline) => {
+                        if let Some(line) = pipeline.apply(&record) {
+                            self.emit_bytes(&prefix, &line, output)?;
+                        }
+                    }
+                    Engine::Text(pipeline) if self.skip_decoding => {
+                        let lines = pipeline.apply_bytes(&record)?;
+                        if lines.is_empty() {
+                            self.reject(pipeline, &prefix, &String::from_utf8_lossy(&record))?;
+                        }
+                        for line in lines {
+                            self.emit_bytes(&prefix, &line, output)?;
+                        }
+                    }
+                    Engine::Text(pipeline) => {
+                        if let Some(record_text) =
+                            self.decode(name, offset, &prefix, &record, output)?
+                        {
+                            match (self.workers.as_mut(), self.stages.as_mut()) {
+                                (Some(workers), _) => {
+                                    let processed =
+                                        workers.push(prefix, record_text.into_owned())?;
+                                    self.emit_processed(processed, partitions, output)?;
+                                }
+                                (None, Some(stages)) => {
+                                    let received = stages.push(prefix, record_text.into_owned());
+                                    self.emit_received(received, partitions, output)?;
+                                }
+                                (None, None) => {
+                                    let lines = pipeline.apply(&record_text)?;
+                                    if lines.is_empty() {
+                                        self.reject(pipeline, &prefix, &record_text)?;
+                                    }
+                                    for line in lines {
+                                        self.emit(&prefix, &line, partitions, output)?;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            // Endless inputs may not produce another line for a while.
+            let flush_due = options
+                .flush_interval
+                .is_some_and(|interval| self.last_flush.elapsed() >= interval);
+            if options.is_endless() || flush_due {
+                output.flush().map_err(write_error)?;
+                self.last_flush = Instant::now();
+            }
+
+            offset += bytes_read;
+            self.summary.bytes_read += bytes_read;
+            if self.checkpointer.as_ref().is_some_and(Checkpointer::is_due) {
+                self.checkpoint(engine, offset, record_number, output)?;
+            }
+
+            if self.summary.bytes_read > self.bytes_at_last_message + 256_000 {
+                self.progress.set_position(self.summary.bytes_read as u64);
+
+                let seconds = self.progress.elapsed().as_secs_f64().max(0.001);
+                let mut message = format!(
+                    "{} lines read ({:.0}/s), {} emitted, {} read, {} stored",
+                    self.summary.lines_read,
+                    self.summary.lines_read as f64 / seconds,
+                    self.summary.lines_emitted,
+                    HumanBytes(self.summary.bytes_read as u64),
+                    HumanBytes(self.check_memory(engine)? as u64)
+                );
+                // Worker threads keep their counts until the end.
+                if let Some((step, dropped)) =
+                    top_dropper(engine.step_stats(), self.summary.lines_emitted)
+                {
+                    message.push_str(&format!(", most dropped by {} ({})", step, dropped));
+                }
+                self.progress.set_message(message);
+                self.bytes_at_last_message = self.summary.bytes_read;
+
+                output.flush().map_err(write_error)?;
+            }
+        }
+
+        if let Some(workers) = self.workers.as_mut() {
+            let processed = workers.flush()?;
+            self.emit_processed(processed, partitions, output)?;
+        }
+
+        Ok(())
+    }
+
+    // Swaps in the pipeline a changed --pipeline file describes, between two
+    // records. The old pipeline is drained as at the end of input, once any
+    // steps it shares with the new one have been handed over, so buffered
+    // lines and state files are not lost. A file that fails to load or build
+    // leaves the old pipeline running.
+    fn reload(
+        &mut self,
+        name: &str,
+        engine: &mut Engine,
+        partitions: &mut Option<PartitionedOutput>,
+        output: &mut impl Write,
+    ) -> Result<(), RanglerError> {
+        let options = self.options;
+        let Some(reload) = self.reload.as_mut() else {
+            return Ok(());
+        };
+        let Some(commands) = reload.poll() else {
+            return Ok(());
+        };
+        let path = reload.path().to_string();
+        let mut reloaded = match commands.and_then(|commands| Engine::build(options, &commands)) {
+            Ok(reloaded) => reloaded,
+            Err(error) => {
+                if !options.quiet {
+                    eprintln!(
+                        "rangler: warning: kept the running pipeline, as {} did not load: {}",
+                        path, error
+                    );
+                }
+                return Ok(());
+            }
+        };
+
+        if !options.quiet {
+            for warning in reloaded.lint(options) {
+                eprintln!("rangler: warning: {}", warning);
+            }
+        }
+        if !options.no_optimize {
+            reloaded.optimize();
+        }
+        if let (Engine::Text(pipeline), Engine::Text(old)) = (&mut reloaded, &mut *engine) {
+            pipeline.set_source(name);
+            pipeline.take_over(old, options.reload_keep_state);
+        }
+
+        let mut old = std::mem::replace(engine, reloaded);
+        self.finish(&mut old, partitions, output)?;
+        // The summary describes the steps running at the end.
+        self.summary.steps.clear();
+        self.highlighter = match &*engine {
+            Engine::Text(pipeline) if self.color => Highlighter::new(pipeline.match_patterns()),
+            _ => None,
+        };
+        self.skip_decoding = matches!(&*engine, Engine::Text(pipeline) if !pipeline.needs_text())
+            && !options.is_tracing()
+            && self.highlighter.is_none()
+            && partitions.is_none();
+        if !options.quiet {
+            eprintln!("rangler: reloaded {}", path);
+        }
+
+        Ok(())
+    }
+
+    // Runs the input files on --jobs threads, writing every batch as it comes
+    // back.
+    fn process_files(
+        &mut self,
+        paths: &[String],
+        commands: &[String],
+        partitions: &mut Option<PartitionedOutput>,
+        output: &mut impl Write,
+    ) -> Result<(), RanglerError> {
+        let options = self.options;
+
+        thread::scope(|scope| {
+            let jobs = Jobs::start(scope, options.jobs, commands, options, paths)?;
+            for batch in jobs.batches() {
+                self.summary.lines_read += batch.records;
+                self.summary.bytes_read += batch.bytes;
+                for event in batch.events {
+                    self.degradations.record(event)?;
+                }
+                for (prefix, lines) in batch.lines {
+                    for line in lines {
+                        self.emit(&prefix, &line, partitions, output)?;
+                    }
+                }
+                if let Some(error) = batch.error {
+                    return Err(error);
+                }
+                self.progress.set_position(self.summary.bytes_read as u64);
+            }
+            for steps in jobs.stop() {
+                self.summary.add_steps(&steps);
+            }
+
+            Ok(())
+        })
+    }
+
+    // Decodes a record according to the --invalid-utf8 policy. Records that
+    // are skipped, or written out raw, come back as None.
+    fn decode<'r>(
+        &mut self,
+        name: &str,
+        offset: usize,
+        prefix: &str,
+        record: &'r [u8],
+        output: &mut impl Write,
+    ) -> Result<Option<Cow<'r, str>>, RanglerError> {
+        let error = match std::str::from_utf8(record) {
+            Ok(record_text) => return Ok(Some(Cow::Borrowed(record_text))),
+            Err(error) => error,
+        };
+
+        match self.options.invalid_utf8 {
+            InvalidUtf8::Lossy => {
+                self.degradations.record(LossyEvent::InvalidUtf8Replaced)?;
+                Ok(Some(String::from_utf8_lossy(record)))
+            }
+            InvalidUtf8::Skip => {
+                self.degradations.record(LossyEvent::InvalidUtf8Skipped)?;
+                Ok(None)
+            }
+            InvalidUtf8::Abort => Err(format!(
+                "Invalid UTF-8 in {} at byte {}",
+                name,
+                offset + error.valid_up_to()
+            ))?,
+            // Raw records skip the pipeline and partitioning, which both work
+            // on text.
+            InvalidUtf8::Raw => {
+                self.emit_bytes(prefix, record, output)?;
+                Ok(None)
+            }
+        }
+    }
+
+    // Flushes the output first, so a checkpoint never counts lines that are
+    // not written yet.
+    fn checkpoint(
+        &mut self,
+        engine: &mut Engine,
+        offset: usize,
+        record_number: usize,
+        output: &mut impl Write,
+    ) -> Result<(), RanglerError> {
+        output.flush().map_err(write_error)?;
+        let pipeline = match engine {
+            Engine::Text(pipeline) => pipeline.save_state()?,
+            Engine::Bytes(_) => Value::Null,
+        };
+
+        match self.checkpointer.as_mut() {
+            Some(checkpointer) => checkpointer.save(
+                self.input,
+                offset,
+                record_number,
+                self.summary.bytes_written,
+                pipeline,
+            ),
+            None => Ok(()),
+        }
+    }
+
+    // Samples how much the steps hold and fails cleanly once that goes over
+    // --max-memory, rather than letting the host run out.
+    fn check_memory(&self, engine: &mut Engine) -> Result<usize, RanglerError> {
+        let memory = engine.sample_memory();
+
+        match self.options.max_memory {
+            Some(budget) if memory > budget => Err(format!(
+                "Steps hold {}, over the memory limit of {}",
+                HumanBytes(memory as u64),
+                HumanBytes(budget as u64)
+            ))?,
+            _ => Ok(memory),
+        }
+    }
+
+    fn finish(
+        &mut self,
+        engine: &mut Engine,
+        partitions: &mut Option<PartitionedOutput>,
+        output: &mut impl Write,
+    ) -> Result<(), RanglerError> {
+        // Buffering steps hold the most right before they are drained.
+        self.check_memory(engine)?;
+        if let Some(stages) = self.stages.take() {
+            let (received, (steps, events)) = stages.finish();
+            self.emit_received(received, partitions, output)?;
+            for (event, count) in events {
+                self.degradations.record_count(event, count)?;
+            }
+            self.summary.add_steps(&steps);
+        } else {
+            if let Engine::Text(pipeline) = engine {
+                for line in pipeline.finish()? {
+                    self.emit("", &line, partitions, output)?;
+                }
+                for (event, count) in pipeline.lossy_events() {
+                    self.degradations.record_count(event, count)?;
+                }
+            }
+            self.summary.add_steps(engine.step_stats());
+        }
+        if let Some(partitions) = partitions.as_mut() {
+            partitions.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn emit(
+        &mut self,
+        prefix: &str,
+        line: &str,
+        partitions: &mut Option<PartitionedOutput>,
+        output: &mut impl Write,
+    ) -> Result<(), RanglerError> {
+        let line = match &self.highlighter {
+            Some(highlighter) => highlighter.highlight(line),
+            None => Cow::Borrowed(line),
+        };
+        self.summary.lines_emitted += 1;
+        self.summary.bytes_written += prefix.len() + line.len() + self.terminator.len();
+        self.digest(prefix.as_bytes(), line.as_bytes());
+
+        write_line(
+            prefix,
+            &line,
+            self.terminator,
+            partitions,
+            output,
+            &mut self.record,
+        )?;
+        if self.options.line_buffered {
+            output.flush().map_err(write_error)?;
+        }
+
+        Ok(())
+    }
+
+    // Emits what came back from the worker threads, stopping at the line a
+    // batch failed on.
+    fn emit_processed(
+        &mut self,
+        processed: Vec<Processed>,
+        partitions: &mut Option<PartitionedOutput>,
+        output: &mut impl Write,
+    ) -> Result<(), RanglerError> {
+        for (lines, error) in processed {
+            for (prefix, lines) in lines {
+                for line in lines {
+                    self.emit(&prefix, &line, partitions, output)?;
+                }
+            }
+            if let Some(message) = error {
+                Err(message)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Emits what came out of the last step thread, then the error that
+    // stopped the steps, if any.
+    fn emit_received(
+        &mut self,
+        (lines, error): Received,
+        partitions: &mut Option<PartitionedOutput>,
+        output: &mut impl Write,
+    ) -> Result<(), RanglerError> {
+        for (prefix, line) in lines {
+            self.emit(&prefix, &line, partitions, output)?;
+        }
+
+        match error {
+            Some(message) => Err(message)?,
+            None => Ok(()),
+        }
+    }
+
+    // Writes the whole record at once, as split and encoded outputs expect.
+    fn emit_bytes(
+        &mut self,
+        prefix: &str,
+        line: &[u8],
+        output: &mut impl Write,
+    ) -> Result<(), RanglerError> {
+        let terminator = self.terminator.as_bytes();
+        write_record(
+            output,
+            &mut self.record,
+            &[prefix.as_bytes(), line, terminator],
+        )?;
+
+        self.summary.lines_emitted += 1;
+        self.summary.bytes_written += prefix.len() + line.len() + self.terminator.len();
+        self.digest(prefix.as_bytes(), line);
+        if self.options.line_buffered {
+            output.flush().map_err(write_error)?;
+        }
+
+        Ok(())
+    }
+
+    // Writes a line the pipeline dropped to the --rejects sink, after the
+    // number and name of the step that dropped it with --annotate-rejects.
+    fn reject(
+        &mut self,
+        pipeline: &Pipeline,
+        prefix: &str,
+        line: &str,
+    ) -> Result<(), RanglerError> {
+        let (Some(rejects), Some((number, step))) = (self.rejects.as_mut(), pipeline.rejected_by())
+        else {
+            return Ok(());
+        };
+
+        let rejected = match self.options.annotate_rejects {
+            true => format!("{}{}:{}\t{}", prefix, number, step, line),
+            false => format!("{}{}", prefix, line),
+        };
+        rejects.receive(rejected)?;
+
+        Ok(())
+    }
+
+    // Writes a --header or --footer line. It frames the output rather than
+    // being one of its lines, so only the bytes are counted.
+    fn frame(&mut self, text: Option<&str>, output: &mut impl Write) -> Result<(), RanglerError> {
+        let Some(text) = text else {
+            return Ok(());
+        };
+
+        let terminator = self.terminator.as_bytes();
+        write_record(output, &mut self.record, &[text.as_bytes(), terminator])?;
+        self.summary.bytes_written += text.len() + self.terminator.len();
+        self.digest(b"", text.as_bytes());
+
+        Ok(())
+    }
+
+    // Feeds a written record to the --checksum digest, as it went out before
+    // any compression or encoding.
+    fn digest(&mut self, prefix: &[u8], line: &[u8]) {
+        if let Some(digest) = &mut self.checksum {
+            digest.update(prefix);
+            digest.update(line);
+            digest.update(self.terminator.as_bytes());
+        }
+    }
+}
+
+/// Text mode runs the full pipeline on decoded records, while byte mode runs
+/// the steps that work on raw bytes and never decodes anything.
+pub enum Engine {
+    Text(Pipeline),
+    Bytes(BytePipeline),
+}
+
+impl Engine {
+    pub fn build<T: AsRef<str>>(options: &Options, commands: &[T]) -> Result<Engine, RanglerError> {
+        if options.bytes {
+            BytePipeline::build_pipeline(commands).map(Engine::Bytes)
+        } else {
+            build_pipeline(options, commands).map(Engine::Text)
+        }
+    }
+
+    /// Warnings about step orderings that probably do not do what was meant,
+    /// such as `dedupe` before `trim`.
+    pub fn lint(&self, options: &Options) -> Vec<String> {
+        match self {
+            Engine::Text(pipeline) => crate::lint::lint(pipeline, options),
+            Engine::Bytes(_) => vec![],
+        }
+    }
+
+    /// Rewrites a text pipeline to produce the same lines with less work, see
+    /// [`Pipeline::optimize`].
+    pub fn optimize(&mut self) {
+        if let Engine::Text(pipeline) = self {
+            pipeline.optimize();
+        }
+    }
+
+    /// Runs the first lines of the input through the pipeline and shows what
+    /// became of each, for --preview.
+    pub fn preview(&mut self, options: &Options) -> Result<String, RanglerError> {
+        let (Engine::Text(pipeline), Some(count)) = (self, options.preview) else {
+            Err("Previews need text mode")?
+        };
+
+        let mut paths = options.inputs.clone();
+        paths.extend(expand_globs(&options.globs)?);
+        let (sources, _) = open_inputs(&paths, options.read_buffer, options.mmap)?;
+        let mut lines = vec![];
+        for (name, source) in sources {
+            let mut records = RecordReader::new(source, options.record_separator.clone());
+            while lines.len() < count {
+                let Some((mut record, _)) = records.next_record()? else {
+                    break;
+                };
+                if !options.keep_eol {
+                    strip_carriage_returns(&mut record);
+                }
+                lines.push((name.clone(), String::from_utf8_lossy(&record).into_owned()));
+            }
+        }
+
+        Ok(crate::preview::preview(pipeline, &lines)?)
+    }
+
+    /// Describes the steps the engine was built with, for --explain.
+    pub fn explain(&self) -> String {
+        match self {
+            Engine::Text(pipeline) => pipeline.explain(),
+            Engine::Bytes(pipeline) => format!("Byte mode\n{}", pipeline.explain()),
+        }
+    }
+
+    fn sample_memory(&mut self) -> usize {
+        match self {
+            Engine::Text(pipeline) => pipeline.sample_memory(),
+            Engine::Bytes(pipeline) => pipeline.sample_memory(),
+        }
+    }
+
+    fn step_stats(&self) -> &[StepStats] {
+        match self {
+            Engine::Text(pipeline) => pipeline.step_stats(),
+            Engine::Bytes(pipeline) => pipeline.step_stats(),
+        }
+    }
+}
+
+// Builds a text pipeline with every option that changes how its steps run,
+// for the run and for the copies --jobs threads make of it.
+pub(crate) fn build_pipeline<T: AsRef<str>>(
+    options: &Options,
+    commands: &[T],
+) -> Result<Pipeline, RanglerError> {
+    let mut pipeline = Pipeline::build_pipeline(commands)?;
+    if let Some(policy) = options.on_error {
+        pipeline.set_error_policy(policy);
+    }
+    // Half the budget is left for the other steps.
+    if let Some(budget) = options.max_memory {
+        pipeline.spill_dedupes(budget / 2);
+    }
+    if let Some(seed) = options.seed {
+        pipeline.set_seed(seed);
+    }
+    if let Some(rules) = options.case_rules {
+        pipeline.set_case_rules(rules);
+    }
+    if options.ignore_case {
+        pipeline.set_ignore_case();
+    }
+    pipeline.set_terminator(terminator(options));
+    if options.is_tracing() {
+        let pattern = options
+            .trace_match
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|_| "Invalid trace pattern")?;
+        pipeline.set_trace(Trace::new(options.trace, pattern));
+    }
+
+    Ok(pipeline)
+}
+
+fn terminator(options: &Options) -> &'static str {
+    match (options.print0, options.crlf_out) {
+        (true, _) => "\0",
+        (false, true) => "\r\n",
+        (false, false) => "\n",
+    }
+}
+
+// What goes before a record's lines with --with-filename, --with-line-number
+// and --with-offset.
+pub(crate) fn record_prefix(
+    options: &Options,
+    name: &str,
+    record_number: usize,
+    offset: usize,
+) -> String {
+    [
+        options.with_filename.then(|| name.to_string()),
+        options.with_line_number.then(|| record_number.to_string()),
+        options.with_offset.then(|| offset.to_string()),
+    ]
+    .into_iter()
+    .flatten()
+    .map(|part| part + ":")
+    .collect()
+}
+
+// Writes the prefix, line and terminator as one record, unless a partition
+// took the line.
+fn write_line(
+    prefix: &str,
+    line: &str,
+    terminator: &str,
+    partitions: &mut Option<PartitionedOutput>,
+    std_out: &mut impl Write,
+    record: &mut Vec<u8>,
+) -> Result<(), RanglerError> {
+    let partitioned = match partitions.as_mut() {
+        Some(partitions) if prefix.is_empty() => partitions.write_line(line)?,
+        Some(partitions) => partitions.write_line(&(prefix.to_string() + line))?,
+        None => false,
+    };
+
+    if !partitioned {
+        let parts = [prefix.as_bytes(), line.as_bytes(), terminator.as_bytes()];
+        write_record(std_out, record, &parts)?;
+    }
+
+    Ok(())
+}
+
+// Puts the parts of a record together in the buffer and writes them with one
+// call, as split outputs count every write as a record and cut files between
+// writes.
+fn write_record(
+    output: &mut impl Write,
+    record: &mut Vec<u8>,
+    parts: &[&[u8]],
+) -> Result<(), RanglerError> {
+    record.clear();
+    for part in parts {
+        record.extend_from_slice(part);
+    }
+
+    Ok(output.write_all(record).map_err(write_error)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Engine};
+    use crate::options::Options;
+
+    #[test]
+    fn run_splits_prefixed_and_framed_output_between_records() {
+        //+ Arrange
+        let directory =
+            std::env::temp_dir().join(format!("rangler-run-split-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let input = directory.join("input.txt");
+        std::fs::write(&input, "A\nB\nC\nD\nE\n").unwrap();
+        let (input, output) = (
+            input.to_string_lossy().into_owned(),
+            directory.join("out").to_string_lossy().into_owned(),
+        );
+
+        //+ Act
+        let mut files = vec![];
+        for limit in [["--split-lines", "2"], ["--split-bytes", "4"]] {
+            let args = [
+                limit[0],
+                limit[1],
+                "-o",
+                &output,
+                "--with-line-number",
+                "--header",
+                "H",
+                "--no-progress",
+                "upper",
+                "--",
+                &input,
+            ];
+            let (options, commands) = Options::parse(&args).unwrap();
+            let commands: Vec<String> = commands.iter().map(|c| c.to_string()).collect();
+            let engine = Engine::build(&options, &commands).unwrap();
+            run(&options, &commands, engine).unwrap();
+
+            let mut parts: Vec<_> = std::fs::read_dir(&directory)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|path| path.to_string_lossy().contains("out."))
+                .collect();
+            parts.sort();
+            files.push(
+                parts
+                    .iter()
+                    .map(|path| {
+                        let text = std::fs::read_to_string(path).unwrap();
+                        std::fs::remove_file(path).unwrap();
+                        text
+                    })
+                    .collect::<Vec<_>>(),
+            );
+        }
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        //+ Assert
+        assert_eq!(files[0], ["H\n1:A\n", "2:B\n3:C\n", "4:D\n5:E\n"]);
+        assert!(files[1].iter().all(|file| file.ends_with('\n')));
+        assert_eq!(files[1].concat(), "H\n1:A\n2:B\n3:C\n4:D\n5:E\n");
+    }
+}
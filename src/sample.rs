@@ -0,0 +1,158 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// A SplitMix64 generator: small, fast and good enough for picking lines,
+// and the same seed always gives the same sequence.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    // Without --seed every run picks differently.
+    pub fn from_entropy() -> Random {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+
+        Random::new(nanos ^ (std::process::id() as u64).rotate_left(32))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+        z ^ (z >> 31)
+    }
+
+    // Uniform in [0, 1), from the top 53 bits.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Uniform in [0, bound); the bias of the modulo is far below anything a
+    // shuffle of lines would show.
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+}
+
+// Keeps each line with the given probability.
+#[derive(Debug, PartialEq)]
+pub struct Sample {
+    rate: f64,
+    random: Random,
+}
+
+impl Sample {
+    pub fn new(rate: f64) -> Sample {
+        Sample {
+            rate,
+            random: Random::from_entropy(),
+        }
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    pub fn keeps(&mut self) -> bool {
+        self.random.next_f64() < self.rate
+    }
+}
+
+// Buffers the whole stream and emits it in a random order at the end.
+#[derive(Debug, PartialEq)]
+pub struct Shuffle {
+    lines: Vec<String>,
+    stored: usize,
+    random: Random,
+}
+
+impl Shuffle {
+    pub fn new() -> Shuffle {
+        Shuffle {
+            lines: vec![],
+            stored: 0,
+            random: Random::from_entropy(),
+        }
+    }
+
+    pub fn reseed(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
+    pub fn push(&mut self, line: String) {
+        self.stored += line.len();
+        self.lines.push(line);
+    }
+
+    // Fisher-Yates, from the back.
+    pub fn flush(&mut self) -> Vec<String> {
+        let mut lines = std::mem::take(&mut self.lines);
+        for index in (1..lines.len()).rev() {
+            let other = self.random.below(index + 1);
+            lines.swap(index, other);
+        }
+
+        self.stored = 0;
+        lines
+    }
+
+    pub fn memory(&self) -> usize {
+        self.stored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Random, Sample, Shuffle};
+
+    #[test]
+    fn same_seed_gives_same_picks() {
+        //+ Arrange
+        let mut first = Sample::new(0.5);
+        let mut second = Sample::new(0.5);
+        first.reseed(7);
+        second.reseed(7);
+
+        //+ Act
+        let first_picks: Vec<bool> = (0..64).map(|_| first.keeps()).collect();
+        let second_picks: Vec<bool> = (0..64).map(|_| second.keeps()).collect();
+
+        //+ Assert
+        assert_eq!(first_picks, second_picks);
+        assert!(first_picks.contains(&true) && first_picks.contains(&false));
+        assert_ne!(Random::new(1).next_u64(), Random::new(2).next_u64());
+    }
+
+    #[test]
+    fn shuffle_emits_every_line_once() {
+        //+ Arrange
+        let mut shuffle = Shuffle::new();
+        shuffle.reseed(42);
+        for number in 0..100 {
+            shuffle.push(number.to_string());
+        }
+
+        //+ Act
+        let mut lines = shuffle.flush();
+        let order = lines.clone();
+        lines.sort_by_key(|line| line.parse::<usize>().unwrap());
+
+        //+ Assert
+        assert_eq!(
+            lines,
+            (0..100)
+                .map(|number| number.to_string())
+                .collect::<Vec<_>>()
+        );
+        assert_ne!(order, lines);
+        assert_eq!(shuffle.memory(), 0);
+    }
+}
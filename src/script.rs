@@ -0,0 +1,134 @@
+use std::fmt;
+
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST};
+
+// Runs a Rhai script on every line. The script sees `line`, the line number
+// `n`, the groups of the last filter as `captures` (0 is the whole match) and
+// the named ones as `groups`. Whatever it evaluates to replaces the line: a
+// string or number is one line, `()` drops it and an array fans out.
+pub struct Script {
+    source: String,
+    engine: Engine,
+    ast: AST,
+}
+
+// Keeps a script that loops forever from hanging the whole run.
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+impl Script {
+    // Takes a path to a script file, or the script itself.
+    pub fn load(script: &str) -> Result<Script, &'static str> {
+        let source = match std::fs::read_to_string(script) {
+            Ok(source) => source,
+            Err(_) => script.to_string(),
+        };
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let ast = engine.compile(&source).map_err(|_| "Invalid script")?;
+
+        Ok(Script {
+            source,
+            engine,
+            ast,
+        })
+    }
+
+    pub fn run(
+        &self,
+        line: String,
+        number: usize,
+        captures: &[(Option<String>, Option<String>)],
+    ) -> Result<Vec<String>, &'static str> {
+        let mut groups = Map::new();
+        let mut indexed = Array::new();
+        for (name, value) in captures {
+            let value = value.clone().map_or(Dynamic::UNIT, Dynamic::from);
+            if let Some(name) = name {
+                groups.insert(name.into(), value.clone());
+            }
+            indexed.push(value);
+        }
+
+        let mut scope = Scope::new();
+        scope.push("line", line);
+        scope.push("n", number as i64);
+        scope.push("captures", indexed);
+        scope.push("groups", groups);
+
+        let result: Dynamic = self
+            .engine
+            .eval_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|_| "Script failed")?;
+
+        if result.is_unit() {
+            Ok(vec![])
+        } else if result.is_array() {
+            Ok(result
+                .cast::<Array>()
+                .into_iter()
+                .filter(|item| !item.is_unit())
+                .map(|item| item.to_string())
+                .collect())
+        } else {
+            Ok(vec![result.to_string()])
+        }
+    }
+}
+
+impl fmt::Debug for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Script")
+            .field("source", &self.source)
+            .finish()
+    }
+}
+
+impl PartialEq for Script {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Script;
+
+    #[test]
+    fn run_replaces_drops_or_fans_out() {
+        //+ Arrange
+        let script = Script::load(
+            r##"if line.starts_with("#") { () } else if line.contains(",") { line.split(",") } else { `${n}: ${line}` }"##,
+        )
+        .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            script.run("a".to_string(), 1, &[]),
+            Ok(vec!["1: a".to_string()])
+        );
+        assert_eq!(script.run("# note".to_string(), 2, &[]), Ok(vec![]));
+        assert_eq!(
+            script.run("b,c".to_string(), 3, &[]),
+            Ok(vec!["b".to_string(), "c".to_string()])
+        );
+    }
+
+    #[test]
+    fn run_exposes_captures() {
+        //+ Arrange
+        let script = Script::load(r#"groups.user + "@" + captures[2]"#).unwrap();
+        let captures = vec![
+            (None, Some("alice example.com".to_string())),
+            (Some("user".to_string()), Some("alice".to_string())),
+            (None, Some("example.com".to_string())),
+        ];
+
+        //+ Act + Assert
+        assert_eq!(
+            script.run("ignored".to_string(), 1, &captures),
+            Ok(vec!["alice@example.com".to_string()])
+        );
+        assert_eq!(Script::load("1 +").err(), Some("Invalid script"));
+    }
+}
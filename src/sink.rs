@@ -0,0 +1,73 @@
+use std::{
+    borrow::Cow,
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use crate::pipeline::Pipeline;
+
+#[derive(Debug)]
+pub enum Sink {
+    File(String, BufWriter<File>),
+    Stderr,
+    Drop,
+    Pipeline(Pipeline),
+}
+
+impl Sink {
+    pub fn parse(target: &str) -> Result<Sink, &'static str> {
+        match target {
+            "stderr" => Ok(Sink::Stderr),
+            "drop" => Ok(Sink::Drop),
+            path => Sink::file(path),
+        }
+    }
+
+    pub fn file(path: &str) -> Result<Sink, &'static str> {
+        let file = File::create(path).map_err(|_| "Could not create output file")?;
+
+        Ok(Sink::File(path.to_string(), BufWriter::new(file)))
+    }
+
+    pub fn receive(&mut self, line: String) -> Result<Vec<String>, &'static str> {
+        match self {
+            Sink::File(_, writer) => {
+                writeln!(writer, "{}", line).map_err(|_| "IO Error")?;
+                Ok(vec![])
+            }
+            Sink::Stderr => {
+                eprintln!("{}", line);
+                Ok(vec![])
+            }
+            Sink::Drop => Ok(vec![]),
+            Sink::Pipeline(pipeline) => Ok(pipeline
+                .apply(&line)?
+                .into_iter()
+                .map(Cow::into_owned)
+                .collect()),
+        }
+    }
+
+    pub fn finish(&mut self) -> Result<Vec<String>, &'static str> {
+        match self {
+            Sink::File(_, writer) => {
+                writer.flush().map_err(|_| "IO Error")?;
+                Ok(vec![])
+            }
+            Sink::Pipeline(pipeline) => pipeline.finish(),
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+impl PartialEq for Sink {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::File(left_path, _), Self::File(right_path, _)) => left_path == right_path,
+            (Self::Pipeline(left_pipeline), Self::Pipeline(right_pipeline)) => {
+                left_pipeline == right_pipeline
+            }
+            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
+        }
+    }
+}
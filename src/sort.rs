@@ -0,0 +1,232 @@
+use std::cmp::Ordering;
+
+use regex::Regex;
+
+// What a line is sorted by: a whitespace-separated field, counted from 1, or
+// the first capture group of a regex (or its whole match).
+#[derive(Debug)]
+pub enum SortKey {
+    Field(usize),
+    Pattern(Regex),
+}
+
+impl SortKey {
+    // A bare number picks a field; anything else is a regex.
+    pub fn parse(key: &str) -> Result<SortKey, &'static str> {
+        match key.parse::<usize>() {
+            Ok(0) => Err("Invalid sort field"),
+            Ok(field) => Ok(SortKey::Field(field)),
+            Err(_) => Regex::new(key)
+                .map(SortKey::Pattern)
+                .map_err(|_| "Invalid regular expression"),
+        }
+    }
+
+    pub(crate) fn extract<'a>(&self, line: &'a str) -> Option<&'a str> {
+        match self {
+            SortKey::Field(field) => line.split_whitespace().nth(field - 1),
+            SortKey::Pattern(regex) => {
+                let captures = regex.captures(line)?;
+                Some(captures.get(1).or(captures.get(0))?.as_str())
+            }
+        }
+    }
+}
+
+impl PartialEq for SortKey {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (SortKey::Field(left), SortKey::Field(right)) => left == right,
+            (SortKey::Pattern(left), SortKey::Pattern(right)) => left.as_str() == right.as_str(),
+            _ => false,
+        }
+    }
+}
+
+// How two keys compare. Numeric reads the number the key starts with, so
+// `250ms` sorts as 250; keys without one sort before every number. Natural
+// compares runs of digits by their value and the text between them as text,
+// so `file2` sorts before `file10` and `v1.9.0` before `v1.10.0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    Text,
+    Numeric,
+    Natural,
+}
+
+// Buffers the whole stream and emits it sorted at the end of input. The sort
+// is stable, so lines with equal keys keep their input order, reversed or
+// not. Lines without a key sort first.
+#[derive(Debug, PartialEq)]
+pub struct Sort {
+    order: SortOrder,
+    reverse: bool,
+    key: Option<SortKey>,
+    lines: Vec<String>,
+    stored: usize,
+}
+
+impl Sort {
+    pub fn new(order: SortOrder, reverse: bool, key: Option<SortKey>) -> Sort {
+        Sort {
+            order,
+            reverse,
+            key,
+            lines: vec![],
+            stored: 0,
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        self.stored += line.len();
+        self.lines.push(line);
+    }
+
+    pub fn flush(&mut self) -> Vec<String> {
+        let mut lines = std::mem::take(&mut self.lines);
+        lines.sort_by(|left, right| {
+            let ordering = self.compare(left, right);
+            match self.reverse {
+                true => ordering.reverse(),
+                false => ordering,
+            }
+        });
+
+        self.stored = 0;
+        lines
+    }
+
+    pub fn memory(&self) -> usize {
+        self.stored
+    }
+
+    fn compare(&self, left: &str, right: &str) -> Ordering {
+        let (left, right) = match &self.key {
+            Some(key) => (key.extract(left), key.extract(right)),
+            None => (Some(left), Some(right)),
+        };
+
+        match (self.order, left, right) {
+            (SortOrder::Text, left, right) => left.cmp(&right),
+            (SortOrder::Numeric, left, right) => {
+                let left = left.and_then(leading_number);
+                let right = right.and_then(leading_number);
+                match (left, right) {
+                    (Some(left), Some(right)) => left.total_cmp(&right),
+                    (left, right) => left.is_some().cmp(&right.is_some()),
+                }
+            }
+            (SortOrder::Natural, Some(left), Some(right)) => natural_cmp(left, right),
+            (SortOrder::Natural, left, right) => left.is_some().cmp(&right.is_some()),
+        }
+    }
+}
+
+// Walks both texts a run at a time. Digit runs compare by value without
+// parsing, so any length works: leading zeros are skipped, then the longer
+// run is the larger number. Texts equal that way, like `01` and `1`, fall
+// back to comparing as text so the order is still total.
+fn natural_cmp(left: &str, right: &str) -> Ordering {
+    let (mut rest_left, mut rest_right) = (left, right);
+    while let (Some(a), Some(b)) = (rest_left.chars().next(), rest_right.chars().next()) {
+        let ordering = match (a.is_ascii_digit(), b.is_ascii_digit()) {
+            (true, true) => {
+                let (digits_left, tail_left) = split_digits(rest_left);
+                let (digits_right, tail_right) = split_digits(rest_right);
+                rest_left = tail_left;
+                rest_right = tail_right;
+
+                let digits_left = digits_left.trim_start_matches('0');
+                let digits_right = digits_right.trim_start_matches('0');
+                digits_left
+                    .len()
+                    .cmp(&digits_right.len())
+                    .then_with(|| digits_left.cmp(digits_right))
+            }
+            _ => {
+                rest_left = &rest_left[a.len_utf8()..];
+                rest_right = &rest_right[b.len_utf8()..];
+                a.cmp(&b)
+            }
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    rest_left
+        .len()
+        .cmp(&rest_right.len())
+        .then_with(|| left.cmp(right))
+}
+
+fn split_digits(text: &str) -> (&str, &str) {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+
+    text.split_at(end)
+}
+
+// The number at the start of the text, after any leading whitespace, e.g.
+// -1.5 from `-1.5s`.
+pub(crate) fn leading_number(text: &str) -> Option<f64> {
+    let text = text.trim_start();
+    let bytes = text.as_bytes();
+    let mut end = usize::from(matches!(bytes.first(), Some(b'-' | b'+')));
+    let digits = end;
+    while end < bytes.len() && bytes[end].is_ascii_digit() {
+        end += 1;
+    }
+    if end < bytes.len() && bytes[end] == b'.' {
+        end += 1;
+        while end < bytes.len() && bytes[end].is_ascii_digit() {
+            end += 1;
+        }
+    }
+
+    match text[digits..end].bytes().any(|byte| byte.is_ascii_digit()) {
+        true => text[..end].parse().ok(),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sort, SortKey, SortOrder};
+
+    #[test]
+    fn numeric_sort_by_field_keeps_ties_in_input_order() {
+        //+ Arrange
+        let mut sort = Sort::new(SortOrder::Numeric, true, Some(SortKey::parse("2").unwrap()));
+        for line in ["a 9ms", "b 100ms", "c -", "d 9.0ms", "e 20ms"] {
+            sort.push(line.to_string());
+        }
+
+        //+ Act
+        let lines = sort.flush();
+
+        //+ Assert
+        assert_eq!(lines, ["b 100ms", "e 20ms", "a 9ms", "d 9.0ms", "c -"]);
+        assert_eq!(sort.memory(), 0);
+        assert!(SortKey::parse("0").is_err());
+    }
+
+    #[test]
+    fn natural_sort_compares_digit_runs_by_value() {
+        //+ Arrange
+        let mut sort = Sort::new(SortOrder::Natural, false, None);
+        for line in ["v1.10.0", "file10", "v1.9.0", "file2", "file02", "file"] {
+            sort.push(line.to_string());
+        }
+
+        //+ Act
+        let lines = sort.flush();
+
+        //+ Assert
+        assert_eq!(
+            lines,
+            ["file", "file02", "file2", "file10", "v1.9.0", "v1.10.0"]
+        );
+    }
+}
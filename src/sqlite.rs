@@ -0,0 +1,213 @@
+use std::io::Write;
+
+use rusqlite::{params_from_iter, types::Value as SqlValue, Connection};
+use serde_json::Value;
+
+// Rows go in one transaction per batch, which is what makes SQLite fast at
+// bulk inserts; a crash loses at most the batch in progress.
+const BATCH: usize = 1000;
+
+// Inserts every record written to it as a row of a table, for
+// `--output sqlite://file.db#table`. A JSON object fills one column per key
+// and any other record the `line` column. The table is created on the first
+// row and gains a column whenever a key turns up that it does not have yet,
+// so columns missing from a row are left NULL.
+pub struct SqliteOutput {
+    connection: Connection,
+    table: String,
+    columns: Vec<String>,
+    terminator: Vec<u8>,
+    record: Vec<u8>,
+    batched: usize,
+}
+
+impl SqliteOutput {
+    // The table defaults to `lines` when the location does not name one.
+    pub fn open(location: &str, terminator: &str) -> Result<SqliteOutput, String> {
+        let location = location
+            .strip_prefix("sqlite://")
+            .ok_or("Invalid SQLite location")?;
+        let (path, table) = location.split_once('#').unwrap_or((location, "lines"));
+        if path.is_empty() || table.is_empty() {
+            return Err("Invalid SQLite location".to_string());
+        }
+
+        let connection = Connection::open(path)
+            .map_err(|error| format!("Could not open SQLite database {}: {}", path, error))?;
+        let columns = connection
+            .prepare(&format!("PRAGMA table_info({})", quote(table)))
+            .and_then(|mut statement| {
+                statement
+                    .query_map([], |row| row.get::<_, String>(1))?
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .map_err(sql_error)?;
+        connection.execute_batch("BEGIN").map_err(sql_error)?;
+
+        Ok(SqliteOutput {
+            connection,
+            table: table.to_string(),
+            columns,
+            terminator: terminator.as_bytes().to_vec(),
+            record: vec![],
+            batched: 0,
+        })
+    }
+
+    fn insert(&mut self, record: &[u8]) -> Result<(), String> {
+        let text = String::from_utf8_lossy(record);
+        let fields: Vec<(String, SqlValue)> = match serde_json::from_str(&text) {
+            Ok(Value::Object(object)) => object
+                .into_iter()
+                .map(|(key, value)| (key, to_sql(value)))
+                .collect(),
+            _ => vec![("line".to_string(), SqlValue::Text(text.into_owned()))],
+        };
+
+        for (name, _) in &fields {
+            if self.columns.contains(name) {
+                continue;
+            }
+            let statement = match self.columns.is_empty() {
+                true => format!("CREATE TABLE {} ({})", quote(&self.table), quote(name)),
+                false => format!(
+                    "ALTER TABLE {} ADD COLUMN {}",
+                    quote(&self.table),
+                    quote(name)
+                ),
+            };
+            self.connection.execute(&statement, []).map_err(sql_error)?;
+            self.columns.push(name.clone());
+        }
+
+        let names: Vec<String> = fields.iter().map(|(name, _)| quote(name)).collect();
+        let placeholders = vec!["?"; fields.len()].join(", ");
+        self.connection
+            .prepare_cached(&format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                quote(&self.table),
+                names.join(", "),
+                placeholders
+            ))
+            .and_then(|mut statement| {
+                statement.execute(params_from_iter(fields.into_iter().map(|(_, value)| value)))
+            })
+            .map_err(sql_error)?;
+
+        self.batched += 1;
+        if self.batched >= BATCH {
+            self.connection
+                .execute_batch("COMMIT; BEGIN")
+                .map_err(sql_error)?;
+            self.batched = 0;
+        }
+
+        Ok(())
+    }
+
+    // A last record without a terminator is still inserted.
+    pub fn finish(mut self) -> Result<(), &'static str> {
+        if !self.record.is_empty() {
+            let record = std::mem::take(&mut self.record);
+            self.insert(&record)
+                .map_err(|_| "Could not write SQLite output")?;
+        }
+
+        self.connection
+            .execute_batch("COMMIT")
+            .map_err(|_| "Could not write SQLite output")
+    }
+}
+
+// Records arrive in pieces, the line and its terminator written separately,
+// so they are only inserted once the terminator is in.
+impl Write for SqliteOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.record.extend_from_slice(buf);
+        while let Some(end) = self
+            .record
+            .windows(self.terminator.len())
+            .position(|window| window == self.terminator)
+        {
+            let rest = self.record.split_off(end + self.terminator.len());
+            let record = std::mem::replace(&mut self.record, rest);
+            self.insert(&record[..end]).map_err(std::io::Error::other)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+// Numbers and booleans keep their type; nested arrays and objects are stored
+// as JSON text.
+fn to_sql(value: Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(flag) => SqlValue::Integer(flag as i64),
+        Value::Number(number) => match number.as_i64() {
+            Some(integer) => SqlValue::Integer(integer),
+            None => SqlValue::Real(number.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(text) => SqlValue::Text(text),
+        nested => SqlValue::Text(nested.to_string()),
+    }
+}
+
+fn sql_error(error: rusqlite::Error) -> String {
+    format!("SQLite error: {}", error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::SqliteOutput;
+
+    #[test]
+    fn write_inserts_json_keys_as_columns() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-sqlite-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let location = format!("sqlite://{}#events", path.display());
+        let mut output = SqliteOutput::open(&location, "\n").unwrap();
+
+        //+ Act
+        output.write_all(b"{\"id\":1,\"user\":\"ann\"}").unwrap();
+        output
+            .write_all(b"\n{\"id\":2,\"ok\":true}\nnot json")
+            .unwrap();
+        output.finish().unwrap();
+
+        //+ Assert
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let rows: Vec<String> = connection
+            .prepare("SELECT quote(id), quote(user), quote(ok), quote(line) FROM events")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((0..4)
+                    .map(|index| row.get::<_, String>(index).unwrap())
+                    .collect::<Vec<_>>()
+                    .join(","))
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                "1,'ann',NULL,NULL",
+                "2,NULL,1,NULL",
+                "NULL,NULL,NULL,'not json'"
+            ]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+use std::{
+    sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender, TrySendError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::{
+    degradation::LossyEvent,
+    error::RanglerError,
+    options::Options,
+    pipeline::{Pipeline, PipelineStep},
+    stats::StepStats,
+};
+
+// How many lines can wait between two steps before the earlier one blocks.
+const CHANNEL_CAPACITY: usize = 1024;
+
+enum Message {
+    // A line with its number and the prefix it is written out with.
+    Line(usize, String, String),
+    Failed(&'static str),
+}
+
+/// Lines that came out of the last step, each with its prefix, and the error
+/// that stopped the steps, if any.
+pub type Received = (Vec<(String, String)>, Option<&'static str>);
+
+type Finished = (Vec<StepStats>, Vec<(LossyEvent, usize)>);
+
+// Runs every step on a thread of its own, each handing its lines to the next
+// over a bounded channel, so slow steps overlap with each other and with
+// reading and writing.
+pub struct Stages {
+    input: Option<SyncSender<Message>>,
+    output: Receiver<Message>,
+    threads: Vec<JoinHandle<Finished>>,
+    line_number: usize,
+}
+
+impl Stages {
+    // Steps that hand each other more than the line, the captures of a
+    // filter or the tags a route reads, have to stay on one thread.
+    pub fn can_stage(pipeline: &Pipeline) -> bool {
+        let steps: Vec<&PipelineStep> = pipeline.named_steps().map(|(_, step)| step).collect();
+
+        steps.len() > 1
+            && !steps.iter().any(|step| {
+                step.uses_captures()
+                    || step.uses_source()
+                    || matches!(step, PipelineStep::Tag(..) | PipelineStep::Route(..))
+            })
+    }
+
+    // Every step is built again from its own commands on its own thread, as
+    // steps stay on the thread they were built on.
+    pub fn start(commands: &[String], options: &Options) -> Result<Stages, RanglerError> {
+        let steps = Pipeline::build_pipeline(commands)?.step_commands();
+        let (input, mut received) = sync_channel(CHANNEL_CAPACITY);
+        let mut threads = vec![];
+
+        for (index, commands) in steps.into_iter().enumerate() {
+            let (sender, next) = sync_channel(CHANNEL_CAPACITY);
+            let (sent_ready, ready) = sync_channel(1);
+            let on_error = options.on_error;
+            let (case_rules, ignore_case) = (options.case_rules, options.ignore_case);
+            // The seed each step would get in one pipeline, so staging picks
+            // the same lines.
+            let seed = options.seed.map(|seed| seed.wrapping_add(index as u64));
+
+            threads.push(thread::spawn(move || {
+                let mut pipeline = match Pipeline::build_pipeline(&commands) {
+                    Ok(pipeline) => pipeline,
+                    Err(error) => {
+                        sent_ready.send(Err(error)).ok();
+                        return (vec![], vec![]);
+                    }
+                };
+                if let Some(policy) = on_error {
+                    pipeline.set_error_policy(policy);
+                }
+                if let Some(seed) = seed {
+                    pipeline.set_seed(seed);
+                }
+                if let Some(rules) = case_rules {
+                    pipeline.set_case_rules(rules);
+                }
+                if ignore_case {
+                    pipeline.set_ignore_case();
+                }
+                sent_ready.send(Ok(())).ok();
+
+                run_stage(&mut pipeline, received, sender);
+                (pipeline.step_stats().to_vec(), pipeline.lossy_events())
+            }));
+
+            ready.recv().map_err(|_| "Step thread stopped")??;
+            received = next;
+        }
+
+        Ok(Stages {
+            input: Some(input),
+            output: received,
+            threads,
+            line_number: 0,
+        })
+    }
+
+    // Hands a line to the first step, along with whatever came out of the
+    // last one meanwhile. While the first step is behind, the output is
+    // drained so the steps never wait on each other for good.
+    pub fn push(&mut self, prefix: String, line: String) -> Received {
+        self.line_number += 1;
+        let mut message = Message::Line(self.line_number, prefix, line);
+        let mut received = (vec![], None);
+        let input = self.input.as_ref().expect("Stages used after finishing");
+
+        loop {
+            match input.try_send(message) {
+                Ok(()) => break,
+                Err(TrySendError::Full(unsent)) => {
+                    message = unsent;
+                    match self.output.recv_timeout(Duration::from_millis(1)) {
+                        Ok(output) => receive(output, &mut received),
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+                // The first step stopped, so its error is on its way out.
+                Err(TrySendError::Disconnected(_)) => {
+                    for output in self.output.iter() {
+                        receive(output, &mut received);
+                    }
+                    break;
+                }
+            }
+            if received.1.is_some() {
+                return received;
+            }
+        }
+
+        while let Ok(output) = self.output.try_recv() {
+            receive(output, &mut received);
+        }
+
+        received
+    }
+
+    // Ends the input, so every step drains what it holds in turn, and waits
+    // for the rest of the output and the stats of every step.
+    pub fn finish(mut self) -> (Received, Finished) {
+        drop(self.input.take());
+
+        let mut received = (vec![], None);
+        for output in self.output.iter() {
+            receive(output, &mut received);
+        }
+
+        let mut finished: Finished = (vec![], vec![]);
+        for thread in self.threads {
+            if let Ok((steps, events)) = thread.join() {
+                finished.0.extend(steps);
+                finished.1.extend(events);
+            }
+        }
+
+        (received, finished)
+    }
+}
+
+fn receive(message: Message, received: &mut Received) {
+    match message {
+        Message::Line(_, prefix, line) if received.1.is_none() => received.0.push((prefix, line)),
+        Message::Line(..) => {}
+        Message::Failed(error) => received.1 = received.1.or(Some(error)),
+    }
+}
+
+// A step passes on what every line turns into and, once the lines before it
+// are done, what it held back. An error goes on in place of the lines.
+fn run_stage(pipeline: &mut Pipeline, received: Receiver<Message>, sender: SyncSender<Message>) {
+    let mut last_number = 0;
+
+    for message in received {
+        let (number, prefix, line) = match message {
+            Message::Line(number, prefix, line) => (number, prefix, line),
+            failed => {
+                sender.send(failed).ok();
+                return;
+            }
+        };
+        last_number = number;
+
+        match pipeline.apply_numbered(number, line) {
+            Ok(lines) => {
+                for line in lines {
+                    if sender
+                        .send(Message::Line(number, prefix.clone(), line))
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+            }
+            Err(error) => {
+                sender.send(Message::Failed(error)).ok();
+                return;
+            }
+        }
+    }
+
+    match pipeline.finish() {
+        Ok(lines) => {
+            for line in lines {
+                sender
+                    .send(Message::Line(last_number, String::new(), line))
+                    .ok();
+            }
+        }
+        Err(error) => {
+            sender.send(Message::Failed(error)).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stages;
+    use crate::{options::Options, pipeline::Pipeline};
+
+    #[test]
+    fn stages_run_steps_in_order_and_drain_at_the_end() {
+        //+ Arrange
+        let commands = ["filter", "[0-9]", "upper", "top", "2"].map(String::from);
+        let mut stages = Stages::start(&commands, &Options::default()).unwrap();
+
+        //+ Act
+        let mut lines = vec![];
+        for line in ["a1", "b2", "a1", "c", "a1", "b2", "d3"] {
+            let (received, error) = stages.push("x:".to_string(), line.to_string());
+            assert_eq!(error, None);
+            lines.extend(received);
+        }
+        let ((received, error), (steps, _)) = stages.finish();
+        lines.extend(received);
+
+        //+ Assert
+        assert_eq!(error, None);
+        assert_eq!(
+            lines,
+            [
+                ("".to_string(), "3 A1".to_string()),
+                ("".to_string(), "2 B2".to_string())
+            ]
+        );
+        let received: Vec<usize> = steps.iter().map(|step| step.received).collect();
+        assert_eq!(received, [7, 6, 6]);
+    }
+
+    #[test]
+    fn can_stage_keeps_steps_sharing_captures_together() {
+        //+ Act + Assert
+        let staged =
+            |commands: &[&str]| Stages::can_stage(&Pipeline::build_pipeline(commands).unwrap());
+        assert!(staged(&["filter", "a", "json", ".b"]));
+        assert!(!staged(&["filter", "(?P<a>.)", "format", "{a}"]));
+        assert!(!staged(&[
+            "tag", "e", "when", "E", "route", "e", "to", "drop"
+        ]));
+        assert!(!staged(&["trim"]));
+    }
+}
@@ -0,0 +1,241 @@
+use std::time::Duration;
+
+use indicatif::HumanBytes;
+use serde_json::{json, Value};
+
+// What one step of the top-level pipeline saw over a run. A step's output is
+// whatever the next step received, so only the input side is counted.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StepStats {
+    pub name: String,
+    pub received: usize,
+    pub peak_memory: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    pub lines_read: usize,
+    pub lines_emitted: usize,
+    pub bytes_read: usize,
+    pub bytes_written: usize,
+    pub elapsed: Duration,
+    pub steps: Vec<StepStats>,
+    // The digest of everything written, as `<algorithm>:<hex>`, when one
+    // was asked for.
+    pub checksum: Option<String>,
+}
+
+impl StepStats {
+    pub fn new(name: &str) -> StepStats {
+        StepStats {
+            name: name.to_string(),
+            ..StepStats::default()
+        }
+    }
+}
+
+impl RunSummary {
+    // Folds in the steps of a finished pipeline. In-place editing runs one
+    // pipeline per file, so counts add up and peaks keep the largest.
+    pub fn add_steps(&mut self, steps: &[StepStats]) {
+        if self.steps.is_empty() {
+            self.steps = steps.to_vec();
+            return;
+        }
+
+        for (total, step) in self.steps.iter_mut().zip(steps) {
+            total.received += step.received;
+            total.peak_memory = total.peak_memory.max(step.peak_memory);
+        }
+    }
+
+    // Lines each step passed on, taken from the step after it; the last step
+    // passes on whatever the run emitted.
+    pub fn step_outputs(&self) -> Vec<usize> {
+        step_outputs(&self.steps, self.lines_emitted)
+    }
+
+    pub fn to_json(&self) -> Value {
+        let steps: Vec<Value> = self
+            .steps
+            .iter()
+            .zip(self.step_outputs())
+            .map(|(step, out)| {
+                json!({
+                    "name": step.name,
+                    "in": step.received,
+                    "out": out,
+                    "dropped": step.received.saturating_sub(out),
+                    "peak_memory_bytes": step.peak_memory,
+                })
+            })
+            .collect();
+
+        let mut summary = json!({
+            "lines_read": self.lines_read,
+            "lines_emitted": self.lines_emitted,
+            "bytes_read": self.bytes_read,
+            "bytes_written": self.bytes_written,
+            "elapsed_seconds": self.elapsed.as_secs_f64(),
+            "steps": steps,
+        });
+        if let Some(checksum) = &self.checksum {
+            summary["checksum"] = json!(checksum);
+        }
+
+        summary
+    }
+
+    pub fn table(&self) -> Vec<String> {
+        let mut rows = vec![[
+            "step".to_string(),
+            "in".to_string(),
+            "out".to_string(),
+            "dropped".to_string(),
+            "peak memory".to_string(),
+        ]];
+        for (step, out) in self.steps.iter().zip(self.step_outputs()) {
+            rows.push([
+                step.name.clone(),
+                step.received.to_string(),
+                out.to_string(),
+                step.received.saturating_sub(out).to_string(),
+                match step.peak_memory {
+                    0 => "-".to_string(),
+                    memory => HumanBytes(memory as u64).to_string(),
+                },
+            ]);
+        }
+
+        let mut widths = [0; 5];
+        for row in rows.iter() {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        let mut lines = vec![format!(
+            "{} lines read ({}), {} emitted ({}) in {:.2}s",
+            self.lines_read,
+            HumanBytes(self.bytes_read as u64),
+            self.lines_emitted,
+            HumanBytes(self.bytes_written as u64),
+            self.elapsed.as_secs_f64()
+        )];
+        for row in rows {
+            let mut line = format!("  {:<width$}", row[0], width = widths[0]);
+            for (cell, width) in row.iter().zip(widths).skip(1) {
+                line.push_str(&format!("  {:>width$}", cell, width = width));
+            }
+            lines.push(line);
+        }
+
+        lines
+    }
+}
+
+fn step_outputs(steps: &[StepStats], emitted: usize) -> Vec<usize> {
+    steps
+        .iter()
+        .skip(1)
+        .map(|step| step.received)
+        .chain([emitted])
+        .collect()
+}
+
+/// The step that has dropped the most lines so far, and how many, when any
+/// has. Part way through a run, lines a step still holds count as dropped.
+pub fn top_dropper(steps: &[StepStats], emitted: usize) -> Option<(&str, usize)> {
+    steps
+        .iter()
+        .zip(step_outputs(steps, emitted))
+        .map(|(step, out)| (step.name.as_str(), step.received.saturating_sub(out)))
+        .filter(|(_, dropped)| *dropped > 0)
+        .max_by_key(|(_, dropped)| *dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{top_dropper, RunSummary, StepStats};
+
+    #[test]
+    fn table_derives_step_outputs_from_the_next_step() {
+        //+ Arrange
+        let mut summary = RunSummary {
+            lines_read: 10,
+            lines_emitted: 3,
+            bytes_read: 100,
+            bytes_written: 12,
+            elapsed: Duration::from_millis(1500),
+            steps: vec![],
+            checksum: None,
+        };
+        let filter = StepStats {
+            name: "filter".to_string(),
+            received: 10,
+            peak_memory: 0,
+        };
+        let dedupe = StepStats {
+            name: "dedupe".to_string(),
+            received: 4,
+            peak_memory: 2048,
+        };
+
+        //+ Act
+        summary.add_steps(&[filter, dedupe]);
+        let table = summary.table();
+
+        //+ Assert
+        assert_eq!(
+            table,
+            vec![
+                "10 lines read (100B), 3 emitted (12B) in 1.50s",
+                "  step    in  out  dropped  peak memory",
+                "  filter  10    4        6            -",
+                "  dedupe   4    3        1     2.00 KiB",
+            ]
+        );
+    }
+
+    #[test]
+    fn to_json_reports_counts_per_step() {
+        //+ Arrange
+        let summary = RunSummary {
+            lines_read: 5,
+            lines_emitted: 2,
+            elapsed: Duration::from_millis(250),
+            steps: vec![StepStats {
+                name: "filter".to_string(),
+                received: 5,
+                peak_memory: 0,
+            }],
+            ..RunSummary::default()
+        };
+
+        //+ Act
+        let json = summary.to_json();
+
+        //+ Assert
+        assert_eq!(
+            json.to_string(),
+            r#"{"lines_read":5,"lines_emitted":2,"bytes_read":0,"bytes_written":0,"elapsed_seconds":0.25,"steps":[{"name":"filter","in":5,"out":2,"dropped":3,"peak_memory_bytes":0}]}"#
+        );
+    }
+
+    #[test]
+    fn top_dropper_picks_the_step_dropping_most() {
+        //+ Arrange
+        let step = |name: &str, received| StepStats {
+            name: name.to_string(),
+            received,
+            peak_memory: 0,
+        };
+        let steps = [step("trim", 10), step("filter", 10), step("dedupe", 4)];
+
+        //+ Act + Assert
+        assert_eq!(top_dropper(&steps, 3), Some(("filter", 6)));
+        assert_eq!(top_dropper(&steps[..1], 10), None);
+    }
+}
@@ -0,0 +1,23 @@
+use std::fmt::Debug;
+
+/// A step that only needs the line itself, which is how custom steps plug
+/// into a [`Pipeline`](crate::Pipeline) without a variant of their own. The
+/// built-in steps stay variants of [`PipelineStep`](crate::PipelineStep),
+/// since most of them also draw on the tags, captures and input name that
+/// travel with a line.
+pub trait Step: Debug {
+    /// Takes one line and returns the lines to pass on: none drops it, and
+    /// more than one fans out.
+    fn apply(&mut self, line: String) -> Result<Vec<String>, &'static str>;
+
+    /// Releases whatever is still held at the end of input.
+    fn flush(&mut self) -> Result<Vec<String>, &'static str> {
+        Ok(vec![])
+    }
+
+    /// Roughly how many bytes the step holds, for --max-memory and the run
+    /// summary.
+    fn memory(&self) -> usize {
+        0
+    }
+}
@@ -0,0 +1,174 @@
+/// Splits a pipeline written as one string, such as
+/// `filter /error/i | trim | dedupe | append " <-"`, into the tokens
+/// [`Pipeline::build_pipeline`](crate::Pipeline::build_pipeline) takes.
+///
+/// Steps are separated by `|` and their words by whitespace. Words can be
+/// quoted with `'...'`, taken literally, or `"..."`, where a backslash escapes
+/// the next character, as it does outside quotes. A word written as
+/// `/regex/flags` is a regex literal: `|` inside it does not end the step,
+/// `\/` stands for a slash and flags such as `i` become a `(?i)` prefix.
+pub fn split_pipeline(source: &str) -> Result<Vec<String>, &'static str> {
+    let mut tokens = vec![];
+    let mut words_in_step = 0;
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '|' {
+            chars.next();
+            if words_in_step == 0 {
+                return Err("Empty step in pipeline");
+            }
+            words_in_step = 0;
+        } else {
+            let rest: String = chars.clone().collect();
+            let (word, length) = match regex_literal(&rest) {
+                Some(literal) => literal,
+                None => word(&rest)?,
+            };
+
+            tokens.push(word);
+            words_in_step += 1;
+            chars.nth(length - 1);
+        }
+    }
+
+    if words_in_step == 0 {
+        return Err("Empty step in pipeline");
+    }
+
+    Ok(tokens)
+}
+
+// Reads one shell-style word from the start of the text and returns it with
+// how many characters it took up.
+fn word(text: &str) -> Result<(String, usize), &'static str> {
+    let mut word = String::new();
+    let mut chars = text.chars().enumerate().peekable();
+
+    while let Some((_, c)) = chars.peek().copied() {
+        if c.is_whitespace() || c == '|' {
+            break;
+        }
+        chars.next();
+
+        match c {
+            '\'' => loop {
+                match chars.next() {
+                    Some((_, '\'')) => break,
+                    Some((_, quoted)) => word.push(quoted),
+                    None => return Err("Unterminated quote in pipeline"),
+                }
+            },
+            '"' => loop {
+                match chars.next() {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) => word.push(escaped),
+                        None => return Err("Unterminated quote in pipeline"),
+                    },
+                    Some((_, quoted)) => word.push(quoted),
+                    None => return Err("Unterminated quote in pipeline"),
+                }
+            },
+            '\\' => match chars.next() {
+                Some((_, escaped)) => word.push(escaped),
+                None => return Err("Trailing backslash in pipeline"),
+            },
+            c => word.push(c),
+        }
+    }
+
+    let length = chars
+        .peek()
+        .map_or(text.chars().count(), |(index, _)| *index);
+
+    Ok((word, length))
+}
+
+// A word that starts with a slash is a regex literal only if it is closed by
+// another and followed by nothing but flags, so paths like /tmp/out.log stay
+// plain words.
+fn regex_literal(text: &str) -> Option<(String, usize)> {
+    let mut chars = text.chars().enumerate();
+    if chars.next()?.1 != '/' {
+        return None;
+    }
+
+    let mut pattern = String::new();
+    loop {
+        match chars.next()?.1 {
+            '/' => break,
+            '\\' => match chars.next()?.1 {
+                '/' => pattern.push('/'),
+                escaped => {
+                    pattern.push('\\');
+                    pattern.push(escaped);
+                }
+            },
+            c => pattern.push(c),
+        }
+    }
+
+    let mut flags = String::new();
+    let mut length = text.chars().count();
+    for (index, c) in chars {
+        match c {
+            'i' | 'm' | 's' | 'x' | 'U' => flags.push(c),
+            c if c.is_whitespace() || c == '|' => {
+                length = index;
+                break;
+            }
+            _ => return None,
+        }
+    }
+
+    match flags.is_empty() {
+        true => Some((pattern, length)),
+        false => Some((format!("(?{}){}", flags, pattern), length)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_pipeline;
+
+    #[test]
+    fn split_pipeline_handles_quotes_escapes_and_regex_literals() {
+        //+ Act
+        let tokens = split_pipeline(
+            r#"filter /error|warn/i | trim | append " <-\"" | tee /tmp/out.log|prepend 'a b'\!"#,
+        );
+
+        //+ Assert
+        assert_eq!(
+            tokens,
+            Ok(vec![
+                "filter".to_string(),
+                "(?i)error|warn".to_string(),
+                "trim".to_string(),
+                "append".to_string(),
+                " <-\"".to_string(),
+                "tee".to_string(),
+                "/tmp/out.log".to_string(),
+                "prepend".to_string(),
+                "a b!".to_string(),
+            ])
+        );
+    }
+
+    #[test]
+    fn split_pipeline_rejects_malformed_input() {
+        //+ Act + Assert
+        assert_eq!(
+            split_pipeline("trim | | upper"),
+            Err("Empty step in pipeline")
+        );
+        assert_eq!(split_pipeline("trim |"), Err("Empty step in pipeline"));
+        assert_eq!(
+            split_pipeline("append 'oops"),
+            Err("Unterminated quote in pipeline")
+        );
+    }
+}
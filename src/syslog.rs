@@ -0,0 +1,121 @@
+use regex::Regex;
+
+// Splits RFC 5424 and RFC 3164 syslog lines into named fields: facility and
+// severity (from the priority, when present), timestamp, host, app, pid,
+// msgid (RFC 5424 only) and message. Nil values (`-`) are left out.
+#[derive(Debug)]
+pub struct SyslogParser {
+    rfc5424: Regex,
+    rfc3164: Regex,
+}
+
+impl SyslogParser {
+    pub fn new() -> SyslogParser {
+        SyslogParser {
+            rfc5424: Regex::new(
+                r"^<(\d{1,3})>1 (\S+) (\S+) (\S+) (\S+) (\S+) (-|(?:\[(?:[^\]\\]|\\.)*\])+)(?: (.*))?$",
+            )
+            .unwrap(),
+            rfc3164: Regex::new(
+                r"^(?:<(\d{1,3})>)?([A-Z][a-z]{2} [ \d]\d \d\d:\d\d:\d\d) (\S+) ([^\s:\[]+)(?:\[(\d+)\])?: ?(.*)$",
+            )
+            .unwrap(),
+        }
+    }
+
+    pub fn fields(&self, line: &str) -> Option<Vec<(String, String)>> {
+        let (names, captures): (&[&str], _) = if let Some(captures) = self.rfc5424.captures(line) {
+            (
+                &["timestamp", "host", "app", "pid", "msgid", "", "message"],
+                captures,
+            )
+        } else if let Some(captures) = self.rfc3164.captures(line) {
+            (&["timestamp", "host", "app", "pid", "message"], captures)
+        } else {
+            return None;
+        };
+
+        let mut fields = vec![];
+        if let Some(priority) = captures.get(1).and_then(|p| p.as_str().parse::<u8>().ok()) {
+            fields.push(("facility".to_string(), (priority / 8).to_string()));
+            fields.push(("severity".to_string(), (priority % 8).to_string()));
+        }
+
+        for (name, value) in names.iter().zip(captures.iter().skip(2)) {
+            match value.map(|v| v.as_str()) {
+                Some(value) if !name.is_empty() && value != "-" => {
+                    fields.push((name.to_string(), value.to_string()))
+                }
+                _ => {}
+            }
+        }
+
+        Some(fields)
+    }
+}
+
+impl PartialEq for SyslogParser {
+    fn eq(&self, other: &Self) -> bool {
+        self.rfc5424.as_str() == other.rfc5424.as_str()
+            && self.rfc3164.as_str() == other.rfc3164.as_str()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SyslogParser;
+
+    fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn fields_parses_rfc5424() {
+        //+ Act
+        let fields = SyslogParser::new()
+            .fields(r#"<165>1 2003-10-11T22:14:15.003Z mymachine evntslog - ID47 [exampleSDID@32473 iut="3"] An application event"#)
+            .unwrap();
+
+        //+ Assert
+        assert_eq!(field(&fields, "facility"), Some("20"));
+        assert_eq!(field(&fields, "severity"), Some("5"));
+        assert_eq!(
+            field(&fields, "timestamp"),
+            Some("2003-10-11T22:14:15.003Z")
+        );
+        assert_eq!(field(&fields, "host"), Some("mymachine"));
+        assert_eq!(field(&fields, "app"), Some("evntslog"));
+        assert_eq!(field(&fields, "pid"), None);
+        assert_eq!(field(&fields, "msgid"), Some("ID47"));
+        assert_eq!(field(&fields, "message"), Some("An application event"));
+    }
+
+    #[test]
+    fn fields_parses_rfc3164_with_and_without_priority() {
+        //+ Arrange
+        let parser = SyslogParser::new();
+
+        //+ Act
+        let with_priority = parser
+            .fields("<34>Oct 11 22:14:15 mymachine su: 'su root' failed")
+            .unwrap();
+        let without_priority = parser
+            .fields("Oct  1 02:00:01 web1 sshd[4242]: Accepted publickey")
+            .unwrap();
+
+        //+ Assert
+        assert_eq!(field(&with_priority, "severity"), Some("2"));
+        assert_eq!(field(&with_priority, "app"), Some("su"));
+        assert_eq!(field(&with_priority, "message"), Some("'su root' failed"));
+        assert_eq!(field(&without_priority, "severity"), None);
+        assert_eq!(
+            field(&without_priority, "timestamp"),
+            Some("Oct  1 02:00:01")
+        );
+        assert_eq!(field(&without_priority, "pid"), Some("4242"));
+        assert_eq!(parser.fields("not syslog at all"), None);
+    }
+}
@@ -0,0 +1,266 @@
+use std::{process::Command, sync::OnceLock};
+
+static HOSTNAME: OnceLock<String> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Part {
+    Literal(String),
+    Line,
+    Number,
+    Length,
+    File,
+    Host,
+    Group(usize),
+    NamedGroup(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+// Values a template can draw on for the line being formatted. Captures are
+// (group name, value) pairs from the most recent filter that matched, and the
+// file is the input the line was read from.
+pub struct TemplateContext<'a> {
+    pub line: &'a str,
+    pub number: usize,
+    pub file: &'a str,
+    pub captures: &'a [(Option<String>, Option<String>)],
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template, &'static str> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = source.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err("Unterminated placeholder"),
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(match name.as_str() {
+                        "line" => Part::Line,
+                        "n" => Part::Number,
+                        "len" => Part::Length,
+                        "file" => Part::File,
+                        "host" => Part::Host,
+                        "" => return Err("Empty placeholder"),
+                        _ => match name.parse::<usize>() {
+                            Ok(group) => Part::Group(group),
+                            Err(_) => Part::NamedGroup(name),
+                        },
+                    });
+                }
+                '}' => return Err("Unmatched } in template"),
+                c => literal.push(c),
+            }
+        }
+
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Template { parts })
+    }
+
+    // The template `append` and `prepend` stand for when their text names the
+    // file, host or line number. Any other braces stay as they are, so text
+    // without those placeholders is appended as before.
+    pub fn annotation(text: &str, prepend: bool) -> Option<Template> {
+        let mut parts = vec![];
+        let mut rest = text;
+        while let Some(start) = rest.find('{') {
+            let placeholder = [
+                ("{file}", Part::File),
+                ("{host}", Part::Host),
+                ("{n}", Part::Number),
+            ]
+            .into_iter()
+            .find(|(name, _)| rest[start..].starts_with(name));
+            match placeholder {
+                Some((name, part)) => {
+                    push_literal(&mut parts, &rest[..start]);
+                    parts.push(part);
+                    rest = &rest[start + name.len()..];
+                }
+                None => {
+                    push_literal(&mut parts, &rest[..=start]);
+                    rest = &rest[start + 1..];
+                }
+            }
+        }
+        push_literal(&mut parts, rest);
+
+        if !parts.iter().any(|part| !matches!(part, Part::Literal(_))) {
+            return None;
+        }
+        match prepend {
+            true => parts.push(Part::Line),
+            false => parts.insert(0, Part::Line),
+        }
+
+        Some(Template { parts })
+    }
+
+    pub fn uses_file(&self) -> bool {
+        self.parts.contains(&Part::File)
+    }
+
+    pub fn uses_captures(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Group(_) | Part::NamedGroup(_)))
+    }
+
+    pub fn uses_group(&self, index: usize, name: Option<&str>) -> bool {
+        self.parts.iter().any(|part| match part {
+            Part::Group(group) => *group == index,
+            Part::NamedGroup(group) => Some(group.as_str()) == name,
+            _ => false,
+        })
+    }
+
+    // Groups that did not participate in the match (or were never captured)
+    // render as empty text, the same way regex replacement treats them.
+    pub fn render(&self, context: &TemplateContext) -> String {
+        let mut output = String::with_capacity(context.line.len());
+
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(text) => output.push_str(text),
+                Part::Line => output.push_str(context.line),
+                Part::Number => output.push_str(&context.number.to_string()),
+                Part::Length => output.push_str(&context.line.chars().count().to_string()),
+                Part::File => output.push_str(context.file),
+                Part::Host => output.push_str(hostname()),
+                Part::Group(group) => {
+                    if let Some((_, Some(value))) = context.captures.get(*group) {
+                        output.push_str(value);
+                    }
+                }
+                Part::NamedGroup(name) => {
+                    let value = context
+                        .captures
+                        .iter()
+                        .find(|(group_name, _)| group_name.as_deref() == Some(name.as_str()));
+                    if let Some((_, Some(value))) = value {
+                        output.push_str(value);
+                    }
+                }
+            }
+        }
+
+        output
+    }
+}
+
+// Joins literal text onto the literal before it, if any.
+fn push_literal(parts: &mut Vec<Part>, text: &str) {
+    match parts.last_mut() {
+        _ if text.is_empty() => {}
+        Some(Part::Literal(literal)) => literal.push_str(text),
+        _ => parts.push(Part::Literal(text.to_string())),
+    }
+}
+
+// Looked up once; the kernel's record of it on Linux, otherwise whatever the
+// `hostname` command says.
+fn hostname() -> &'static str {
+    HOSTNAME.get_or_init(|| {
+        std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .ok()
+            .or_else(|| {
+                Command::new("hostname")
+                    .output()
+                    .ok()
+                    .and_then(|output| String::from_utf8(output.stdout).ok())
+            })
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| "localhost".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hostname, Template, TemplateContext};
+
+    #[test]
+    fn parse_rejects_malformed_templates() {
+        //+ Act + Assert
+        assert_eq!(
+            Template::parse("{line").err(),
+            Some("Unterminated placeholder")
+        );
+        assert_eq!(
+            Template::parse("a}b").err(),
+            Some("Unmatched } in template")
+        );
+        assert_eq!(Template::parse("{}").err(), Some("Empty placeholder"));
+    }
+
+    #[test]
+    fn render_substitutes_placeholders() {
+        //+ Arrange
+        let template = Template::parse("{n}: {{{line}}} len={len} id={1} user={user}{9}").unwrap();
+        let captures = vec![
+            (None, Some("id=7 bob".to_string())),
+            (None, Some("7".to_string())),
+            (Some("user".to_string()), Some("bob".to_string())),
+        ];
+        let context = TemplateContext {
+            line: "id=7 bob",
+            number: 3,
+            file: "users.log",
+            captures: &captures,
+        };
+
+        //+ Act
+        let rendered = template.render(&context);
+
+        //+ Assert
+        assert_eq!(rendered, "3: {id=7 bob} len=8 id=7 user=bob");
+    }
+
+    #[test]
+    fn annotation_fills_in_only_the_source_placeholders() {
+        //+ Arrange
+        let appended = Template::annotation(" [{file}:{n}] {x}", false).unwrap();
+        let prepended = Template::annotation("{host} ", true).unwrap();
+        let context = TemplateContext {
+            line: "started",
+            number: 12,
+            file: "app.log",
+            captures: &[],
+        };
+
+        //+ Act + Assert
+        assert_eq!(appended.render(&context), "started [app.log:12] {x}");
+        assert_eq!(
+            prepended.render(&context),
+            format!("{} started", hostname())
+        );
+        assert!(Template::annotation("{line} {}", false).is_none());
+        assert!(appended.uses_file());
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+// The longest gap a throttle may put between lines.
+const MAX_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Paces lines one interval apart. A line that comes late goes out at once and
+// the next is due an interval after it, so a slow stretch upstream is not
+// followed by a burst that exceeds the rate.
+#[derive(Debug)]
+pub struct Throttle {
+    lines_per_second: f64,
+    interval: Duration,
+    due: Option<Instant>,
+}
+
+impl Throttle {
+    // Rates below one line a day are refused, as are ones too small for a
+    // Duration to hold their interval.
+    pub fn new(lines_per_second: f64) -> Result<Throttle, &'static str> {
+        let interval = Duration::try_from_secs_f64(1.0 / lines_per_second)
+            .ok()
+            .filter(|interval| *interval <= MAX_INTERVAL)
+            .ok_or("Invalid rate")?;
+
+        Ok(Throttle {
+            lines_per_second,
+            interval,
+            due: None,
+        })
+    }
+
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        let due = self.due.map_or(now, |due| due.max(now));
+        self.due = Some(due + self.interval);
+
+        if due > now {
+            std::thread::sleep(due - now);
+        }
+    }
+}
+
+impl PartialEq for Throttle {
+    fn eq(&self, other: &Self) -> bool {
+        self.lines_per_second == other.lines_per_second
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Throttle;
+
+    #[test]
+    fn wait_caps_throughput() {
+        //+ Arrange
+        let mut throttle = Throttle::new(1_000.0).unwrap();
+        let started = Instant::now();
+
+        //+ Act
+        for _ in 0..51 {
+            throttle.wait();
+        }
+
+        //+ Assert
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn wait_does_not_burst_after_a_stall() {
+        //+ Arrange
+        let mut throttle = Throttle::new(100.0).unwrap();
+        throttle.wait();
+        std::thread::sleep(Duration::from_millis(100));
+        let resumed = Instant::now();
+
+        //+ Act
+        for _ in 0..6 {
+            throttle.wait();
+        }
+
+        //+ Assert
+        assert!(resumed.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn new_refuses_rates_below_one_line_a_day() {
+        //+ Act + Assert
+        assert!(Throttle::new(1e-300).is_err());
+        assert!(Throttle::new(1e-6).is_err());
+        assert!(Throttle::new(0.001).is_ok());
+    }
+}
@@ -0,0 +1,416 @@
+use std::{
+    ops::Range,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, SecondsFormat, Utc,
+};
+use regex::Regex;
+
+static ISO_TIMESTAMP: OnceLock<Regex> = OnceLock::new();
+static RFC2822_TIMESTAMP: OnceLock<Regex> = OnceLock::new();
+static EPOCH_TIMESTAMP: OnceLock<Regex> = OnceLock::new();
+
+const CLF_FORMAT: &str = "%d/%b/%Y:%H:%M:%S %z";
+
+#[derive(Debug)]
+pub enum TimestampFormat {
+    Iso,
+    Rfc2822,
+    Pattern(String, Regex),
+}
+
+impl TimestampFormat {
+    pub fn parse(name: &str) -> Result<TimestampFormat, &'static str> {
+        match name {
+            "iso" => Ok(TimestampFormat::Iso),
+            "rfc2822" => Ok(TimestampFormat::Rfc2822),
+            "clf" => TimestampFormat::pattern(CLF_FORMAT),
+            format => TimestampFormat::pattern(format),
+        }
+    }
+
+    fn pattern(format: &str) -> Result<TimestampFormat, &'static str> {
+        validate_format(format)?;
+        let regex = Regex::new(&format_to_regex(format)?).map_err(|_| "Invalid date format")?;
+
+        Ok(TimestampFormat::Pattern(format.to_string(), regex))
+    }
+
+    // Finds the first substring of the line that parses as a timestamp in this
+    // format. Timestamps without an offset are taken to be UTC.
+    pub fn find(&self, line: &str) -> Option<(Range<usize>, DateTime<FixedOffset>)> {
+        let (regex, format) = match self {
+            TimestampFormat::Iso => (iso_regex(), None),
+            TimestampFormat::Rfc2822 => (rfc2822_regex(), None),
+            TimestampFormat::Pattern(format, regex) => (regex, Some(format.as_str())),
+        };
+
+        regex.find_iter(line).find_map(|candidate| {
+            let text = candidate.as_str();
+            let timestamp = match (self, format) {
+                (TimestampFormat::Rfc2822, _) => DateTime::parse_from_rfc2822(text).ok(),
+                (_, Some(format)) => parse_with_format(text, format),
+                (_, None) => parse_timestamp(text),
+            }?;
+
+            Some((candidate.range(), timestamp))
+        })
+    }
+}
+
+impl PartialEq for TimestampFormat {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Pattern(left_format, _), Self::Pattern(right_format, _)) => {
+                left_format == right_format
+            }
+            _ => core::mem::discriminant(self) == core::mem::discriminant(other),
+        }
+    }
+}
+
+pub fn validate_format(format: &str) -> Result<(), &'static str> {
+    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+        Err("Invalid date format")
+    } else {
+        Ok(())
+    }
+}
+
+// `iso` renders as RFC 3339 in UTC; anything else is a strftime format applied
+// in the timestamp's own offset.
+pub fn format_timestamp(timestamp: &DateTime<FixedOffset>, format: &str) -> String {
+    match format {
+        "iso" => timestamp
+            .with_timezone(&Utc)
+            .to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        format => timestamp.format(format).to_string(),
+    }
+}
+
+// What the `timestamp` step prefixes lines with: the time each line went
+// through, in a strftime format in local time or `iso` in UTC, or the seconds
+// since the first line, like `ts -s`.
+#[derive(Debug)]
+pub struct Stamp {
+    format: Option<String>,
+    started: Option<Instant>,
+}
+
+impl Stamp {
+    pub fn clock(format: &str) -> Result<Stamp, &'static str> {
+        validate_format(format)?;
+
+        Ok(Stamp {
+            format: Some(format.to_string()),
+            started: None,
+        })
+    }
+
+    pub fn elapsed() -> Stamp {
+        Stamp {
+            format: None,
+            started: None,
+        }
+    }
+
+    pub fn next(&mut self) -> String {
+        match &self.format {
+            Some(format) => format_timestamp(&Local::now().fixed_offset(), format),
+            None => format_elapsed(self.started.get_or_insert_with(Instant::now).elapsed()),
+        }
+    }
+}
+
+impl PartialEq for Stamp {
+    fn eq(&self, other: &Self) -> bool {
+        self.format == other.format
+    }
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    format!("{:.3}", elapsed.as_secs_f64())
+}
+
+// Replaces every standalone 10-digit (seconds) or 13-digit (milliseconds)
+// number with a formatted date, or with its distance from `now` when
+// `relative` is set.
+pub fn humanize_epochs(line: &str, format: &str, relative: bool, now: DateTime<Utc>) -> String {
+    let regex = EPOCH_TIMESTAMP.get_or_init(|| Regex::new(r"\b(?:\d{13}|\d{10})\b").unwrap());
+
+    regex
+        .replace_all(line, |captures: &regex::Captures| {
+            let digits = &captures[0];
+            let value: i64 = digits.parse().unwrap_or(0);
+            let timestamp = if digits.len() == 13 {
+                DateTime::from_timestamp_millis(value)
+            } else {
+                DateTime::from_timestamp(value, 0)
+            };
+
+            match timestamp {
+                Some(timestamp) if relative => format_relative(timestamp, now),
+                Some(timestamp) => format_timestamp(&timestamp.fixed_offset(), format),
+                None => digits.to_string(),
+            }
+        })
+        .into_owned()
+}
+
+fn format_relative(timestamp: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let seconds = (now - timestamp).num_seconds();
+    let magnitude = seconds.unsigned_abs();
+
+    let amount = match magnitude {
+        0..=59 => format!("{}s", magnitude),
+        60..=3_599 => format!("{}m", magnitude / 60),
+        3_600..=86_399 => format!("{}h", magnitude / 3_600),
+        _ => format!("{}d", magnitude / 86_400),
+    };
+
+    if seconds < 0 {
+        format!("in {}", amount)
+    } else {
+        format!("{} ago", amount)
+    }
+}
+
+pub fn find_timestamp(line: &str) -> Option<NaiveDateTime> {
+    TimestampFormat::Iso
+        .find(line)
+        .map(|(_, timestamp)| timestamp.naive_utc())
+}
+
+pub fn parse_timestamp(text: &str) -> Option<DateTime<FixedOffset>> {
+    let text = text.replace(',', ".");
+
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(&text) {
+        return Some(timestamp);
+    }
+
+    for format in ["%Y-%m-%dT%H:%M:%S%.f%z", "%Y-%m-%d %H:%M:%S%.f%z"] {
+        if let Ok(timestamp) = DateTime::parse_from_str(&text, format) {
+            return Some(timestamp);
+        }
+    }
+
+    for format in [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%d %H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ] {
+        if let Ok(timestamp) = NaiveDateTime::parse_from_str(&text, format) {
+            return Some(timestamp.and_utc().fixed_offset());
+        }
+    }
+
+    NaiveDate::parse_from_str(&text, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|timestamp| timestamp.and_utc().fixed_offset())
+}
+
+fn parse_with_format(text: &str, format: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(timestamp) = DateTime::parse_from_str(text, format) {
+        return Some(timestamp);
+    }
+
+    if let Ok(timestamp) = NaiveDateTime::parse_from_str(text, format) {
+        return Some(timestamp.and_utc().fixed_offset());
+    }
+
+    NaiveDate::parse_from_str(text, format)
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|timestamp| timestamp.and_utc().fixed_offset())
+}
+
+fn iso_regex() -> &'static Regex {
+    ISO_TIMESTAMP.get_or_init(|| {
+        Regex::new(
+            r"\d{4}-\d{2}-\d{2}(?:[T ]\d{2}:\d{2}(?::\d{2}(?:[.,]\d+)?)?(?:Z|[+-]\d{2}:?\d{2})?)?",
+        )
+        .unwrap()
+    })
+}
+
+fn rfc2822_regex() -> &'static Regex {
+    RFC2822_TIMESTAMP.get_or_init(|| {
+        Regex::new(
+            r"(?:[A-Z][a-z]{2}, )?\d{1,2} [A-Z][a-z]{2} \d{4} \d{2}:\d{2}(?::\d{2})? (?:[+-]\d{4}|GMT|UT|[A-Z]{3})",
+        )
+        .unwrap()
+    })
+}
+
+// Translates a strftime format into a regex that locates candidate
+// timestamps inside a line; chrono then does the actual parsing.
+fn format_to_regex(format: &str) -> Result<String, &'static str> {
+    let mut pattern = String::new();
+    let mut chars = format.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            pattern.push_str(&regex::escape(&c.to_string()));
+            continue;
+        }
+
+        let mut specifier = chars.next().ok_or("Invalid date format")?;
+        if matches!(specifier, '-' | '_' | '0') {
+            specifier = chars.next().ok_or("Invalid date format")?;
+        }
+
+        pattern.push_str(match specifier {
+            'Y' => r"[+-]?\d{4}",
+            'C' | 'y' | 'g' => r"\d{2}",
+            'm' | 'd' | 'H' | 'I' | 'M' | 'S' | 'U' | 'W' | 'V' => r"\d{1,2}",
+            'e' | 'k' | 'l' => r"\s?\d{1,2}",
+            'j' => r"\d{1,3}",
+            'u' | 'w' => r"\d",
+            'b' | 'h' | 'a' => r"[A-Za-z]{3}",
+            'B' | 'A' => r"[A-Za-z]+",
+            'p' | 'P' => r"(?:[AaPp][Mm])",
+            'f' => r"\d+",
+            '.' => {
+                chars.next();
+                r"(?:\.\d+)?"
+            }
+            'z' | ':' => {
+                if specifier == ':' {
+                    chars.next();
+                }
+                r"(?:Z|[+-]\d{2}:?\d{2})"
+            }
+            'Z' => r"[A-Za-z]+",
+            's' => r"\d+",
+            'T' => r"\d{2}:\d{2}:\d{2}",
+            'R' => r"\d{2}:\d{2}",
+            'D' | 'x' => r"\d{2}/\d{2}/\d{2}",
+            'F' => r"[+-]?\d{4}-\d{2}-\d{2}",
+            'n' | 't' => r"\s",
+            '%' => "%",
+            _ => return Err("Unsupported date format specifier"),
+        });
+    }
+
+    Ok(pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::{
+        find_timestamp, format_elapsed, format_timestamp, humanize_epochs, Stamp, TimestampFormat,
+    };
+
+    #[test]
+    fn find_timestamp_parses_common_shapes() {
+        //+ Arrange
+        let expected = NaiveDate::from_ymd_opt(2024, 5, 1)
+            .unwrap()
+            .and_hms_opt(13, 4, 5)
+            .unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            find_timestamp("[2024-05-01 13:04:05] GET /"),
+            Some(expected)
+        );
+        assert_eq!(
+            find_timestamp("at 2024-05-01T13:04:05.250Z"),
+            Some(expected + chrono::Duration::milliseconds(250))
+        );
+        assert_eq!(
+            find_timestamp("2024-05-01T15:04:05+02:00 up"),
+            Some(expected)
+        );
+        assert_eq!(
+            find_timestamp("day 2024-05-01"),
+            NaiveDate::from_ymd_opt(2024, 5, 1)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn find_timestamp_skips_invalid_dates() {
+        //+ Act + Assert
+        assert_eq!(find_timestamp("build 9999-99-99 failed"), None);
+        assert_eq!(find_timestamp("no date here"), None);
+    }
+
+    #[test]
+    fn find_locates_pattern_and_preset_timestamps() {
+        //+ Arrange
+        let line = r#"127.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.0" 200"#;
+        let us_dates = TimestampFormat::parse("%m/%d/%Y %H:%M").unwrap();
+        let rfc2822 = TimestampFormat::parse("rfc2822").unwrap();
+
+        //+ Act
+        let (range, timestamp) = TimestampFormat::parse("clf").unwrap().find(line).unwrap();
+
+        //+ Assert
+        assert_eq!(&line[range], "10/Oct/2000:13:55:36 -0700");
+        assert_eq!(format_timestamp(&timestamp, "iso"), "2000-10-10T20:55:36Z");
+        assert_eq!(
+            format_timestamp(&us_dates.find("at 12/31/1999 23:59 ok").unwrap().1, "%F %R"),
+            "1999-12-31 23:59"
+        );
+        assert!(rfc2822
+            .find("Date: Tue, 1 Jul 2003 10:52:37 +0200")
+            .is_some());
+    }
+
+    #[test]
+    fn humanize_epochs_replaces_seconds_and_milliseconds() {
+        //+ Arrange
+        let now = chrono::DateTime::from_timestamp(1_700_010_800, 0).unwrap();
+        let line = "t=1700000000 ms=1700000000250 id=12345678901 tail=17000000000000";
+
+        //+ Act
+        let absolute = humanize_epochs(line, "iso", false, now);
+        let relative = humanize_epochs("a 1700000000 b 1700100000", "iso", true, now);
+
+        //+ Assert
+        assert_eq!(
+            absolute,
+            "t=2023-11-14T22:13:20Z ms=2023-11-14T22:13:20.250Z id=12345678901 tail=17000000000000"
+        );
+        assert_eq!(relative, "a 3h ago b in 1d");
+    }
+
+    #[test]
+    fn parse_rejects_invalid_formats() {
+        //+ Act + Assert
+        assert_eq!(
+            TimestampFormat::parse("%Y-%Q").err(),
+            Some("Invalid date format")
+        );
+    }
+
+    #[test]
+    fn stamp_formats_the_clock_or_the_time_since_the_first_line() {
+        //+ Arrange
+        let mut clock = Stamp::clock("%Y").unwrap();
+        let mut elapsed = Stamp::elapsed();
+
+        //+ Act
+        let year = clock.next();
+        let first = elapsed.next();
+
+        //+ Assert
+        assert_eq!(year.len(), 4);
+        assert!(first.starts_with("0.00"));
+        assert_eq!(
+            format_elapsed(std::time::Duration::from_millis(62_345)),
+            "62.345"
+        );
+        assert!(Stamp::clock("%Q").is_err());
+    }
+}
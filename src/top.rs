@@ -0,0 +1,246 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::sort::{leading_number, SortKey};
+
+// Counts how often each line occurs and, once the input ends, emits the k
+// most frequent as `<count> <line>`, most frequent first. With a capacity the
+// counters follow the Space-Saving algorithm: a new line evicts the least
+// frequent counter and inherits its count, so memory stays bounded and the
+// reported counts may overestimate.
+#[derive(Debug, PartialEq)]
+pub struct Top {
+    k: usize,
+    capacity: Option<usize>,
+    counts: HashMap<String, usize>,
+    evicted: usize,
+}
+
+impl Top {
+    pub fn new(k: usize, capacity: Option<usize>) -> Result<Top, &'static str> {
+        if k == 0 || capacity.is_some_and(|capacity| capacity < k) {
+            return Err("Invalid top size");
+        }
+
+        Ok(Top {
+            k,
+            capacity,
+            counts: HashMap::new(),
+            evicted: 0,
+        })
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        self.capacity.is_some()
+    }
+
+    pub fn push(&mut self, line: String) {
+        if let Some(count) = self.counts.get_mut(&line) {
+            *count += 1;
+            return;
+        }
+
+        let floor = match self.capacity {
+            Some(capacity) if self.counts.len() >= capacity => {
+                let (smallest, floor) = self
+                    .counts
+                    .iter()
+                    .min_by_key(|(_, count)| **count)
+                    .map(|(line, count)| (line.clone(), *count))
+                    .unwrap();
+                self.counts.remove(&smallest);
+                self.evicted += 1;
+
+                floor
+            }
+            _ => 0,
+        };
+
+        self.counts.insert(line, floor + 1);
+    }
+
+    pub fn flush(&mut self) -> Vec<String> {
+        let mut ranked: Vec<(String, usize)> = self.counts.drain().collect();
+        ranked.sort_by(|(left, left_count), (right, right_count)| {
+            right_count.cmp(left_count).then_with(|| left.cmp(right))
+        });
+
+        ranked
+            .into_iter()
+            .take(self.k)
+            .map(|(line, count)| format!("{} {}", count, line))
+            .collect()
+    }
+
+    pub fn evicted(&self) -> usize {
+        self.evicted
+    }
+
+    pub fn memory(&self) -> usize {
+        self.counts
+            .keys()
+            .map(|line| line.len() + std::mem::size_of::<usize>())
+            .sum()
+    }
+}
+
+// Keeps the k lines with the largest (or smallest) number in their key and,
+// once the input ends, emits them best first, earlier lines first among
+// equals. Only k lines are ever held, in a heap whose top is the one to evict
+// next. Lines whose key holds no number are dropped.
+#[derive(Debug)]
+pub struct TopBy {
+    k: usize,
+    key: SortKey,
+    smallest: bool,
+    kept: BinaryHeap<Ranked>,
+    seen: usize,
+}
+
+impl TopBy {
+    pub fn new(k: usize, key: SortKey, smallest: bool) -> Result<TopBy, &'static str> {
+        if k == 0 {
+            return Err("Invalid top size");
+        }
+
+        Ok(TopBy {
+            k,
+            key,
+            smallest,
+            kept: BinaryHeap::with_capacity(k),
+            seen: 0,
+        })
+    }
+
+    pub fn push(&mut self, line: String) {
+        let Some(value) = self.key.extract(&line).and_then(leading_number) else {
+            return;
+        };
+        let ranked = Ranked {
+            score: if self.smallest { -value } else { value },
+            order: self.seen,
+            line,
+        };
+        self.seen += 1;
+
+        if self.kept.len() < self.k {
+            self.kept.push(ranked);
+        } else if self.kept.peek().is_some_and(|worst| ranked < *worst) {
+            self.kept.pop();
+            self.kept.push(ranked);
+        }
+    }
+
+    pub fn flush(&mut self) -> Vec<String> {
+        self.seen = 0;
+        std::mem::take(&mut self.kept)
+            .into_sorted_vec()
+            .into_iter()
+            .map(|ranked| ranked.line)
+            .collect()
+    }
+
+    pub fn memory(&self) -> usize {
+        self.kept.iter().map(|ranked| ranked.line.len()).sum()
+    }
+}
+
+impl PartialEq for TopBy {
+    fn eq(&self, other: &Self) -> bool {
+        self.k == other.k
+            && self.key == other.key
+            && self.smallest == other.smallest
+            && self.seen == other.seen
+            && self.kept.iter().eq(other.kept.iter())
+    }
+}
+
+// Orders kept lines from best to worst: a higher score first, then the
+// earlier line.
+#[derive(Debug)]
+struct Ranked {
+    score: f64,
+    order: usize,
+    line: String,
+}
+
+impl Ord for Ranked {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .score
+            .total_cmp(&self.score)
+            .then_with(|| self.order.cmp(&other.order))
+    }
+}
+
+impl PartialOrd for Ranked {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Ranked {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Ranked {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Top, TopBy};
+    use crate::sort::SortKey;
+
+    #[test]
+    fn flush_ranks_by_count_then_line() {
+        //+ Arrange
+        let mut top = Top::new(2, None).unwrap();
+
+        //+ Act
+        for line in ["b", "a", "c", "a", "b", "c", "a"] {
+            top.push(line.to_string());
+        }
+
+        //+ Assert
+        assert_eq!(top.flush(), vec!["3 a".to_string(), "2 b".to_string()]);
+    }
+
+    #[test]
+    fn push_bounds_counters_with_space_saving() {
+        //+ Arrange
+        let mut top = Top::new(1, Some(2)).unwrap();
+
+        //+ Act
+        for line in ["hot", "hot", "hot", "x", "y", "z", "hot"] {
+            top.push(line.to_string());
+        }
+
+        //+ Assert
+        assert_eq!(top.memory(), 2 * std::mem::size_of::<usize>() + 4);
+        assert_eq!(top.evicted(), 2);
+        assert_eq!(top.flush(), vec!["4 hot".to_string()]);
+        assert!(Top::new(3, Some(2)).is_err());
+    }
+
+    #[test]
+    fn top_by_keeps_the_largest_values_in_a_bounded_heap() {
+        //+ Arrange
+        let mut slowest = TopBy::new(2, SortKey::parse("2").unwrap(), false).unwrap();
+        let mut fastest = TopBy::new(1, SortKey::parse("rt=(\\S+)").unwrap(), true).unwrap();
+
+        //+ Act
+        for line in ["/a 30", "/b 250", "/c -", "/d 250", "/e 90"] {
+            slowest.push(line.to_string());
+        }
+        for line in ["GET rt=0.9", "GET rt=0.02", "GET"] {
+            fastest.push(line.to_string());
+        }
+
+        //+ Assert
+        assert_eq!(slowest.memory(), 12);
+        assert_eq!(slowest.flush(), ["/b 250", "/d 250"]);
+        assert_eq!(fastest.flush(), ["GET rt=0.02"]);
+        assert!(TopBy::new(0, SortKey::Field(1), false).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use regex::Regex;
+
+/// Follows chosen lines through the pipeline for --trace, printing each one's
+/// value after every step to stderr, and the step that stopped it if it never
+/// reaches the end.
+#[derive(Debug)]
+pub struct Trace {
+    remaining: Option<usize>,
+    pattern: Option<Regex>,
+    active: bool,
+    // The step the traced line got to; steps that stop it leave it short of
+    // the end of the pipeline.
+    pub(crate) reached: usize,
+}
+
+impl Trace {
+    // A count alone follows the first lines, a pattern alone every line it
+    // matches, and both together the first lines it matches.
+    pub fn new(count: Option<usize>, pattern: Option<Regex>) -> Trace {
+        Trace {
+            remaining: count,
+            pattern,
+            active: false,
+            reached: 0,
+        }
+    }
+
+    pub(crate) fn is_active(&self) -> bool {
+        self.active
+    }
+
+    // Decides whether the line about to go through the pipeline is followed.
+    pub(crate) fn start(&mut self, number: usize, line: &str) -> bool {
+        self.active = self.remaining != Some(0)
+            && self
+                .pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.is_match(line));
+        if self.active {
+            self.remaining = self.remaining.map(|remaining| remaining - 1);
+            eprintln!("trace: line {} {:?}", number, line);
+        }
+
+        self.active
+    }
+
+    pub(crate) fn stop(&mut self) {
+        self.active = false;
+    }
+
+    pub(crate) fn step(&mut self, index: usize, name: &str, line: &str) {
+        self.reached = index + 1;
+        eprintln!("  {}. {}: {:?}", index + 1, name, line);
+    }
+
+    pub(crate) fn stopped(&mut self, index: usize, name: &str, end: usize) {
+        self.reached = end;
+        eprintln!("  {}. {}: dropped or held back", index + 1, name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+
+    use super::Trace;
+
+    #[test]
+    fn start_follows_the_first_matching_lines() {
+        //+ Arrange
+        let mut trace = Trace::new(Some(2), Some(Regex::new("WARN").unwrap()));
+
+        //+ Act
+        let followed = ["WARN a", "info b", "WARN c", "WARN d"]
+            .iter()
+            .enumerate()
+            .map(|(index, line)| trace.start(index + 1, line))
+            .collect::<Vec<_>>();
+
+        //+ Assert
+        assert_eq!(followed, [true, false, true, false]);
+    }
+}
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+// A tr-like character mapping. Sets support ranges (`a-z`) and the escapes
+// `\n`, `\t`, `\\` and `\-`. When the second set is shorter its last
+// character is repeated; without one, characters in the first set are
+// deleted.
+#[derive(Debug, PartialEq)]
+pub struct Translate {
+    mapping: HashMap<char, Option<char>>,
+}
+
+impl Translate {
+    pub fn new(from: &str, to: Option<&str>) -> Result<Translate, &'static str> {
+        let from = expand_set(from)?;
+
+        let mapping = match to {
+            None => from.into_iter().map(|c| (c, None)).collect(),
+            Some(to) => {
+                let to = expand_set(to)?;
+                let last = *to.last().ok_or("Empty character set")?;
+
+                from.into_iter()
+                    .enumerate()
+                    .map(|(index, c)| (c, Some(to.get(index).copied().unwrap_or(last))))
+                    .collect()
+            }
+        };
+
+        Ok(Translate { mapping })
+    }
+
+    pub fn apply(&self, line: &str) -> String {
+        line.chars()
+            .filter_map(|c| match self.mapping.get(&c) {
+                Some(mapped) => *mapped,
+                None => Some(c),
+            })
+            .collect()
+    }
+}
+
+pub(crate) fn expand_set(set: &str) -> Result<Vec<char>, &'static str> {
+    // Each character remembers whether it was escaped, so `\-` never forms
+    // a range.
+    let mut chars = vec![];
+    let mut source = set.chars();
+    while let Some(c) = source.next() {
+        chars.push(match c {
+            '\\' => match source.next() {
+                Some('n') => ('\n', true),
+                Some('t') => ('\t', true),
+                Some(other) => (other, true),
+                None => ('\\', true),
+            },
+            c => (c, false),
+        });
+    }
+
+    let mut expanded = vec![];
+    let mut index = 0;
+    while index < chars.len() {
+        match chars[index..] {
+            [(start, _), ('-', false), (end, _), ..] => {
+                if start > end {
+                    return Err("Invalid character range");
+                }
+                expanded.extend(start..=end);
+                index += 3;
+            }
+            [(c, _), ..] => {
+                expanded.push(c);
+                index += 1;
+            }
+            [] => break,
+        }
+    }
+
+    if expanded.is_empty() {
+        return Err("Empty character set");
+    }
+
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Translate;
+
+    #[test]
+    fn apply_translates_ranges_and_pads_short_sets() {
+        //+ Arrange
+        let upper = Translate::new("a-z", Some("A-Z")).unwrap();
+        let padded = Translate::new("abc", Some("x")).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(upper.apply("hello, world"), "HELLO, WORLD");
+        assert_eq!(padded.apply("cabbage"), "xxxxxge");
+        assert_eq!(
+            Translate::new("z-a", Some("x")).err(),
+            Some("Invalid character range")
+        );
+    }
+
+    #[test]
+    fn apply_deletes_without_second_set() {
+        //+ Arrange
+        let delete = Translate::new(r"0-9\-\t", None).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(delete.apply("a1-b2\tc-3"), "abc");
+    }
+}
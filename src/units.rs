@@ -0,0 +1,90 @@
+use std::time::Duration;
+
+// Longer durations are refused, so adding one to an Instant cannot overflow.
+const MAX_DURATION: Duration = Duration::from_secs(100 * 365 * 86400);
+
+// Parses sizes such as `4096`, `64K`, `4MiB` or `2GB`. Both decimal-looking
+// and binary suffixes are treated as powers of 1024, matching how the sizes
+// are reported back by indicatif's HumanBytes.
+pub fn parse_size(text: &str) -> Result<usize, &'static str> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, suffix) = text.split_at(split);
+
+    let number: f64 = number.parse().map_err(|_| "Invalid size")?;
+    let multiplier = match suffix.trim().to_lowercase().as_str() {
+        "" | "b" => 1u64,
+        "k" | "kb" | "kib" => 1 << 10,
+        "m" | "mb" | "mib" => 1 << 20,
+        "g" | "gb" | "gib" => 1 << 30,
+        "t" | "tb" | "tib" => 1 << 40,
+        _ => return Err("Invalid size"),
+    };
+
+    Ok((number * multiplier as f64) as usize)
+}
+
+// Parses durations such as `500ms`, `30s`, `5m`, `1h` or `1d`, up to a
+// hundred years. A bare number is seconds.
+pub fn parse_duration(text: &str) -> Result<Duration, &'static str> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, suffix) = text.split_at(split);
+
+    let number: f64 = number.parse().map_err(|_| "Invalid duration")?;
+    let seconds = match suffix.trim().to_lowercase().as_str() {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        "d" => number * 86400.0,
+        _ => return Err("Invalid duration"),
+    };
+
+    Duration::try_from_secs_f64(seconds)
+        .ok()
+        .filter(|duration| *duration <= MAX_DURATION)
+        .ok_or("Invalid duration")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_duration, parse_size};
+
+    #[test]
+    fn parse_size_understands_suffixes() {
+        //+ Act + Assert
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("64K"), Ok(65_536));
+        assert_eq!(parse_size("4MiB"), Ok(4_194_304));
+        assert_eq!(parse_size("1.5 GB"), Ok(1_610_612_736));
+    }
+
+    #[test]
+    fn parse_size_rejects_garbage() {
+        //+ Act + Assert
+        assert_eq!(parse_size("lots"), Err("Invalid size"));
+        assert_eq!(parse_size("10 parsecs"), Err("Invalid size"));
+    }
+
+    #[test]
+    fn parse_duration_understands_suffixes() {
+        //+ Act + Assert
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1w"), Err("Invalid duration"));
+        assert_eq!(
+            parse_duration("99999999999999999999d"),
+            Err("Invalid duration")
+        );
+        assert_eq!(parse_duration("36600d"), Err("Invalid duration"));
+    }
+}
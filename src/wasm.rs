@@ -0,0 +1,133 @@
+use std::fmt;
+
+use wasmtime::{Engine, Instance, Memory, Module, Store, TypedFunc};
+
+use crate::step::Step;
+
+// Runs every line through a WebAssembly module. The module gets no imports,
+// so it cannot touch files, the network or the clock, only its own memory.
+//
+// It exports `memory`, `alloc(length: i32) -> i32`, which returns where the
+// next line should be written, and `apply(pointer: i32, length: i32) -> i64`,
+// which returns where its output is as `pointer << 32 | length`, or -1 to drop
+// the line.
+pub struct WasmStep {
+    path: String,
+    store: Store<()>,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+    apply: TypedFunc<(i32, i32), i64>,
+}
+
+impl WasmStep {
+    pub fn load(path: &str) -> Result<WasmStep, &'static str> {
+        let engine = Engine::default();
+        let module =
+            Module::from_file(&engine, path).map_err(|_| "Could not load WebAssembly module")?;
+        let mut store = Store::new(&engine, ());
+        let instance = Instance::new(&mut store, &module, &[])
+            .map_err(|_| "Could not instantiate WebAssembly module")?;
+
+        let exports = "WebAssembly module must export memory, alloc and apply";
+        let memory = instance.get_memory(&mut store, "memory").ok_or(exports)?;
+        let alloc = instance
+            .get_typed_func(&mut store, "alloc")
+            .map_err(|_| exports)?;
+        let apply = instance
+            .get_typed_func(&mut store, "apply")
+            .map_err(|_| exports)?;
+
+        Ok(WasmStep {
+            path: path.to_string(),
+            store,
+            memory,
+            alloc,
+            apply,
+        })
+    }
+}
+
+impl Step for WasmStep {
+    fn apply(&mut self, line: String) -> Result<Vec<String>, &'static str> {
+        let failed = "WebAssembly step failed";
+        let length = i32::try_from(line.len()).map_err(|_| failed)?;
+
+        let pointer = self
+            .alloc
+            .call(&mut self.store, length)
+            .map_err(|_| failed)?;
+        self.memory
+            .write(&mut self.store, pointer as u32 as usize, line.as_bytes())
+            .map_err(|_| failed)?;
+
+        let result = self
+            .apply
+            .call(&mut self.store, (pointer, length))
+            .map_err(|_| failed)?;
+        if result < 0 {
+            return Ok(vec![]);
+        }
+
+        let start = (result >> 32) as usize;
+        let end = start + (result as u32) as usize;
+        let output = self
+            .memory
+            .data(&self.store)
+            .get(start..end)
+            .ok_or(failed)?;
+
+        Ok(vec![String::from_utf8_lossy(output).into_owned()])
+    }
+
+    fn memory(&self) -> usize {
+        self.memory.data_size(&self.store)
+    }
+}
+
+impl fmt::Debug for WasmStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WasmStep")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WasmStep;
+    use crate::step::Step;
+
+    // Drops comment lines and cuts the last character off the rest.
+    const MODULE: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "alloc") (param i32) (result i32)
+            (i32.const 1024))
+          (func (export "apply") (param $pointer i32) (param $length i32) (result i64)
+            (if (result i64) (i32.eq (i32.load8_u (local.get $pointer)) (i32.const 35))
+              (then (i64.const -1))
+              (else
+                (i64.or
+                  (i64.shl (i64.extend_i32_u (local.get $pointer)) (i64.const 32))
+                  (i64.extend_i32_u (i32.sub (local.get $length) (i32.const 1))))))))
+    "#;
+
+    #[test]
+    fn apply_runs_module_on_every_line() {
+        //+ Arrange
+        let path = std::env::temp_dir().join(format!("rangler-wasm-{}.wat", std::process::id()));
+        std::fs::write(&path, MODULE).unwrap();
+        let mut step = WasmStep::load(path.to_str().unwrap()).unwrap();
+
+        //+ Act + Assert
+        assert_eq!(
+            step.apply("hello!".to_string()),
+            Ok(vec!["hello".to_string()])
+        );
+        assert_eq!(step.apply("# note".to_string()), Ok(vec![]));
+        assert_eq!(
+            WasmStep::load("/nonexistent/rangler.wasm").err(),
+            Some("Could not load WebAssembly module")
+        );
+    }
+}
@@ -0,0 +1,48 @@
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// The widest a pad step will fill lines out to, in columns.
+pub const MAX_PAD_WIDTH: usize = 1 << 16;
+
+/// How many terminal columns text takes up: East Asian wide characters and
+/// emoji take two, combining marks and other zero-width characters none,
+/// and emoji joined into one grapheme, like a family or a flag, count once.
+pub fn display_width(text: &str) -> usize {
+    text.width()
+}
+
+/// The fill that brings text out to `width` columns, or None when it is
+/// already that wide. A wide fill character only fills whole pairs of
+/// columns, so the result can fall one column short.
+pub fn padding(text: &str, width: usize, fill: char) -> Option<String> {
+    let missing = width.checked_sub(display_width(text))?;
+    let count = missing / fill.width().unwrap_or(1).max(1);
+
+    (count > 0).then(|| std::iter::repeat_n(fill, count).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{display_width, padding};
+
+    #[test]
+    fn display_width_counts_terminal_columns() {
+        //+ Act + Assert
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("Cafe\u{301}"), 4);
+        assert_eq!(
+            display_width("\u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}"),
+            2
+        );
+        assert_eq!(display_width("\u{1f1ef}\u{1f1f5}"), 2);
+    }
+
+    #[test]
+    fn padding_fills_the_missing_columns() {
+        //+ Act + Assert
+        assert_eq!(padding("日本", 6, '.'), Some("..".to_string()));
+        assert_eq!(padding("ab", 7, '・'), Some("・・".to_string()));
+        assert_eq!(padding("日本", 4, '.'), None);
+        assert_eq!(padding("日本", 3, '.'), None);
+    }
+}
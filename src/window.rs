@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use chrono::DateTime;
+use regex::Regex;
+
+use crate::timestamp::{format_timestamp, TimestampFormat};
+
+// Counts lines per fixed time window, optionally split by a key taken from the
+// first capture group (or whole match) of a regex. Counts are emitted in time
+// order once the input ends, as `<window start> [<key>] <count>`; lines
+// without a timestamp are not counted.
+#[derive(Debug)]
+pub struct Window {
+    width: i64,
+    input: TimestampFormat,
+    key: Option<Regex>,
+    counts: BTreeMap<(i64, String), usize>,
+}
+
+impl Window {
+    pub fn new(
+        width: std::time::Duration,
+        input: TimestampFormat,
+        key: Option<Regex>,
+    ) -> Result<Window, &'static str> {
+        if width.as_secs() == 0 || width.subsec_nanos() != 0 {
+            return Err("Window must be a whole number of seconds");
+        }
+
+        Ok(Window {
+            width: width.as_secs() as i64,
+            input,
+            key,
+            counts: BTreeMap::new(),
+        })
+    }
+
+    pub fn push(&mut self, line: &str) {
+        let timestamp = match self.input.find(line) {
+            Some((_, timestamp)) => timestamp.timestamp(),
+            None => return,
+        };
+
+        let key = match &self.key {
+            None => String::new(),
+            Some(regex) => match regex.captures(line) {
+                Some(captures) => captures
+                    .get(1)
+                    .or(captures.get(0))
+                    .unwrap()
+                    .as_str()
+                    .to_string(),
+                None => "-".to_string(),
+            },
+        };
+
+        let start = timestamp.div_euclid(self.width) * self.width;
+        *self.counts.entry((start, key)).or_insert(0) += 1;
+    }
+
+    pub fn flush(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.counts)
+            .into_iter()
+            .filter_map(|((start, key), count)| {
+                let start =
+                    format_timestamp(&DateTime::from_timestamp(start, 0)?.fixed_offset(), "iso");
+
+                Some(match self.key {
+                    None => format!("{} {}", start, count),
+                    Some(_) => format!("{} {} {}", start, key, count),
+                })
+            })
+            .collect()
+    }
+
+    pub fn memory(&self) -> usize {
+        self.counts
+            .keys()
+            .map(|(_, key)| key.len() + std::mem::size_of::<(i64, usize)>())
+            .sum()
+    }
+}
+
+impl PartialEq for Window {
+    fn eq(&self, other: &Self) -> bool {
+        self.width == other.width
+            && self.input == other.input
+            && self.key.as_ref().map(Regex::as_str) == other.key.as_ref().map(Regex::as_str)
+            && self.counts == other.counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use regex::Regex;
+
+    use super::Window;
+    use crate::timestamp::TimestampFormat;
+
+    #[test]
+    fn flush_counts_lines_per_window_and_key() {
+        //+ Arrange
+        let mut window = Window::new(
+            Duration::from_secs(60),
+            TimestampFormat::Iso,
+            Some(Regex::new(r"level=(\w+)").unwrap()),
+        )
+        .unwrap();
+
+        //+ Act
+        for line in [
+            "2024-05-01T14:01:59Z level=warn",
+            "2024-05-01T14:00:05Z level=error",
+            "2024-05-01T14:00:30Z level=error",
+            "2024-05-01T14:01:00Z",
+            "no timestamp level=error",
+        ] {
+            window.push(line);
+        }
+
+        //+ Assert
+        assert_eq!(
+            window.flush(),
+            vec![
+                "2024-05-01T14:00:00Z error 2".to_string(),
+                "2024-05-01T14:01:00Z - 1".to_string(),
+                "2024-05-01T14:01:00Z warn 1".to_string(),
+            ]
+        );
+        assert!(Window::new(Duration::from_millis(1500), TimestampFormat::Iso, None).is_err());
+    }
+}